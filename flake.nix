@@ -97,6 +97,7 @@
               pname = "riff";
               version = (builtins.fromTOML (builtins.readFile "${self}/Cargo.toml")).package.version;
               src = self;
+              RIFF_GIT_COMMIT = self.shortRev or "dirty";
 
               nativeBuildInputs = with pkgs; [
                 pkg-config
@@ -0,0 +1,55 @@
+//! Bazel and Buck2 projects, whose toolchains fetch their own dependencies but expect some tools
+//! from the host.
+
+use std::path::Path;
+
+use crate::dev_env::DevEnvironment;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildTool {
+    Bazel,
+    Buck2,
+}
+
+impl BuildTool {
+    /// The files marking the root of a project, any of which is enough
+    pub fn root_files(&self) -> &'static [&'static str] {
+        match self {
+            BuildTool::Bazel => &["MODULE.bazel", "WORKSPACE", "WORKSPACE.bazel"],
+            BuildTool::Buck2 => &[".buckconfig", "BUCK"],
+        }
+    }
+
+    /// Whether `project_dir` is the root of a project built with this tool
+    pub fn detect(&self, project_dir: &Path) -> bool {
+        self.root_files()
+            .iter()
+            .any(|file| project_dir.join(file).is_file())
+    }
+
+    /// The Nixpkgs attributes providing the build tool, and the host tools its rules commonly run
+    ///
+    /// `bazelisk` downloads the Bazel release the project's `.bazelversion` asks for.
+    pub fn nixpkgs_attributes(&self) -> &'static [&'static str] {
+        match self {
+            BuildTool::Bazel => &["bazelisk", "python3", "jdk", "stdenv.cc"],
+            BuildTool::Buck2 => &["buck2", "python3", "stdenv.cc"],
+        }
+    }
+
+    /// Add the build tool and its host tools to `dev_env`
+    ///
+    /// The C and C++ compilers are set by absolute path, as both tools run actions with a
+    /// restricted `PATH` and their C++ toolchains look the compilers up through `CC` and `CXX`.
+    pub fn apply(&self, dev_env: &mut DevEnvironment<'_>) {
+        dev_env
+            .build_inputs
+            .extend(self.nixpkgs_attributes().iter().map(ToString::to_string));
+        dev_env
+            .environment_variables
+            .insert("CC".to_string(), "${stdenv.cc}/bin/cc".to_string());
+        dev_env
+            .environment_variables
+            .insert("CXX".to_string(), "${stdenv.cc}/bin/c++".to_string());
+    }
+}
@@ -0,0 +1,56 @@
+//! `cargo riff`, which runs riff as a cargo subcommand, like `cargo riff run -- cargo build`.
+//!
+//! cargo runs `cargo-riff riff <args>`, so this drops the `riff` it inserts and runs the `riff`
+//! installed next to it, or the one on the `PATH`, with the rest.
+
+use std::ffi::OsString;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    let riff = riff_path();
+    let err = Command::new(&riff)
+        .args(riff_args(std::env::args_os().skip(1)))
+        .exec();
+    eprintln!("Could not run `{}`: {err}", riff.display());
+    std::process::exit(1);
+}
+
+/// The `riff` in the same directory, which is where `cargo install` and Nix put it
+fn riff_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("riff")))
+        .filter(|riff| riff.is_file())
+        .unwrap_or_else(|| PathBuf::from("riff"))
+}
+
+/// The arguments for riff, without the subcommand name cargo passes first
+fn riff_args(args: impl IntoIterator<Item = OsString>) -> impl Iterator<Item = OsString> {
+    let mut args = args.into_iter().peekable();
+    if args.peek().map(|arg| arg == "riff").unwrap_or(false) {
+        args.next();
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        riff_args(args.iter().map(OsString::from)).collect()
+    }
+
+    #[test]
+    fn strips_subcommand() {
+        assert_eq!(args(&["riff", "shell"]), args(&["shell"]));
+        assert_eq!(
+            args(&["riff", "run", "--", "riff"]),
+            args(&["run", "--", "riff"])
+        );
+        // Run directly as `cargo-riff`
+        assert_eq!(args(&["run", "riff"]), vec!["run", "riff"]);
+    }
+}
@@ -0,0 +1,72 @@
+//! How this riff binary was built.
+
+use serde::{Deserialize, Serialize};
+
+use crate::dependency_registry::bundled_registry_hash;
+
+/// Build details recorded by `build.rs`, shown by `riff --version --verbose`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildInfo {
+    /// The `CARGO_PKG_VERSION` from a `riff` build
+    pub version: String,
+    /// The commit riff was built from, or `unknown` outside of a Git checkout
+    pub git_commit: String,
+    /// The UTC date of the build, or of `SOURCE_DATE_EPOCH` for reproducible builds
+    pub build_date: String,
+    /// The target triple riff was compiled for
+    pub target: String,
+    /// The Cargo features riff was compiled with
+    pub features: Vec<String>,
+    /// The SHA-256 of the registry snapshot embedded in riff
+    pub registry_snapshot: String,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: env!("RIFF_GIT_COMMIT").to_string(),
+            build_date: env!("RIFF_BUILD_DATE").to_string(),
+            target: env!("RIFF_TARGET").to_string(),
+            features: env!("RIFF_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+            registry_snapshot: bundled_registry_hash(),
+        }
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "riff {}", self.version)?;
+        writeln!(f, "commit: {}", self.git_commit)?;
+        writeln!(f, "build date: {}", self.build_date)?;
+        writeln!(f, "target: {}", self.target)?;
+        if self.features.is_empty() {
+            writeln!(f, "features: none")?;
+        } else {
+            writeln!(f, "features: {}", self.features.join(", "))?;
+        }
+        write!(f, "registry snapshot: {}", self.registry_snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_build_info() {
+        let build_info = BuildInfo::current();
+        assert_eq!(build_info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(build_info.build_date.len(), "2022-10-01".len());
+        assert!(!build_info.target.is_empty());
+        assert_eq!(build_info.registry_snapshot.len(), 64);
+        assert!(build_info
+            .to_string()
+            .starts_with(&format!("riff {}\n", env!("CARGO_PKG_VERSION"))));
+    }
+}
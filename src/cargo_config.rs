@@ -0,0 +1,137 @@
+//! Cargo configuration which points builds at the tools and libraries of a riff environment, so
+//! cargo behaves the same when it is run outside of the environment, such as by an IDE.
+
+use std::path::Path;
+
+use eyre::WrapErr;
+use toml_edit::{Document, InlineTable, Item, Table};
+
+use crate::nix_dev_env::ActivationScript;
+
+/// Prefixes of the variables Nix's compiler wrappers and `pkg-config` read
+const TOOLCHAIN_VAR_PREFIXES: [&str; 5] = [
+    "PKG_CONFIG_",
+    "NIX_CFLAGS_",
+    "NIX_LDFLAGS",
+    "NIX_CC_",
+    "NIX_BINTOOLS_",
+];
+
+/// Other variables build scripts commonly read
+const TOOLCHAIN_VARS: [&str; 5] = ["CC", "CXX", "AR", "LD_LIBRARY_PATH", "LIBRARY_PATH"];
+
+/// The `[env]` and `[target.<host>]` settings for `activation_script`
+///
+/// `environment_variables` are the names of the variables the project's environment declares,
+/// which are included alongside the toolchain's. `linker` is used for the host target.
+pub fn fragment<'a>(
+    activation_script: &ActivationScript,
+    environment_variables: impl IntoIterator<Item = &'a str>,
+    linker: Option<&Path>,
+) -> Document {
+    let declared = environment_variables.into_iter().collect::<Vec<_>>();
+    let mut env = Table::new();
+    env.decor_mut()
+        .set_prefix("# Written by `riff export cargo-config`\n");
+    for (name, value) in activation_script
+        .set
+        .iter()
+        .chain(activation_script.prepend.iter())
+    {
+        let wanted = declared.contains(&name.as_str())
+            || TOOLCHAIN_VARS.contains(&name.as_str())
+            || TOOLCHAIN_VAR_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix));
+        if !wanted {
+            continue;
+        }
+        let mut entry = InlineTable::new();
+        entry.insert("value", value.as_str().into());
+        // Override the variables of whatever runs cargo, so it builds as it does in the shell
+        entry.insert("force", true.into());
+        env.insert(name, toml_edit::value(entry));
+    }
+
+    let mut document = Document::new();
+    document.insert("env", Item::Table(env));
+    if let Some(linker) = linker {
+        let mut host = Table::new();
+        host.insert("linker", toml_edit::value(linker.display().to_string()));
+        let mut target = Table::new();
+        target.set_implicit(true);
+        target.insert(&target_lexicon::HOST.to_string(), Item::Table(host));
+        document.insert("target", Item::Table(target));
+    }
+    document
+}
+
+/// Merge `fragment` into the existing configuration `contents`, keeping its other settings
+pub fn merge(contents: &str, fragment: &Document) -> color_eyre::Result<String> {
+    let mut document = contents
+        .parse::<Document>()
+        .wrap_err("Could not parse the existing cargo configuration")?;
+    merge_tables(document.as_table_mut(), fragment.as_table());
+    Ok(document.to_string())
+}
+
+fn merge_tables(into: &mut Table, from: &Table) {
+    for (key, item) in from.iter() {
+        match (into.get_mut(key).and_then(Item::as_table_mut), item) {
+            (Some(existing), Item::Table(table)) => merge_tables(existing, table),
+            _ => {
+                into.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn merge_cargo_config() -> eyre::Result<()> {
+        let activation_script = ActivationScript {
+            set: [
+                ("PKG_CONFIG_PATH", "/nix/store/openssl-dev/lib/pkgconfig"),
+                ("HELLO", "WORLD"),
+                ("out", "/nix/store/riff-shell"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            prepend: [("PATH".to_string(), "/nix/store/a/bin".to_string())]
+                .into_iter()
+                .collect(),
+            shell_hook: None,
+        };
+        let fragment = fragment(
+            &activation_script,
+            ["HELLO"],
+            Some(&PathBuf::from("/nix/store/gcc-wrapper/bin/cc")),
+        );
+
+        let merged = merge(
+            "[build]\njobs = 4\n\n[env]\nHELLO = \"old\"\nKEEP = \"me\"\n",
+            &fragment,
+        )?;
+        let merged = merged.parse::<toml::Value>()?;
+        assert_eq!(merged["build"]["jobs"].as_integer(), Some(4));
+        assert_eq!(merged["env"]["KEEP"].as_str(), Some("me"));
+        assert_eq!(merged["env"]["HELLO"]["value"].as_str(), Some("WORLD"));
+        assert_eq!(
+            merged["env"]["PKG_CONFIG_PATH"]["force"].as_bool(),
+            Some(true)
+        );
+        assert!(merged["env"].get("out").is_none());
+        assert!(merged["env"].get("PATH").is_none());
+        assert_eq!(
+            merged["target"][&target_lexicon::HOST.to_string()]["linker"].as_str(),
+            Some("/nix/store/gcc-wrapper/bin/cc")
+        );
+        Ok(())
+    }
+}
@@ -1,17 +1,456 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use atty::Stream;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use tokio::process::Command;
+
 use crate::dependency_registry::rust::RustDependencyData;
+use crate::progress;
+use crate::spinner::SimpleSpinner;
+
+/// Placeholders for the machine-specific directories [`scrub_paths`] removes, most specific first
+const SCRUBBED_DIRS: [&str; 3] = ["$WORKSPACE", "$CARGO_HOME", "$HOME"];
 
 #[derive(serde::Deserialize)]
 pub struct CargoMetadata {
     pub packages: Vec<CargoMetadataPackage>,
+    pub resolve: Option<CargoMetadataResolve>,
+    #[serde(default)]
+    pub workspace_members: Vec<String>,
+    /// The `workspace.metadata` table of the root `Cargo.toml`
+    #[serde(default)]
+    pub metadata: Option<RiffMetadata>,
+}
+
+impl CargoMetadata {
+    /// The manifest of the package `cargo metadata` was run for, if any
+    pub fn root_manifest_path(&self) -> Option<PathBuf> {
+        let root = self.resolve.as_ref()?.root.as_ref()?;
+        self.packages
+            .iter()
+            .find(|package| &package.id == root)
+            .map(|package| package.manifest_path.clone())
+    }
+
+    /// Drop the packages which are only reachable from the workspace through dev-dependencies
+    /// (if `dev`) or build-dependencies (if `build`)
+    ///
+    /// Without a resolved dependency graph there is nothing to walk, so nothing is dropped.
+    pub fn exclude_dependencies(&mut self, dev: bool, build: bool) {
+        if !dev && !build {
+            return;
+        }
+        let nodes = match &self.resolve {
+            Some(resolve) if !resolve.nodes.is_empty() => &resolve.nodes,
+            _ => {
+                tracing::debug!("No resolved dependency graph, keeping every dependency");
+                return;
+            }
+        };
+        let nodes: BTreeMap<&str, &CargoMetadataNode> =
+            nodes.iter().map(|node| (node.id.as_str(), node)).collect();
+
+        let mut reachable: BTreeSet<&str> = self
+            .workspace_members
+            .iter()
+            .chain(
+                self.resolve
+                    .as_ref()
+                    .and_then(|resolve| resolve.root.as_ref()),
+            )
+            .map(String::as_str)
+            .collect();
+        let mut queue: Vec<&str> = reachable.iter().copied().collect();
+        while let Some(id) = queue.pop() {
+            let node = match nodes.get(id) {
+                Some(node) => node,
+                None => continue,
+            };
+            for dep in &node.deps {
+                // Old versions of Cargo don't report kinds, so every dependency counts
+                let included = dep.dep_kinds.is_empty()
+                    || dep
+                        .dep_kinds
+                        .iter()
+                        .any(|dep_kind| match dep_kind.kind.as_deref() {
+                            Some("dev") => !dev,
+                            Some("build") => !build,
+                            _ => true,
+                        });
+                if included && reachable.insert(dep.pkg.as_str()) {
+                    queue.push(dep.pkg.as_str());
+                }
+            }
+        }
+
+        let reachable: BTreeSet<String> = reachable.into_iter().map(String::from).collect();
+        self.packages.retain(|package| {
+            let keep = reachable.contains(&package.id);
+            if !keep {
+                tracing::debug!(package = %package.name, "Leaving out excluded dependency");
+            }
+            keep
+        });
+    }
+
+    /// The features the package with `id` is built with, if the dependency graph was resolved
+    pub fn enabled_features(&self, id: &str) -> Option<BTreeSet<String>> {
+        self.resolve
+            .as_ref()?
+            .nodes
+            .iter()
+            .find(|node| node.id == id)
+            .map(|node| node.features.iter().cloned().collect())
+    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct CargoMetadataPackage {
     pub name: String,
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub manifest_path: PathBuf,
+    /// The native library the package links, from the `links` key of its `Cargo.toml`
+    #[serde(default)]
+    pub links: Option<String>,
     pub metadata: Option<RiffMetadata>,
 }
 
+#[derive(serde::Deserialize)]
+pub struct CargoMetadataResolve {
+    pub root: Option<String>,
+    #[serde(default)]
+    pub nodes: Vec<CargoMetadataNode>,
+}
+
+/// A package in the resolved dependency graph
+#[derive(serde::Deserialize)]
+pub struct CargoMetadataNode {
+    pub id: String,
+    /// The features enabled across the whole graph
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub deps: Vec<CargoMetadataNodeDep>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct CargoMetadataNodeDep {
+    /// The id of the package depended on
+    pub pkg: String,
+    #[serde(default)]
+    pub dep_kinds: Vec<CargoMetadataDepKind>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct CargoMetadataDepKind {
+    /// `dev` or `build`, or none for a normal dependency
+    pub kind: Option<String>,
+}
+
 #[derive(serde::Deserialize)]
 pub struct RiffMetadata {
     pub riff: Option<RustDependencyData>,
+    /// The pkg-config packages a crate using `system-deps` looks for
+    #[serde(default, rename = "system-deps")]
+    pub system_deps: BTreeMap<String, serde_json::Value>,
+}
+
+impl RiffMetadata {
+    /// The names of the pkg-config packages in the `system-deps` table
+    pub fn system_deps(&self) -> Vec<String> {
+        self.system_deps
+            .iter()
+            .map(
+                |(key, value)| match value.get("name").and_then(|name| name.as_str()) {
+                    Some(name) => name.to_string(),
+                    None => key.clone(),
+                },
+            )
+            .collect()
+    }
+}
+
+/// The metadata of the project in `project_dir` and the manifest of its root package, read from
+/// `snapshot` instead of running `cargo metadata` if there is one
+pub async fn load(
+    project_dir: &Path,
+    snapshot: Option<&Path>,
+    offline: bool,
+) -> color_eyre::Result<(CargoMetadata, Option<PathBuf>)> {
+    match snapshot {
+        Some(snapshot) => {
+            tracing::debug!(snapshot = %snapshot.display(), "Replaying `cargo metadata` output");
+            let metadata = read_snapshot(snapshot).await?;
+            let root_manifest_path = metadata.root_manifest_path();
+            Ok((metadata, root_manifest_path))
+        }
+        None => {
+            let output = run(project_dir, offline).await?;
+            let metadata: CargoMetadata = serde_json::from_str(&output).wrap_err(
+                "Unable to parse output produced by `cargo metadata` into our desired structure",
+            )?;
+            let root_manifest_path =
+                tokio::fs::canonicalize(project_dir.join("Cargo.toml")).await?;
+            Ok((metadata, Some(root_manifest_path)))
+        }
+    }
+}
+
+/// Run `cargo metadata` for the project in `project_dir`, returning its JSON output
+///
+/// Fetching git dependencies can prompt for an SSH passphrase or HTTPS credentials. When riff runs
+/// in a terminal, cargo is attached to it, without a spinner, so the prompts are seen and
+/// answered rather than waiting behind the spinner. Otherwise its output is captured, and there's
+/// nobody to prompt.
+#[tracing::instrument(skip_all, fields(project_dir = %project_dir.display(), %offline))]
+pub async fn run(project_dir: &Path, offline: bool) -> color_eyre::Result<String> {
+    let mut cargo_metadata_command = Command::new("cargo");
+    cargo_metadata_command
+        .args(&["metadata", "--format-version", "1"])
+        .kill_on_drop(true);
+    cargo_metadata_command.arg("--manifest-path");
+    cargo_metadata_command.arg(project_dir.join("Cargo.toml"));
+
+    if offline || crate::network::disabled() {
+        cargo_metadata_command.arg("--offline");
+    }
+
+    let interactive = atty::is(Stream::Stdin) && atty::is(Stream::Stderr);
+    if interactive {
+        cargo_metadata_command
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+    }
+
+    tracing::trace!(command = ?cargo_metadata_command.as_std(), %interactive, "Running");
+    let spinner = if interactive {
+        None
+    } else {
+        Some(
+            SimpleSpinner::new_with_message(Some(&format!(
+                "Running `{cargo_metadata}`",
+                cargo_metadata = "cargo metadata".cyan()
+            )))
+            .context("Failed to construct progress spinner")?,
+        )
+    };
+    let cargo_metadata_progress =
+        progress::start(progress::Phase::CargoMetadata, "Running `cargo metadata`");
+
+    let cargo_metadata_output = match cargo_metadata_command.output().await {
+        Ok(output) => output,
+        err @ Err(_) => {
+            cargo_metadata_progress.finish(false);
+            let wrapped_err = err
+                .wrap_err_with(|| {
+                    format!(
+                        "\
+                    Could not execute `{cargo_metadata}`. Is `{cargo}` installed?\n\n\
+                    Get instructions for installing Cargo: {rust_install_url}\n\
+                    Run `{riff_doctor}` to check your setup.\n\
+                    Underlying error\
+                ",
+                        cargo_metadata = "cargo metadata".cyan(),
+                        cargo = "cargo".cyan(),
+                        rust_install_url =
+                            "https://www.rust-lang.org/tools/install".blue().underline(),
+                        riff_doctor = "riff doctor".cyan(),
+                    )
+                })
+                .unwrap_err();
+            eprintln!("{wrapped_err:#}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+    cargo_metadata_progress.finish(cargo_metadata_output.status.success());
+
+    if !cargo_metadata_output.status.success() {
+        let code = cargo_metadata_output
+            .status
+            .code()
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        if interactive {
+            // Its errors already went to the terminal
+            return Err(eyre!(
+                "`cargo metadata` exited with code {code}, see its output above"
+            ));
+        }
+        return Err(eyre!(
+            "`cargo metadata` exited with code {code}:\n{}",
+            std::str::from_utf8(&cargo_metadata_output.stderr)?,
+        ));
+    }
+
+    String::from_utf8(cargo_metadata_output.stdout)
+        .wrap_err("Output produced by `cargo metadata` was not valid UTF8")
+}
+
+/// Read `cargo metadata` output saved by `riff dev snapshot-metadata`, or by `cargo` itself
+pub async fn read_snapshot(path: &Path) -> color_eyre::Result<CargoMetadata> {
+    serde_json::from_value(read_snapshot_document(path).await?).wrap_err_with(|| {
+        format!(
+            "`{}` does not contain the output of `cargo metadata`",
+            path.display()
+        )
+    })
+}
+
+/// Read saved `cargo metadata` output without interpreting it
+pub async fn read_snapshot_document(path: &Path) -> color_eyre::Result<serde_json::Value> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+    let mut document: serde_json::Value = serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("Could not parse `{}`", path.display()))?;
+    // Fixtures for `riff registry test` keep the snapshot next to their expectations
+    if document.get("packages").is_none() {
+        if let Some(metadata) = document.get_mut("metadata") {
+            document = metadata.take();
+        }
+    }
+    Ok(document)
+}
+
+/// Replace the workspace root, Cargo home and home directories in the strings of a `cargo
+/// metadata` document with placeholders, so it can be shared without leaking the layout of
+/// this machine
+pub fn scrub_paths(document: &mut serde_json::Value) {
+    let workspace_root = document
+        .get("workspace_root")
+        .and_then(|root| root.as_str())
+        .map(PathBuf::from);
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".cargo")));
+
+    let dirs = [workspace_root, cargo_home, home]
+        .into_iter()
+        .zip(SCRUBBED_DIRS)
+        .filter_map(|(dir, placeholder)| {
+            let dir = dir?.to_str()?.trim_end_matches('/').to_string();
+            (!dir.is_empty()).then_some((dir, placeholder))
+        })
+        .collect::<Vec<_>>();
+    scrub_strings(document, &dirs);
+}
+
+fn scrub_strings(value: &mut serde_json::Value, dirs: &[(String, &str)]) {
+    match value {
+        serde_json::Value::String(string) => {
+            for (dir, placeholder) in dirs {
+                if string.contains(dir.as_str()) {
+                    *string = string.replace(dir.as_str(), placeholder);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                scrub_strings(value, dirs);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for value in map.values_mut() {
+                scrub_strings(value, dirs);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrubbed_snapshot() -> eyre::Result<()> {
+        let mut document = serde_json::json!({
+            "packages": [{
+                "name": "riff-test",
+                "id": "path+file:///build/riff-test#0.1.0",
+                "manifest_path": "/build/riff-test/Cargo.toml",
+            }],
+            "resolve": {"root": "path+file:///build/riff-test#0.1.0"},
+            "workspace_root": "/build/riff-test",
+        });
+        scrub_paths(&mut document);
+        assert_eq!(document["workspace_root"], "$WORKSPACE");
+        assert_eq!(
+            document["packages"][0]["manifest_path"],
+            "$WORKSPACE/Cargo.toml"
+        );
+
+        let metadata: CargoMetadata = serde_json::from_value(document)?;
+        assert_eq!(
+            metadata.root_manifest_path(),
+            Some(PathBuf::from("$WORKSPACE/Cargo.toml"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn excluded_dependencies() -> eyre::Result<()> {
+        let document = serde_json::json!({
+            "packages": [
+                {"name": "riff-test", "id": "riff-test"},
+                {"name": "openssl-sys", "id": "openssl-sys"},
+                {"name": "pkg-config", "id": "pkg-config"},
+                {"name": "libsqlite3-sys", "id": "libsqlite3-sys"},
+            ],
+            "workspace_members": ["riff-test"],
+            "resolve": {
+                "root": "riff-test",
+                "nodes": [
+                    {"id": "riff-test", "deps": [
+                        {"pkg": "openssl-sys", "dep_kinds": [{"kind": null}]},
+                        {"pkg": "libsqlite3-sys", "dep_kinds": [{"kind": "dev"}]},
+                    ]},
+                    {"id": "openssl-sys", "deps": [
+                        {"pkg": "pkg-config", "dep_kinds": [{"kind": "build"}]},
+                    ]},
+                ],
+            },
+        });
+        let names = |metadata: &CargoMetadata| {
+            metadata
+                .packages
+                .iter()
+                .map(|package| package.name.clone())
+                .collect::<BTreeSet<_>>()
+        };
+
+        let mut no_dev: CargoMetadata = serde_json::from_value(document.clone())?;
+        no_dev.exclude_dependencies(true, false);
+        assert_eq!(
+            names(&no_dev),
+            ["openssl-sys", "pkg-config", "riff-test"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+
+        let mut no_build_deps: CargoMetadata = serde_json::from_value(document)?;
+        no_build_deps.exclude_dependencies(false, true);
+        assert_eq!(
+            names(&no_build_deps),
+            ["libsqlite3-sys", "openssl-sys", "riff-test"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+        Ok(())
+    }
 }
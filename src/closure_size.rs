@@ -0,0 +1,107 @@
+//! Closure sizes of an environment's inputs, for `riff du`.
+
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use tokio::process::Command;
+
+use crate::flake_generator::GeneratedFlake;
+
+/// The size of the closure of the nixpkgs attribute `input`, in bytes
+///
+/// Packages come from the nixpkgs `flake` is locked to, so the sizes match the environment's.
+/// Inputs with several outputs report the sum of their closures.
+pub async fn closure_size(flake: &GeneratedFlake, input: &str) -> color_eyre::Result<u64> {
+    let mut command = Command::new("nix");
+    command
+        .arg("path-info")
+        .arg("--closure-size")
+        .arg("--json")
+        .args(["--extra-experimental-features", "flakes nix-command"])
+        .args(flake.nix_args())
+        .arg("--inputs-from")
+        .arg(format!("path://{}", flake.path().display()))
+        .arg(format!("nixpkgs#{input}"))
+        .kill_on_drop(true);
+    tracing::trace!(command = ?command.as_std(), "Running");
+
+    let output = command.output().await.wrap_err_with(|| {
+        format!(
+            "Could not execute `{nix_path_info}`. Is `{nix}` installed?",
+            nix_path_info = "nix path-info".cyan(),
+            nix = "nix".cyan(),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "`nix path-info` could not find the closure of `{input}`:\n{}",
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    parse_path_info(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Sum the `closureSize`s in the output of `nix path-info --closure-size --json`
+///
+/// Older versions of Nix output a list of paths, newer ones an object keyed by path.
+fn parse_path_info(json: &str) -> color_eyre::Result<u64> {
+    let document: serde_json::Value = serde_json::from_str(json)
+        .wrap_err("Output produced by `nix path-info` was not valid JSON")?;
+    let paths: Vec<&serde_json::Value> = match &document {
+        serde_json::Value::Array(paths) => paths.iter().collect(),
+        serde_json::Value::Object(paths) => paths.values().collect(),
+        _ => {
+            return Err(eyre!(
+                "Output produced by `nix path-info` was not a list of paths"
+            ))
+        }
+    };
+    paths
+        .into_iter()
+        .map(|path| {
+            path.get("closureSize")
+                .and_then(serde_json::Value::as_u64)
+                .ok_or_else(|| eyre!("Output produced by `nix path-info` had no `closureSize`"))
+        })
+        .sum()
+}
+
+/// Format `bytes` for people, like `du -h`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_info_formats() -> eyre::Result<()> {
+        assert_eq!(
+            parse_path_info(
+                r#"[{"path": "/nix/store/a-sqlite-bin", "closureSize": 100},
+                    {"path": "/nix/store/b-sqlite-dev", "closureSize": 20}]"#
+            )?,
+            120
+        );
+        assert_eq!(
+            parse_path_info(r#"{"/nix/store/a-sqlite-bin": {"closureSize": 100}}"#)?,
+            100
+        );
+        assert!(parse_path_info(r#"[{"path": "/nix/store/a-sqlite-bin"}]"#).is_err());
+
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+//! The `add` subcommand.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::environment_overrides::parse_environment_variable;
+use crate::metadata_editor::{InputKind, MetadataEditor};
+use crate::nix_expr;
+
+/// Add inputs or environment variables to your project's riff metadata
+///
+/// For example, add `openssl` to the build inputs:
+///
+///     $ riff add openssl
+///
+/// Add `libGL` to the runtime inputs and set an environment variable:
+///
+///     $ riff add runtime-input libGL --env HI=BYE
+///
+/// Without `build-input` or `runtime-input` first, inputs are build inputs, unless `--runtime` is
+/// passed.
+#[derive(Debug, Args)]
+pub struct Add {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// The Nixpkgs attributes to add (eg `openssl`), optionally after `build-input` or `runtime-input`
+    inputs: Vec<String>,
+    /// Add the inputs to `runtime-inputs` instead of `build-inputs`
+    #[clap(long)]
+    runtime: bool,
+    /// Set an environment variable, in the form `NAME=VALUE`
+    #[clap(long = "env", value_parser = parse_environment_variable)]
+    environment_variables: Vec<(String, String)>,
+}
+
+impl Add {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let (kind, inputs) = InputKind::from_args(&self.inputs, self.runtime)?;
+        for input in inputs {
+            nix_expr::check_attr_path(input).map_err(|err| eyre!(err))?;
+        }
+        if inputs.is_empty() && self.environment_variables.is_empty() {
+            return Err(eyre!(
+                "Nothing to add, pass some inputs or `--env NAME=VALUE`"
+            ));
+        }
+
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let mut editor = MetadataEditor::open(&project_dir).await?;
+
+        for input in inputs {
+            if editor.add_input(kind, input)? {
+                eprintln!(
+                    "{check} Added {input} to {key}",
+                    check = "✓".green(),
+                    input = input.cyan(),
+                    key = kind.key()
+                );
+            } else {
+                eprintln!(
+                    "{input} is already in {key}",
+                    input = input.cyan(),
+                    key = kind.key()
+                );
+            }
+        }
+        for (name, value) in &self.environment_variables {
+            editor.set_environment_variable(name, value)?;
+            eprintln!(
+                "{check} Set {name}",
+                check = "✓".green(),
+                name = name.green()
+            );
+        }
+
+        editor.save().await?;
+        Ok(None)
+    }
+}
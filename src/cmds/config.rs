@@ -0,0 +1,161 @@
+//! The `config` subcommand.
+
+use std::path::PathBuf;
+
+use clap::{Args, CommandFactory, Subcommand};
+use eyre::WrapErr;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+
+use crate::config;
+use crate::Cli;
+
+/// Inspect riff's settings and the configuration files they come from
+#[derive(Debug, Args)]
+pub struct Config {
+    #[clap(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    Show(ConfigShow),
+    Get(ConfigGet),
+    Set(ConfigSet),
+    Unset(ConfigUnset),
+}
+
+impl Config {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        match self.command {
+            ConfigCommand::Show(show) => show.cmd().await,
+            ConfigCommand::Get(get) => get.cmd().await,
+            ConfigCommand::Set(set) => set.cmd().await,
+            ConfigCommand::Unset(unset) => unset.cmd().await,
+        }
+    }
+}
+
+/// Print every setting in effect for the project, in TOML
+///
+/// Settings come from, in increasing precedence: riff's defaults, the system's
+/// `$XDG_CONFIG_DIRS/riff/config.toml`, the user's `$XDG_CONFIG_HOME/riff/config.toml`, the
+/// project's `riff.toml`, the file passed to `--config`, environment variables and flags:
+///
+///     $ riff config show --origin --offline
+#[derive(Debug, Args)]
+pub struct ConfigShow {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Note where each setting came from
+    #[clap(long)]
+    origin: bool,
+}
+
+impl ConfigShow {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let matches = Cli::command().try_get_matches()?;
+        let settings = config::settings(&project_dir, &matches).await?;
+        for (name, setting) in settings {
+            if self.origin {
+                // A comment, so the output is still TOML
+                println!(
+                    "{name} = {}  # {}",
+                    setting.value,
+                    setting.origins.iter().join(", ")
+                );
+            } else {
+                println!("{name} = {}", setting.value);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Print the value of a setting in effect for the project
+///
+/// Strings are printed as they are, other values in TOML. If the setting isn't set, this prints
+/// nothing and exits with 1.
+#[derive(Debug, Args)]
+pub struct ConfigGet {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// The name of the setting, like `offline` or `build-inputs`
+    name: String,
+}
+
+impl ConfigGet {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let matches = Cli::command().try_get_matches()?;
+        let settings = config::settings(&project_dir, &matches).await?;
+        match settings.get(&self.name).map(|setting| &setting.value) {
+            Some(toml::Value::String(value)) => println!("{value}"),
+            Some(value) => println!("{value}"),
+            None => return Ok(Some(1)),
+        }
+        Ok(None)
+    }
+}
+
+/// Set a setting in your own configuration file, for every project
+///
+/// The value is checked against what the setting takes, and is read as TOML when it isn't a
+/// plain string, so lists work too:
+///
+///     $ riff config set offline true
+///     $ riff config set build-inputs '["cmake"]'
+#[derive(Debug, Args)]
+pub struct ConfigSet {
+    /// The name of the setting, like `offline` or `build-inputs`
+    name: String,
+    /// The value to set it to
+    value: String,
+}
+
+impl ConfigSet {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let path = config::set_user_setting(&self.name, &self.value).await?;
+        eprintln!(
+            "{check} Set {name} in `{path}`",
+            check = "✓".green(),
+            name = self.name.cyan(),
+            path = path.display(),
+        );
+        Ok(None)
+    }
+}
+
+/// Remove a setting from your own configuration file
+#[derive(Debug, Args)]
+pub struct ConfigUnset {
+    /// The name of the setting, like `offline`
+    name: String,
+}
+
+impl ConfigUnset {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        if config::unset_user_setting(&self.name).await? {
+            eprintln!(
+                "{check} Removed {name} from your configuration",
+                check = "✓".green(),
+                name = self.name.cyan(),
+            );
+        } else {
+            eprintln!(
+                "{name} was not set in your configuration",
+                name = self.name.cyan()
+            );
+        }
+        Ok(None)
+    }
+}
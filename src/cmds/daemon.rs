@@ -0,0 +1,127 @@
+//! The `daemon` subcommand.
+
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use tokio::net::{UnixListener, UnixStream};
+use xdg::BaseDirectories;
+
+use crate::daemon;
+use crate::flake_generator::{DetectArgs, DetectOptions};
+use crate::RIFF_XDG_PREFIX;
+
+const DAEMON_SOCKET_PATH: &str = "daemon.sock";
+
+/// Serve project environments to editors and scripts over a unix socket
+///
+/// Environments are evaluated once and kept in memory, so clients such as direnv hooks and IDE
+/// extensions can fetch them without re-running `cargo metadata` or Nix. The JSON-RPC protocol
+/// spoken on the socket is documented in `src/daemon.rs`.
+///
+/// For example:
+///
+///     $ riff daemon &
+///     $ echo '{"jsonrpc": "2.0", "id": 1, "method": "get-env", "params": {"project_dir": "'$PWD'"}}' \
+///         | nc -U $XDG_RUNTIME_DIR/riff/daemon.sock
+#[derive(Debug, Args)]
+pub struct Daemon {
+    /// The socket to listen on (defaults to `$XDG_RUNTIME_DIR/riff/daemon.sock`)
+    #[clap(long, value_parser)]
+    socket: Option<PathBuf>,
+    /// Refuse to serve an environment which differs from its project's `riff.lock`, and use its
+    /// nixpkgs
+    #[clap(long)]
+    locked: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl Daemon {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let socket = match self.socket {
+            Some(socket) => socket,
+            None => default_socket_path()?,
+        };
+        remove_stale_socket(&socket).await?;
+        let listener = UnixListener::bind(&socket)
+            .wrap_err_with(|| format!("Could not listen on `{}`", socket.display()))?;
+        eprintln!(
+            "{check} Listening on {socket}",
+            check = "✓".green(),
+            socket = socket.display().to_string().cyan()
+        );
+
+        let options = DetectOptions {
+            locked: self.locked,
+            ..DetectOptions::from(&self.detect)
+        };
+        daemon::Daemon::new(self.offline, options)
+            .serve(listener)
+            .await?;
+        Ok(None)
+    }
+}
+
+/// Remove the socket a previous daemon left behind at `socket`, if there is one
+///
+/// Nothing but a socket which no daemon is listening on is removed.
+async fn remove_stale_socket(socket: &Path) -> color_eyre::Result<()> {
+    let metadata = match tokio::fs::symlink_metadata(socket).await {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(eyre!(
+            "`{}` already exists and is not a socket",
+            socket.display()
+        ));
+    }
+    if UnixStream::connect(socket).await.is_ok() {
+        return Err(eyre!(
+            "A riff daemon is already running on `{}`",
+            socket.display()
+        ));
+    }
+    tokio::fs::remove_file(socket)
+        .await
+        .wrap_err_with(|| format!("Could not remove stale socket `{}`", socket.display()))
+}
+
+fn default_socket_path() -> color_eyre::Result<PathBuf> {
+    let xdg_dirs = BaseDirectories::with_prefix(RIFF_XDG_PREFIX)?;
+    // `$XDG_RUNTIME_DIR` is not always set, for example in containers.
+    xdg_dirs
+        .place_runtime_file(Path::new(DAEMON_SOCKET_PATH))
+        .or_else(|_| xdg_dirs.place_cache_file(Path::new(DAEMON_SOCKET_PATH)))
+        .wrap_err("Could not place the daemon socket in an XDG directory")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn stale_sockets() -> eyre::Result<()> {
+        let dir = TempDir::new()?;
+        let socket = dir.path().join(DAEMON_SOCKET_PATH);
+        remove_stale_socket(&socket).await?;
+
+        let listener = UnixListener::bind(&socket)?;
+        let err = remove_stale_socket(&socket).await.unwrap_err();
+        assert!(err.to_string().contains("already running"), "{err}");
+        drop(listener);
+        remove_stale_socket(&socket).await?;
+        assert!(!socket.exists());
+
+        std::fs::write(&socket, "")?;
+        assert!(remove_stale_socket(&socket).await.is_err());
+        assert!(socket.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! The `detect` subcommand.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::WrapErr;
+
+use crate::dependency_registry::DependencyRegistry;
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+
+/// Detect your project's languages and print what each adds to its environment
+///
+/// Nothing is evaluated with Nix. With `--json`, the languages, their inputs, environment
+/// variables and how many rules matched are printed for scripts and editors:
+///
+///     $ riff detect --json
+#[derive(Debug, Args)]
+pub struct Detect {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Print the detected environment as JSON
+    #[clap(long)]
+    json: bool,
+    /// Detect for this target triple, like `aarch64-unknown-linux-gnu`
+    #[clap(long, value_name = "TRIPLE")]
+    target: Option<String>,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl Detect {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.detect.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let dev_env = flake_generator::detect_dev_env(
+            &project_dir,
+            &registry,
+            self.disable_telemetry,
+            DetectOptions {
+                target: self.target.clone(),
+                ..DetectOptions::from(&self.detect)
+            },
+        )
+        .await?;
+
+        if self.json {
+            let output = serde_json::json!({
+                "languages": dev_env.language_summaries,
+                "build-inputs": dev_env.build_inputs.iter().collect::<BTreeSet<_>>(),
+                "runtime-inputs": dev_env.runtime_inputs.iter().collect::<BTreeSet<_>>(),
+                "darwin-frameworks": dev_env.darwin_frameworks.iter().collect::<BTreeSet<_>>(),
+                "platform-build-inputs": dev_env
+                    .platform_build_inputs
+                    .iter()
+                    .map(|(platform, inputs)| (platform, inputs.iter().collect::<BTreeSet<_>>()))
+                    .collect::<BTreeMap<_, _>>(),
+                "environment-variables": dev_env.environment_variables.iter().collect::<BTreeMap<_, _>>(),
+                "rules": dev_env.applied_rules,
+            });
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+
+        Ok(Some(0))
+    }
+}
@@ -0,0 +1,167 @@
+//! The `dev` subcommand.
+
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+
+use crate::cargo_metadata;
+use crate::dependency_registry::DependencyRegistry;
+use crate::detect_report;
+use crate::registry_test::{self, Fixture};
+
+/// Tools for developing riff and its registry
+#[derive(Debug, Args)]
+pub struct Dev {
+    #[clap(subcommand)]
+    command: DevCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DevCommand {
+    SnapshotMetadata(SnapshotMetadata),
+    DetectReport(DetectReport),
+}
+
+impl Dev {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        match self.command {
+            DevCommand::SnapshotMetadata(snapshot) => snapshot.cmd().await,
+            DevCommand::DetectReport(report) => report.cmd().await,
+        }
+    }
+}
+
+/// Save your project's `cargo metadata` output as a fixture for `riff registry test`
+///
+/// Paths inside the workspace, Cargo home and home directories are replaced with placeholders,
+/// so the fixture can be attached to a bug report. Its expectations are what the current
+/// registry produces for the project.
+///
+/// Replay the fixture rather than running `cargo metadata`:
+///
+///     $ riff dev snapshot-metadata --output snapshot.json
+///     $ riff print-dev-env --from-metadata snapshot.json
+#[derive(Debug, Args)]
+pub struct SnapshotMetadata {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Write the fixture to this path instead of stdout
+    #[clap(long, short, value_parser)]
+    output: Option<PathBuf>,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(from_global)]
+    registry_url: Option<String>,
+    #[clap(from_global, value_parser)]
+    from_metadata: Option<PathBuf>,
+}
+
+impl SnapshotMetadata {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let mut metadata = match &self.from_metadata {
+            Some(snapshot) => cargo_metadata::read_snapshot_document(snapshot).await?,
+            None => {
+                let output = cargo_metadata::run(&project_dir, registry.offline()).await?;
+                serde_json::from_str(&output)
+                    .wrap_err("Output produced by `cargo metadata` was not valid JSON")?
+            }
+        };
+        cargo_metadata::scrub_paths(&mut metadata);
+
+        let expect = registry_test::resolve(
+            &registry,
+            serde_json::from_value(metadata.clone()).wrap_err(
+                "Unable to parse output produced by `cargo metadata` into our desired structure",
+            )?,
+        )
+        .await;
+        let fixture = serde_json::to_string_pretty(&Fixture { metadata, expect })? + "\n";
+
+        match self.output {
+            Some(output) => {
+                tokio::fs::write(&output, fixture)
+                    .await
+                    .wrap_err_with(|| format!("Could not write `{}`", output.display()))?;
+                eprintln!(
+                    "{check} Wrote {output}",
+                    check = "✓".green(),
+                    output = output.display().to_string().cyan()
+                );
+            }
+            None => print!("{fixture}"),
+        }
+        Ok(None)
+    }
+}
+
+/// Capture the files riff reads from your project into a detection report
+///
+/// The report holds the project's manifests, lockfiles, task runner files and scrubbed `cargo
+/// metadata` output, but none of its code. Maintainers can reproduce the environment from it:
+///
+///     $ riff dev detect-report --output report.json
+///     $ riff print-dev-env --from-detect-report report.json
+#[derive(Debug, Args)]
+pub struct DetectReport {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Write the report to this path instead of stdout
+    #[clap(long, short, value_parser)]
+    output: Option<PathBuf>,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(from_global)]
+    registry_url: Option<String>,
+    #[clap(from_global, value_parser)]
+    from_metadata: Option<PathBuf>,
+}
+
+impl DetectReport {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let report =
+            detect_report::DetectReport::capture(&project_dir, &registry, self.from_metadata)
+                .await?;
+        let report = serde_json::to_string_pretty(&report)? + "\n";
+
+        match self.output {
+            Some(output) => {
+                tokio::fs::write(&output, report)
+                    .await
+                    .wrap_err_with(|| format!("Could not write `{}`", output.display()))?;
+                eprintln!(
+                    "{check} Wrote {output}",
+                    check = "✓".green(),
+                    output = output.display().to_string().cyan()
+                );
+            }
+            None => print!("{report}"),
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,156 @@
+//! The `direnv` subcommand.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+use crate::nix_dev_env::{self, shell_quote, ActivationScript};
+use crate::project_fingerprint;
+
+/// Where the environment is cached, relative to the project directory
+const CACHE_PATH: &str = ".direnv/riff-env.json";
+
+/// Load your project's environment with direnv
+///
+/// Add this to the project's `.envrc`, which `riff direnv --envrc` prints:
+///
+///     eval "$(riff direnv)"
+///
+/// The environment is cached in `.direnv`, and only evaluated again when a file riff reads,
+/// such as `Cargo.lock`, changes.
+#[derive(Debug, Args)]
+pub struct Direnv {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Print a `.envrc` which loads the environment, rather than the environment itself
+    #[clap(long)]
+    envrc: bool,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedEnvironment {
+    key: String,
+    activation_script: ActivationScript,
+}
+
+impl Direnv {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        if self.envrc {
+            print!("{}", include_str!("../envrc.sh"));
+            return Ok(None);
+        }
+
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+
+        let options = DetectOptions::from(&self.detect);
+        let watched_files = project_fingerprint::watched_files(&project_dir);
+        let key = cache_key(&project_dir, &watched_files, &options).await?;
+
+        let cache_path = project_dir.join(CACHE_PATH);
+        let cached = match tokio::fs::read_to_string(&cache_path).await {
+            Ok(contents) => serde_json::from_str::<CachedEnvironment>(&contents)
+                .ok()
+                .filter(|cached| cached.key == key),
+            Err(_) => None,
+        };
+        let activation_script = match cached {
+            Some(cached) => {
+                tracing::debug!(cache = %cache_path.display(), "Using the cached environment");
+                cached.activation_script
+            }
+            None => {
+                let mut flake_dir = flake_generator::generate_flake_from_project_dir(
+                    Some(project_dir.clone()),
+                    self.offline,
+                    self.disable_telemetry,
+                    options,
+                )
+                .await?;
+                flake_dir.root_for_project(&project_dir).await;
+                let dev_env = nix_dev_env::get_nix_dev_env(&flake_dir).await?;
+                let activation_script = ActivationScript::from(&dev_env);
+
+                let cached = CachedEnvironment {
+                    key,
+                    activation_script,
+                };
+                if let Some(cache_dir) = cache_path.parent() {
+                    tokio::fs::create_dir_all(cache_dir).await?;
+                }
+                tokio::fs::write(&cache_path, serde_json::to_string(&cached)?)
+                    .await
+                    .wrap_err_with(|| format!("Could not write `{}`", cache_path.display()))?;
+                cached.activation_script
+            }
+        };
+
+        // `watch_file` is part of direnv's standard library
+        for file_name in &watched_files {
+            println!(
+                "watch_file {}",
+                shell_quote(&project_dir.join(file_name).display().to_string())
+            );
+        }
+        print!("{}", activation_script.to_shell());
+
+        Ok(None)
+    }
+}
+
+/// A key which changes whenever the files riff reads, or the options it reads them with, do
+async fn cache_key(
+    project_dir: &Path,
+    watched_files: &[PathBuf],
+    options: impl Hash,
+) -> color_eyre::Result<String> {
+    let mut hasher = DefaultHasher::new();
+    project_fingerprint::fingerprint(project_dir, watched_files)
+        .await?
+        .hash(&mut hasher);
+    options.hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::fs::write;
+
+    #[tokio::test]
+    async fn cache_key_follows_options() -> eyre::Result<()> {
+        let project_dir = TempDir::new()?;
+        let files = [PathBuf::from("Cargo.toml")];
+        write(project_dir.path().join("Cargo.toml"), "[package]\n").await?;
+
+        let key = cache_key(project_dir.path(), &files, (false, false)).await?;
+        assert_eq!(
+            key,
+            cache_key(project_dir.path(), &files, (false, false)).await?
+        );
+        assert_ne!(
+            key,
+            cache_key(project_dir.path(), &files, (true, false)).await?
+        );
+        assert_ne!(
+            key,
+            cache_key(project_dir.path(), &files, (false, true)).await?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+//! The `doctor` subcommand.
+
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::Output;
+use std::time::Duration;
+
+use clap::Args;
+use owo_colors::OwoColorize;
+use tokio::process::Command;
+use xdg::BaseDirectories;
+
+use crate::dependency_registry::DEPENDENCY_REGISTRY_REMOTE_URL;
+use crate::legacy_nix::{parse_nix_version, MINIMUM_NIX_VERSION};
+use crate::network;
+use crate::wsl;
+use crate::RIFF_XDG_PREFIX;
+
+/// Check that riff has everything it needs, and suggest fixes for anything missing
+///
+/// Checks for a working `nix` which can evaluate flakes, `cargo`, the registry, a writable cache
+/// directory, and under WSL, for WSL 2 and a running Nix daemon. Exits with an error if anything
+/// is wrong.
+#[derive(Debug, Args)]
+pub struct Doctor {
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(from_global)]
+    registry_url: Option<String>,
+}
+
+enum Outcome {
+    Ok(String),
+    Skipped(String),
+    Problem { problem: String, fix: String },
+}
+
+impl Doctor {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let checks = [
+            ("nix", check_nix().await),
+            ("flakes", check_flakes().await),
+            ("cargo", check_cargo().await),
+            (
+                "registry",
+                check_registry(
+                    self.offline,
+                    self.registry_url
+                        .as_deref()
+                        .unwrap_or(DEPENDENCY_REGISTRY_REMOTE_URL),
+                )
+                .await,
+            ),
+            ("cache", check_cache()),
+            ("wsl", check_wsl()),
+        ];
+
+        let mut problems = 0;
+        for (name, outcome) in checks {
+            match outcome {
+                Outcome::Ok(detail) => println!("{} {name}: {detail}", "✓".green()),
+                Outcome::Skipped(reason) => {
+                    println!("{} {name}: {}", "-".dimmed(), reason.dimmed())
+                }
+                Outcome::Problem { problem, fix } => {
+                    problems += 1;
+                    println!("{} {name}: {problem}", "✗".red());
+                    for line in fix.lines() {
+                        println!("    {line}");
+                    }
+                }
+            }
+        }
+
+        if problems > 0 {
+            eprintln!(
+                "\n{problems} problem{s} found",
+                s = if problems == 1 { "" } else { "s" }
+            );
+            Ok(Some(1))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Run `program` with `args`, returning its output if it ran at all
+async fn run(program: &str, args: &[&str]) -> std::io::Result<Output> {
+    Command::new(program).args(args).output().await
+}
+
+async fn check_nix() -> Outcome {
+    let mut install_fix = format!(
+        "Get instructions for installing Nix: {}",
+        "https://nixos.org/download.html".blue().underline()
+    );
+    if wsl::version().is_some() {
+        install_fix
+            .push_str("\nUnder WSL, install Nix inside the Linux distribution, not on Windows.");
+    }
+    let output = match run("nix", &["--version"]).await {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            return Outcome::Problem {
+                problem: format!("`{}` failed", "nix --version".cyan()),
+                fix: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            }
+        }
+        Err(_) => {
+            return Outcome::Problem {
+                problem: format!("`{}` was not found on the `PATH`", "nix".cyan()),
+                fix: install_fix,
+            }
+        }
+    };
+    let version_line = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    match parse_nix_version(&version_line) {
+        Some(version) if version < MINIMUM_NIX_VERSION => Outcome::Problem {
+            problem: format!("Nix {version} is too old for flakes"),
+            fix: format!(
+                "Riff loads environments with `{nix_shell}` instead, but commands which build, \
+                 lock, or export flakes need Nix {MINIMUM_NIX_VERSION} or later. {install_fix}",
+                nix_shell = "nix-shell".cyan(),
+            ),
+        },
+        _ => Outcome::Ok(version_line),
+    }
+}
+
+async fn check_flakes() -> Outcome {
+    // The same features riff enables for every `nix` it runs
+    let args = [
+        "eval",
+        "--extra-experimental-features",
+        "flakes nix-command",
+        "--expr",
+        "builtins.nixVersion",
+    ];
+    match run("nix", &args).await {
+        Ok(output) if output.status.success() => {
+            Outcome::Ok("`nix` can evaluate with flakes enabled".to_string())
+        }
+        Ok(output) => Outcome::Problem {
+            problem: "`nix` could not evaluate with flakes enabled".to_string(),
+            fix: format!(
+                "{}\nCheck that the Nix daemon is running, and that `{}` in `nix.conf` allows flakes.",
+                String::from_utf8_lossy(&output.stderr).trim(),
+                "experimental-features".cyan(),
+            ),
+        },
+        Err(_) => Outcome::Skipped("needs `nix`".to_string()),
+    }
+}
+
+async fn check_cargo() -> Outcome {
+    match run("cargo", &["--version"]).await {
+        Ok(output) if output.status.success() => {
+            Outcome::Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        _ => Outcome::Problem {
+            problem: format!(
+                "`{}` was not found, so riff can't read Rust projects",
+                "cargo".cyan()
+            ),
+            fix: format!(
+                "Get instructions for installing Cargo: {}",
+                "https://www.rust-lang.org/tools/install".blue().underline()
+            ),
+        },
+    }
+}
+
+async fn check_registry(offline: bool, remote_url: &str) -> Outcome {
+    if offline {
+        return Outcome::Skipped("offline".to_string());
+    }
+    let client_builder = match network::client_builder("check the registry") {
+        Ok(client_builder) => client_builder,
+        Err(_) => return Outcome::Skipped("network disabled".to_string()),
+    };
+    let response = match client_builder.timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client.get(remote_url).send().await,
+        Err(err) => Err(err),
+    };
+    match response.and_then(|response| response.error_for_status()) {
+        Ok(_) => Outcome::Ok(format!("{remote_url} is reachable")),
+        Err(err) => Outcome::Problem {
+            problem: format!("Could not reach {remote_url}: {err}"),
+            fix: format!(
+                "Riff falls back to its cached registry, or the one bundled with it, which may be out of date.\n\
+                 Check your network or proxy settings, or pass `{}` to stop trying.",
+                "--offline".cyan()
+            ),
+        },
+    }
+}
+
+fn check_cache() -> Outcome {
+    let problem = |err: &dyn std::fmt::Display| Outcome::Problem {
+        problem: format!("The cache directory is not writable: {err}"),
+        fix: format!(
+            "Make the directory writable, or point `{}` at one which is.",
+            "XDG_CACHE_HOME".cyan()
+        ),
+    };
+    let probe = match BaseDirectories::with_prefix(RIFF_XDG_PREFIX)
+        .map_err(|err| err.to_string())
+        .and_then(|dirs| {
+            dirs.place_cache_file(format!("doctor-{}", std::process::id()))
+                .map_err(|err| err.to_string())
+        }) {
+        Ok(probe) => probe,
+        Err(err) => return problem(&err),
+    };
+    match std::fs::write(&probe, "") {
+        Ok(()) => {
+            std::fs::remove_file(&probe).ok();
+            match probe.parent() {
+                Some(dir) => Outcome::Ok(format!("{} is writable", dir.display())),
+                None => Outcome::Ok("writable".to_string()),
+            }
+        }
+        Err(err) => problem(&err),
+    }
+}
+
+fn check_wsl() -> Outcome {
+    match wsl::version() {
+        None => Outcome::Skipped("not running under WSL".to_string()),
+        Some(1) => Outcome::Problem {
+            problem: "WSL 1 can't run Nix reliably".to_string(),
+            fix: format!(
+                "Convert the distribution to WSL 2 by running `{}` on Windows.",
+                format!(
+                    "wsl --set-version {} 2",
+                    std::env::var("WSL_DISTRO_NAME").unwrap_or_else(|_| "<distribution>".to_string())
+                )
+                .cyan()
+            ),
+        },
+        // Only multi-user installs have a daemon, which WSL doesn't start without systemd
+        Some(version)
+            if Path::new(wsl::NIX_DAEMON_SOCKET).parent().map(Path::is_dir) == Some(true)
+                && UnixStream::connect(wsl::NIX_DAEMON_SOCKET).is_err() =>
+        {
+            Outcome::Problem {
+                problem: format!("The Nix daemon isn't running under WSL {version}"),
+                fix: format!(
+                    "WSL only starts it with systemd. Add `{systemd}` to the `{boot}` section of `{wsl_conf}` \
+                     and restart WSL with `{shutdown}` on Windows, or start it yourself with `{nix_daemon}`.",
+                    systemd = "systemd=true".cyan(),
+                    boot = "[boot]".cyan(),
+                    wsl_conf = "/etc/wsl.conf".cyan(),
+                    shutdown = "wsl --shutdown".cyan(),
+                    nix_daemon = "sudo nix-daemon".cyan(),
+                ),
+            }
+        }
+        Some(version) => Outcome::Ok(format!("WSL {version}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nix_versions() {
+        assert_eq!(
+            parse_nix_version("nix (Nix) 2.11.1"),
+            Some(semver::Version::new(2, 11, 1))
+        );
+        assert_eq!(
+            parse_nix_version("nix (Nix) 2.12.0pre20221005_abcdef"),
+            Some(semver::Version::new(2, 12, 0))
+        );
+        assert!(parse_nix_version("nix (Nix) 2.3").unwrap() < MINIMUM_NIX_VERSION);
+        assert_eq!(parse_nix_version(""), None);
+    }
+}
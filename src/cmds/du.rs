@@ -0,0 +1,159 @@
+//! The `du` subcommand.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::WrapErr;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+
+use crate::cargo_metadata;
+use crate::closure_size::{closure_size, human_size};
+use crate::contribution_graph::ContributionGraph;
+use crate::dependency_registry::DependencyRegistry;
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+use crate::progress;
+use crate::spinner::SimpleSpinner;
+
+/// Who inputs are attributed to when no crate adds them, such as the inputs of other languages
+const PROJECT: &str = "(project)";
+
+/// Show how much each crate, or each input, adds to your project's environment
+///
+/// Sizes are the closure sizes of the Nix packages, as `nix path-info --closure-size` reports
+/// them. Closures share dependencies, so a crate's total counts anything its inputs have in
+/// common with others again, and the totals don't add up to the size of the whole environment.
+/// See which inputs are the largest, and which crates add them:
+///
+///     $ riff du --by-input
+#[derive(Debug, Args)]
+pub struct Du {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// List each input and the crates which add it, rather than each crate and its inputs
+    #[clap(long)]
+    by_input: bool,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl Du {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.detect.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let dev_env = flake_generator::detect_dev_env(
+            &project_dir,
+            &registry,
+            self.disable_telemetry,
+            DetectOptions::from(&self.detect),
+        )
+        .await?;
+        let flake = flake_generator::write_locked_flake(&dev_env).await?;
+
+        let graph =
+            if self.detect.from_metadata.is_some() || project_dir.join("Cargo.toml").exists() {
+                let (mut metadata, root_manifest_path) = cargo_metadata::load(
+                    &project_dir,
+                    self.detect.from_metadata.as_deref(),
+                    self.offline,
+                )
+                .await?;
+                metadata.exclude_dependencies(self.detect.no_dev, self.detect.no_build_deps);
+                ContributionGraph::from_cargo_metadata(
+                    &metadata,
+                    root_manifest_path.as_deref(),
+                    &registry.language().await.rust,
+                    !self.detect.no_sys_heuristics,
+                    self.detect.conservative,
+                )
+            } else {
+                ContributionGraph::default()
+            };
+        let origins = graph.origins();
+
+        let inputs = dev_env
+            .build_inputs
+            .iter()
+            .chain(&dev_env.runtime_inputs)
+            .collect::<BTreeSet<_>>();
+        let mut sizes = BTreeMap::new();
+        for input in inputs {
+            let spinner = SimpleSpinner::new_with_message(Some(&format!(
+                "Measuring `{input}`",
+                input = input.cyan()
+            )))
+            .context("Failed to construct progress spinner")?;
+            let measure_progress =
+                progress::start(progress::Phase::Measure, &format!("Measuring `{input}`"));
+            let size = closure_size(&flake, input).await;
+            spinner.finish_and_clear();
+            measure_progress.finish(size.is_ok());
+            sizes.insert(input.as_str(), size?);
+        }
+
+        let crates_of = |input: &str| {
+            origins
+                .get(input)
+                .cloned()
+                .unwrap_or_else(|| [PROJECT].into_iter().collect())
+        };
+        if self.by_input {
+            let mut by_input = sizes.iter().collect::<Vec<_>>();
+            by_input.sort_by(|(a_name, a_size), (b_name, b_size)| {
+                b_size.cmp(a_size).then(a_name.cmp(b_name))
+            });
+            for (input, size) in by_input {
+                println!(
+                    "{size:>10}  {input}  {via}",
+                    size = human_size(*size),
+                    input = input.cyan(),
+                    via = format!("via {}", crates_of(input).into_iter().join(", ")).dimmed(),
+                );
+            }
+        } else {
+            let mut by_crate = BTreeMap::<&str, Vec<(&str, u64)>>::new();
+            for (input, size) in &sizes {
+                for crate_name in crates_of(input) {
+                    by_crate.entry(crate_name).or_default().push((input, *size));
+                }
+            }
+            let mut by_crate = by_crate
+                .into_iter()
+                .map(|(crate_name, inputs)| {
+                    let total = inputs.iter().map(|(_, size)| size).sum::<u64>();
+                    (crate_name, total, inputs)
+                })
+                .collect::<Vec<_>>();
+            by_crate.sort_by(|(a_name, a_total, _), (b_name, b_total, _)| {
+                b_total.cmp(a_total).then(a_name.cmp(b_name))
+            });
+            for (crate_name, total, inputs) in by_crate {
+                println!(
+                    "{total:>10}  {}",
+                    crate_name.bold(),
+                    total = human_size(total)
+                );
+                for (input, size) in inputs {
+                    println!("{size:>12}    {}", input.cyan(), size = human_size(size));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -0,0 +1,315 @@
+//! The `env` subcommand.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+use owo_colors::OwoColorize;
+
+use crate::environment_overrides::EnvironmentOverrides;
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+use crate::nix_dev_env::{self, ActivationScript};
+
+/// Inspect the environment riff sets up
+#[derive(Debug, Args)]
+pub struct Env {
+    #[clap(subcommand)]
+    command: EnvCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum EnvCommand {
+    Diff(EnvDiff),
+}
+
+impl Env {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        match self.command {
+            EnvCommand::Diff(diff) => diff.cmd().await,
+        }
+    }
+}
+
+/// Show what the environment changes from the shell riff runs in
+///
+/// Variables the environment adds are marked `+`, and those it changes `~`. Directories it puts
+/// in front of `PATH` and the like are `+` when they're new and `~` when they were already
+/// there further back, and the commands in them which shadow others from the shell's `PATH` are
+/// `!`. Run it where something works outside riff but not inside, or the other way around:
+///
+///     $ riff env diff
+#[derive(Debug, Args)]
+pub struct EnvDiff {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Refuse to compare unless the environment matches `riff.lock`, and use its nixpkgs
+    #[clap(long)]
+    locked: bool,
+    /// Cross-compile to this target triple, like `aarch64-unknown-linux-gnu`
+    #[clap(long, value_name = "TRIPLE")]
+    target: Option<String>,
+    #[clap(flatten)]
+    overrides: EnvironmentOverrides,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl EnvDiff {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let flake_dir = flake_generator::generate_flake_from_project_dir(
+            self.project_dir.clone(),
+            self.offline,
+            self.disable_telemetry,
+            DetectOptions {
+                locked: self.locked,
+                target: self.target.clone(),
+                overrides: self.overrides.clone(),
+                ..DetectOptions::from(&self.detect)
+            },
+        )
+        .await?;
+        nix_dev_env::warn_if_nested();
+
+        let dev_env = nix_dev_env::get_nix_dev_env(&flake_dir).await?;
+        let activation_script = ActivationScript::from(&dev_env);
+        let parent = std::env::vars().collect::<BTreeMap<_, _>>();
+        let diff = EnvironmentDiff::between(&activation_script, &parent);
+
+        for (name, change) in &diff.variables {
+            match change {
+                Change::Added(value) => println!("{} {name}={value}", "+".green()),
+                Change::Changed { from, to } => println!(
+                    "{} {name}={to} {}",
+                    "~".yellow(),
+                    format!("(was {from})").dimmed()
+                ),
+            }
+        }
+        for (name, entries) in &diff.prepended {
+            println!("{}", name.bold());
+            for (entry, moved) in entries {
+                if *moved {
+                    println!(
+                        "  {} {} {}",
+                        "~".yellow(),
+                        entry.display(),
+                        "(moved to the front)".dimmed()
+                    );
+                } else {
+                    println!("  {} {}", "+".green(), entry.display());
+                }
+            }
+        }
+
+        let parent_path = parent
+            .get("PATH")
+            .map(|path| std::env::split_paths(path).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let shadowed = shadowed_commands(&activation_script.path(), &parent_path);
+        if !shadowed.is_empty() {
+            println!("{}", "Shadowed commands".bold());
+        }
+        for (name, riff_command, parent_command) in &shadowed {
+            println!(
+                "  {} {name}: {} {}",
+                "!".red(),
+                riff_command.display(),
+                format!("shadows {}", parent_command.display()).dimmed()
+            );
+        }
+
+        if diff.unchanged > 0 {
+            eprintln!(
+                "{} more variables are the same as in the shell",
+                diff.unchanged
+            );
+        }
+        Ok(None)
+    }
+}
+
+/// How the environment changes a variable riff sets outright
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Change {
+    Added(String),
+    Changed { from: String, to: String },
+}
+
+/// What an environment changes from the one riff runs in
+#[derive(Debug, Default, PartialEq, Eq)]
+struct EnvironmentDiff {
+    /// The variables the environment sets which the shell doesn't have, or has another value for
+    variables: BTreeMap<String, Change>,
+    /// The entries put in front of variables like `PATH`, and whether each was there already
+    prepended: BTreeMap<String, Vec<(PathBuf, bool)>>,
+    /// How many variables the environment sets to the value the shell has
+    unchanged: usize,
+}
+
+impl EnvironmentDiff {
+    fn between(activation_script: &ActivationScript, parent: &BTreeMap<String, String>) -> Self {
+        let mut diff = Self::default();
+        for (name, value) in &activation_script.set {
+            let change = match parent.get(name) {
+                None => Change::Added(value.clone()),
+                Some(from) if from != value => Change::Changed {
+                    from: from.clone(),
+                    to: value.clone(),
+                },
+                Some(_) => {
+                    diff.unchanged += 1;
+                    continue;
+                }
+            };
+            diff.variables.insert(name.clone(), change);
+        }
+        for (name, value) in &activation_script.prepend {
+            let existing = parent
+                .get(name)
+                .map(|existing| std::env::split_paths(existing).collect::<BTreeSet<_>>())
+                .unwrap_or_default();
+            let entries = std::env::split_paths(value)
+                .map(|entry| {
+                    let moved = existing.contains(&entry);
+                    (entry, moved)
+                })
+                .collect();
+            diff.prepended.insert(name.clone(), entries);
+        }
+        diff
+    }
+}
+
+/// The commands in `riff_path` which shadow one in `parent_path`, by name, with the path of each
+fn shadowed_commands(
+    riff_path: &[PathBuf],
+    parent_path: &[PathBuf],
+) -> Vec<(String, PathBuf, PathBuf)> {
+    // The first directory with a command is the one it runs from
+    let mut riff_commands = BTreeMap::new();
+    for dir in riff_path {
+        for name in commands_in(dir) {
+            riff_commands.entry(name).or_insert_with(|| dir.clone());
+        }
+    }
+    let mut shadowed = Vec::new();
+    for (name, riff_dir) in riff_commands {
+        let parent_dir = parent_path
+            .iter()
+            .filter(|dir| !riff_path.contains(dir))
+            .find(|dir| is_executable(&dir.join(&name)));
+        if let Some(parent_dir) = parent_dir {
+            shadowed.push((name.clone(), riff_dir.join(&name), parent_dir.join(&name)));
+        }
+    }
+    shadowed
+}
+
+/// The names of the executables in `dir`
+fn commands_in(dir: &Path) -> Vec<String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            tracing::debug!(%err, dir = %dir.display(), "Could not list the commands");
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| is_executable(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn environment_diff() -> eyre::Result<()> {
+        let activation_script = ActivationScript {
+            set: [("HI", "BYE"), ("CC", "clang"), ("LANG", "C.UTF-8")]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            prepend: [(
+                "PATH".to_string(),
+                "/nix/store/abc-openssl/bin:/usr/bin".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+            shell_hook: None,
+        };
+        let parent = [
+            ("CC", "gcc"),
+            ("LANG", "C.UTF-8"),
+            ("PATH", "/usr/bin:/bin"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let diff = EnvironmentDiff::between(&activation_script, &parent);
+        assert_eq!(
+            diff.variables,
+            [
+                (
+                    "CC".to_string(),
+                    Change::Changed {
+                        from: "gcc".to_string(),
+                        to: "clang".to_string()
+                    }
+                ),
+                ("HI".to_string(), Change::Added("BYE".to_string())),
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            diff.prepended["PATH"],
+            [
+                (PathBuf::from("/nix/store/abc-openssl/bin"), false),
+                (PathBuf::from("/usr/bin"), true),
+            ]
+        );
+        assert_eq!(diff.unchanged, 1);
+
+        let riff_bin = TempDir::new()?;
+        let host_bin = TempDir::new()?;
+        for (dir, name, mode) in [
+            (&riff_bin, "cargo", 0o755),
+            (&riff_bin, "openssl", 0o755),
+            (&riff_bin, "README", 0o644),
+            (&host_bin, "cargo", 0o755),
+            (&host_bin, "README", 0o755),
+        ] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "")?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+        }
+        assert_eq!(
+            shadowed_commands(
+                &[riff_bin.path().to_path_buf()],
+                &[host_bin.path().to_path_buf()]
+            ),
+            [(
+                "cargo".to_string(),
+                riff_bin.path().join("cargo"),
+                host_bin.path().join("cargo")
+            )]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+//! The `exec-wrapper` subcommand.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+
+use crate::tool_wrappers;
+
+/// Generate wrapper executables which run tools inside your project's environment
+///
+/// Each wrapper runs the real tool through `riff run`, so editors and scripts which can't be
+/// taught about riff get the right environment by putting the wrapper directory on their `PATH`.
+///
+/// For example, wrap `cargo` and `rust-analyzer`:
+///
+///     $ riff exec-wrapper cargo rust-analyzer
+///     $ PATH=$PWD/.riff/bin:$PATH cargo build
+#[derive(Debug, Args)]
+pub struct ExecWrapper {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// The tools to wrap (eg `cargo`)
+    #[clap(required = true, value_parser = tool_wrappers::parse_tool_name)]
+    tools: Vec<String>,
+    /// Where to write the wrappers, relative to the project directory
+    #[clap(long, value_parser, default_value = ".riff/bin")]
+    wrapper_dir: PathBuf,
+}
+
+impl ExecWrapper {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let project_dir = tokio::fs::canonicalize(&project_dir)
+            .await
+            .wrap_err_with(|| format!("Could not resolve `{}`", project_dir.display()))?;
+        let riff = std::env::current_exe().wrap_err("Could not find the `riff` executable")?;
+
+        let wrapper_dir = project_dir.join(&self.wrapper_dir);
+        for tool in &self.tools {
+            let script = tool_wrappers::exec_wrapper_script(tool, &riff, &project_dir);
+            let wrapper_path = tool_wrappers::write_wrapper(&wrapper_dir, tool, &script).await?;
+            eprintln!(
+                "{check} {wrapper} → {riff_run}",
+                check = "✓".green(),
+                wrapper = wrapper_path.display().to_string().cyan(),
+                riff_run = format!("riff run -- {tool}").cyan(),
+            );
+        }
+
+        Ok(None)
+    }
+}
@@ -0,0 +1,117 @@
+//! The `explain` subcommand.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::cargo_metadata;
+use crate::contribution_graph::{ContributionGraph, NodeKind};
+use crate::dependency_registry::{Confidence, DependencyRegistry};
+
+/// Explain why inputs are in your project's environment, and how sure riff is of each
+///
+/// Each input is listed with the crates and rules which add it. Rules are `exact` when the
+/// project or the crate declares them, `curated` when they come from the registry, and
+/// `heuristic` when riff guessed them. Only Rust projects are supported. For example:
+///
+///     $ riff explain openssl
+#[derive(Debug, Args)]
+pub struct Explain {
+    /// Only explain this input
+    input: Option<String>,
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(from_global)]
+    registry_url: Option<String>,
+    #[clap(from_global)]
+    no_sys_heuristics: bool,
+    #[clap(from_global)]
+    conservative: bool,
+    #[clap(from_global)]
+    no_dev: bool,
+    #[clap(from_global)]
+    no_build_deps: bool,
+    #[clap(from_global, value_parser)]
+    from_metadata: Option<PathBuf>,
+}
+
+impl Explain {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        if self.from_metadata.is_none() && !project_dir.join("Cargo.toml").exists() {
+            return Err(eyre!(
+                "`{}` has no `Cargo.toml`; `{riff_explain}` only supports Rust projects",
+                project_dir.display(),
+                riff_explain = "riff explain".cyan(),
+            ));
+        }
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let (mut metadata, root_manifest_path) =
+            cargo_metadata::load(&project_dir, self.from_metadata.as_deref(), self.offline).await?;
+        metadata.exclude_dependencies(self.no_dev, self.no_build_deps);
+        let graph = ContributionGraph::from_cargo_metadata(
+            &metadata,
+            root_manifest_path.as_deref(),
+            &registry.language().await.rust,
+            !self.no_sys_heuristics,
+            self.conservative,
+        );
+
+        let explanations = graph
+            .explain()
+            .into_iter()
+            .filter(|explanation| {
+                self.input
+                    .iter()
+                    .all(|input| &explanation.input.name == input)
+            })
+            .collect::<Vec<_>>();
+        if let Some(input) = &self.input {
+            if explanations.is_empty() {
+                return Err(eyre!(
+                    "No crate in `{}` adds `{input}`",
+                    project_dir.display()
+                ));
+            }
+        }
+
+        let mut previous_input = None;
+        for explanation in explanations {
+            if previous_input != Some(explanation.input) {
+                let kind = match explanation.input.kind {
+                    NodeKind::RuntimeInput => "runtime input",
+                    _ => "build input",
+                };
+                println!("{} ({kind})", explanation.input.name.bold());
+                previous_input = Some(explanation.input);
+            }
+            let confidence = format!("{:<9}", explanation.confidence.to_string());
+            let confidence = match explanation.confidence {
+                Confidence::Exact => confidence.green().to_string(),
+                Confidence::Curated => confidence.cyan().to_string(),
+                Confidence::Heuristic => confidence.yellow().to_string(),
+            };
+            println!(
+                "    {confidence}  {rule}  {via}",
+                rule = explanation.rule,
+                via = format!("via {}", explanation.crate_name).dimmed(),
+            );
+        }
+
+        Ok(None)
+    }
+}
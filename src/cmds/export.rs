@@ -0,0 +1,439 @@
+//! The `export` subcommand.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::dependency_registry::DependencyRegistry;
+use crate::exporter::{self, ExportContext, ExportFile, Exporter, EXPORTERS};
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+
+/// Export your project's environment for use without riff
+#[derive(Debug, Args)]
+pub struct Export {
+    #[clap(subcommand)]
+    format: ExportFormat,
+}
+
+/// One of the formats in [`EXPORTERS`], with the arguments of its subcommand
+pub struct ExportFormat {
+    exporter: &'static dyn Exporter,
+    args: ExportArgs,
+    matches: ArgMatches,
+}
+
+impl std::fmt::Debug for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExportFormat")
+            .field("exporter", &self.exporter.name())
+            .field("args", &self.args)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromArgMatches for ExportFormat {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let (name, matches) = matches.subcommand().ok_or_else(|| {
+            clap::Error::raw(
+                clap::ErrorKind::MissingSubcommand,
+                "A format to export is required",
+            )
+        })?;
+        let exporter = exporter::find(name).ok_or_else(|| {
+            clap::Error::raw(
+                clap::ErrorKind::UnrecognizedSubcommand,
+                format!("`{name}` is not a format riff exports"),
+            )
+        })?;
+        Ok(Self {
+            exporter,
+            args: ExportArgs::from_arg_matches(matches)?,
+            matches: matches.clone(),
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Subcommand for ExportFormat {
+    fn augment_subcommands(cmd: Command<'_>) -> Command<'_> {
+        EXPORTERS.iter().fold(cmd, |cmd, exporter| {
+            let subcommand =
+                ExportArgs::augment_args(exporter.augment_args(Command::new(exporter.name())))
+                    // After the arguments, whose doc comments would otherwise describe it
+                    .about(exporter.about())
+                    .long_about(exporter.long_about());
+            cmd.subcommand(subcommand)
+        })
+    }
+
+    fn augment_subcommands_for_update(cmd: Command<'_>) -> Command<'_> {
+        Self::augment_subcommands(cmd)
+    }
+
+    fn has_subcommand(name: &str) -> bool {
+        exporter::find(name).is_some()
+    }
+}
+
+/// The arguments every format takes
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Write the file to this path instead of stdout
+    #[clap(long, short, value_parser, conflicts_with = "output-dir")]
+    output: Option<PathBuf>,
+    #[clap(flatten)]
+    export_output: ExportOutput,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl Export {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let ExportFormat {
+            exporter,
+            args,
+            matches,
+        } = self.format;
+        let project_dir = match args.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+
+        let registry = DependencyRegistry::for_project(
+            args.offline,
+            args.detect.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let dev_env = flake_generator::detect_dev_env(
+            &project_dir,
+            &registry,
+            args.disable_telemetry,
+            DetectOptions {
+                // Most exports are used on their own rather than on top of a `shell.nix` from the
+                // machine they were exported on.
+                ignore_existing_nix: exporter.standalone() || args.detect.ignore_existing_nix,
+                ..DetectOptions::from(&args.detect)
+            },
+        )
+        .await?;
+
+        let files = exporter
+            .render(
+                &dev_env,
+                &ExportContext {
+                    project_dir: &project_dir,
+                    matches: &matches,
+                },
+            )
+            .await?;
+        let output_dir = match (&args.output, &args.export_output.output_dir) {
+            (Some(_), _) => None,
+            (None, Some(output_dir)) => Some(output_dir.clone()),
+            (None, None) => exporter.default_dir().map(|dir| project_dir.join(dir)),
+        };
+        let default_paths = match (&args.output, &output_dir) {
+            // All checked before anything is written
+            (None, None) => files
+                .iter()
+                .map(|file| exporter.default_path(file.name))
+                .collect::<color_eyre::Result<Option<Vec<_>>>>()?,
+            _ => None,
+        };
+        match (output_dir, default_paths) {
+            (Some(output_dir), _) => args.export_output.write(&output_dir, files).await?,
+            (None, Some(paths)) => {
+                for (file, path) in files.into_iter().zip(paths) {
+                    args.export_output.write_one(file, &path).await?;
+                }
+            }
+            (None, None) => {
+                args.export_output
+                    .write_or_print(exporter.name(), args.output, files)
+                    .await?
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// The manifest of the files riff wrote to an `--output-dir`, with their SHA-256 hashes
+const EXPORT_MANIFEST: &str = "riff-export.json";
+
+/// Where exporters write their files
+#[derive(Debug, Args)]
+pub struct ExportOutput {
+    /// Write the files into this directory, recording them in its `riff-export.json`
+    #[clap(long, value_parser, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+    /// Overwrite files in the output directory which riff didn't write, or which were changed since
+    #[clap(long)]
+    force: bool,
+    /// Only report which files would be written
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ExportManifest {
+    files: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileChange {
+    Create,
+    Update,
+    Unchanged,
+}
+
+impl ExportOutput {
+    /// Write the single file of the format `format` to `output`, or else print it
+    ///
+    /// Unlike the files in an output directory, `output` is replaced whatever it holds.
+    async fn write_or_print(
+        &self,
+        format: &str,
+        output: Option<PathBuf>,
+        mut files: Vec<ExportFile>,
+    ) -> color_eyre::Result<()> {
+        if files.len() != 1 {
+            return Err(eyre!(
+                "`riff export {format}` writes several files; pass `{output_dir}` to choose where",
+                output_dir = "--output-dir".cyan()
+            ));
+        }
+        let file = files.remove(0);
+        match output {
+            Some(output) => self.write_one(file, &output).await,
+            None => {
+                print!("{}", file.contents);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write `file` to `output`, merging it into what's there if the exporter merges it
+    async fn write_one(&self, file: ExportFile, output: &Path) -> color_eyre::Result<()> {
+        if self.dry_run {
+            eprintln!("Would write {}", output.display().to_string().cyan());
+            return Ok(());
+        }
+        let contents = match read_existing(output).await? {
+            Some(existing) => merged_contents(&file, &existing, output)?,
+            None => file.contents,
+        };
+        write_file(output, &contents).await?;
+        eprintln!(
+            "{check} Wrote {output}",
+            check = "✓".green(),
+            output = output.display().to_string().cyan()
+        );
+        Ok(())
+    }
+
+    /// Write `files` to `output_dir` and record them in its manifest
+    ///
+    /// Files riff wrote before, and which weren't changed since, are replaced, as are files the
+    /// exporter merges into. Any other existing file is an error without `--force`, which is
+    /// checked before anything is written.
+    async fn write(&self, output_dir: &Path, files: Vec<ExportFile>) -> color_eyre::Result<()> {
+        let manifest_path = output_dir.join(EXPORT_MANIFEST);
+        let mut manifest = match read_existing(&manifest_path).await? {
+            Some(manifest) => serde_json::from_str::<ExportManifest>(&manifest)
+                .wrap_err_with(|| format!("Could not parse `{}`", manifest_path.display()))?,
+            None => ExportManifest::default(),
+        };
+
+        let mut changes = Vec::new();
+        for file in files {
+            let path = output_dir.join(file.name);
+            let (contents, change) = match read_existing(&path).await? {
+                Some(existing) => {
+                    let contents = merged_contents(&file, &existing, &path)?;
+                    let written_by_riff =
+                        manifest.files.get(file.name) == Some(&content_hash(existing.as_bytes()));
+                    if contents == existing {
+                        (contents, FileChange::Unchanged)
+                    } else if written_by_riff || file.merge.is_some() || self.force {
+                        (contents, FileChange::Update)
+                    } else {
+                        return Err(eyre!(
+                            "`{}` wasn't written by Riff, or was changed since; pass `{force}` to overwrite it",
+                            path.display(),
+                            force = "--force".cyan()
+                        ));
+                    }
+                }
+                None => (file.contents, FileChange::Create),
+            };
+            changes.push((file.name, path, contents, change));
+        }
+
+        for (name, path, contents, change) in changes {
+            let path = path.display().to_string();
+            match (change, self.dry_run) {
+                (FileChange::Unchanged, _) => {
+                    eprintln!("{check} {} is up to date", path.cyan(), check = "✓".green())
+                }
+                (FileChange::Create, true) => eprintln!("Would create {}", path.cyan()),
+                (FileChange::Update, true) => eprintln!("Would update {}", path.cyan()),
+                (_, false) => {
+                    write_file(Path::new(&path), &contents).await?;
+                    eprintln!("{check} Wrote {}", path.cyan(), check = "✓".green());
+                }
+            }
+            manifest
+                .files
+                .insert(name.to_string(), content_hash(contents.as_bytes()));
+        }
+
+        if !self.dry_run {
+            write_file(
+                &manifest_path,
+                &(serde_json::to_string_pretty(&manifest)? + "\n"),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// The contents of `path`, if it exists
+async fn read_existing(path: &Path) -> color_eyre::Result<Option<String>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).wrap_err_with(|| format!("Could not read `{}`", path.display())),
+    }
+}
+
+/// The contents of `file` once merged into the `existing` contents of `path`, if the exporter
+/// merges it
+fn merged_contents(file: &ExportFile, existing: &str, path: &Path) -> color_eyre::Result<String> {
+    match file.merge {
+        Some(merge) => merge(existing, &file.contents)
+            .wrap_err_with(|| format!("Could not update `{}`", path.display())),
+        None => Ok(file.contents.clone()),
+    }
+}
+
+/// Write `contents` to `path`, creating its directory if needed
+async fn write_file(path: &Path, contents: &str) -> color_eyre::Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .wrap_err_with(|| format!("Could not create `{}`", parent.display()))?;
+    }
+    tokio::fs::write(path, contents)
+        .await
+        .wrap_err_with(|| format!("Could not write `{}`", path.display()))
+}
+
+fn content_hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn export_output(force: bool, dry_run: bool) -> ExportOutput {
+        ExportOutput {
+            output_dir: None,
+            force,
+            dry_run,
+        }
+    }
+
+    #[tokio::test]
+    async fn output_dir() -> eyre::Result<()> {
+        let output_dir = TempDir::new()?;
+        let flake_nix = output_dir.path().join("flake.nix");
+        let files = |contents: &str| vec![ExportFile::new("flake.nix", contents.to_string())];
+
+        export_output(false, true)
+            .write(output_dir.path(), files("{ }"))
+            .await?;
+        assert!(!flake_nix.exists());
+        assert!(!output_dir.path().join(EXPORT_MANIFEST).exists());
+
+        export_output(false, false)
+            .write(output_dir.path(), files("{ }"))
+            .await?;
+        assert_eq!(tokio::fs::read_to_string(&flake_nix).await?, "{ }");
+        // Riff replaces what it wrote, whether it changed or not
+        export_output(false, false)
+            .write(output_dir.path(), files("{ }"))
+            .await?;
+        export_output(false, false)
+            .write(output_dir.path(), files("{ inputs = { }; }"))
+            .await?;
+        assert_eq!(
+            tokio::fs::read_to_string(&flake_nix).await?,
+            "{ inputs = { }; }"
+        );
+
+        // But not what someone else changed
+        tokio::fs::write(&flake_nix, "{ edited = true; }").await?;
+        assert!(export_output(false, false)
+            .write(output_dir.path(), files("{ }"))
+            .await
+            .is_err());
+        assert!(export_output(false, true)
+            .write(output_dir.path(), files("{ }"))
+            .await
+            .is_err());
+        export_output(true, false)
+            .write(output_dir.path(), files("{ }"))
+            .await?;
+        assert_eq!(tokio::fs::read_to_string(&flake_nix).await?, "{ }");
+
+        let manifest: ExportManifest = serde_json::from_str(
+            &tokio::fs::read_to_string(output_dir.path().join(EXPORT_MANIFEST)).await?,
+        )?;
+        assert_eq!(manifest.files.get("flake.nix"), Some(&content_hash(b"{ }")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn merged_files() -> eyre::Result<()> {
+        let output_dir = TempDir::new()?;
+        let notes = output_dir.path().join("notes.txt");
+        tokio::fs::write(&notes, "mine\n").await?;
+        let file = ExportFile {
+            merge: Some(|existing, contents| Ok(format!("{existing}{contents}"))),
+            ..ExportFile::new("notes.txt", "riff's\n".to_string())
+        };
+
+        // Riff didn't write the file, but keeps what's in it
+        export_output(false, false)
+            .write(output_dir.path(), vec![file])
+            .await?;
+        assert_eq!(tokio::fs::read_to_string(&notes).await?, "mine\nriff's\n");
+        Ok(())
+    }
+}
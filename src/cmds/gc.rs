@@ -0,0 +1,60 @@
+//! The `gc` subcommand.
+
+use clap::Args;
+use owo_colors::OwoColorize;
+
+use crate::gc_roots;
+
+/// Stop keeping the environments of deleted projects in the Nix store
+///
+/// `riff shell`, `riff run` and `riff direnv` keep the latest environment of each project alive,
+/// so Nix's garbage collector doesn't delete it and force a rebuild. This removes the roots of
+/// projects which no longer exist; run `nix store gc` afterwards to free the space:
+///
+///     $ riff gc
+///     $ nix store gc
+#[derive(Debug, Args)]
+pub struct Gc {
+    /// Only list the roots which would be removed
+    #[clap(long)]
+    dry_run: bool,
+}
+
+impl Gc {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let roots = gc_roots::roots().await?;
+        let (stale, kept): (Vec<_>, Vec<_>) = roots.into_iter().partition(|root| root.is_stale());
+
+        for root in &stale {
+            let project_dir = root
+                .project_dir
+                .as_ref()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_else(|| "an unknown project".to_string());
+            if self.dry_run {
+                eprintln!("Would remove the root for {}", project_dir.cyan());
+            } else {
+                root.remove().await?;
+                eprintln!("Removed the root for {}", project_dir.cyan());
+            }
+        }
+
+        if self.dry_run || stale.is_empty() {
+            eprintln!(
+                "{check} {count} of {total} roots belong to projects which no longer exist",
+                check = "✓".green(),
+                count = stale.len(),
+                total = stale.len() + kept.len(),
+            );
+        } else {
+            eprintln!(
+                "{check} Removed {count} roots, keeping {kept}; run `{nix_store_gc}` to delete their environments",
+                check = "✓".green(),
+                count = stale.len(),
+                kept = kept.len(),
+                nix_store_gc = "nix store gc".cyan(),
+            );
+        }
+        Ok(None)
+    }
+}
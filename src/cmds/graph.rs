@@ -0,0 +1,106 @@
+//! The `graph` subcommand.
+
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::cargo_metadata;
+use crate::contribution_graph::ContributionGraph;
+use crate::dependency_registry::DependencyRegistry;
+
+/// Show which crates and rules add which inputs to your project's environment
+///
+/// Crates point to the registry rules or `package.metadata.riff` tables which apply to them, and
+/// those point to the inputs they add. Only Rust projects are supported. For example, render the
+/// graph with Graphviz:
+///
+///     $ riff graph | dot -Tsvg > riff.svg
+#[derive(Debug, Args)]
+pub struct Graph {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// The language to render the graph in
+    #[clap(long, value_enum, default_value_t)]
+    format: GraphFormat,
+    /// Write the graph to this path instead of stdout
+    #[clap(long, short, value_parser)]
+    output: Option<PathBuf>,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(from_global)]
+    registry_url: Option<String>,
+    #[clap(from_global)]
+    no_sys_heuristics: bool,
+    #[clap(from_global)]
+    conservative: bool,
+    #[clap(from_global)]
+    no_dev: bool,
+    #[clap(from_global)]
+    no_build_deps: bool,
+    #[clap(from_global, value_parser)]
+    from_metadata: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz's `dot` language
+    #[default]
+    Dot,
+    /// A Mermaid flowchart, which GitHub renders in Markdown
+    Mermaid,
+}
+
+impl Graph {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        if self.from_metadata.is_none() && !project_dir.join("Cargo.toml").exists() {
+            return Err(eyre!(
+                "`{}` has no `Cargo.toml`; `{riff_graph}` only supports Rust projects",
+                project_dir.display(),
+                riff_graph = "riff graph".cyan(),
+            ));
+        }
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let (mut metadata, root_manifest_path) =
+            cargo_metadata::load(&project_dir, self.from_metadata.as_deref(), self.offline).await?;
+        metadata.exclude_dependencies(self.no_dev, self.no_build_deps);
+        let graph = ContributionGraph::from_cargo_metadata(
+            &metadata,
+            root_manifest_path.as_deref(),
+            &registry.language().await.rust,
+            !self.no_sys_heuristics,
+            self.conservative,
+        );
+        let rendered = match self.format {
+            GraphFormat::Dot => graph.to_dot(),
+            GraphFormat::Mermaid => graph.to_mermaid(),
+        };
+
+        match self.output {
+            Some(output) => {
+                tokio::fs::write(&output, rendered)
+                    .await
+                    .wrap_err_with(|| format!("Could not write `{}`", output.display()))?;
+                eprintln!(
+                    "{check} Wrote {output}",
+                    check = "✓".green(),
+                    output = output.display().to_string().cyan()
+                );
+            }
+            None => print!("{rendered}"),
+        }
+        Ok(None)
+    }
+}
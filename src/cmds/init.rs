@@ -0,0 +1,103 @@
+//! The `init` subcommand.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use atty::Stream;
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::dependency_registry::DependencyRegistry;
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+
+/// Write your project's environment to a `flake.nix` in the project directory
+///
+/// Commit the flake to share the environment with your team, and edit it as the project grows.
+/// Afterwards, `nix develop` starts the same shell as `riff shell`.
+#[derive(Debug, Args)]
+pub struct Init {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Overwrite an existing `flake.nix` without asking
+    #[clap(long)]
+    force: bool,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl Init {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let flake_path = project_dir.join("flake.nix");
+
+        if flake_path.exists() && !self.force && !confirm_overwrite()? {
+            eprintln!("Left the existing `{}` untouched", "flake.nix".cyan());
+            return Ok(Some(1));
+        }
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.detect.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let dev_env = flake_generator::detect_dev_env(
+            &project_dir,
+            &registry,
+            self.disable_telemetry,
+            DetectOptions {
+                // The flake lives next to any `shell.nix`, and should not depend on where the
+                // project is checked out.
+                ignore_existing_nix: true,
+                ..DetectOptions::from(&self.detect)
+            },
+        )
+        .await?;
+
+        tokio::fs::write(&flake_path, dev_env.to_flake())
+            .await
+            .wrap_err_with(|| format!("Could not write `{}`", flake_path.display()))?;
+        eprintln!(
+            "{check} Wrote {flake}",
+            check = "✓".green(),
+            flake = flake_path.display().to_string().cyan()
+        );
+        if project_dir.join(".git").exists() {
+            eprintln!(
+                "Nix only sees files tracked by Git, so run `{git_add}` before `{nix_develop}`",
+                git_add = "git add flake.nix".cyan(),
+                nix_develop = "nix develop".cyan(),
+            );
+        }
+
+        Ok(None)
+    }
+}
+
+/// Ask whether to overwrite the existing `flake.nix`, which requires an interactive terminal
+fn confirm_overwrite() -> color_eyre::Result<bool> {
+    if !atty::is(Stream::Stdin) {
+        return Err(eyre!(
+            "`flake.nix` already exists; pass `{force}` to overwrite it",
+            force = "--force".cyan()
+        ));
+    }
+
+    eprint!(
+        "`{flake}` already exists. Overwrite it? [y/N] ",
+        flake = "flake.nix".cyan()
+    );
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -0,0 +1,123 @@
+//! The `lock` subcommand.
+
+use std::io::Write;
+use std::path::PathBuf;
+
+use atty::Stream;
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::dependency_registry::DependencyRegistry;
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+use crate::riff_lock::{RiffLock, LOCK_FILE};
+
+/// Record your project's environment in `riff.lock`
+///
+/// The lock records the registry the environment was detected with, the exact nixpkgs revision
+/// and the resolved inputs. Commit it, and `--locked` refuses to use anything else:
+///
+///     $ riff lock
+///     $ riff shell --locked
+///
+/// `--update` replaces an existing lock, after showing what changes and asking to confirm.
+#[derive(Debug, Args)]
+pub struct Lock {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// Replace an existing `riff.lock` with the current environment
+    #[clap(long)]
+    update: bool,
+    /// Write the updated lock without asking
+    #[clap(long, requires = "update")]
+    yes: bool,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl Lock {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+
+        let existing = RiffLock::read(&project_dir).await?;
+        if existing.is_some() && !self.update {
+            return Err(eyre!(
+                "`{lock_file}` already exists; run `{riff_lock_update}` to update it",
+                lock_file = LOCK_FILE,
+                riff_lock_update = "riff lock --update".cyan(),
+            ));
+        }
+
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.detect.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let dev_env = flake_generator::detect_dev_env(
+            &project_dir,
+            &registry,
+            self.disable_telemetry,
+            DetectOptions::from(&self.detect),
+        )
+        .await?;
+        let flake = flake_generator::write_locked_flake(&dev_env).await?;
+
+        let mut lock = RiffLock::new(&dev_env, &flake).await?;
+        if let Some(existing) = existing {
+            // `riff pin` updates the pins itself, against the environment of the new lock
+            lock.tools = existing.tools.clone();
+            let changes = existing.diff(&lock);
+            if changes.is_empty() {
+                eprintln!(
+                    "{check} {lock_file} is up to date",
+                    check = "✓".green(),
+                    lock_file = LOCK_FILE.cyan(),
+                );
+                return Ok(None);
+            }
+            eprintln!("Updating {lock_file}:", lock_file = LOCK_FILE.cyan());
+            for change in changes {
+                eprintln!("{change}");
+            }
+            if !self.yes && !confirm_update()? {
+                eprintln!("Left the existing `{}` untouched", LOCK_FILE.cyan());
+                return Ok(Some(1));
+            }
+        }
+        lock.write(&project_dir).await?;
+        eprintln!(
+            "{check} Wrote {lock_file}, locking nixpkgs to {nixpkgs}",
+            check = "✓".green(),
+            lock_file = LOCK_FILE.cyan(),
+            nixpkgs = lock.nixpkgs.locked.cyan(),
+        );
+
+        Ok(None)
+    }
+}
+
+/// Ask whether to write the updated lock, which requires an interactive terminal
+fn confirm_update() -> color_eyre::Result<bool> {
+    if !atty::is(Stream::Stdin) {
+        return Err(eyre!(
+            "Not updating `{lock_file}` without confirmation; pass `{yes}` to update it",
+            lock_file = LOCK_FILE,
+            yes = "--yes".cyan()
+        ));
+    }
+
+    eprint!("Write the updated `{}`? [y/N] ", LOCK_FILE.cyan());
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -1,12 +1,62 @@
+mod add;
+mod config;
+mod daemon;
+mod detect;
+mod dev;
+mod direnv;
+mod doctor;
+mod du;
+mod env;
+mod exec_wrapper;
+mod explain;
+mod export;
+mod gc;
+mod graph;
+mod init;
+mod lock;
+mod new;
+mod pin;
 mod print_dev_env;
+mod registry;
+mod reload;
+mod remove;
 mod run;
 mod shell;
+mod telemetry;
+mod try_package;
+mod verify_exports;
+mod watch;
 
 use clap::Subcommand;
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     Shell(shell::Shell),
+    Reload(reload::Reload),
+    Watch(watch::Watch),
     Run(run::Run),
     PrintDevEnv(print_dev_env::PrintDevEnv),
+    Env(env::Env),
+    Detect(detect::Detect),
+    Add(add::Add),
+    Remove(remove::Remove),
+    Pin(pin::Pin),
+    Lock(lock::Lock),
+    ExecWrapper(exec_wrapper::ExecWrapper),
+    Direnv(direnv::Direnv),
+    Export(export::Export),
+    VerifyExports(verify_exports::VerifyExports),
+    Gc(gc::Gc),
+    Graph(graph::Graph),
+    Explain(explain::Explain),
+    Du(du::Du),
+    Init(init::Init),
+    New(new::New),
+    Try(try_package::Try),
+    Doctor(doctor::Doctor),
+    Daemon(daemon::Daemon),
+    Registry(registry::Registry),
+    Telemetry(telemetry::Telemetry),
+    Config(config::Config),
+    Dev(dev::Dev),
 }
@@ -0,0 +1,190 @@
+//! The `new` subcommand.
+
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::metadata_editor::{InputKind, MetadataEditor};
+
+/// Create a new Cargo project from a template, with its environment already declared
+///
+/// The template's native dependencies are written to `package.metadata.riff`, so `riff shell`
+/// works in the new project straight away:
+///
+///     $ riff new bevy my-game
+///     $ cd my-game && riff run cargo run
+#[derive(Debug, Args)]
+pub struct New {
+    /// The kind of project to create
+    #[clap(value_enum)]
+    template: Template,
+    /// The name of the crate, which is also the directory it is created in
+    name: String,
+    /// Create the project in this directory instead of one named after the crate
+    #[clap(long, value_parser)]
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Template {
+    /// A web service using axum and tokio
+    Axum,
+    /// A command line application using clap
+    Cli,
+    /// A game using bevy
+    Bevy,
+}
+
+impl Template {
+    /// The crates the project depends on, with their versions and features
+    fn dependencies(&self) -> &'static [(&'static str, &'static str, &'static [&'static str])] {
+        match self {
+            Template::Axum => &[
+                ("axum", "0.7", &[]),
+                ("tokio", "1", &["full"]),
+                ("tracing", "0.1", &[]),
+                ("tracing-subscriber", "0.3", &[]),
+            ],
+            Template::Cli => &[("clap", "4", &["derive"])],
+            Template::Bevy => &[("bevy", "0.12", &[])],
+        }
+    }
+
+    fn main_rs(&self) -> &'static str {
+        match self {
+            Template::Axum => include_str!("../new-axum-template.inc"),
+            Template::Cli => include_str!("../new-cli-template.inc"),
+            Template::Bevy => include_str!("../new-bevy-template.inc"),
+        }
+    }
+
+    /// Inputs the project needs which riff can't infer from its dependencies
+    fn inputs(&self) -> &'static [(InputKind, &'static str)] {
+        match self {
+            Template::Axum | Template::Cli => &[],
+            // Loaded with `dlopen` when the window opens, so they never show up as `-sys` crates
+            Template::Bevy => &[
+                (InputKind::Runtime, "vulkan-loader"),
+                (InputKind::Runtime, "libxkbcommon"),
+                (InputKind::Runtime, "wayland"),
+                (InputKind::Runtime, "xorg.libX11"),
+                (InputKind::Runtime, "xorg.libXcursor"),
+                (InputKind::Runtime, "xorg.libXi"),
+                (InputKind::Runtime, "xorg.libXrandr"),
+            ],
+        }
+    }
+
+    fn environment_variables(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Template::Axum => &[("RUST_LOG", "info")],
+            Template::Cli | Template::Bevy => &[],
+        }
+    }
+}
+
+impl New {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = self.path.unwrap_or_else(|| PathBuf::from(&self.name));
+        if project_dir.exists() {
+            return Err(eyre!("`{}` already exists", project_dir.display()));
+        }
+
+        write_project(&project_dir, &self.name, self.template).await?;
+
+        eprintln!(
+            "{check} Created {project_dir}. Start working on it with `{riff_shell}`",
+            check = "✓".green(),
+            project_dir = project_dir.display().to_string().cyan(),
+            riff_shell = format!("cd {} && riff shell", project_dir.display()).cyan(),
+        );
+        Ok(None)
+    }
+}
+
+/// Write a project named `name` to `project_dir` from `template`
+async fn write_project(
+    project_dir: &Path,
+    name: &str,
+    template: Template,
+) -> color_eyre::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(eyre!("`{name}` is not a valid crate name"));
+    }
+
+    let mut manifest = toml_edit::Document::new();
+    manifest["package"] = toml_edit::table();
+    manifest["package"]["name"] = toml_edit::value(name);
+    manifest["package"]["version"] = toml_edit::value("0.1.0");
+    manifest["package"]["edition"] = toml_edit::value("2021");
+    manifest["dependencies"] = toml_edit::table();
+    for (dependency, version, features) in template.dependencies() {
+        manifest["dependencies"][dependency] = if features.is_empty() {
+            toml_edit::value(*version)
+        } else {
+            let mut table = toml_edit::InlineTable::new();
+            table.insert("version", (*version).into());
+            table.insert(
+                "features",
+                features
+                    .iter()
+                    .copied()
+                    .collect::<toml_edit::Array>()
+                    .into(),
+            );
+            toml_edit::value(table)
+        };
+    }
+
+    let manifest_path = project_dir.join("Cargo.toml");
+    let mut editor = MetadataEditor::from_contents(manifest_path, &manifest.to_string())?;
+    for (kind, input) in template.inputs() {
+        editor.add_input(*kind, input)?;
+    }
+    for (name, value) in template.environment_variables() {
+        editor.set_environment_variable(name, value)?;
+    }
+
+    tokio::fs::create_dir_all(project_dir.join("src"))
+        .await
+        .wrap_err_with(|| format!("Could not create `{}`", project_dir.display()))?;
+    tokio::fs::write(project_dir.join("src/main.rs"), template.main_rs()).await?;
+    tokio::fs::write(project_dir.join(".gitignore"), "/target\n").await?;
+    editor.save().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn bevy_template() -> eyre::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let project_dir = temp_dir.path().join("my-game");
+        write_project(&project_dir, "my-game", Template::Bevy).await?;
+
+        let manifest = tokio::fs::read_to_string(project_dir.join("Cargo.toml")).await?;
+        let manifest = manifest.parse::<toml::Value>()?;
+        assert_eq!(manifest["package"]["name"].as_str(), Some("my-game"));
+        assert_eq!(manifest["dependencies"]["bevy"].as_str(), Some("0.12"));
+        assert!(manifest["package"]["metadata"]["riff"]["runtime-inputs"]
+            .as_array()
+            .map(|inputs| inputs.iter().any(|v| v.as_str() == Some("vulkan-loader")))
+            .unwrap_or(false));
+        assert!(project_dir.join("src/main.rs").is_file());
+
+        write_project(&temp_dir.path().join("service"), "service", Template::Axum).await?;
+        let manifest =
+            tokio::fs::read_to_string(temp_dir.path().join("service/Cargo.toml")).await?;
+        assert!(manifest.contains(r#"tokio = { version = "1", features = ["full"] }"#));
+        assert!(manifest.contains(r#"RUST_LOG = "info""#));
+        Ok(())
+    }
+}
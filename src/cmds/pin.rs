@@ -0,0 +1,96 @@
+//! The `pin` subcommand.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+use crate::nix_dev_env::ActivationScript;
+use crate::riff_lock::{RiffLock, LOCK_FILE};
+use crate::tool_wrappers;
+
+/// Generate wrapper scripts which pin tools to exact Nix store paths
+///
+/// Instead of picking tools up from the `PATH`, each wrapper runs the exact store path resolved
+/// from your project's locked environment and recorded in `riff.lock`, failing loudly if that
+/// path is missing. Re-pinning to a different store path requires `--update`.
+///
+/// For example, pin `cargo` and `protoc`:
+///
+///     $ riff lock
+///     $ riff pin cargo protoc
+///     $ PATH=$PWD/.riff/bin:$PATH cargo build
+#[derive(Debug, Args)]
+pub struct Pin {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// The tools to pin (eg `cargo`)
+    #[clap(required = true, value_parser = tool_wrappers::parse_tool_name)]
+    tools: Vec<String>,
+    /// Where to write the wrappers, relative to the project directory
+    #[clap(long, value_parser, default_value = ".riff/bin")]
+    wrapper_dir: PathBuf,
+    /// Accept store paths which differ from the previously pinned ones
+    #[clap(long)]
+    update: bool,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl Pin {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let mut lock = RiffLock::read(&project_dir).await?.ok_or_else(|| {
+            eyre!(
+                "`{lock_file}` does not exist, so there's nowhere to record the pins. Create it with `{riff_lock}`.",
+                lock_file = LOCK_FILE,
+                riff_lock = "riff lock".cyan(),
+            )
+        })?;
+        let flake_dir = flake_generator::generate_flake_from_project_dir(
+            Some(project_dir.clone()),
+            self.offline,
+            self.disable_telemetry,
+            DetectOptions {
+                locked: true,
+                ..DetectOptions::from(&self.detect)
+            },
+        )
+        .await?;
+
+        let dev_env = crate::nix_dev_env::get_nix_dev_env(&flake_dir).await?;
+        let activation_script = ActivationScript::from(&dev_env);
+
+        let mut resolved = Vec::with_capacity(self.tools.len());
+        for tool in &self.tools {
+            let store_path = tool_wrappers::resolve_tool(&activation_script, tool)?;
+            lock.pin_tool(tool, store_path.clone(), self.update)?;
+            resolved.push((tool, store_path));
+        }
+
+        let wrapper_dir = project_dir.join(&self.wrapper_dir);
+        for (tool, store_path) in resolved {
+            let script = tool_wrappers::pinned_wrapper_script(tool, &store_path);
+            let wrapper_path = tool_wrappers::write_wrapper(&wrapper_dir, tool, &script).await?;
+            eprintln!(
+                "{check} {wrapper} → {store_path}",
+                check = "✓".green(),
+                wrapper = wrapper_path.display().to_string().cyan(),
+                store_path = store_path.display(),
+            );
+        }
+        lock.write(&project_dir).await?;
+
+        Ok(None)
+    }
+}
@@ -1,28 +1,68 @@
 //! The `run` subcommand.
 
-use std::{path::PathBuf, process::Stdio};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 
-use clap::Args;
+use clap::{Args, ValueEnum};
 use eyre::WrapErr;
 use owo_colors::OwoColorize;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 
-use crate::flake_generator;
+use crate::environment_overrides::EnvironmentOverrides;
+use crate::exporter::github_file;
+use crate::flake_generator::{self, DetectArgs, DetectOptions, GeneratedFlake};
+use crate::nix_dev_env::{self, ActivationScript};
 
 /// print shell code that can be sourced by bash to reproduce the riff environment
 ///
 /// For example, run `cargo build` inside riff:
 ///
 ///     $ eval $(riff print-dev-env)
+///
+/// Other shells, editors, and scripts can ask for the environment in a format they read with
+/// `--format`, such as `riff print-dev-env --format fish | source`. In a GitHub Actions step,
+/// `--format github` sets the environment up for every later step of the job instead.
 #[derive(Debug, Args)]
 pub struct PrintDevEnv {
     /// The root directory of the project
-    #[clap(long, value_parser)]
+    #[clap(long, value_parser = crate::wsl::parse_path)]
     project_dir: Option<PathBuf>,
+    /// Refuse to print the environment unless the environment matches `riff.lock`, and use its nixpkgs
+    #[clap(long)]
+    locked: bool,
+    /// Cross-compile to this target triple, like `aarch64-unknown-linux-gnu`
+    #[clap(long, value_name = "TRIPLE")]
+    target: Option<String>,
+    /// The format to print the environment in
+    #[clap(long, value_enum, default_value_t)]
+    format: PrintDevEnvFormat,
+    #[clap(flatten)]
+    overrides: EnvironmentOverrides,
     #[clap(from_global)]
     disable_telemetry: bool,
     #[clap(from_global)]
     offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum PrintDevEnvFormat {
+    /// The bash code `nix print-dev-env` prints, including the setup functions of Nix's builder
+    #[default]
+    Nix,
+    /// The variables to set, the variables to prepend to like `PATH`, and the shell hook
+    Json,
+    /// POSIX shell code exporting the variables and running the shell hook
+    Bash,
+    /// fish shell code exporting the variables
+    Fish,
+    /// A `.env` file of the variables, with `PATH` and the like prepended to their current values
+    Dotenv,
+    /// Append the variables to `$GITHUB_ENV` and the `PATH` to `$GITHUB_PATH`, for the later steps
+    /// of a GitHub Actions job, instead of printing them
+    Github,
 }
 
 impl PrintDevEnv {
@@ -31,56 +71,124 @@ impl PrintDevEnv {
             self.project_dir.clone(),
             self.offline,
             self.disable_telemetry,
+            DetectOptions {
+                locked: self.locked,
+                target: self.target.clone(),
+                overrides: self.overrides.clone(),
+                ..DetectOptions::from(&self.detect)
+            },
         )
         .await?;
 
-        let mut nix_print_dev_env_command = Command::new("nix");
-        nix_print_dev_env_command
-            .arg("print-dev-env")
-            .args(&["--extra-experimental-features", "flakes nix-command"])
-            .arg("-L")
-            .arg(format!("path://{}", flake_dir.path().to_str().unwrap()))
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit());
+        crate::nix_dev_env::warn_if_nested();
 
-        // TODO(@hoverbear): Try to enable this somehow. Right now since we don't keep the lock
-        // in a consistent place, we can't reliably pick up a lock generated in online mode.
-        //
-        // If we stored the generated flake/lock in a consistent place this could be enabled.
-        //
-        // if self.offline {
-        //     nix_develop_command.arg("--offline");
-        // }
+        // Without flakes, there's no `nix print-dev-env` to defer to
+        if self.format == PrintDevEnvFormat::Nix && !flake_dir.legacy() {
+            return print_nix_dev_env(&flake_dir).await;
+        }
 
-        tracing::trace!(command = ?nix_print_dev_env_command.as_std(), "Running");
-        let nix_print_dev_env_exit = match nix_print_dev_env_command
-            .spawn()
-            .wrap_err("Failed to spawn `nix print-dev-env`")?
-            .wait_with_output()
-            .await
-        {
-            Ok(nix_print_dev_env_exit) => nix_print_dev_env_exit,
-            err @ Err(_) => {
-                let wrapped_err = err
-                    .wrap_err_with(|| {
-                        format!(
-                            "\
-                        Could not execute `{nix_print_dev_env}`. Is `{nix}` installed?\n\n\
-                        Get instructions for installing Nix: {nix_install_url}\n\
-                        Underlying error\
-                        ",
-                            nix_print_dev_env = "nix print-dev-env".cyan(),
-                            nix = "nix".cyan(),
-                            nix_install_url = "https://nixos.org/download.html".blue().underline(),
-                        )
-                    })
-                    .unwrap_err();
-                eprintln!("{wrapped_err:#}");
-                std::process::exit(1);
+        let dev_env = nix_dev_env::get_nix_dev_env(&flake_dir).await?;
+        let activation_script = ActivationScript::from(&dev_env);
+        let output = match self.format {
+            PrintDevEnvFormat::Json => serde_json::to_string_pretty(&activation_script)? + "\n",
+            PrintDevEnvFormat::Fish => activation_script.to_fish(),
+            PrintDevEnvFormat::Dotenv => activation_script.to_dotenv(),
+            PrintDevEnvFormat::Github => {
+                // Check both are set before writing either
+                let env_file = github_file("GITHUB_ENV")?;
+                let path_file = github_file("GITHUB_PATH")?;
+                let (env, path) = activation_script.to_github();
+                append(&env_file, &env).await?;
+                append(&path_file, &path).await?;
+                eprintln!(
+                    "{check} Set up the environment for the rest of the job",
+                    check = "✓".green(),
+                );
+                String::new()
             }
+            PrintDevEnvFormat::Nix | PrintDevEnvFormat::Bash => activation_script.to_shell(),
         };
-
-        Ok(nix_print_dev_env_exit.status.code())
+        if activation_script.shell_hook.is_some()
+            && matches!(
+                self.format,
+                PrintDevEnvFormat::Fish | PrintDevEnvFormat::Dotenv | PrintDevEnvFormat::Github
+            )
+        {
+            eprintln!(
+                "{warning} The environment's shell hook is bash code, so it's left out of the output",
+                warning = "⚠️".yellow(),
+            );
+        }
+        print!("{output}");
+        Ok(Some(0))
     }
 }
+
+/// Append `contents` to the file at `path`, creating it if needed
+async fn append(path: &Path, contents: &str) -> color_eyre::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .await
+        .wrap_err_with(|| format!("Could not open `{}`", path.display()))?;
+    file.write_all(contents.as_bytes())
+        .await
+        .wrap_err_with(|| format!("Could not write to `{}`", path.display()))?;
+    Ok(())
+}
+
+/// Print the environment the way `nix print-dev-env` does
+async fn print_nix_dev_env(flake_dir: &GeneratedFlake) -> color_eyre::Result<Option<i32>> {
+    let mut nix_print_dev_env_command = Command::new("nix");
+    nix_print_dev_env_command
+        .arg("print-dev-env")
+        .args(&["--extra-experimental-features", "flakes nix-command"])
+        .arg("-L")
+        .args(flake_dir.nix_args())
+        .arg(format!("path://{}", flake_dir.path().to_str().unwrap()))
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    // TODO(@hoverbear): Try to enable this somehow. Right now since we don't keep the lock
+    // in a consistent place, we can't reliably pick up a lock generated in online mode.
+    //
+    // If we stored the generated flake/lock in a consistent place this could be enabled.
+    //
+    // if self.offline {
+    //     nix_develop_command.arg("--offline");
+    // }
+
+    tracing::trace!(command = ?nix_print_dev_env_command.as_std(), "Running");
+    let nix_print_dev_env_exit = match nix_print_dev_env_command
+        .spawn()
+        .wrap_err("Failed to spawn `nix print-dev-env`")?
+        .wait_with_output()
+        .await
+    {
+        Ok(nix_print_dev_env_exit) => nix_print_dev_env_exit,
+        err @ Err(_) => {
+            let wrapped_err = err
+                .wrap_err_with(|| {
+                    format!(
+                        "\
+                    Could not execute `{nix_print_dev_env}`. Is `{nix}` installed?\n\n\
+                    Get instructions for installing Nix: {nix_install_url}\n\
+                    Run `{riff_doctor}` to check your setup.\n\
+                    Underlying error\
+                    ",
+                        nix_print_dev_env = "nix print-dev-env".cyan(),
+                        nix = "nix".cyan(),
+                        nix_install_url = "https://nixos.org/download.html".blue().underline(),
+                        riff_doctor = "riff doctor".cyan(),
+                    )
+                })
+                .unwrap_err();
+            eprintln!("{wrapped_err:#}");
+            crate::telemetry::exit(1).await;
+        }
+    };
+
+    Ok(nix_print_dev_env_exit.status.code())
+}
@@ -0,0 +1,237 @@
+//! The `registry` subcommand.
+
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use eyre::{eyre, WrapErr};
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+
+use crate::dependency_registry::{
+    DependencyRegistry, RegistrySource, DEPENDENCY_REGISTRY_REMOTE_URL,
+};
+use crate::project_config::ProjectConfig;
+use crate::registry_test;
+
+/// Work with dependency registry rules
+#[derive(Debug, Args)]
+pub struct Registry {
+    #[clap(subcommand)]
+    command: RegistryCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RegistryCommand {
+    Show(RegistryShow),
+    Update(RegistryUpdate),
+    ClearCache(RegistryClearCache),
+    Test(RegistryTest),
+}
+
+impl Registry {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        match self.command {
+            RegistryCommand::Show(show) => show.cmd().await,
+            RegistryCommand::Update(update) => update.cmd().await,
+            RegistryCommand::ClearCache(clear_cache) => clear_cache.cmd().await,
+            RegistryCommand::Test(test) => test.cmd().await,
+        }
+    }
+}
+
+/// Show the rule riff applies for a crate on this machine
+///
+/// The rule comes from the cached registry, or the one bundled with riff if there is no cache.
+/// Run `riff registry update` first to see the latest rules:
+///
+///     $ riff registry show openssl-sys
+#[derive(Debug, Args)]
+pub struct RegistryShow {
+    /// The name of the crate
+    #[clap(value_parser)]
+    crate_name: String,
+    #[clap(from_global)]
+    registry_url: Option<String>,
+}
+
+impl RegistryShow {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir =
+            std::env::current_dir().wrap_err("Current working directory was invalid")?;
+        let registry =
+            DependencyRegistry::for_project(true, self.registry_url.as_deref(), &project_dir)
+                .await?;
+        let language = registry.language().await;
+        let rule = language
+            .rust
+            .dependencies
+            .get(&self.crate_name)
+            .ok_or_else(|| {
+                eyre!(
+                    "The registry has no rule for `{crate_name}`. If it's new, try `{riff_registry_update}`.",
+                    crate_name = self.crate_name,
+                    riff_registry_update = "riff registry update".cyan(),
+                )
+            })?;
+
+        println!(
+            "{} ({confidence})",
+            self.crate_name.bold(),
+            confidence = rule.confidence,
+        );
+        println!(
+            "    build inputs:          {}",
+            rule.build_inputs()
+                .iter()
+                .sorted()
+                .map(|v| v.cyan())
+                .join(", ")
+        );
+        println!(
+            "    runtime inputs:        {}",
+            rule.runtime_inputs()
+                .iter()
+                .sorted()
+                .map(|v| v.cyan())
+                .join(", ")
+        );
+        println!(
+            "    environment variables: {}",
+            rule.environment_variables()
+                .iter()
+                .sorted()
+                .map(|(k, v)| format!("{}={v}", k.green()))
+                .join(", ")
+        );
+        if registry.source() == RegistrySource::Bundled {
+            println!(
+                "    {}",
+                format!(
+                    "from the registry bundled with riff, as there is no usable cache of {}",
+                    registry.remote_url()
+                )
+                .dimmed()
+            );
+        }
+        if !rule.targets.is_empty() {
+            println!(
+                "    {}",
+                format!(
+                    "with extra rules for {}; shown for {}",
+                    rule.targets.keys().sorted().join(", "),
+                    target_lexicon::HOST,
+                )
+                .dimmed()
+            );
+        }
+        Ok(None)
+    }
+}
+
+/// Fetch the latest registry into the cache now
+///
+/// riff usually refreshes its cache in the background, so the latest rules only apply on the
+/// run after they are published.
+#[derive(Debug, Args)]
+pub struct RegistryUpdate {
+    #[clap(from_global)]
+    registry_url: Option<String>,
+}
+
+impl RegistryUpdate {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let remote_url = remote_url(self.registry_url).await?;
+        let data = DependencyRegistry::update(&remote_url)
+            .await
+            .map_err(|err| {
+                eyre!(err).wrap_err(format!("Could not fetch the registry from {remote_url}"))
+            })?;
+        eprintln!(
+            "{check} Updated the registry: {count} crates",
+            check = "✓".green(),
+            count = data.language.rust.dependencies.len(),
+        );
+        Ok(None)
+    }
+}
+
+/// Delete the cached registry
+///
+/// Until the next refresh, riff uses the registry bundled with it instead.
+#[derive(Debug, Args)]
+pub struct RegistryClearCache {
+    #[clap(from_global)]
+    registry_url: Option<String>,
+}
+
+impl RegistryClearCache {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let remote_url = remote_url(self.registry_url).await?;
+        match DependencyRegistry::clear_cache(&remote_url).await? {
+            Some(path) => eprintln!("{check} Deleted `{}`", path.display(), check = "✓".green()),
+            None => eprintln!("There is no cached registry"),
+        }
+        Ok(None)
+    }
+}
+
+/// The URL of the registry used in the current directory: `--registry-url`, or the one its
+/// `riff.toml` names, or riff's own
+async fn remote_url(registry_url: Option<String>) -> color_eyre::Result<String> {
+    if let Some(registry_url) = registry_url {
+        return Ok(registry_url);
+    }
+    let project_dir = std::env::current_dir().wrap_err("Current working directory was invalid")?;
+    Ok(ProjectConfig::read(&project_dir)
+        .await?
+        .and_then(|config| config.registry_url)
+        .unwrap_or_else(|| DEPENDENCY_REGISTRY_REMOTE_URL.to_string()))
+}
+
+/// Check registry rules against fixture `cargo metadata` documents
+///
+/// Each fixture is a JSON document holding the output of `cargo metadata --format-version 1`
+/// under `metadata`, and the `build-inputs`, `environment-variables` and `runtime-inputs` the
+/// rules should produce for it under `expect`:
+///
+///     $ riff registry test --rules registry/registry.json registry/tests/*.json
+#[derive(Debug, Args)]
+pub struct RegistryTest {
+    /// The registry document holding the rules to check
+    #[clap(long, value_parser)]
+    rules: PathBuf,
+    /// The fixtures to check the rules against
+    #[clap(value_parser, required = true)]
+    fixtures: Vec<PathBuf>,
+}
+
+impl RegistryTest {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let registry = registry_test::read_registry(&self.rules).await?;
+
+        let mut failed = 0;
+        for path in &self.fixtures {
+            let fixture = registry_test::read_fixture(path).await?;
+            let mismatches = registry_test::check_fixture(&registry, fixture).await?;
+            if mismatches.is_empty() {
+                eprintln!("{check} {}", path.display(), check = "✓".green());
+            } else {
+                failed += 1;
+                eprintln!("{cross} {}", path.display(), cross = "✗".red());
+                for mismatch in mismatches {
+                    eprintln!("    {mismatch}");
+                }
+            }
+        }
+
+        if failed == 0 {
+            Ok(None)
+        } else {
+            eprintln!(
+                "{failed} of {total} fixtures failed",
+                total = self.fixtures.len()
+            );
+            Ok(Some(1))
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! The `reload` subcommand.
+
+use clap::{Args, ValueEnum};
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::project_fingerprint;
+
+/// Set by `riff shell` to the file `riff reload` writes the directory to restart the shell in
+pub(crate) const RELOAD_FILE_VAR: &str = "RIFF_RELOAD_FILE";
+/// Set by `riff shell` to the project directory the environment was detected in
+pub(crate) const PROJECT_DIR_VAR: &str = "RIFF_PROJECT_DIR";
+/// Set by `riff shell` to the fingerprint of the project's files when the shell started
+pub(crate) const FINGERPRINT_VAR: &str = "RIFF_FINGERPRINT";
+
+/// Restart the current `riff shell` with a freshly detected environment
+///
+/// The shell restarts in the current directory, and saves its history as it does when its
+/// terminal closes. To reload automatically whenever `Cargo.toml` or `Cargo.lock` changes, add
+/// the prompt hook to your shell's startup file:
+///
+///     eval "$(riff reload --hook bash)"
+#[derive(Debug, Args)]
+pub struct Reload {
+    /// Only reload if a file riff reads has changed since the shell started
+    #[clap(long)]
+    if_changed: bool,
+    /// Print a prompt hook for this shell which runs `riff reload --if-changed`
+    #[clap(long, value_enum, value_name = "SHELL")]
+    hook: Option<HookShell>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HookShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl HookShell {
+    fn hook(&self) -> &'static str {
+        match self {
+            HookShell::Bash => {
+                "_riff_reload() { [ -n \"$RIFF_RELOAD_FILE\" ] && riff reload --if-changed; }\n\
+                 PROMPT_COMMAND=\"_riff_reload${PROMPT_COMMAND:+;$PROMPT_COMMAND}\"\n"
+            }
+            HookShell::Zsh => {
+                "_riff_reload() { [ -n \"$RIFF_RELOAD_FILE\" ] && riff reload --if-changed; }\n\
+                 autoload -Uz add-zsh-hook\n\
+                 add-zsh-hook precmd _riff_reload\n"
+            }
+            HookShell::Fish => {
+                "function _riff_reload --on-event fish_prompt\n    \
+                     set -q RIFF_RELOAD_FILE; and riff reload --if-changed\n\
+                 end\n"
+            }
+        }
+    }
+}
+
+impl Reload {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        if let Some(shell) = self.hook {
+            print!("{}", shell.hook());
+            return Ok(None);
+        }
+
+        let reload_file = std::env::var_os(RELOAD_FILE_VAR).ok_or_else(|| {
+            eyre!(
+                "`{riff_reload}` only works inside `{riff_shell}`",
+                riff_reload = "riff reload".cyan(),
+                riff_shell = "riff shell".cyan(),
+            )
+        })?;
+
+        if self.if_changed && !changed_since_shell_started().await? {
+            return Ok(None);
+        }
+
+        let current_dir =
+            std::env::current_dir().wrap_err("Current working directory was invalid")?;
+        tokio::fs::write(&reload_file, current_dir.to_string_lossy().as_bytes())
+            .await
+            .wrap_err("Could not ask `riff shell` to reload")?;
+
+        eprintln!("Reloading the riff shell...");
+        // The shell is our parent; hanging it up makes it save its history and exit, after which
+        // `riff shell` starts a new one
+        let shell = std::os::unix::process::parent_id();
+        if unsafe { libc::kill(shell as libc::pid_t, libc::SIGHUP) } != 0 {
+            return Err(std::io::Error::last_os_error()).wrap_err("Could not restart the shell");
+        }
+        Ok(None)
+    }
+}
+
+/// Whether the project's files differ from when `riff shell` started the current shell
+async fn changed_since_shell_started() -> color_eyre::Result<bool> {
+    let (project_dir, started) = match (
+        std::env::var_os(PROJECT_DIR_VAR),
+        std::env::var(FINGERPRINT_VAR),
+    ) {
+        (Some(project_dir), Ok(started)) => (project_dir, started),
+        _ => return Ok(true),
+    };
+    let project_dir = std::path::PathBuf::from(project_dir);
+    let now = project_fingerprint::fingerprint(
+        &project_dir,
+        &project_fingerprint::watched_files(&project_dir),
+    )
+    .await?;
+    Ok(now.to_string() != started)
+}
@@ -0,0 +1,77 @@
+//! The `remove` subcommand.
+
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::metadata_editor::{InputKind, MetadataEditor};
+
+/// Remove inputs or environment variables from your project's riff metadata
+///
+/// For example, remove `openssl` from the build inputs:
+///
+///     $ riff remove openssl
+///
+/// Or from the runtime inputs, like `riff remove runtime-input libGL`.
+#[derive(Debug, Args)]
+pub struct Remove {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    /// The Nixpkgs attributes to remove (eg `openssl`), optionally after `build-input` or `runtime-input`
+    inputs: Vec<String>,
+    /// Remove the inputs from `runtime-inputs` instead of `build-inputs`
+    #[clap(long)]
+    runtime: bool,
+    /// The name of an environment variable to remove
+    #[clap(long = "env")]
+    environment_variables: Vec<String>,
+}
+
+impl Remove {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let (kind, inputs) = InputKind::from_args(&self.inputs, self.runtime)?;
+        if inputs.is_empty() && self.environment_variables.is_empty() {
+            return Err(eyre!("Nothing to remove, pass some inputs or `--env NAME`"));
+        }
+
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let mut editor = MetadataEditor::open(&project_dir).await?;
+
+        for input in inputs {
+            if editor.remove_input(kind, input) {
+                eprintln!(
+                    "{check} Removed {input} from {key}",
+                    check = "✓".green(),
+                    input = input.cyan(),
+                    key = kind.key()
+                );
+            } else {
+                eprintln!(
+                    "{input} was not in {key}",
+                    input = input.cyan(),
+                    key = kind.key()
+                );
+            }
+        }
+        for name in &self.environment_variables {
+            if editor.remove_environment_variable(name) {
+                eprintln!(
+                    "{check} Unset {name}",
+                    check = "✓".green(),
+                    name = name.green()
+                );
+            } else {
+                eprintln!("{name} was not set", name = name.green());
+            }
+        }
+
+        editor.save().await?;
+        Ok(None)
+    }
+}
@@ -1,12 +1,20 @@
 //! The `run` subcommand.
 
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 
 use clap::Args;
-use eyre::WrapErr;
+use eyre::{eyre, WrapErr};
 use owo_colors::OwoColorize;
+use tokio::process::Command;
 
-use crate::flake_generator;
+use crate::env_cache::EnvCache;
+use crate::environment_overrides::EnvironmentOverrides;
+use crate::flake_generator::{self, DetectArgs, DetectOptions, GeneratedFlake};
+use crate::nix_dev_env::{ActivationScript, NixDevEnv};
+use crate::project_config::ProjectConfig;
+use crate::resource_limits;
+use crate::timeouts::{Timeouts, SETUP_TIMEOUT_STATUS};
 
 /// Run a command with your project's dependencies
 ///
@@ -17,65 +25,398 @@ use crate::flake_generator;
 /// Run cargo check and cargo build at the same time:
 ///
 ///     $ riff run -- sh -c 'cargo check && cargo build'
+///
+/// Run the tests of one crate with only the environment riff provides, plus `.env`:
+///
+///     $ riff run --clear-env --env-file .env --chdir crates/server -- cargo test
+///
+/// Pass an input's store path to a build system which needs it, rather than a variable:
+///
+///     $ riff run --expand -- cmake -DOPENSSL_ROOT_DIR={{openssl.dev}} ..
+///
+/// Fail a CI job with 123 if setting up the environment takes over 10 minutes, or with 124 if the
+/// tests take over an hour:
+///
+///     $ riff run --setup-timeout 10m --command-timeout 1h -- cargo test
+///
+/// On Linux, the `resource-limits` in the project's `riff.toml` hold the command to an amount of
+/// memory, CPU time and processes, with `systemd-run`.
 #[derive(Debug, Args)]
 pub struct Run {
     /// The root directory of the project
-    #[clap(long, value_parser)]
+    #[clap(long, value_parser = crate::wsl::parse_path)]
     project_dir: Option<PathBuf>,
+    /// Run the command in this directory instead of the current one
+    #[clap(long, value_parser = crate::wsl::parse_path, value_name = "DIR")]
+    chdir: Option<PathBuf>,
+    /// Don't pass riff's own environment variables on, like `env -i`
+    ///
+    /// The command only sees the variables of the project's environment and any `--env-file`.
+    #[clap(long)]
+    clear_env: bool,
+    /// Set the `KEY=VALUE` variables in this file, overriding the project's environment
+    ///
+    /// Blank lines and lines starting with `#` are skipped. May be given more than once.
+    #[clap(long, value_parser, value_name = "FILE")]
+    env_file: Vec<PathBuf>,
     /// The command to run with your project's dependencies
     #[clap(required = true)]
     pub(crate) command: Vec<String>,
+    /// Replace placeholders like `{{openssl}}` or `{{openssl.dev}}` in the command with the store
+    /// paths of those packages, from the environment's package set
+    ///
+    /// Without it, the command runs as written, so braces meant for something else, like the
+    /// `{{title}}` of a template, are left alone.
+    #[clap(long)]
+    expand: bool,
+    /// Refuse to run the command unless the environment matches `riff.lock`, and use its nixpkgs
+    #[clap(long)]
+    locked: bool,
+    /// Cross-compile to this target triple, like `aarch64-unknown-linux-gnu`
+    #[clap(long, value_name = "TRIPLE")]
+    target: Option<String>,
+    /// Evaluate the environment again, even if it's cached and nothing changed
+    #[clap(long)]
+    refresh: bool,
+    #[clap(flatten)]
+    timeouts: Timeouts,
+    #[clap(flatten)]
+    overrides: EnvironmentOverrides,
     #[clap(from_global)]
     disable_telemetry: bool,
     #[clap(from_global)]
     offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
     // TODO(@cole-h): support additional nix develop args?
 }
 
 impl Run {
     pub async fn cmd(&self) -> color_eyre::Result<Option<i32>> {
-        let flake_dir = flake_generator::generate_flake_from_project_dir(
-            self.project_dir.clone(),
-            self.offline,
-            self.disable_telemetry,
-        )
-        .await?;
+        let project_dir = match &self.project_dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let options = DetectOptions {
+            locked: self.locked,
+            target: self.target.clone(),
+            overrides: self.overrides.clone(),
+            ..DetectOptions::from(&self.detect)
+        };
 
-        let dev_env = crate::nix_dev_env::get_nix_dev_env(flake_dir.path()).await?;
+        let mut env_file_vars = Vec::new();
+        for env_file in &self.env_file {
+            let contents = tokio::fs::read_to_string(env_file)
+                .await
+                .wrap_err_with(|| format!("Could not read `{}`", env_file.display()))?;
+            env_file_vars.extend(
+                parse_env_file(&contents)
+                    .wrap_err_with(|| format!("Could not parse `{}`", env_file.display()))?,
+            );
+        }
 
-        let command_name = &self.command[0];
+        // Placeholders are resolved against the flake, so it is needed even if the environment is
+        // cached
+        let inputs = self
+            .command
+            .iter()
+            .filter(|_| self.expand)
+            .flat_map(|arg| placeholders(arg))
+            .collect::<BTreeSet<_>>();
+        let (dev_env, store_paths) = match self
+            .timeouts
+            .setup(self.resolve(&project_dir, options, inputs))
+            .await?
+        {
+            Some(resolved) => resolved,
+            None => return Ok(Some(SETUP_TIMEOUT_STATUS)),
+        };
+        let mut args = self
+            .command
+            .iter()
+            .map(|arg| expand_placeholders(arg, &store_paths))
+            .collect::<Vec<_>>();
+        let config = ProjectConfig::read(&project_dir).await?.unwrap_or_default();
+        // The user's command, even once it's wrapped in `systemd-run`
+        let program = args[0].clone();
+        let limited = !config.resource_limits.is_empty();
+        if limited {
+            // `systemd-run` would only find out once the scope is started, and fail as the command
+            if !program.contains('/') && !self.finds(&dev_env, &program) {
+                self.print_not_found_hint();
+                return Err(eyre!("Cannot run the command `{program}`: it wasn't found"));
+            }
+            args = config.resource_limits.wrap(&args).await?;
+        }
+        let command_name = &args[0];
 
-        let mut command = crate::nix_dev_env::run_in_dev_env(&dev_env, command_name).await?;
+        let mut command =
+            crate::nix_dev_env::run_in_dev_env(&dev_env, command_name, self.clear_env).await?;
+        if limited && self.clear_env {
+            crate::nix_dev_env::keep_variables(
+                &mut command,
+                &dev_env,
+                resource_limits::MANAGER_VARIABLES,
+            );
+        }
 
-        command.args(&self.command[1..]);
+        command.args(&args[1..]);
+        command.envs(env_file_vars);
+        if let Some(chdir) = &self.chdir {
+            command.current_dir(chdir);
+        }
 
-        Ok(command
+        let mut child = command
             .spawn()
             .map_err(|err| {
                 if err.kind() == std::io::ErrorKind::NotFound {
-                    eprintln!(
-                        "The command you attempted to run was not found.
-Try running it in a shell; for example:
-\t{riff_run_example}\n",
-                        riff_run_example =
-                            format!("riff run -- sh -c '{}'", self.command.join(" ")).cyan(),
-                    );
+                    self.print_not_found_hint();
                 };
                 err
             })
-            .wrap_err(format!("Cannot run the command `{}`", command_name))?
-            .wait_with_output()
-            .await?
-            .status
-            .code())
+            .wrap_err(format!("Cannot run the command `{program}`"))?;
+        self.timeouts.wait(&mut child).await
+    }
+
+    fn print_not_found_hint(&self) {
+        eprintln!(
+            "The command you attempted to run was not found.
+Try running it in a shell; for example:
+\t{riff_run_example}\n",
+            riff_run_example = format!("riff run -- sh -c '{}'", self.command.join(" ")).cyan(),
+        );
+    }
+
+    /// Whether the command `name` is on the `PATH` it runs with in `dev_env`
+    fn finds(&self, dev_env: &NixDevEnv, name: &str) -> bool {
+        let mut dirs = ActivationScript::from(dev_env).path();
+        if !self.clear_env {
+            dirs.extend(
+                std::env::var_os("PATH")
+                    .iter()
+                    .flat_map(std::env::split_paths),
+            );
+        }
+        resource_limits::find_on_path(std::env::join_paths(dirs).ok(), name).is_some()
     }
+
+    /// The environment of `project_dir`, from the cache if it can be, and the store paths of
+    /// the `inputs` the command's placeholders name
+    async fn resolve<'a>(
+        &self,
+        project_dir: &Path,
+        options: DetectOptions,
+        inputs: BTreeSet<&'a str>,
+    ) -> color_eyre::Result<(NixDevEnv, BTreeMap<&'a str, PathBuf>)> {
+        let cache = EnvCache::for_project(project_dir, &options).await?;
+        let cached = match cache
+            .as_ref()
+            .filter(|_| !self.refresh && inputs.is_empty())
+        {
+            Some(cache) => cache.load().await,
+            None => None,
+        };
+        if let Some(dev_env) = cached {
+            return Ok((dev_env, BTreeMap::new()));
+        }
+
+        let mut flake_dir = flake_generator::generate_flake_from_project_dir(
+            Some(project_dir.to_owned()),
+            self.offline,
+            self.disable_telemetry,
+            options,
+        )
+        .await?;
+        flake_dir.root_for_project(project_dir).await;
+
+        let dev_env = crate::nix_dev_env::get_nix_dev_env(&flake_dir).await?;
+        if let Some(cache) = &cache {
+            if let Err(err) = cache.store(&dev_env).await {
+                tracing::debug!(%err, "Could not cache the environment");
+            }
+        }
+        let mut store_paths = BTreeMap::new();
+        for input in inputs {
+            store_paths.insert(input, store_path(&flake_dir, input).await?);
+        }
+        Ok((dev_env, store_paths))
+    }
+}
+
+/// The inputs named by the `{{input}}` placeholders in `arg`
+///
+/// Braces around anything which isn't an attribute path, like `{{ x }}`, aren't placeholders.
+fn placeholders(arg: &str) -> Vec<&str> {
+    let mut inputs = Vec::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) if is_attr_path(&after[..end]) => {
+                inputs.push(&after[..end]);
+                rest = &after[end + 2..];
+            }
+            _ => rest = after,
+        }
+    }
+    inputs
+}
+
+fn is_attr_path(input: &str) -> bool {
+    !input.is_empty()
+        && input.split('.').all(|name| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_'-+".contains(c))
+        })
+}
+
+/// `arg` with its placeholders replaced by the store paths in `store_paths`
+fn expand_placeholders(arg: &str, store_paths: &BTreeMap<&str, PathBuf>) -> String {
+    store_paths
+        .iter()
+        .fold(arg.to_string(), |arg, (input, store_path)| {
+            arg.replace(
+                &format!("{{{{{input}}}}}"),
+                &store_path.display().to_string(),
+            )
+        })
+}
+
+/// The store path of the package `input` in the package set of `flake`, with its overlays and
+/// cross-compilation package sets
+///
+/// Packages the environment provides are already built, so the path exists.
+async fn store_path(flake: &GeneratedFlake, input: &str) -> color_eyre::Result<PathBuf> {
+    if flake.legacy() {
+        return Err(eyre!(
+            "Replacing `{{{{{input}}}}}` with a store path needs Nix {} or later",
+            crate::legacy_nix::MINIMUM_NIX_VERSION
+        ));
+    }
+    let mut command = Command::new("nix");
+    command
+        .arg("eval")
+        .arg("--raw")
+        .args(["--extra-experimental-features", "flakes nix-command"])
+        .args(flake.nix_args())
+        // Found in the flake's `legacyPackages`
+        .arg(format!("path://{}#{input}.outPath", flake.path().display()))
+        .kill_on_drop(true);
+    tracing::trace!(command = ?command.as_std(), "Running");
+
+    let output = command.output().await.wrap_err_with(|| {
+        format!(
+            "Could not execute `{nix_eval}`. Is `{nix}` installed?",
+            nix_eval = "nix eval".cyan(),
+            nix = "nix".cyan(),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Could not find the package `{input}` for `{{{{{input}}}}}` in the environment's packages:\n{}",
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    let store_path = PathBuf::from(String::from_utf8(output.stdout)?);
+    if !store_path.exists() {
+        return Err(eyre!(
+            "`{{{{{input}}}}}` is `{}`, which isn't built. Is `{input}` one of the environment's inputs?",
+            store_path.display(),
+        ));
+    }
+    Ok(store_path)
+}
+
+/// The variables in an env file, in order
+fn parse_env_file(contents: &str) -> color_eyre::Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| eyre!("Line {} is not of the form `KEY=VALUE`", index + 1))?;
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|&quote| {
+                value
+                    .strip_prefix(quote)
+                    .and_then(|value| value.strip_suffix(quote))
+            })
+            .unwrap_or(value);
+        vars.push((name.trim().to_string(), value.to_string()));
+    }
+    Ok(vars)
 }
 
 #[cfg(test)]
 mod tests {
     use tempfile::TempDir;
 
-    use super::Run;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::{expand_placeholders, parse_env_file, placeholders, Run};
+
+    #[test]
+    fn argument_placeholders() {
+        assert_eq!(
+            placeholders("-DOPENSSL_ROOT_DIR={{openssl.dev}}"),
+            ["openssl.dev"]
+        );
+        assert_eq!(
+            placeholders("{{zlib}}:{{libxml2.dev}}:{{zlib}}"),
+            ["zlib", "libxml2.dev", "zlib"]
+        );
+        assert!(placeholders("{{ not a placeholder }} {{}} {{a..b}} ${{x}").is_empty());
+
+        let store_paths = BTreeMap::from([
+            (
+                "openssl.dev",
+                PathBuf::from("/nix/store/abc-openssl-3.0.7-dev"),
+            ),
+            ("zlib", PathBuf::from("/nix/store/def-zlib-1.2.13")),
+        ]);
+        assert_eq!(
+            expand_placeholders("-DOPENSSL_ROOT_DIR={{openssl.dev}}", &store_paths),
+            "-DOPENSSL_ROOT_DIR=/nix/store/abc-openssl-3.0.7-dev"
+        );
+        assert_eq!(
+            expand_placeholders("{{zlib}}/lib:{{zlib}}/include {{ zlib }}", &store_paths),
+            "/nix/store/def-zlib-1.2.13/lib:/nix/store/def-zlib-1.2.13/include {{ zlib }}"
+        );
+    }
+
+    #[test]
+    fn env_file() -> eyre::Result<()> {
+        let env_file = r#"
+# Database settings
+DATABASE_URL=postgres://localhost/riff
+export RUST_LOG = debug
+GREETING="hello world"
+EMPTY=
+"#;
+        assert_eq!(
+            parse_env_file(env_file)?,
+            [
+                ("DATABASE_URL", "postgres://localhost/riff"),
+                ("RUST_LOG", "debug"),
+                ("GREETING", "hello world"),
+                ("EMPTY", ""),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+        );
+        assert!(parse_env_file("NOT A VARIABLE").is_err());
+        Ok(())
+    }
 
     // We can't run this test by default because it calls Nix. Calling Nix inside Nix doesn't appear
     // to work very well (at least, for this use case).
@@ -105,12 +446,22 @@ path = "lib.rs"
 
         let run = Run {
             project_dir: Some(temp_dir.path().to_owned()),
+            chdir: None,
+            clear_env: false,
+            env_file: Vec::new(),
             command: ["sh", "-c", "exit 6"]
                 .into_iter()
                 .map(String::from)
                 .collect(),
+            expand: false,
             offline: true,
+            detect: Default::default(),
             disable_telemetry: true,
+            locked: false,
+            target: None,
+            refresh: false,
+            timeouts: Default::default(),
+            overrides: Default::default(),
         };
 
         let run_cmd = tokio_test::task::spawn(run.cmd());
@@ -1,53 +1,428 @@
 //! The `shell` subcommand.
-use std::path::PathBuf;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
 
 use clap::Args;
-use eyre::WrapErr;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use tempfile::TempDir;
+use tokio::process::Child;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::Instant;
 
-use crate::flake_generator;
+use super::reload;
+use crate::env_cache::EnvCache;
+use crate::environment_overrides::EnvironmentOverrides;
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+use crate::gc_roots;
+use crate::nix_dev_env::NixDevEnv;
+use crate::project_config::ProjectConfig;
+use crate::project_fingerprint;
+use crate::timeouts::{parse_duration, Timeouts, COMMAND_TIMEOUT_STATUS, SETUP_TIMEOUT_STATUS};
+
+/// How often `--exit-with-parent` checks whether the parent process is still running
+const PARENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long an ending shell gets to exit after being hung up before it is killed
+const HANG_UP_GRACE: Duration = Duration::from_secs(5);
+/// The variables a `--pure` shell always keeps from the outer environment
+const PURE_KEPT_VARS: [&str; 2] = ["HOME", "TERM"];
 
 /// Start a development shell
+///
+/// For ephemeral machines like CI runners, `--expire` and `--exit-with-parent` end the shell
+/// on their own and then remove the garbage collector root of its environment:
+///
+///     $ riff shell --expire 2h
+///
+/// `--setup-timeout` bounds how long setting up the environment may take, exiting with 123 if it
+/// runs over, and `--command-timeout` ends the shell like `--expire`, exiting with 124.
+///
+/// `--pure` starts the shell with only the environment's variables, like
+/// `nix develop --ignore-environment`, so it can't depend on tools and libraries installed on the
+/// host. It keeps `HOME`, `TERM` and the variables listed in `keep-environment` in `riff.toml`,
+/// and runs the environment's `bash` rather than the user's shell.
+///
+/// `--shell` starts another shell than `$SHELL`, by name or path, which reads its rc files as
+/// usual. If they undo the environment, say by setting `PATH`, `--no-rc` skips them:
+///
+///     $ riff shell --shell fish --no-rc
+///
+/// `--command` runs a command in the shell instead of starting it interactively, like
+/// `nix develop --command` with the shell's `-c`, and exits with the command's status:
+///
+///     $ riff shell -c "cargo test --all"
 #[derive(Debug, Args, Clone)]
 pub struct Shell {
     /// The root directory of the project
-    #[clap(long, value_parser)]
+    #[clap(long, value_parser = crate::wsl::parse_path)]
     project_dir: Option<PathBuf>,
+    /// Refuse to start the shell unless the environment matches `riff.lock`, and use its nixpkgs
+    #[clap(long)]
+    locked: bool,
+    /// Cross-compile to this target triple, like `aarch64-unknown-linux-gnu`
+    #[clap(long, value_name = "TRIPLE")]
+    target: Option<String>,
+    /// End the shell after this long, like `90s`, `30m` or `1h30m`, and remove its root
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    expire: Option<Duration>,
+    #[clap(flatten)]
+    timeouts: Timeouts,
+    /// End the shell when the process which started riff exits, and remove its root
+    #[clap(long)]
+    exit_with_parent: bool,
+    /// Evaluate the environment again, even if it's cached and nothing changed
+    #[clap(long)]
+    refresh: bool,
+    /// Start the shell with only the environment's variables, and `HOME`, `TERM` and those kept in `riff.toml`
+    #[clap(long)]
+    pure: bool,
+    /// The shell to start, like `zsh`, `fish` or a path, instead of `RIFF_SHELL` or `$SHELL`
+    ///
+    /// `riff watch` only runs a shell when given `--shell`, which there needs no value.
+    #[clap(
+        long,
+        value_name = "SHELL",
+        min_values = 0,
+        max_values = 1,
+        default_missing_value = ""
+    )]
+    shell: Option<String>,
+    /// Start the shell without reading its rc files, like `~/.bashrc` or fish's `config.fish`
+    #[clap(long)]
+    no_rc: bool,
+    /// Run this command with the shell's `-c` rather than starting it interactively, and exit
+    /// with its status
+    #[clap(short = 'c', long, value_name = "COMMAND")]
+    command: Option<String>,
+    #[clap(flatten)]
+    overrides: EnvironmentOverrides,
     #[clap(from_global)]
     disable_telemetry: bool,
     #[clap(from_global)]
     offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
 }
 
 impl Shell {
     pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
-        let flake_dir = flake_generator::generate_flake_from_project_dir(
-            self.project_dir,
+        self.run(None).await
+    }
+
+    /// Run the shell, and restart it in a freshly detected environment whenever `restart`
+    /// receives
+    pub(crate) async fn run(
+        self,
+        mut restart: Option<UnboundedReceiver<()>>,
+    ) -> color_eyre::Result<Option<i32>> {
+        let project_dir = self.project_dir()?;
+        let expiry = self.expire.map(|expire| Instant::now() + expire);
+        let parent = self
+            .exit_with_parent
+            .then(std::os::unix::process::parent_id);
+        let (mut dev_env, gc_root) = match self.timeouts.setup(self.resolve(&project_dir)).await? {
+            Some(resolved) => resolved,
+            None => return Ok(Some(SETUP_TIMEOUT_STATUS)),
+        };
+        // The shell is the command, so `--command-timeout` ends it like `--expire` does, but
+        // counts from when it starts
+        let deadline = expiry
+            .into_iter()
+            .chain(
+                self.timeouts
+                    .command_timeout
+                    .map(|timeout| Instant::now() + timeout),
+            )
+            .min();
+        let mut gc_roots = Vec::from_iter(gc_root);
+
+        // Like `nix develop`, a pure shell is the environment's own `bash`, not the user's shell
+        // Read here rather than by clap, so it doesn't tell `riff watch` to run a shell
+        let chosen_shell = self
+            .shell
+            .clone()
+            .filter(|shell| !shell.is_empty())
+            .or_else(|| std::env::var("RIFF_SHELL").ok())
+            .filter(|shell| !shell.is_empty());
+        let shell = match (chosen_shell, self.pure) {
+            (Some(shell), _) => shell,
+            (None, true) => "bash".to_string(),
+            (None, false) => crate::nix_dev_env::get_shell().await?,
+        };
+        let shell_args = match self.no_rc {
+            true => no_rc_args(&shell)?,
+            false => &[],
+        };
+        let kept_vars = match self.pure {
+            true => {
+                let config = ProjectConfig::read(&project_dir).await?.unwrap_or_default();
+                PURE_KEPT_VARS
+                    .iter()
+                    .map(ToString::to_string)
+                    .chain(config.keep_environment)
+                    .collect()
+            }
+            false => Vec::new(),
+        };
+
+        // `riff reload` writes the directory to restart the shell in here, then hangs it up
+        let reload_dir = TempDir::new()?;
+        let reload_file = reload_dir.path().join("reload");
+        let mut current_dir = None;
+        let status = loop {
+            let fingerprint = project_fingerprint::fingerprint(
+                &project_dir,
+                &project_fingerprint::watched_files(&project_dir),
+            )
+            .await?;
+
+            let mut command =
+                crate::nix_dev_env::run_in_dev_env(&dev_env, &shell, self.pure).await?;
+            command.args(shell_args);
+            if let Some(shell_command) = &self.command {
+                // One argument, which the shell parses, so the command needs no more quoting
+                command.arg("-c").arg(shell_command);
+            }
+            crate::nix_dev_env::keep_variables(
+                &mut command,
+                &dev_env,
+                kept_vars.iter().map(String::as_str),
+            );
+            command
+                .env(reload::RELOAD_FILE_VAR, &reload_file)
+                .env(reload::PROJECT_DIR_VAR, &project_dir)
+                .env(reload::FINGERPRINT_VAR, fingerprint.to_string());
+            if let Some(current_dir) = &current_dir {
+                command.current_dir(current_dir);
+            }
+            let mut child = command
+                .spawn()
+                .wrap_err(format!("Cannot run the shell `{}`", shell))?;
+            let status = tokio::select! {
+                status = child.wait() => Some(status?),
+                Some(()) = restarted(&mut restart) => {
+                    hang_up(&mut child).await?;
+                    None
+                }
+                ending = ending(deadline, parent) => {
+                    eprintln!(
+                        "{warning} {ending}, ending the riff shell",
+                        warning = "⚠️".yellow(),
+                    );
+                    hang_up(&mut child).await?;
+                    break match ending {
+                        Ending::Expired => Some(COMMAND_TIMEOUT_STATUS),
+                        Ending::ParentExited => None,
+                    };
+                }
+            };
+
+            if let Some(status) = status {
+                match tokio::fs::read_to_string(&reload_file).await {
+                    Ok(reload_dir) => {
+                        tokio::fs::remove_file(&reload_file).await?;
+                        current_dir = Some(PathBuf::from(reload_dir));
+                    }
+                    Err(_) => break exit_code(status),
+                }
+            }
+            match self.resolve(&project_dir).await {
+                Ok((reloaded, gc_root)) => {
+                    dev_env = reloaded;
+                    gc_roots.extend(gc_root);
+                }
+                Err(err) => eprintln!(
+                    "{warning} Could not reload the environment, keeping the previous one: {err:#}",
+                    warning = "⚠️".yellow(),
+                ),
+            }
+        };
+
+        if self.expire.is_some() || self.exit_with_parent {
+            for gc_root in gc_roots {
+                if let Err(err) = gc_roots::remove_root_of(&gc_root).await {
+                    tracing::debug!(%err, "Could not remove the garbage collector root");
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    /// Detect and evaluate the environment of `project_dir`, along with the profile which keeps
+    /// it in the Nix store
+    async fn resolve(
+        &self,
+        project_dir: &Path,
+    ) -> color_eyre::Result<(NixDevEnv, Option<PathBuf>)> {
+        let options = self.detect_options();
+        let cache = EnvCache::for_project(project_dir, &options).await?;
+        if let Some(cache) = cache.as_ref().filter(|_| !self.refresh) {
+            if let Some(dev_env) = cache.load().await {
+                return Ok((dev_env, None));
+            }
+        }
+
+        let mut flake_dir = flake_generator::generate_flake_from_project_dir(
+            Some(project_dir.to_owned()),
             self.offline,
             self.disable_telemetry,
+            options,
         )
         .await?;
+        flake_dir.root_for_project(project_dir).await;
+
+        let dev_env = crate::nix_dev_env::get_nix_dev_env(&flake_dir).await?;
+        if let Some(cache) = cache {
+            if let Err(err) = cache.store(&dev_env).await {
+                tracing::debug!(%err, "Could not cache the environment");
+            }
+        }
+        Ok((dev_env, flake_dir.gc_root().map(Path::to_path_buf)))
+    }
+
+    /// The root directory of the project, which defaults to the current one
+    pub(crate) fn project_dir(&self) -> color_eyre::Result<PathBuf> {
+        match &self.project_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => std::env::current_dir().wrap_err("Current working directory was invalid"),
+        }
+    }
+
+    /// Whether `--shell` was given, with or without a shell to start, or `--command` to run in one
+    pub(crate) fn shell_given(&self) -> bool {
+        self.shell.is_some() || self.command.is_some()
+    }
 
-        let dev_env = crate::nix_dev_env::get_nix_dev_env(flake_dir.path()).await?;
+    /// Whether riff only uses what it has already downloaded
+    pub(crate) fn offline(&self) -> bool {
+        self.offline
+    }
 
-        let shell = crate::nix_dev_env::get_shell().await?;
+    /// The options the environment is detected with
+    pub(crate) fn detect_options(&self) -> DetectOptions {
+        DetectOptions {
+            locked: self.locked,
+            target: self.target.clone(),
+            overrides: self.overrides.clone(),
+            ..DetectOptions::from(&self.detect)
+        }
+    }
+}
 
-        Ok(crate::nix_dev_env::run_in_dev_env(&dev_env, &shell)
-            .await?
-            .spawn()
-            .wrap_err(format!("Cannot run the shell `{}`", shell))?
-            .wait_with_output()
-            .await?
-            .status
-            .code())
+/// The next restart `restart` receives; never, if there's nothing to restart the shell
+async fn restarted(restart: &mut Option<UnboundedReceiver<()>>) -> Option<()> {
+    match restart {
+        Some(restart) => restart.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Why an ephemeral shell ended before its user exited it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ending {
+    Expired,
+    ParentExited,
+}
+
+impl std::fmt::Display for Ending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ending::Expired => write!(f, "The shell expired"),
+            Ending::ParentExited => write!(f, "The process which started riff exited"),
+        }
+    }
+}
+
+/// Wait until `deadline` passes, or the process `parent` stops being riff's parent, whichever
+/// comes first; never, if there's neither
+async fn ending(deadline: Option<Instant>, parent: Option<u32>) -> Ending {
+    let expired = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    let parent_exited = async {
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return std::future::pending().await,
+        };
+        let mut interval = tokio::time::interval(PARENT_POLL_INTERVAL);
+        // Orphans are adopted by another process, so the parent changes once it exits
+        while std::os::unix::process::parent_id() == parent {
+            interval.tick().await;
+        }
+    };
+    tokio::select! {
+        _ = expired => Ending::Expired,
+        _ = parent_exited => Ending::ParentExited,
+    }
+}
+
+/// The exit code of the shell, or like a shell's `$?`, 128 plus the signal which ended it
+fn exit_code(status: ExitStatus) -> Option<i32> {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+}
+
+/// Hang up the shell, so it saves its history and exits, killing it if it doesn't in time
+async fn hang_up(child: &mut Child) -> color_eyre::Result<()> {
+    if let Some(pid) = child.id() {
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGHUP) };
+    }
+    if tokio::time::timeout(HANG_UP_GRACE, child.wait())
+        .await
+        .is_err()
+    {
+        child.kill().await.wrap_err("Could not end the shell")?;
+    }
+    Ok(())
+}
+
+/// The arguments which start `shell` without reading its rc files
+fn no_rc_args(shell: &str) -> color_eyre::Result<&'static [&'static str]> {
+    let name = Path::new(shell)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(shell);
+    match name {
+        "bash" => Ok(&["--norc"]),
+        "zsh" => Ok(&["--no-rcs"]),
+        "fish" => Ok(&["--no-config"]),
+        _ => Err(eyre!(
+            "Riff doesn't know how to start `{shell}` without its rc files. Leave out `{no_rc}`, or pass `{shell_flag}` with `bash`, `zsh` or `fish`.",
+            shell = shell.cyan(),
+            no_rc = "--no-rc".cyan(),
+            shell_flag = "--shell".cyan(),
+        )),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::TempDir;
     use tokio::fs::write;
 
+    #[test]
+    fn shells_without_rc_files() -> eyre::Result<()> {
+        assert_eq!(no_rc_args("bash")?, ["--norc"]);
+        assert_eq!(no_rc_args("/usr/bin/zsh")?, ["--no-rcs"]);
+        assert_eq!(no_rc_args("fish")?, ["--no-config"]);
+        assert!(no_rc_args("nu").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ephemeral_shell_ending() {
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(ending(Some(deadline), None).await, Ending::Expired);
+        let other_parent = std::os::unix::process::parent_id() + 1;
+        assert_eq!(ending(None, Some(other_parent)).await, Ending::ParentExited);
+    }
+
     // We can't run this test by default because it calls Nix. Calling Nix inside Nix doesn't appear
     // to work very well (at least, for this use case). We also don't want to run this in CI because
     // the shell is not interactive, leading `nix develop` to exit without evaluating the
@@ -72,8 +447,8 @@ edition = "2021"
 name = "riff_test"
 path = "lib.rs"
 
-[package.metadata.riff.environment-variables]
-shellHook = "exit 6"
+[package.metadata.riff]
+shell-hook = "exit 6"
 
 [dependencies]
         "#,
@@ -83,7 +458,19 @@ shellHook = "exit 6"
         let shell = Shell {
             project_dir: Some(temp_dir.path().to_owned()),
             offline: true,
+            detect: Default::default(),
             disable_telemetry: true,
+            locked: false,
+            target: None,
+            expire: None,
+            timeouts: Default::default(),
+            exit_with_parent: false,
+            pure: false,
+            shell: None,
+            no_rc: false,
+            command: None,
+            refresh: false,
+            overrides: Default::default(),
         };
 
         let shell_cmd = shell.cmd().await?;
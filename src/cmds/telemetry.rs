@@ -0,0 +1,106 @@
+//! The `telemetry` subcommand.
+
+use clap::{Args, CommandFactory, Subcommand};
+use owo_colors::OwoColorize;
+
+use crate::telemetry;
+use crate::Cli;
+
+/// Control the telemetry riff sends
+#[derive(Debug, Args)]
+pub struct Telemetry {
+    #[clap(subcommand)]
+    command: TelemetryCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TelemetryCommand {
+    Disable(TelemetryDisable),
+    Enable(TelemetryEnable),
+    Show(TelemetryShow),
+}
+
+impl Telemetry {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        match self.command {
+            TelemetryCommand::Disable(disable) => disable.cmd().await,
+            TelemetryCommand::Enable(enable) => enable.cmd().await,
+            TelemetryCommand::Show(show) => show.cmd().await,
+        }
+    }
+}
+
+/// Never send telemetry from this machine
+///
+/// This also deletes the random ID riff identifies this machine with. To disable telemetry for
+/// one project instead, set `disable-telemetry = true` in its `riff.toml`.
+#[derive(Debug, Args)]
+pub struct TelemetryDisable {}
+
+impl TelemetryDisable {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let path = telemetry::disable().await?;
+        eprintln!(
+            "{check} Disabled telemetry on this machine; `{riff_telemetry_enable}` or deleting `{path}` enables it again",
+            check = "✓".green(),
+            riff_telemetry_enable = "riff telemetry enable".cyan(),
+            path = path.display(),
+        );
+        Ok(None)
+    }
+}
+
+/// Send telemetry from this machine again, after `riff telemetry disable`
+#[derive(Debug, Args)]
+pub struct TelemetryEnable {}
+
+impl TelemetryEnable {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        if telemetry::enable().await? {
+            eprintln!(
+                "{check} Enabled telemetry on this machine",
+                check = "✓".green()
+            );
+        } else {
+            eprintln!("Telemetry was not disabled on this machine");
+        }
+        if telemetry::disabled_by_env() {
+            eprintln!(
+                "{warning} `{var}` still disables it",
+                warning = "⚠️".yellow(),
+                var = "RIFF_DISABLE_TELEMETRY".cyan(),
+            );
+        }
+        Ok(None)
+    }
+}
+
+/// Print the telemetry riff would send, exactly as it would send it
+///
+/// The telemetry describes this command unless you pass another one after `--`. Commands which
+/// detect a project also send the languages they found, which are left empty here:
+///
+///     $ riff telemetry show -- shell --offline
+#[derive(Debug, Args)]
+pub struct TelemetryShow {
+    /// The arguments of the riff command to show the telemetry of
+    #[clap(last = true)]
+    args: Vec<String>,
+}
+
+impl TelemetryShow {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let telemetry = if self.args.is_empty() {
+            telemetry::Telemetry::new().await
+        } else {
+            let matches = Cli::command()
+                .try_get_matches_from(std::iter::once("riff".to_string()).chain(self.args))?;
+            telemetry::Telemetry::from_clap_parse_result(Some(&matches)).await
+        };
+        println!("{}", telemetry.as_header_data()?);
+        if telemetry::disabled() {
+            eprintln!("Telemetry is disabled, so riff sends none of this");
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,148 @@
+//! The `try` subcommand.
+
+use std::path::Path;
+
+use clap::Args;
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use tempfile::TempDir;
+
+use crate::dependency_registry::DependencyRegistry;
+use crate::dev_env::DevEnvironment;
+use crate::flake_generator::{self, DetectOptions};
+use crate::nix_expr;
+
+/// Try a crate or a nixpkgs package without a project
+///
+/// Start a shell in a new Cargo project which depends on a crate, with the native dependencies
+/// the crate needs. The project is deleted when the shell exits:
+///
+///     $ riff try rusqlite@0.28
+///
+/// Start a shell with a package from nixpkgs instead:
+///
+///     $ riff try --nixpkgs ffmpeg
+#[derive(Debug, Args)]
+pub struct Try {
+    /// The crate to depend on, optionally with a version requirement like `openssl@0.10`
+    package: String,
+    /// Treat the package as a nixpkgs attribute, like `sqlite` or `python3Packages.numpy`
+    #[clap(long)]
+    nixpkgs: bool,
+    /// Keep the generated project instead of deleting it when the shell exits
+    #[clap(long, conflicts_with = "nixpkgs")]
+    keep: bool,
+    /// Run this command instead of a shell
+    #[clap(last = true)]
+    command: Vec<String>,
+    #[clap(from_global)]
+    disable_telemetry: bool,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(from_global)]
+    registry_url: Option<String>,
+}
+
+impl Try {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let registry = DependencyRegistry::new(self.offline, self.registry_url.as_deref()).await?;
+        let project = TempDir::new()?;
+
+        let dev_env = if self.nixpkgs {
+            nix_expr::check_attr_path(&self.package).map_err(|err| eyre!(err))?;
+            let mut dev_env = DevEnvironment::new(&registry);
+            dev_env.build_inputs.insert(self.package.clone());
+            dev_env
+        } else {
+            write_crate_project(project.path(), &self.package).await?;
+            flake_generator::detect_dev_env(
+                project.path(),
+                &registry,
+                self.disable_telemetry,
+                DetectOptions::default(),
+            )
+            .await?
+        };
+
+        let flake = flake_generator::write_locked_flake(&dev_env).await?;
+        let nix_dev_env = crate::nix_dev_env::get_nix_dev_env(&flake).await?;
+
+        let command_name = match self.command.first() {
+            Some(command_name) => command_name.clone(),
+            None => crate::nix_dev_env::get_shell().await?,
+        };
+        let mut command =
+            crate::nix_dev_env::run_in_dev_env(&nix_dev_env, &command_name, false).await?;
+        if !self.command.is_empty() {
+            command.args(&self.command[1..]);
+        }
+        if !self.nixpkgs {
+            command.current_dir(project.path());
+        }
+
+        let status = command
+            .spawn()
+            .wrap_err(format!("Cannot run `{}`", command_name))?
+            .wait_with_output()
+            .await?
+            .status;
+
+        if self.keep {
+            let kept = project.into_path();
+            eprintln!(
+                "{check} Kept the project in {kept}",
+                check = "✓".green(),
+                kept = kept.display().to_string().cyan()
+            );
+        }
+
+        Ok(status.code())
+    }
+}
+
+/// Write a binary crate to `project_dir` which depends on `package`, given as `name[@version]`
+async fn write_crate_project(project_dir: &Path, package: &str) -> color_eyre::Result<()> {
+    let (name, version) = package.split_once('@').unwrap_or((package, "*"));
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(eyre!("`{name}` is not a valid crate name"));
+    }
+
+    let mut manifest = toml_edit::Document::new();
+    manifest["package"] = toml_edit::table();
+    manifest["package"]["name"] = toml_edit::value(format!("try-{name}"));
+    manifest["package"]["version"] = toml_edit::value("0.1.0");
+    manifest["package"]["edition"] = toml_edit::value("2021");
+    manifest["dependencies"] = toml_edit::table();
+    manifest["dependencies"][name] = toml_edit::value(version);
+
+    tokio::fs::create_dir(project_dir.join("src")).await?;
+    tokio::fs::write(project_dir.join("src/main.rs"), "fn main() {}\n").await?;
+    tokio::fs::write(project_dir.join("Cargo.toml"), manifest.to_string())
+        .await
+        .wrap_err("Unable to write Cargo.toml")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn crate_project() -> eyre::Result<()> {
+        let project_dir = TempDir::new()?;
+        write_crate_project(project_dir.path(), "openssl@0.10").await?;
+        let manifest = tokio::fs::read_to_string(project_dir.path().join("Cargo.toml")).await?;
+        assert!(manifest.contains(r#"name = "try-openssl""#));
+        assert!(manifest.contains(r#"openssl = "0.10""#));
+        assert!(project_dir.path().join("src/main.rs").is_file());
+
+        assert!(write_crate_project(TempDir::new()?.path(), "bad\"name")
+            .await
+            .is_err());
+        Ok(())
+    }
+}
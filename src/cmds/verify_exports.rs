@@ -0,0 +1,148 @@
+//! The `verify-exports` subcommand.
+
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Command};
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+
+use crate::dependency_registry::DependencyRegistry;
+use crate::exporter::{ExportContext, ExportFile, EXPORTERS};
+use crate::flake_generator::{self, DetectArgs, DetectOptions};
+
+/// Check that every export of your project is the same each time it's made
+///
+/// This detects the environment twice, exports it in every format with their default options
+/// from each, and compares the files byte for byte. It also checks they don't contain the path
+/// of the project, which differs between machines. Exports which pass can be committed, and
+/// only change when the environment does.
+///
+/// `riff export cargo-config` and `riff export github-actions` are left out, as their settings are
+/// specific to this machine.
+#[derive(Debug, Args)]
+pub struct VerifyExports {
+    /// The root directory of the project
+    #[clap(long, value_parser = crate::wsl::parse_path)]
+    project_dir: Option<PathBuf>,
+    #[clap(from_global)]
+    offline: bool,
+    #[clap(flatten)]
+    detect: DetectArgs,
+}
+
+impl VerifyExports {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = match self.project_dir {
+            Some(dir) => dir,
+            None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
+        };
+        let registry = DependencyRegistry::for_project(
+            self.offline,
+            self.detect.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let options = DetectOptions {
+            ignore_existing_nix: true,
+            ..DetectOptions::from(&self.detect)
+        };
+        // Separate detections, whose sets of inputs iterate in different orders
+        let first = flake_generator::redetect_dev_env(&project_dir, &registry, &options).await?;
+        let second = flake_generator::redetect_dev_env(&project_dir, &registry, &options).await?;
+
+        let mut failures = 0;
+        for exporter in EXPORTERS.iter().filter(|exporter| exporter.standalone()) {
+            let matches = exporter
+                .augment_args(Command::new(exporter.name()))
+                .try_get_matches_from([exporter.name()])?;
+            let context = ExportContext {
+                project_dir: &project_dir,
+                matches: &matches,
+            };
+            let first_files = exporter.render(&first, &context).await?;
+            let second_files = exporter.render(&second, &context).await?;
+            let problems = problems(&first_files, &second_files, &project_dir);
+            if problems.is_empty() {
+                eprintln!(
+                    "{check} {name} is reproducible",
+                    check = "✓".green(),
+                    name = exporter.name().cyan()
+                );
+            } else {
+                failures += 1;
+                eprintln!(
+                    "{cross} {name} is not reproducible:",
+                    cross = "✗".red(),
+                    name = exporter.name().cyan()
+                );
+                for problem in problems {
+                    eprintln!("  {problem}");
+                }
+            }
+        }
+        Ok((failures > 0).then_some(1))
+    }
+}
+
+/// What keeps the files of two exports of the same environment from being reproducible
+fn problems(first: &[ExportFile], second: &[ExportFile], project_dir: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+    let names = |files: &[ExportFile]| files.iter().map(|file| file.name).collect::<Vec<_>>();
+    if names(first) != names(second) {
+        problems.push(format!(
+            "The files differ: {:?} and then {:?}",
+            names(first),
+            names(second)
+        ));
+        return problems;
+    }
+    for (first, second) in first.iter().zip(second) {
+        if let Some((line, (a, b))) = first
+            .contents
+            .lines()
+            .zip(second.contents.lines())
+            .enumerate()
+            .find(|(_, (a, b))| a != b)
+        {
+            problems.push(format!(
+                "`{name}` differs at line {number}:\n    {a}\n    {b}",
+                name = first.name,
+                number = line + 1,
+            ));
+        } else if first.contents != second.contents {
+            problems.push(format!("`{}` differs at its end", first.name));
+        }
+        let project_path = project_dir.display().to_string();
+        if first.contents.contains(&project_path) {
+            problems.push(format!(
+                "`{}` contains the path of the project, `{project_path}`",
+                first.name
+            ));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_problems() {
+        let files = |contents: &str| vec![ExportFile::new("flake.nix", contents.to_string())];
+        let project_dir = Path::new("/home/riff/project");
+
+        assert!(problems(&files("a\nb\n"), &files("a\nb\n"), project_dir).is_empty());
+        assert_eq!(
+            problems(&files("a\nb\n"), &files("a\nc\n"), project_dir),
+            vec!["`flake.nix` differs at line 2:\n    b\n    c"]
+        );
+        assert_eq!(
+            problems(&files("a\n"), &files("a\nb\n"), project_dir),
+            vec!["`flake.nix` differs at its end"]
+        );
+        let with_path = files("path = /home/riff/project/shell.nix;\n");
+        assert_eq!(problems(&with_path, &with_path, project_dir).len(), 1);
+        assert_eq!(problems(&files(""), &[], project_dir).len(), 1);
+    }
+}
@@ -0,0 +1,286 @@
+//! The `watch` subcommand.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::Args;
+use eyre::WrapErr;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use owo_colors::OwoColorize;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use super::shell::Shell;
+use crate::dependency_registry::DependencyRegistry;
+use crate::dev_env::DevEnvironment;
+use crate::flake_generator::{self, DetectOptions};
+use crate::project_fingerprint;
+
+/// How long to wait for a burst of file changes (eg `cargo add`) to settle before detecting again
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Watch the project and show how its environment changes
+///
+/// Whenever a file riff reads changes, like `Cargo.toml` or `Cargo.lock`, riff detects the
+/// environment again and prints the inputs and variables which were added or removed. With
+/// `--shell`, it also runs `riff shell`, which it restarts in the new environment after every
+/// change; `--shell` can name the shell to start, `--command` runs a command in it until the
+/// command exits, and the other options are those of `riff shell`:
+///
+///     $ riff watch --shell
+///     $ riff watch --shell fish
+#[derive(Debug, Args)]
+pub struct Watch {
+    #[clap(flatten)]
+    shell_args: Shell,
+}
+
+impl Watch {
+    pub async fn cmd(self) -> color_eyre::Result<Option<i32>> {
+        let project_dir = self.shell_args.project_dir()?;
+        let options = self.shell_args.detect_options();
+        let registry = DependencyRegistry::for_project(
+            self.shell_args.offline(),
+            options.registry_url.as_deref(),
+            &project_dir,
+        )
+        .await?;
+        let detected = Snapshot::of(
+            &flake_generator::redetect_dev_env(&project_dir, &registry, &options).await?,
+        );
+        let (_watcher, changed) = watch_project(&project_dir)?;
+
+        if !self.shell_args.shell_given() {
+            eprintln!(
+                "Watching `{}` for changes, press Ctrl-C to stop",
+                project_dir.display().to_string().green()
+            );
+            follow(project_dir, registry, options, detected, changed, None).await;
+            return Ok(None);
+        }
+
+        let (restart, restarts) = mpsc::unbounded_channel();
+        tokio::spawn(follow(
+            project_dir,
+            registry,
+            options,
+            detected,
+            changed,
+            Some(restart),
+        ));
+        self.shell_args.run(Some(restarts)).await
+    }
+}
+
+/// Watch the files riff reads in `project_dir`, receiving whenever one of them changes
+fn watch_project(
+    project_dir: &Path,
+) -> color_eyre::Result<(RecommendedWatcher, UnboundedReceiver<()>)> {
+    let (changed, changed_rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let relevant = match event {
+            Ok(event) => event.paths.iter().any(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map(project_fingerprint::reads)
+                    .unwrap_or(false)
+            }),
+            Err(_) => false,
+        };
+        if relevant {
+            let _ = changed.send(());
+        }
+    })?;
+    watcher
+        .watch(project_dir, RecursiveMode::NonRecursive)
+        .wrap_err_with(|| format!("Could not watch `{}`", project_dir.display()))?;
+    Ok((watcher, changed_rx))
+}
+
+/// Detect the environment again after every change, print how it changed, and send `restart`
+/// if it did
+async fn follow(
+    project_dir: PathBuf,
+    registry: DependencyRegistry,
+    options: DetectOptions,
+    mut detected: Snapshot,
+    mut changed: UnboundedReceiver<()>,
+    restart: Option<UnboundedSender<()>>,
+) {
+    while changed.recv().await.is_some() {
+        tokio::time::sleep(WATCH_DEBOUNCE).await;
+        while changed.try_recv().is_ok() {}
+
+        let dev_env =
+            match flake_generator::redetect_dev_env(&project_dir, &registry, &options).await {
+                Ok(dev_env) => dev_env,
+                // Likely a file which is still being edited, so wait for the next change
+                Err(err) => {
+                    eprintln!(
+                    "{warning} Could not detect the environment, keeping the previous one: {err:#}",
+                    warning = "⚠️".yellow(),
+                );
+                    continue;
+                }
+            };
+        let snapshot = Snapshot::of(&dev_env);
+        let changes = detected.diff(&snapshot);
+        if changes.is_empty() {
+            eprintln!("{check} The environment didn't change", check = "✓".green());
+            continue;
+        }
+        for change in &changes {
+            eprintln!("{change}");
+        }
+        detected = snapshot;
+        if let Some(restart) = &restart {
+            eprintln!("Restarting the riff shell...");
+            let _ = restart.send(());
+        }
+    }
+}
+
+/// What an environment provides, to compare it before and after a change
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Snapshot {
+    build_inputs: BTreeSet<String>,
+    runtime_inputs: BTreeSet<String>,
+    environment_variables: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    fn of(dev_env: &DevEnvironment<'_>) -> Self {
+        Self {
+            build_inputs: dev_env.build_inputs.iter().cloned().collect(),
+            runtime_inputs: dev_env.runtime_inputs.iter().cloned().collect(),
+            environment_variables: dev_env
+                .environment_variables
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// What changes when `self` is replaced by `updated`
+    fn diff(&self, updated: &Self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for (kind, old, new) in [
+            ("build", &self.build_inputs, &updated.build_inputs),
+            ("runtime", &self.runtime_inputs, &updated.runtime_inputs),
+        ] {
+            changes.extend(old.difference(new).map(|input| Change::Removed {
+                kind,
+                input: input.clone(),
+            }));
+            changes.extend(new.difference(old).map(|input| Change::Added {
+                kind,
+                input: input.clone(),
+            }));
+        }
+        let names = self
+            .environment_variables
+            .keys()
+            .chain(updated.environment_variables.keys())
+            .collect::<BTreeSet<_>>();
+        for name in names {
+            let from = self.environment_variables.get(name);
+            let to = updated.environment_variables.get(name);
+            if from != to {
+                changes.push(Change::Variable {
+                    name: name.clone(),
+                    from: from.cloned(),
+                    to: to.cloned(),
+                });
+            }
+        }
+        changes
+    }
+}
+
+/// One difference between the environment before and after a change
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Change {
+    Added {
+        kind: &'static str,
+        input: String,
+    },
+    Removed {
+        kind: &'static str,
+        input: String,
+    },
+    /// A variable which was added, changed or removed
+    Variable {
+        name: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added { kind, input } => write!(f, "{} {input} ({kind} input)", "+".green()),
+            Change::Removed { kind, input } => write!(f, "{} {input} ({kind} input)", "-".red()),
+            Change::Variable {
+                name,
+                from: None,
+                to: Some(to),
+            } => write!(f, "{} {name}={to}", "+".green()),
+            Change::Variable {
+                name, to: Some(to), ..
+            } => write!(f, "{} {name}={to}", "~".yellow()),
+            Change::Variable { name, to: None, .. } => write!(f, "{} {name}", "-".red()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_diff() {
+        let before = Snapshot {
+            build_inputs: ["openssl".to_string(), "pkg-config".to_string()].into(),
+            environment_variables: [("HI".to_string(), "BYE".to_string())].into(),
+            ..Default::default()
+        };
+        assert!(before.diff(&before).is_empty());
+
+        let after = Snapshot {
+            build_inputs: ["pkg-config".to_string(), "sqlite".to_string()].into(),
+            runtime_inputs: ["libGL".to_string()].into(),
+            environment_variables: [("HI".to_string(), "HELLO".to_string())].into(),
+        };
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                Change::Removed {
+                    kind: "build",
+                    input: "openssl".to_string()
+                },
+                Change::Added {
+                    kind: "build",
+                    input: "sqlite".to_string()
+                },
+                Change::Added {
+                    kind: "runtime",
+                    input: "libGL".to_string()
+                },
+                Change::Variable {
+                    name: "HI".to_string(),
+                    from: Some("BYE".to_string()),
+                    to: Some("HELLO".to_string())
+                },
+            ]
+        );
+        assert_eq!(
+            after.diff(&Snapshot::default()).last(),
+            Some(&Change::Variable {
+                name: "HI".to_string(),
+                from: Some("HELLO".to_string()),
+                to: None
+            })
+        );
+    }
+}
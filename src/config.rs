@@ -0,0 +1,541 @@
+//! riff's settings, layered from configuration files, environment variables and flags.
+//!
+//! From lowest to highest precedence, a setting comes from:
+//!
+//! 1. riff's default
+//! 2. the system's `$XDG_CONFIG_DIRS/riff/config.toml`, like `/etc/xdg/riff/config.toml`
+//! 3. the user's `$XDG_CONFIG_HOME/riff/config.toml`
+//! 4. the project's `riff.toml` or `.riff/config.toml`
+//! 5. the file passed to `--config`
+//! 6. an environment variable, like `RIFF_OFFLINE`
+//! 7. a flag, like `--offline`
+//!
+//! Every file takes the settings of `riff.toml`, and the global options which have an environment
+//! variable by their flag name, like `offline = true`. Lists and tables are merged, so a project
+//! adds to the `build-inputs` of the user's configuration, and other settings are replaced.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, CommandFactory, ValueSource};
+use eyre::{eyre, WrapErr};
+use once_cell::sync::OnceCell;
+use toml::value::{Table, Value};
+use xdg::BaseDirectories;
+
+use crate::project_config::{ProjectConfig, CONFIG_FILES};
+use crate::{Cli, RIFF_XDG_PREFIX};
+
+/// The name of the system's and the user's configuration files in their `riff` directories
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Settings of `riff.toml` which are also global options, which `riff.toml`'s handling applies
+const PROJECT_OPTIONS: [&str; 2] = ["registry-url", "disable-telemetry"];
+
+static FILE: OnceCell<Option<PathBuf>> = OnceCell::new();
+/// The environment variables riff set from configuration files, and the file each came from
+static EXPORTED: OnceCell<BTreeMap<String, Origin>> = OnceCell::new();
+
+/// Read the file from `--config` too, for the rest of the run
+pub fn set_file(file: Option<PathBuf>) {
+    FILE.set(file).ok();
+}
+
+/// Where a setting came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    System(PathBuf),
+    User(PathBuf),
+    Project(PathBuf),
+    /// The file passed to `--config`
+    File(PathBuf),
+    Env(String),
+    Flag(String),
+}
+
+impl Origin {
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Origin::System(path)
+            | Origin::User(path)
+            | Origin::Project(path)
+            | Origin::File(path) => Some(path),
+            Origin::Default | Origin::Env(_) | Origin::Flag(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::System(path) => write!(f, "system config `{}`", path.display()),
+            Origin::User(path) => write!(f, "user config `{}`", path.display()),
+            Origin::Project(path) => write!(f, "project config `{}`", path.display()),
+            Origin::File(path) => write!(f, "`--config {}`", path.display()),
+            Origin::Env(var) => write!(f, "environment variable `{var}`"),
+            Origin::Flag(flag) => write!(f, "flag `{flag}`"),
+        }
+    }
+}
+
+/// The settings of one configuration file
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub origin: Origin,
+    pub table: Table,
+}
+
+/// The settings in `table` which `riff.toml` takes, leaving out the global options
+pub fn project_settings(table: &Table) -> Table {
+    let options = global_options();
+    table
+        .iter()
+        .filter(|(key, _)| {
+            PROJECT_OPTIONS.contains(&key.as_str())
+                || !options.iter().any(|option| &option.name == *key)
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// The configuration files which apply to a project, lowest precedence first
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub layers: Vec<Layer>,
+}
+
+impl Config {
+    /// Read the configuration files which apply to the project in `project_dir`
+    pub async fn load(project_dir: &Path) -> color_eyre::Result<Self> {
+        let mut origins = Vec::new();
+        if let Ok(xdg_dirs) = BaseDirectories::with_prefix(RIFF_XDG_PREFIX) {
+            // `$XDG_CONFIG_DIRS` is in order of preference, so the first file found is used
+            if let Some(system) = xdg_dirs
+                .get_config_dirs()
+                .into_iter()
+                .map(|dir| dir.join(CONFIG_FILE))
+                .find(|path| path.is_file())
+            {
+                origins.push(Origin::System(system));
+            }
+            origins.push(Origin::User(xdg_dirs.get_config_home().join(CONFIG_FILE)));
+        }
+        if let Some(project) = CONFIG_FILES
+            .iter()
+            .map(|file| project_dir.join(file))
+            .find(|path| path.is_file())
+        {
+            origins.push(Origin::Project(project));
+        }
+
+        let mut layers = Vec::new();
+        for origin in origins {
+            let path = origin.path().expect("Configuration files have a path");
+            if path.is_file() {
+                layers.push(read_layer(origin.clone()).await?);
+            }
+        }
+        // Unlike the others, the file the user asked for has to exist
+        if let Some(Some(file)) = FILE.get() {
+            layers.push(read_layer(Origin::File(file.clone())).await?);
+        }
+        Ok(Self { layers })
+    }
+
+    /// The settings of all the files, with those of later files taking precedence
+    pub fn merged(&self) -> Table {
+        let mut merged = Table::new();
+        for layer in &self.layers {
+            merge(&mut merged, &layer.table);
+        }
+        merged
+    }
+
+    /// The files each setting came from, which are several for merged lists and tables
+    pub fn origins(&self) -> BTreeMap<String, Vec<Origin>> {
+        let mut origins = BTreeMap::<String, Vec<Origin>>::new();
+        for layer in &self.layers {
+            for (key, value) in &layer.table {
+                let key_origins = origins.entry(key.clone()).or_default();
+                if !matches!(value, Value::Array(_) | Value::Table(_)) {
+                    key_origins.clear();
+                }
+                key_origins.push(layer.origin.clone());
+            }
+        }
+        origins
+    }
+}
+
+async fn read_layer(origin: Origin) -> color_eyre::Result<Layer> {
+    let path = origin.path().expect("Configuration files have a path");
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+    let table = contents
+        .parse::<Table>()
+        .wrap_err_with(|| format!("Could not parse `{}`", path.display()))?;
+    tracing::debug!(config = %path.display(), "Read configuration");
+    Ok(Layer { origin, table })
+}
+
+/// Merge `layer` into `into`, adding to lists and tables and replacing anything else
+fn merge(into: &mut Table, layer: &Table) {
+    for (key, value) in layer {
+        match (into.get_mut(key), value) {
+            (Some(Value::Array(existing)), Value::Array(values)) => {
+                for value in values {
+                    if !existing.contains(value) {
+                        existing.push(value.clone());
+                    }
+                }
+            }
+            (Some(Value::Table(existing)), Value::Table(values)) => merge(existing, values),
+            _ => {
+                into.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// A global option which configuration files can set
+#[derive(Debug, Clone)]
+pub struct GlobalOption {
+    /// The option's flag name, which is also its name in configuration files
+    pub name: String,
+    /// The id of the option in clap's matches
+    id: String,
+    env: String,
+    takes_value: bool,
+}
+
+/// The global options with an environment variable, except `--config` itself
+pub fn global_options() -> &'static [GlobalOption] {
+    static OPTIONS: OnceCell<Vec<GlobalOption>> = OnceCell::new();
+    OPTIONS.get_or_init(|| {
+        Cli::command()
+            .get_arguments()
+            .filter(|arg| arg.is_global_set())
+            .filter_map(|arg| {
+                Some(GlobalOption {
+                    name: arg.get_long()?.to_string(),
+                    id: arg.get_id().to_string(),
+                    env: arg.get_env()?.to_str()?.to_string(),
+                    takes_value: arg.is_takes_value_set(),
+                })
+            })
+            .filter(|option| option.name != "config")
+            .collect()
+    })
+}
+
+/// The global option `name`, if it is one
+pub fn global_option(name: &str) -> Option<&'static GlobalOption> {
+    global_options().iter().find(|option| option.name == name)
+}
+
+/// The user's configuration file, which `riff config set` writes
+pub fn user_file() -> color_eyre::Result<PathBuf> {
+    let xdg_dirs = BaseDirectories::with_prefix(RIFF_XDG_PREFIX)?;
+    Ok(xdg_dirs.get_config_home().join(CONFIG_FILE))
+}
+
+/// Set `name` to `value` in the user's configuration file, returning the file
+///
+/// `value` is checked against what the setting takes, and the rest of the file is kept as it was,
+/// comments included.
+pub async fn set_user_setting(name: &str, value: &str) -> color_eyre::Result<PathBuf> {
+    let path = user_file()?;
+    let mut document = read_document(&path).await?;
+    document[name] = toml_edit::Item::Value(parse_setting(name, value)?);
+    check_document(&document).wrap_err_with(|| format!("`{value}` is not a valid `{name}`"))?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .wrap_err_with(|| format!("Could not create `{}`", parent.display()))?;
+    }
+    tokio::fs::write(&path, document.to_string())
+        .await
+        .wrap_err_with(|| format!("Could not write `{}`", path.display()))?;
+    Ok(path)
+}
+
+/// Remove `name` from the user's configuration file, returning whether it was set there
+pub async fn unset_user_setting(name: &str) -> color_eyre::Result<bool> {
+    let path = user_file()?;
+    let mut document = read_document(&path).await?;
+    if document.remove(name).is_none() {
+        return Ok(false);
+    }
+    tokio::fs::write(&path, document.to_string())
+        .await
+        .wrap_err_with(|| format!("Could not write `{}`", path.display()))?;
+    Ok(true)
+}
+
+/// The configuration file at `path`, or an empty one if there isn't one
+async fn read_document(path: &Path) -> color_eyre::Result<toml_edit::Document> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("Could not read `{}`", path.display()))
+        }
+    };
+    contents
+        .parse()
+        .wrap_err_with(|| format!("Could not parse `{}`", path.display()))
+}
+
+/// `value` as the TOML value of the setting `name`
+///
+/// Global options are checked the way clap checks their flags. Anything else is read as TOML,
+/// like `["protobuf"]`, or taken as a string if it isn't TOML.
+fn parse_setting(name: &str, value: &str) -> color_eyre::Result<toml_edit::Value> {
+    match global_option(name) {
+        Some(option) if !option.takes_value => match value {
+            "true" => Ok(true.into()),
+            "false" => Ok(false.into()),
+            _ => Err(eyre!("`{name}` is `true` or `false`, not `{value}`")),
+        },
+        Some(_) => {
+            Cli::command().try_get_matches_from([
+                "riff",
+                "config",
+                "show",
+                &format!("--{name}={value}"),
+            ])?;
+            Ok(match value.parse::<i64>() {
+                Ok(number) => number.into(),
+                Err(_) => value.into(),
+            })
+        }
+        None => Ok(format!("value = {value}")
+            .parse::<toml_edit::Document>()
+            .ok()
+            .and_then(|document| document.get("value")?.as_value().cloned())
+            .unwrap_or_else(|| value.into())),
+    }
+}
+
+/// Check the settings of a configuration file, which can't have settings riff doesn't know
+fn check_document(document: &toml_edit::Document) -> color_eyre::Result<()> {
+    let table = document.to_string().parse::<Table>()?;
+    ProjectConfig::from_table(project_settings(&table))?;
+    Ok(())
+}
+
+/// Set the environment variables of the global options the configuration files of the current
+/// directory set, unless they are already set, returning whether any were
+///
+/// clap then reads them like any other variable, so variables and flags take precedence over the
+/// files.
+pub async fn export_global_options() -> color_eyre::Result<bool> {
+    let project_dir = std::env::current_dir().wrap_err("Current working directory was invalid")?;
+    let config = Config::load(&project_dir).await?;
+    let merged = config.merged();
+    let origins = config.origins();
+    let mut exported = BTreeMap::new();
+    for option in global_options() {
+        if PROJECT_OPTIONS.contains(&option.name.as_str()) {
+            continue;
+        }
+        let value = match merged.get(&option.name) {
+            Some(value) => value,
+            None => continue,
+        };
+        if std::env::var_os(&option.env).is_some() {
+            continue;
+        }
+        let origin = origins[&option.name]
+            .last()
+            .cloned()
+            .unwrap_or(Origin::Default);
+        let value = match value {
+            Value::String(value) => value.clone(),
+            Value::Integer(value) => value.to_string(),
+            Value::Boolean(value) => value.to_string(),
+            _ => {
+                return Err(eyre!(
+                    "`{}` in {origin} is not a string, number or boolean",
+                    option.name
+                ))
+            }
+        };
+        std::env::set_var(&option.env, value);
+        exported.insert(option.env.clone(), origin);
+    }
+    let any = !exported.is_empty();
+    EXPORTED.set(exported).ok();
+    Ok(any)
+}
+
+/// A setting in effect, and where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub value: Value,
+    pub origins: Vec<Origin>,
+}
+
+/// Every setting in effect for the project in `project_dir`, given the global options in `matches`
+pub async fn settings(
+    project_dir: &Path,
+    matches: &ArgMatches,
+) -> color_eyre::Result<BTreeMap<String, Setting>> {
+    let config = Config::load(project_dir).await?;
+    let merged = config.merged();
+    let origins = config.origins();
+    let exported = EXPORTED.get();
+
+    let mut settings = merged
+        .into_iter()
+        .map(|(key, value)| {
+            let origins = origins.get(&key).cloned().unwrap_or_default();
+            (key, Setting { value, origins })
+        })
+        .collect::<BTreeMap<_, _>>();
+    for option in global_options() {
+        let origin = match matches.value_source(option.id.as_str()) {
+            Some(ValueSource::CommandLine) => Origin::Flag(format!("--{}", option.name)),
+            Some(ValueSource::EnvVariable) => {
+                match exported.and_then(|exported| exported.get(&option.env)) {
+                    // Already in `settings` from its file
+                    Some(_) => continue,
+                    None => Origin::Env(option.env.clone()),
+                }
+            }
+            // Settings of `riff.toml` aren't exported, so they're only in `settings`
+            _ if settings.contains_key(&option.name) => continue,
+            _ => Origin::Default,
+        };
+        let value = match option.takes_value {
+            true => matches
+                .get_raw(&option.id)
+                .map(|values| {
+                    values
+                        .map(|value| value.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .map(|value| match value.parse::<i64>() {
+                    Ok(number) => Value::Integer(number),
+                    Err(_) => Value::String(value),
+                }),
+            false => Some(Value::Boolean(
+                matches.value_source(option.id.as_str()).is_some(),
+            )),
+        };
+        if let Some(value) = value {
+            settings.insert(
+                option.name.clone(),
+                Setting {
+                    value,
+                    origins: vec![origin],
+                },
+            );
+        }
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(origin: Origin, contents: &str) -> Layer {
+        Layer {
+            origin,
+            table: contents.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn layering() {
+        let user = Origin::User(PathBuf::from("config.toml"));
+        let project = Origin::Project(PathBuf::from("riff.toml"));
+        let config = Config {
+            layers: vec![
+                layer(
+                    user.clone(),
+                    "offline = true\nbuild-inputs = [\"cmake\"]\n[environment-variables]\nA = \"1\"\nB = \"1\"",
+                ),
+                layer(
+                    project.clone(),
+                    "offline = false\nbuild-inputs = [\"protobuf\", \"cmake\"]\n[environment-variables]\nB = \"2\"",
+                ),
+            ],
+        };
+        let merged = config.merged();
+        assert_eq!(merged["offline"], Value::Boolean(false));
+        assert_eq!(
+            merged["build-inputs"],
+            Value::Array(vec!["cmake".into(), "protobuf".into()])
+        );
+        assert_eq!(
+            merged["environment-variables"].to_string(),
+            "{ A = \"1\", B = \"2\" }"
+        );
+
+        let origins = config.origins();
+        assert_eq!(origins["offline"], vec![project.clone()]);
+        assert_eq!(origins["build-inputs"], vec![user, project.clone()]);
+
+        let settings = project_settings(&merged);
+        assert!(settings.get("offline").is_none());
+        assert!(settings.get("build-inputs").is_some());
+    }
+
+    #[test]
+    fn options() {
+        let options = global_options();
+        let offline = options
+            .iter()
+            .find(|option| option.name == "offline")
+            .unwrap();
+        assert_eq!(offline.env, "RIFF_OFFLINE");
+        assert!(!offline.takes_value);
+        assert!(options.iter().all(|option| option.name != "config"));
+        assert!(options.iter().any(|option| option.name == "spinner-delay"));
+    }
+
+    #[test]
+    fn parsed_settings() {
+        assert_eq!(
+            parse_setting("offline", "true").unwrap().as_bool(),
+            Some(true)
+        );
+        assert!(parse_setting("offline", "yes").is_err());
+        assert_eq!(
+            parse_setting("spinner-delay", "50").unwrap().as_integer(),
+            Some(50)
+        );
+        assert!(parse_setting("spinner-delay", "soon").is_err());
+        assert_eq!(
+            parse_setting("progress", "json").unwrap().as_str(),
+            Some("json")
+        );
+        assert!(parse_setting("progress", "loud").is_err());
+        assert_eq!(
+            parse_setting("build-inputs", r#"["protobuf"]"#)
+                .unwrap()
+                .as_array()
+                .map(|inputs| inputs.len()),
+            Some(1)
+        );
+        assert_eq!(
+            parse_setting("nixpkgs", "github:NixOS/nixpkgs/nixos-22.11")
+                .unwrap()
+                .as_str(),
+            Some("github:NixOS/nixpkgs/nixos-22.11")
+        );
+
+        let mut document = toml_edit::Document::new();
+        document["build-inputs"] =
+            toml_edit::Item::Value(parse_setting("build-inputs", "[\"protobuf\"]").unwrap());
+        assert!(check_document(&document).is_ok());
+        document["build-input"] = toml_edit::Item::Value("typo".into());
+        assert!(check_document(&document).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Packages which clash when an environment has more than one of them, like two major versions of
+//! PostgreSQL, or the compiler wrappers of GCC and Clang which each set `CC`.
+//!
+//! The registry lists them in its `conflicts` table. Once the environment is detected, riff warns
+//! about each conflict it has, naming what added the packages and how to remove all but one, or
+//! stops if the registry says such an environment can't work.
+
+use eyre::eyre;
+use owo_colors::OwoColorize;
+
+use crate::dependency_registry::{Conflict, ConflictSeverity};
+use crate::dev_env::DevEnvironment;
+
+/// Warn about the conflicting packages in `dev_env`, or fail if any can't be in it together
+pub async fn check(dev_env: &DevEnvironment<'_>) -> color_eyre::Result<()> {
+    let conflicts = dev_env.registry.conflicts().await;
+    let mut errors = Vec::new();
+    for (conflict, packages) in find(&conflicts, dev_env) {
+        let description = describe(conflict, &packages, dev_env);
+        match conflict.severity {
+            ConflictSeverity::Warn => {
+                eprintln!("{warning} {description}", warning = "⚠️".yellow())
+            }
+            ConflictSeverity::Error => errors.push(description),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre!("{}", errors.join("\n")))
+    }
+}
+
+/// The conflicts `dev_env` has, with the packages of each in it
+fn find<'c>(
+    conflicts: &'c [Conflict],
+    dev_env: &DevEnvironment,
+) -> Vec<(&'c Conflict, Vec<&'c str>)> {
+    conflicts
+        .iter()
+        .filter_map(|conflict| {
+            let packages = conflict
+                .packages
+                .iter()
+                .map(String::as_str)
+                .filter(|package| {
+                    dev_env.build_inputs.contains(*package)
+                        || dev_env.runtime_inputs.contains(*package)
+                })
+                .collect::<Vec<_>>();
+            (packages.len() > 1).then_some((conflict, packages))
+        })
+        .collect()
+}
+
+/// What clashes in `dev_env`, where each of `packages` comes from, and how to keep only the last
+fn describe(conflict: &Conflict, packages: &[&str], dev_env: &DevEnvironment) -> String {
+    let origins = packages
+        .iter()
+        .map(|package| {
+            format!(
+                "`{package}` (from {origin})",
+                package = package.cyan(),
+                origin = origin(package, dev_env)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let (keep, remove) = packages
+        .split_last()
+        .expect("Conflicts have several packages");
+    let quoted = |inputs: &dyn Fn(&str) -> bool| {
+        remove
+            .iter()
+            .filter(|package| inputs(package))
+            .map(|package| format!("\"{package}\""))
+            .collect::<Vec<_>>()
+    };
+    let mut settings = Vec::new();
+    for (setting, inputs) in [
+        (
+            "remove-build-inputs",
+            quoted(&|package| dev_env.build_inputs.contains(package)),
+        ),
+        (
+            "remove-runtime-inputs",
+            quoted(&|package| dev_env.runtime_inputs.contains(package)),
+        ),
+    ] {
+        if !inputs.is_empty() {
+            settings.push(format!("    {setting} = [{}]", inputs.join(", ")));
+        }
+    }
+    format!(
+        "The environment has conflicting packages {origins}: {reason}\n  \
+         To keep only `{keep}`, add to `{riff_toml}`:\n{settings}",
+        reason = conflict.reason,
+        keep = keep.cyan(),
+        riff_toml = "riff.toml".cyan(),
+        settings = settings.join("\n"),
+    )
+}
+
+/// The languages whose detection added `package` to `dev_env`, or else the project's settings
+fn origin(package: &str, dev_env: &DevEnvironment) -> String {
+    let languages = dev_env
+        .language_summaries
+        .iter()
+        .filter(|summary| {
+            summary.build_inputs.contains(package) || summary.runtime_inputs.contains(package)
+        })
+        .map(|summary| summary.language)
+        .collect::<Vec<_>>();
+    if languages.is_empty() {
+        "`riff.toml` or the command line".to_string()
+    } else {
+        languages.join(" and ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependency_registry::{DependencyRegistry, DependencyRegistryData};
+    use crate::dev_env::LanguageSummary;
+
+    #[tokio::test]
+    async fn conflicting_packages() -> eyre::Result<()> {
+        let data: DependencyRegistryData = serde_json::from_str(
+            r#"{
+                "version": 1,
+                "language": {},
+                "conflicts": [
+                    { "packages": ["gcc", "clang"], "reason": "they each set `CC`" },
+                    {
+                        "packages": ["postgresql_14", "postgresql_15"],
+                        "reason": "they each put `psql` on the `PATH`",
+                        "severity": "error"
+                    }
+                ]
+            }"#,
+        )?;
+        let registry = DependencyRegistry::from_data(data)?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.extend(
+            ["clang", "openssl", "postgresql_14"]
+                .into_iter()
+                .map(String::from),
+        );
+        dev_env.runtime_inputs.insert("postgresql_15".to_string());
+        dev_env.language_summaries.push(LanguageSummary {
+            language: "rust",
+            build_inputs: ["postgresql_14".to_string()].into_iter().collect(),
+            runtime_inputs: Default::default(),
+            darwin_frameworks: Default::default(),
+            environment_variables: Default::default(),
+            rules: 1,
+        });
+
+        let conflicts = registry.conflicts().await;
+        let found = find(&conflicts, &dev_env);
+        assert_eq!(found.len(), 1);
+        let (conflict, packages) = &found[0];
+        assert_eq!(packages, &["postgresql_14", "postgresql_15"]);
+        let description = describe(conflict, packages, &dev_env);
+        assert!(description.contains("(from rust)"), "{description}");
+        assert!(
+            description.contains("(from `riff.toml` or the command line)"),
+            "{description}"
+        );
+        assert!(
+            description.ends_with("    remove-build-inputs = [\"postgresql_14\"]"),
+            "{description}"
+        );
+        drop(conflicts);
+        assert!(check(&dev_env).await.is_err());
+
+        // Only one of the packages is no conflict
+        dev_env.runtime_inputs.clear();
+        assert!(check(&dev_env).await.is_ok());
+        Ok(())
+    }
+}
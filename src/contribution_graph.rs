@@ -0,0 +1,340 @@
+//! Which crates and rules contribute which inputs to an environment, for `riff graph`,
+//! `riff du` and `riff explain`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::cargo_metadata::CargoMetadata;
+use crate::dependency_registry::rust::{RustDependencyData, RustDependencyRegistryData};
+use crate::dependency_registry::Confidence;
+use crate::sys_heuristic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeKind {
+    Crate,
+    Rule,
+    BuildInput,
+    RuntimeInput,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub name: String,
+}
+
+impl Node {
+    fn new(kind: NodeKind, name: impl Into<String>) -> Self {
+        Self {
+            kind,
+            name: name.into(),
+        }
+    }
+
+    fn id(&self) -> String {
+        format!("{:?}:{}", self.kind, self.name)
+    }
+}
+
+/// A graph of crates, to the rules which apply to them, to the inputs those rules add
+#[derive(Debug, Default)]
+pub struct ContributionGraph {
+    edges: BTreeSet<(Node, Node)>,
+    /// The confidence of each rule, by rule name
+    confidence: BTreeMap<String, Confidence>,
+}
+
+/// Why an input is in the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation<'a> {
+    pub input: &'a Node,
+    pub crate_name: &'a str,
+    pub rule: &'a str,
+    pub confidence: Confidence,
+}
+
+impl ContributionGraph {
+    /// The contributions to a Rust project's environment, as `add_deps_from_cargo_metadata`
+    /// applies them
+    pub fn from_cargo_metadata(
+        metadata: &CargoMetadata,
+        root_manifest_path: Option<&Path>,
+        registry: &RustDependencyRegistryData,
+        sys_heuristics: bool,
+        conservative: bool,
+    ) -> Self {
+        let mut graph = Self::default();
+        let project = metadata
+            .packages
+            .iter()
+            .find(|package| Some(package.manifest_path.as_path()) == root_manifest_path)
+            .map(|package| package.name.as_str())
+            .unwrap_or("workspace");
+
+        let defaults = RustDependencyData {
+            default: registry.default.clone(),
+            ..Default::default()
+        };
+        graph.add_rule(project, "registry defaults", &defaults, Confidence::Curated);
+        if let Some(workspace_config) = metadata.metadata.as_ref().and_then(|m| m.riff.as_ref()) {
+            graph.add_rule(
+                project,
+                "workspace.metadata.riff",
+                workspace_config,
+                Confidence::Exact,
+            );
+        }
+        for package in &metadata.packages {
+            let enabled_features = metadata.enabled_features(&package.id);
+            let package_config = package
+                .metadata
+                .as_ref()
+                .and_then(|m| m.riff.as_ref())
+                .map(|config| config.with_features(enabled_features.as_ref()));
+            let registry_rule = registry
+                .dependencies
+                .get(&package.name)
+                .filter(|dep_config| {
+                    !(conservative && dep_config.confidence == Confidence::Heuristic)
+                });
+            if let Some(dep_config) = registry_rule {
+                graph.add_rule(
+                    &package.name,
+                    &format!("registry: {}", package.name),
+                    &dep_config.with_features(enabled_features.as_ref()),
+                    dep_config.confidence,
+                );
+            } else if sys_heuristics && !conservative && package_config.is_none() {
+                for guess in sys_heuristic::guess(package) {
+                    let mut dep_config = RustDependencyData::default();
+                    dep_config
+                        .default
+                        .build_inputs
+                        .insert(guess.nixpkgs_attribute);
+                    graph.add_rule(
+                        &package.name,
+                        &format!("heuristic: {} ({})", package.name, guess.source),
+                        &dep_config,
+                        Confidence::Heuristic,
+                    );
+                }
+            }
+            if let Some(dep_config) = package_config {
+                graph.add_rule(
+                    &package.name,
+                    &format!("package.metadata.riff: {}", package.name),
+                    &dep_config,
+                    Confidence::Exact,
+                );
+            }
+        }
+        graph
+    }
+
+    fn add_rule(
+        &mut self,
+        crate_name: &str,
+        rule: &str,
+        config: &RustDependencyData,
+        confidence: Confidence,
+    ) {
+        let inputs = config
+            .build_inputs()
+            .into_iter()
+            .map(|input| Node::new(NodeKind::BuildInput, input))
+            .chain(
+                config
+                    .runtime_inputs()
+                    .into_iter()
+                    .map(|input| Node::new(NodeKind::RuntimeInput, input)),
+            )
+            .collect::<Vec<_>>();
+        // Rules which only set variables don't add anything to the closure
+        if inputs.is_empty() {
+            return;
+        }
+        self.confidence.insert(rule.to_string(), confidence);
+        let rule = Node::new(NodeKind::Rule, rule);
+        self.edges
+            .insert((Node::new(NodeKind::Crate, crate_name), rule.clone()));
+        for input in inputs {
+            self.edges.insert((rule.clone(), input));
+        }
+    }
+
+    /// The crates which add each input, by input name
+    pub fn origins(&self) -> BTreeMap<&str, BTreeSet<&str>> {
+        let mut origins = BTreeMap::<&str, BTreeSet<&str>>::new();
+        for (rule, input) in &self.edges {
+            if rule.kind != NodeKind::Rule {
+                continue;
+            }
+            let crates = self
+                .edges
+                .iter()
+                .filter(|(_, to)| to == rule)
+                .map(|(from, _)| from.name.as_str());
+            origins.entry(&input.name).or_default().extend(crates);
+        }
+        origins
+    }
+
+    /// Every crate and rule which adds each input, ordered by input
+    pub fn explain(&self) -> Vec<Explanation<'_>> {
+        let mut explanations = Vec::new();
+        for (rule, input) in &self.edges {
+            if rule.kind != NodeKind::Rule {
+                continue;
+            }
+            for (crate_node, _) in self.edges.iter().filter(|(_, to)| to == rule) {
+                explanations.push(Explanation {
+                    input,
+                    crate_name: &crate_node.name,
+                    rule: &rule.name,
+                    confidence: self.confidence[&rule.name],
+                });
+            }
+        }
+        explanations.sort_by(|a, b| (a.input, b.confidence).cmp(&(b.input, a.confidence)));
+        explanations
+    }
+
+    fn nodes(&self) -> BTreeSet<&Node> {
+        self.edges
+            .iter()
+            .flat_map(|(from, to)| [from, to])
+            .collect()
+    }
+
+    /// Render the graph in Graphviz's `dot` language
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph riff {\n    rankdir=LR;\n");
+        for node in self.nodes() {
+            let shape = match node.kind {
+                NodeKind::Crate => "box",
+                NodeKind::Rule => "note",
+                NodeKind::BuildInput => "ellipse",
+                NodeKind::RuntimeInput => "octagon",
+            };
+            // Guesses are drawn dashed, so they stand out from rules someone wrote down
+            let style = match self.confidence.get(&node.name) {
+                Some(Confidence::Heuristic) if node.kind == NodeKind::Rule => ", style=dashed",
+                _ => "",
+            };
+            let _ = writeln!(
+                dot,
+                "    {:?} [label={:?}, shape={shape}{style}];",
+                node.id(),
+                node.name
+            );
+        }
+        for (from, to) in &self.edges {
+            let _ = writeln!(dot, "    {:?} -> {:?};", from.id(), to.id());
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the graph as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> String {
+        let ids = self
+            .nodes()
+            .into_iter()
+            .enumerate()
+            .map(|(index, node)| (node, format!("n{index}")))
+            .collect::<BTreeMap<_, _>>();
+        let mut mermaid = String::from("flowchart LR\n");
+        for (node, id) in &ids {
+            let label = node.name.replace('"', "#quot;");
+            let _ = match node.kind {
+                NodeKind::Crate => writeln!(mermaid, "    {id}[\"{label}\"]"),
+                NodeKind::Rule => writeln!(mermaid, "    {id}>\"{label}\"]"),
+                NodeKind::BuildInput => writeln!(mermaid, "    {id}([\"{label}\"])"),
+                NodeKind::RuntimeInput => writeln!(mermaid, "    {id}{{{{\"{label}\"}}}}"),
+            };
+        }
+        for (from, to) in &self.edges {
+            let _ = writeln!(mermaid, "    {} --> {}", ids[from], ids[to]);
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_contributions() -> eyre::Result<()> {
+        let metadata: CargoMetadata = serde_json::from_str(
+            r#"{
+                "packages": [
+                    {"name": "app", "manifest_path": "/src/app/Cargo.toml",
+                     "metadata": {"riff": {"runtime-inputs": ["libGL"]}}},
+                    {"name": "libsqlite3-sys"},
+                    {"name": "serde"},
+                    {"name": "zmq-sys", "links": "zmq"}
+                ],
+                "resolve": null
+            }"#,
+        )?;
+        let registry: RustDependencyRegistryData = serde_json::from_str(
+            r#"{
+                "default": {"build-inputs": ["cargo"]},
+                "dependencies": {"libsqlite3-sys": {"build-inputs": ["sqlite"]}}
+            }"#,
+        )?;
+        let graph = ContributionGraph::from_cargo_metadata(
+            &metadata,
+            Some(Path::new("/src/app/Cargo.toml")),
+            &registry,
+            true,
+            false,
+        );
+
+        let dot = graph.to_dot();
+        assert!(dot.contains(r#""Crate:libsqlite3-sys" -> "Rule:registry: libsqlite3-sys";"#));
+        assert!(dot.contains(r#""Rule:registry: libsqlite3-sys" -> "BuildInput:sqlite";"#));
+        assert!(dot.contains(r#""Crate:app" -> "Rule:registry defaults";"#));
+        assert!(dot.contains(r#""Rule:package.metadata.riff: app" -> "RuntimeInput:libGL";"#));
+        assert!(!dot.contains("serde"), "crates without rules are left out");
+        assert!(
+            dot.contains(r#""Rule:heuristic: zmq-sys (links = \"zmq\")" -> "BuildInput:zeromq";"#)
+        );
+
+        assert!(dot.contains(r#"shape=note, style=dashed];"#));
+
+        let explanations = graph.explain();
+        let sqlite = explanations
+            .iter()
+            .find(|explanation| explanation.input.name == "sqlite")
+            .unwrap();
+        assert_eq!(sqlite.crate_name, "libsqlite3-sys");
+        assert_eq!(sqlite.confidence, Confidence::Curated);
+        assert!(explanations
+            .iter()
+            .any(|explanation| explanation.input.name == "zeromq"
+                && explanation.confidence == Confidence::Heuristic));
+
+        let conservative = ContributionGraph::from_cargo_metadata(
+            &metadata,
+            Some(Path::new("/src/app/Cargo.toml")),
+            &registry,
+            true,
+            true,
+        );
+        assert!(!conservative.to_dot().contains("zeromq"));
+
+        let origins = graph.origins();
+        assert_eq!(origins["sqlite"], ["libsqlite3-sys"].into_iter().collect());
+        assert_eq!(origins["cargo"], ["app"].into_iter().collect());
+
+        let mermaid = graph.to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains(r#"(["sqlite"])"#));
+        assert!(mermaid.contains(r#"{{"libGL"}}"#));
+        assert_eq!(mermaid.matches(" --> ").count(), 8);
+        Ok(())
+    }
+}
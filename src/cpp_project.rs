@@ -0,0 +1,144 @@
+//! The dependencies declared by CMake and Meson build files.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use eyre::WrapErr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildSystem {
+    CMake,
+    Meson,
+}
+
+impl BuildSystem {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            BuildSystem::CMake => "CMakeLists.txt",
+            BuildSystem::Meson => "meson.build",
+        }
+    }
+
+    /// The Nixpkgs attributes providing the build system itself
+    pub fn nixpkgs_attributes(&self) -> &'static [&'static str] {
+        match self {
+            BuildSystem::CMake => &["cmake"],
+            BuildSystem::Meson => &["meson", "ninja"],
+        }
+    }
+
+    /// The lowercased names of the packages the build file asks for
+    pub fn dependency_names(&self, contents: &str) -> BTreeSet<String> {
+        match self {
+            BuildSystem::CMake => cmake_dependencies(contents),
+            BuildSystem::Meson => meson_dependencies(contents),
+        }
+    }
+}
+
+/// The build systems used in `project_dir`, with the dependencies their top level files declare
+pub async fn detect(
+    project_dir: &Path,
+) -> color_eyre::Result<Vec<(BuildSystem, BTreeSet<String>)>> {
+    let mut detected = Vec::new();
+    for build_system in [BuildSystem::CMake, BuildSystem::Meson] {
+        let path = project_dir.join(build_system.file_name());
+        if !path.is_file() {
+            continue;
+        }
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        detected.push((build_system, build_system.dependency_names(&contents)));
+    }
+    Ok(detected)
+}
+
+/// Packages from `find_package(Foo ...)` and modules from `pkg_check_modules(PREFIX foo ...)`
+fn cmake_dependencies(contents: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for (command, arguments) in cmake_commands(contents) {
+        let mut arguments = arguments.split_whitespace();
+        match command.as_str() {
+            "find_package" => names.extend(arguments.next().map(str::to_ascii_lowercase)),
+            "pkg_check_modules" | "pkg_search_module" => {
+                // The first argument is the prefix for the resulting variables
+                let modules = arguments
+                    .skip(1)
+                    .filter(|argument| argument.chars().any(|c| c.is_ascii_lowercase()))
+                    .map(|module| module_name(module).to_ascii_lowercase());
+                names.extend(modules);
+            }
+            _ => (),
+        }
+    }
+    names
+}
+
+/// CMake commands (lowercased, as CMake ignores their case) and their raw arguments
+fn cmake_commands(contents: &str) -> impl Iterator<Item = (String, &str)> {
+    contents.split(')').filter_map(|command| {
+        let (name, arguments) = command.split_once('(')?;
+        let name = name.split_whitespace().last()?;
+        Some((name.to_ascii_lowercase(), arguments))
+    })
+}
+
+/// Dependencies from `dependency('foo', ...)`
+fn meson_dependencies(contents: &str) -> BTreeSet<String> {
+    contents
+        .split("dependency(")
+        .skip(1)
+        .filter_map(|call| {
+            let call = call.trim_start();
+            let quote = call.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+            call[1..].split(quote).next()
+        })
+        .map(|name| module_name(name).to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Strip a version constraint such as in `glib-2.0>=2.56`
+fn module_name(module: &str) -> &str {
+    module.split(['>', '<', '=']).next().unwrap_or(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn cmake_lists() {
+        let cmake_lists = r#"
+cmake_minimum_required(VERSION 3.16)
+project(riff-test C CXX)
+
+find_package(OpenSSL REQUIRED)
+FIND_PACKAGE(ZLIB)
+find_package(PkgConfig REQUIRED)
+pkg_check_modules(GLIB REQUIRED IMPORTED_TARGET glib-2.0>=2.56)
+"#;
+        assert_eq!(
+            BuildSystem::CMake.dependency_names(cmake_lists),
+            names(&["glib-2.0", "openssl", "pkgconfig", "zlib"])
+        );
+    }
+
+    #[test]
+    fn meson_build() {
+        let meson_build = r#"
+project('riff-test', 'c')
+deps = [dependency('gtk+-3.0'), dependency( "libcurl", version: '>=7')]
+executable('riff-test', 'main.c', dependencies: deps)
+"#;
+        assert_eq!(
+            BuildSystem::Meson.dependency_names(meson_build),
+            names(&["gtk+-3.0", "libcurl"])
+        );
+    }
+}
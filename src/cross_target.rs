@@ -0,0 +1,121 @@
+//! Cross-compilation toolchains for `--target`.
+
+use eyre::eyre;
+use itertools::Itertools;
+
+use crate::dev_env::DevEnvironment;
+
+/// The targets riff can cross-compile to, with the `pkgsCross` package set building for each
+const CROSS_PACKAGE_SETS: &[(&str, &str)] = &[
+    ("aarch64-unknown-linux-gnu", "aarch64-multiplatform"),
+    ("aarch64-unknown-linux-musl", "aarch64-multiplatform-musl"),
+    ("armv7-unknown-linux-gnueabihf", "armv7l-hf-multiplatform"),
+    ("riscv64gc-unknown-linux-gnu", "riscv64"),
+    ("x86_64-pc-windows-gnu", "mingwW64"),
+    ("x86_64-unknown-linux-gnu", "gnu64"),
+    ("x86_64-unknown-linux-musl", "musl64"),
+];
+
+/// WebAssembly needs no C toolchain, only a standard library and a linker
+const WASM32_UNKNOWN_UNKNOWN: &str = "wasm32-unknown-unknown";
+
+/// The targets `--target` accepts
+pub fn known_targets() -> impl Iterator<Item = &'static str> {
+    CROSS_PACKAGE_SETS
+        .iter()
+        .map(|(target, _)| *target)
+        .chain([WASM32_UNKNOWN_UNKNOWN])
+        .sorted()
+}
+
+/// Set `dev_env` up to cross-compile to `target`
+///
+/// This swaps nixpkgs' `rustc`, which only has the standard library for the host, for one which
+/// also has it for `target`, adds a C toolchain for `target`, and points cargo and the `cc`
+/// crate at it. `CARGO_BUILD_TARGET` makes `cargo build` build for `target` by default.
+pub fn apply(target: &str, dev_env: &mut DevEnvironment<'_>) -> color_eyre::Result<()> {
+    let target_env = target.replace('-', "_");
+    let (rustc, linker) = if target == WASM32_UNKNOWN_UNKNOWN {
+        dev_env.build_inputs.insert("lld".to_string());
+        ("rustc-wasm32".to_string(), "${lld}/bin/wasm-ld".to_string())
+    } else {
+        let package_set = CROSS_PACKAGE_SETS
+            .iter()
+            .find(|(known, _)| *known == target)
+            .map(|(_, package_set)| format!("pkgsCross.{package_set}"))
+            .ok_or_else(|| {
+                eyre!(
+                    "riff can't cross-compile to `{target}`; it knows how to for {}",
+                    known_targets()
+                        .map(|target| format!("`{target}`"))
+                        .join(", ")
+                )
+            })?;
+        let cc = format!("{package_set}.stdenv.cc");
+        dev_env.build_inputs.insert(cc.clone());
+        let cc_path = format!("${{{cc}}}/bin/${{{cc}.targetPrefix}}cc");
+        dev_env
+            .environment_variables
+            .insert(format!("CC_{target_env}"), cc_path.clone());
+        (format!("{package_set}.buildPackages.rustc"), cc_path)
+    };
+
+    if dev_env.build_inputs.remove("rustc") {
+        dev_env.build_inputs.insert(rustc);
+    }
+    dev_env.environment_variables.insert(
+        format!("CARGO_TARGET_{}_LINKER", target_env.to_ascii_uppercase()),
+        linker,
+    );
+    dev_env
+        .environment_variables
+        .insert("CARGO_BUILD_TARGET".to_string(), target.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependency_registry::DependencyRegistry;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn cross_toolchains() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.insert("rustc".to_string());
+
+        let mut aarch64 = dev_env.clone();
+        apply("aarch64-unknown-linux-gnu", &mut aarch64)?;
+        assert!(aarch64
+            .build_inputs
+            .contains("pkgsCross.aarch64-multiplatform.buildPackages.rustc"));
+        assert!(!aarch64.build_inputs.contains("rustc"));
+        assert_eq!(
+            aarch64
+                .environment_variables
+                .get("CARGO_TARGET_AARCH64_UNKNOWN_LINUX_GNU_LINKER")
+                .map(String::as_str),
+            Some(
+                "${pkgsCross.aarch64-multiplatform.stdenv.cc}/bin/\
+                 ${pkgsCross.aarch64-multiplatform.stdenv.cc.targetPrefix}cc"
+            )
+        );
+
+        let mut wasm = dev_env.clone();
+        apply("wasm32-unknown-unknown", &mut wasm)?;
+        assert!(wasm.build_inputs.contains("rustc-wasm32"));
+        assert_eq!(
+            wasm.environment_variables
+                .get("CARGO_BUILD_TARGET")
+                .map(String::as_str),
+            Some("wasm32-unknown-unknown")
+        );
+
+        let err = apply("mips-unknown-linux-gnu", &mut dev_env.clone()).unwrap_err();
+        assert!(err.to_string().contains("`wasm32-unknown-unknown`"));
+        Ok(())
+    }
+}
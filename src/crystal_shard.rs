@@ -0,0 +1,101 @@
+//! The parts of a Crystal project's `shard.yml` riff cares about.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use eyre::WrapErr;
+
+pub const SHARD_YML: &str = "shard.yml";
+
+/// The dependencies a shard declares
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Shard {
+    /// The shards in its `dependencies` and `development_dependencies`
+    pub dependency_names: BTreeSet<String>,
+    /// The shared libraries in its `libraries`, like `libsqlite3`
+    pub libraries: BTreeSet<String>,
+}
+
+impl Shard {
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Self> {
+        let path = project_dir.join(SHARD_YML);
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Read the keys of the sections riff needs, without parsing the rest of the YAML
+    pub fn parse(contents: &str) -> Self {
+        let mut shard = Self::default();
+        let mut section = None;
+        // The indentation of the keys in the current section, taken from its first key
+        let mut key_indent = None;
+        for line in contents.lines() {
+            let code = line.split(" #").next().unwrap_or_default().trim_end();
+            let key = code.trim_start();
+            if key.is_empty() || key.starts_with('#') {
+                continue;
+            }
+            let indent = code.len() - key.len();
+            if indent == 0 {
+                section = key.strip_suffix(':').map(ToString::to_string);
+                key_indent = None;
+                continue;
+            }
+            let names = match section.as_deref() {
+                Some("dependencies" | "development_dependencies") => &mut shard.dependency_names,
+                Some("libraries") => &mut shard.libraries,
+                _ => continue,
+            };
+            if *key_indent.get_or_insert(indent) != indent {
+                continue;
+            }
+            if let Some((name, _)) = key.split_once(':') {
+                names.insert(name.trim().trim_matches(['"', '\'']).to_string());
+            }
+        }
+        shard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shard_yml() {
+        let shard_yml = r#"
+name: riff-test
+version: 0.1.0
+
+dependencies:
+  sqlite3:
+    github: crystal-lang/crystal-sqlite3
+    version: ~> 0.20.0 # Any 0.20 release
+  kemal:
+    github: kemalcr/kemal
+
+development_dependencies:
+  ameba:
+    github: crystal-ameba/ameba
+
+libraries:
+  libsqlite3: ">= 3.35"
+
+targets:
+  riff-test:
+    main: src/riff_test.cr
+"#;
+        assert_eq!(
+            Shard::parse(shard_yml),
+            Shard {
+                dependency_names: ["ameba", "kemal", "sqlite3"]
+                    .into_iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                libraries: ["libsqlite3".to_string()].into_iter().collect(),
+            }
+        );
+    }
+}
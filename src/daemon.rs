@@ -0,0 +1,660 @@
+//! The riff daemon, which evaluates environments once and serves them over a unix socket.
+//!
+//! # Protocol
+//!
+//! Clients speak [JSON-RPC 2.0](https://www.jsonrpc.org/specification) over the socket, one
+//! message per line. `get-env` and `refresh` take a `project_dir` parameter:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "id": 1, "method": "get-env", "params": {"project_dir": "/src/app"}}
+//! ```
+//!
+//! * `get-env` returns the environment of the project, evaluating it on first use.
+//! * `refresh` re-evaluates the environment of the project and returns it.
+//! * `subscribe-changes` returns `{"subscribed": true}`, after which the connection also receives
+//!   an `env-changed` notification whenever a refresh changes an environment.
+//! * `subscribe` takes a `project_dir` and returns `{"subscribed": true}`. The daemon then watches
+//!   the files detection reads in the project, like the `Cargo.toml` of each workspace member,
+//!   `Cargo.lock` and `riff.toml`, refreshing the environment when they change, and the
+//!   connection receives the project's `env-changed` and `rebuild-finished` notifications. The
+//!   project is watched until the last connection subscribed to it closes.
+//!
+//! Environments are returned as:
+//!
+//! ```json
+//! {"project_dir": "/src/app", "hash": "9f2c...", "variables": {"PATH": "/nix/store/...", ...}}
+//! ```
+//!
+//! `hash` changes whenever `variables` does. Notifications carry the `project_dir` and new `hash`:
+//!
+//! ```json
+//! {"jsonrpc": "2.0", "method": "env-changed", "params": {"project_dir": "/src/app", "hash": "..."}}
+//! ```
+//!
+//! `rebuild-finished` is sent after every refresh triggered by a file change, even when the
+//! environment did not change. If the refresh failed, it carries an `error` instead of a `hash`.
+//!
+//! Failures use the standard JSON-RPC error codes, or `-32000` if evaluation failed.
+//!
+//! Each project is detected the way `riff shell` detects it, with the registry its `riff.toml`
+//! names and the options the daemon was started with, so the environments are the same.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use eyre::WrapErr;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+use crate::dependency_registry::DependencyRegistry;
+use crate::flake_generator::{self, DetectOptions};
+use crate::{conflicts, project_fingerprint};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const EVALUATION_FAILED: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl Response {
+    fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    fn error(id: Value, code: i64, message: impl ToString) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// How long to wait for a burst of file changes (eg `cargo add`) to settle before refreshing
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: Value,
+    #[serde(skip)]
+    project_dir: PathBuf,
+}
+
+impl Notification {
+    fn new(method: &'static str, project_dir: &Path, mut params: Value) -> Self {
+        params["project_dir"] = serde_json::json!(project_dir);
+        Self {
+            jsonrpc: "2.0",
+            method,
+            params,
+            project_dir: project_dir.to_owned(),
+        }
+    }
+}
+
+/// Which notifications a connection asked for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subscription {
+    /// Every `env-changed` notification, via `subscribe-changes`
+    AllChanges,
+    /// Every notification about one project, via `subscribe`
+    Project(PathBuf),
+}
+
+impl Subscription {
+    fn wants(&self, notification: &Notification) -> bool {
+        match self {
+            Subscription::AllChanges => notification.method == "env-changed",
+            Subscription::Project(project_dir) => &notification.project_dir == project_dir,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectParams {
+    project_dir: PathBuf,
+}
+
+/// An evaluated environment, as returned by `get-env` and `refresh`
+#[derive(Debug, Clone, Serialize)]
+pub struct Environment {
+    project_dir: PathBuf,
+    hash: String,
+    variables: BTreeMap<String, String>,
+}
+
+impl Environment {
+    fn new(project_dir: PathBuf, variables: BTreeMap<String, String>) -> Self {
+        let mut hasher = Sha256::new();
+        for (name, value) in &variables {
+            // Each is followed by a NUL, which neither can contain
+            hasher.update(name);
+            hasher.update([0]);
+            hasher.update(value);
+            hasher.update([0]);
+        }
+        Self {
+            project_dir,
+            hash: hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect(),
+            variables,
+        }
+    }
+}
+
+/// The watcher of a subscribed project, and how many connections are subscribed to it
+struct ProjectWatcher {
+    _watcher: RecommendedWatcher,
+    subscribers: usize,
+}
+
+pub struct Daemon {
+    offline: bool,
+    options: DetectOptions,
+    /// The registry of each project, opened on first use
+    registries: Mutex<HashMap<PathBuf, Arc<DependencyRegistry>>>,
+    environments: Mutex<HashMap<PathBuf, Environment>>,
+    changes: broadcast::Sender<Notification>,
+    watchers: Mutex<HashMap<PathBuf, ProjectWatcher>>,
+}
+
+impl Daemon {
+    pub fn new(offline: bool, options: DetectOptions) -> Arc<Self> {
+        let (changes, _) = broadcast::channel(64);
+        Arc::new(Self {
+            offline,
+            options,
+            registries: Default::default(),
+            environments: Default::default(),
+            changes,
+            watchers: Default::default(),
+        })
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: UnixListener) -> color_eyre::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let daemon = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(err) = daemon.handle_connection(stream).await {
+                    tracing::debug!(%err, "Daemon connection closed with an error");
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(self: Arc<Self>, stream: UnixStream) -> color_eyre::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        // Responses and notifications are funneled through one task so lines never interleave.
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel::<String>();
+        let writer_task = tokio::spawn(async move {
+            while let Some(line) = outgoing_rx.recv().await {
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut lines = BufReader::new(reader).lines();
+        let mut subscriptions = HashMap::new();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (response, subscription) = self.handle_line(&line).await;
+            outgoing.send(serde_json::to_string(&response)? + "\n")?;
+            let subscription = match subscription {
+                Some(subscription) if !subscriptions.contains_key(&subscription) => subscription,
+                // The connection was already subscribed, and counts once
+                Some(Subscription::Project(project_dir)) => {
+                    self.unwatch(&project_dir).await;
+                    continue;
+                }
+                _ => continue,
+            };
+            let changes = self.changes.subscribe();
+            let forwarding = forward_notifications(changes, subscription.clone(), outgoing.clone());
+            subscriptions.insert(subscription, tokio::spawn(forwarding));
+        }
+
+        // Let the writer flush any remaining responses before the connection closes.
+        for (subscription, forwarder) in subscriptions {
+            forwarder.abort();
+            if let Subscription::Project(project_dir) = subscription {
+                self.unwatch(&project_dir).await;
+            }
+        }
+        drop(outgoing);
+        writer_task.await?;
+        Ok(())
+    }
+
+    /// Handle one request, returning the response and any subscription the client asked for
+    async fn handle_line(self: &Arc<Self>, line: &str) -> (Response, Option<Subscription>) {
+        let request: Request = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(err) => return (Response::error(Value::Null, PARSE_ERROR, err), None),
+        };
+        let id = request.id;
+        match request.method.as_str() {
+            "get-env" | "refresh" | "subscribe" => (),
+            "subscribe-changes" => {
+                return (
+                    Response::result(id, serde_json::json!({ "subscribed": true })),
+                    Some(Subscription::AllChanges),
+                )
+            }
+            method => {
+                return (
+                    Response::error(id, METHOD_NOT_FOUND, format!("Unknown method `{method}`")),
+                    None,
+                )
+            }
+        }
+
+        let params: ProjectParams = match serde_json::from_value(request.params) {
+            Ok(params) => params,
+            Err(err) => return (Response::error(id, INVALID_PARAMS, err), None),
+        };
+        // One project is one environment, however a client spells its path
+        let project_dir = match params.project_dir.canonicalize() {
+            Ok(project_dir) => project_dir,
+            Err(err) => {
+                let message = format!(
+                    "Could not find the project `{}`: {err}",
+                    params.project_dir.display()
+                );
+                return (Response::error(id, INVALID_PARAMS, message), None);
+            }
+        };
+        let environment = match request.method.as_str() {
+            "get-env" => self.get_env(&project_dir).await,
+            "refresh" => self.refresh(&project_dir).await,
+            _ => {
+                return match self.watch(&project_dir).await {
+                    Ok(()) => (
+                        Response::result(id, serde_json::json!({ "subscribed": true })),
+                        Some(Subscription::Project(project_dir)),
+                    ),
+                    Err(err) => (
+                        Response::error(id, INVALID_PARAMS, format!("{err:#}")),
+                        None,
+                    ),
+                }
+            }
+        };
+        let response =
+            match environment.and_then(|environment| Ok(serde_json::to_value(environment)?)) {
+                Ok(environment) => Response::result(id, environment),
+                Err(err) => Response::error(id, EVALUATION_FAILED, format!("{err:#}")),
+            };
+        (response, None)
+    }
+
+    /// Start refreshing the environment of `project_dir` whenever the files detection reads in it
+    /// change, for one more subscriber
+    async fn watch(self: &Arc<Self>, project_dir: &Path) -> color_eyre::Result<()> {
+        let mut watchers = self.watchers.lock().await;
+        if let Some(watcher) = watchers.get_mut(project_dir) {
+            watcher.subscribers += 1;
+            return Ok(());
+        }
+
+        let (changed, mut changed_rx) = mpsc::unbounded_channel();
+        let watched_dir = project_dir.to_owned();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let relevant = match event {
+                Ok(event) => event
+                    .paths
+                    .iter()
+                    .any(|path| project_fingerprint::affects(&watched_dir, path)),
+                Err(_) => false,
+            };
+            if relevant {
+                let _ = changed.send(());
+            }
+        })?;
+        // Recursively, for the manifests of workspace members
+        watcher
+            .watch(project_dir, RecursiveMode::Recursive)
+            .wrap_err_with(|| format!("Could not watch `{}`", project_dir.display()))?;
+        watchers.insert(
+            project_dir.to_owned(),
+            ProjectWatcher {
+                _watcher: watcher,
+                subscribers: 1,
+            },
+        );
+
+        let daemon = Arc::clone(self);
+        let project_dir = project_dir.to_owned();
+        tokio::spawn(async move {
+            while changed_rx.recv().await.is_some() {
+                tokio::time::sleep(WATCH_DEBOUNCE).await;
+                while changed_rx.try_recv().is_ok() {}
+
+                tracing::debug!(project_dir = %project_dir.display(), "Manifest changed, refreshing");
+                let params = match daemon.refresh(&project_dir).await {
+                    Ok(environment) => serde_json::json!({ "hash": environment.hash }),
+                    Err(err) => serde_json::json!({ "error": format!("{err:#}") }),
+                };
+                let _ = daemon.changes.send(Notification::new(
+                    "rebuild-finished",
+                    &project_dir,
+                    params,
+                ));
+            }
+        });
+        Ok(())
+    }
+
+    /// Stop watching `project_dir` for one subscriber, dropping its watcher after the last
+    ///
+    /// Without the watcher, the task refreshing the project's environment ends too.
+    async fn unwatch(&self, project_dir: &Path) {
+        let mut watchers = self.watchers.lock().await;
+        if let Some(watcher) = watchers.get_mut(project_dir) {
+            watcher.subscribers -= 1;
+            if watcher.subscribers == 0 {
+                tracing::debug!(project_dir = %project_dir.display(), "Unwatching the project");
+                watchers.remove(project_dir);
+            }
+        }
+    }
+
+    async fn get_env(&self, project_dir: &Path) -> color_eyre::Result<Environment> {
+        if let Some(environment) = self.environments.lock().await.get(project_dir) {
+            return Ok(environment.clone());
+        }
+        self.refresh(project_dir).await
+    }
+
+    async fn refresh(&self, project_dir: &Path) -> color_eyre::Result<Environment> {
+        let environment = self.evaluate(project_dir).await?;
+        let previous = self
+            .environments
+            .lock()
+            .await
+            .insert(project_dir.to_owned(), environment.clone());
+        if previous.map(|previous| previous.hash) != Some(environment.hash.clone()) {
+            // Nobody listening is fine.
+            let _ = self.changes.send(Notification::new(
+                "env-changed",
+                project_dir,
+                serde_json::json!({ "hash": environment.hash }),
+            ));
+        }
+        Ok(environment)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn evaluate(&self, project_dir: &Path) -> color_eyre::Result<Environment> {
+        let registry = self.registry(project_dir).await?;
+        let dev_env =
+            flake_generator::redetect_dev_env(project_dir, &registry, &self.options).await?;
+        conflicts::check(&dev_env).await?;
+        let activation_script = dev_env.to_activation_script().await?;
+        let variables = activation_script
+            .set
+            .into_iter()
+            .chain(activation_script.prepend)
+            .collect();
+        Ok(Environment::new(project_dir.to_owned(), variables))
+    }
+
+    /// The registry of the project in `project_dir`, which its `riff.toml` can name
+    async fn registry(&self, project_dir: &Path) -> color_eyre::Result<Arc<DependencyRegistry>> {
+        let mut registries = self.registries.lock().await;
+        if let Some(registry) = registries.get(project_dir) {
+            return Ok(Arc::clone(registry));
+        }
+        let registry = Arc::new(
+            DependencyRegistry::for_project(
+                self.offline,
+                self.options.registry_url.as_deref(),
+                project_dir,
+            )
+            .await?,
+        );
+        registries.insert(project_dir.to_owned(), Arc::clone(&registry));
+        Ok(registry)
+    }
+}
+
+/// Send the notifications `wanted` asks for to `outgoing`, until the connection or the daemon
+/// closes
+///
+/// A connection which falls behind misses the oldest notifications rather than all later ones.
+async fn forward_notifications(
+    mut changes: broadcast::Receiver<Notification>,
+    wanted: Subscription,
+    outgoing: mpsc::UnboundedSender<String>,
+) {
+    loop {
+        let notification = match changes.recv().await {
+            Ok(notification) => notification,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::debug!(missed, "A subscriber fell behind the notifications");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if !wanted.wants(&notification) {
+            continue;
+        }
+        let line = match serde_json::to_string(&notification) {
+            Ok(line) => line + "\n",
+            Err(_) => continue,
+        };
+        if outgoing.send(line).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn protocol_errors() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let daemon = Daemon::new(true, DetectOptions::default());
+
+        let (response, _) = daemon.handle_line("not json").await;
+        assert_eq!(response.error.map(|e| e.code), Some(PARSE_ERROR));
+
+        let (response, _) = daemon
+            .handle_line(r#"{"jsonrpc": "2.0", "id": 1, "method": "get-env", "params": {}}"#)
+            .await;
+        assert_eq!(response.error.map(|e| e.code), Some(INVALID_PARAMS));
+
+        let (response, _) = daemon
+            .handle_line(
+                r#"{"jsonrpc": "2.0", "id": 2, "method": "nope", "params": {"project_dir": "/"}}"#,
+            )
+            .await;
+        assert_eq!(response.id, serde_json::json!(2));
+        assert_eq!(response.error.map(|e| e.code), Some(METHOD_NOT_FOUND));
+
+        let (response, subscription) = daemon
+            .handle_line(r#"{"jsonrpc": "2.0", "id": 3, "method": "subscribe-changes"}"#)
+            .await;
+        assert_eq!(subscription, Some(Subscription::AllChanges));
+        assert_eq!(
+            response.result,
+            Some(serde_json::json!({ "subscribed": true }))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscribe_to_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let project_dir = TempDir::new()?;
+        std::fs::create_dir(project_dir.path().join("src"))?;
+        let daemon = Daemon::new(true, DetectOptions::default());
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "subscribe",
+            "params": { "project_dir": project_dir.path().join("src/..") },
+        });
+        let (response, subscription) = daemon.handle_line(&request.to_string()).await;
+        assert_eq!(
+            response.result,
+            Some(serde_json::json!({ "subscribed": true }))
+        );
+        let canonical = project_dir.path().canonicalize()?;
+        assert_eq!(subscription, Some(Subscription::Project(canonical.clone())));
+        assert!(daemon.watchers.lock().await.contains_key(&canonical));
+
+        // Another subscriber shares the watcher, which is dropped after the last unsubscribes
+        daemon.handle_line(&request.to_string()).await;
+        assert_eq!(daemon.watchers.lock().await[&canonical].subscribers, 2);
+        daemon.unwatch(&canonical).await;
+        assert!(daemon.watchers.lock().await.contains_key(&canonical));
+        daemon.unwatch(&canonical).await;
+        assert!(daemon.watchers.lock().await.is_empty());
+
+        let (response, subscription) = daemon
+            .handle_line(r#"{"jsonrpc": "2.0", "id": 2, "method": "subscribe", "params": {"project_dir": "/does/not/exist"}}"#)
+            .await;
+        assert_eq!(subscription, None);
+        assert_eq!(response.error.map(|e| e.code), Some(INVALID_PARAMS));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn evaluates_like_riff_shell() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let project_dir = TempDir::new()?;
+        std::fs::write(project_dir.path().join("package.json"), "{}")?;
+        let daemon = Daemon::new(
+            true,
+            DetectOptions {
+                locked: true,
+                ..Default::default()
+            },
+        );
+
+        let err = daemon.evaluate(project_dir.path()).await.unwrap_err();
+        assert!(format!("{err:#}").contains("riff.lock"), "{err:#}");
+        assert!(daemon
+            .registries
+            .lock()
+            .await
+            .contains_key(project_dir.path()));
+        Ok(())
+    }
+
+    #[test]
+    fn subscriptions_filter_notifications() {
+        let rebuilt = Notification::new("rebuild-finished", Path::new("/a"), serde_json::json!({}));
+        let changed = Notification::new("env-changed", Path::new("/b"), serde_json::json!({}));
+        assert_eq!(rebuilt.params["project_dir"], serde_json::json!("/a"));
+
+        assert!(!Subscription::AllChanges.wants(&rebuilt));
+        assert!(Subscription::AllChanges.wants(&changed));
+        assert!(Subscription::Project("/a".into()).wants(&rebuilt));
+        assert!(!Subscription::Project("/a".into()).wants(&changed));
+    }
+
+    #[tokio::test]
+    async fn lagging_subscribers_keep_receiving() {
+        let (changes, receiver) = broadcast::channel(2);
+        let changed = |hash: &str| {
+            Notification::new(
+                "env-changed",
+                Path::new("/a"),
+                serde_json::json!({ "hash": hash }),
+            )
+        };
+        // More than the channel holds before the subscriber reads any
+        for hash in ["1", "2", "3", "4"] {
+            changes.send(changed(hash)).unwrap();
+        }
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+        let forwarding = tokio::spawn(forward_notifications(
+            receiver,
+            Subscription::AllChanges,
+            outgoing,
+        ));
+
+        let hash = |line: Option<String>| {
+            let notification: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            notification["params"]["hash"].clone()
+        };
+        assert_eq!(hash(outgoing_rx.recv().await), "3");
+        assert_eq!(hash(outgoing_rx.recv().await), "4");
+        changes.send(changed("5")).unwrap();
+        assert_eq!(hash(outgoing_rx.recv().await), "5");
+
+        drop(changes);
+        forwarding.await.unwrap();
+    }
+
+    #[test]
+    fn environment_hash_tracks_variables() {
+        let variables: BTreeMap<String, String> =
+            [("A".to_string(), "1".to_string())].into_iter().collect();
+        let first = Environment::new("/a".into(), variables.clone());
+        let same = Environment::new("/a".into(), variables);
+        let different = Environment::new(
+            "/a".into(),
+            [("A".to_string(), "2".to_string())].into_iter().collect(),
+        );
+        assert_eq!(first.hash, same.hash);
+        assert_ne!(first.hash, different.hash);
+    }
+}
@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of C and C++ libraries to riff settings
+///
+/// Libraries use the same settings (including per-target settings) as crates do.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct CppDependencyRegistryData {
+    /// Settings which are needed for every C or C++ project (Eg a compiler)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of dependencies (by lowercased CMake package or pkg-config module name) to configuration
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of Crystal shards to riff settings
+///
+/// They use the same settings (including per-target settings) as crates do.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct CrystalDependencyRegistryData {
+    /// Settings which are needed for every Crystal project (Eg `crystal`)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of dependencies (by shard name) to configuration
+    ///
+    /// The `libraries` of `shard.yml`, like `libsqlite3`, are looked up here and then in the C
+    /// and C++ registry, with and without their `lib` prefix.
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
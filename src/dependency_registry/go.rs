@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of Go modules to riff settings
+///
+/// Modules use the same settings (including per-target settings) as crates do.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct GoDependencyRegistryData {
+    /// Settings which are needed for every Go project (Eg `go`)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of dependencies (by module path, without any major version suffix) to configuration
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of Julia packages to riff settings
+///
+/// Most Julia packages get their native libraries from JLL packages, so only the ones which use
+/// a system library or program instead need an entry. They use the same settings (including
+/// per-target settings) as crates do.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct JuliaDependencyRegistryData {
+    /// Settings which are needed for every Julia project (Eg `julia`)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of dependencies (by package name) to configuration
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of Lua rocks and Neovim plugins to riff settings
+///
+/// They use the same settings (including per-target settings) as crates do.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct LuaDependencyRegistryData {
+    /// Settings which are needed for every Lua project (Eg `luarocks`)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of dependencies (by rock or Neovim plugin name) to configuration
+    ///
+    /// The lowercased `external_dependencies` of rockspecs, like `openssl`, are looked up here
+    /// and then in the C and C++ registry.
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
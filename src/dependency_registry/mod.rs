@@ -1,6 +1,11 @@
+use crate::network::{self, NetworkDisabled};
+use crate::project_config::ProjectConfig;
+use crate::riff_dirs;
 use crate::RIFF_XDG_PREFIX;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::{
+    collections::{BTreeSet, HashMap},
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -12,15 +17,89 @@ use tokio::{
 };
 use xdg::{BaseDirectories, BaseDirectoriesError};
 
-use self::rust::RustDependencyRegistryData;
+use self::cpp::CppDependencyRegistryData;
+use self::crystal::CrystalDependencyRegistryData;
+use self::go::GoDependencyRegistryData;
+use self::julia::JuliaDependencyRegistryData;
+use self::lua::LuaDependencyRegistryData;
+use self::nim::NimDependencyRegistryData;
+use self::node::NodeDependencyRegistryData;
+use self::ocaml::OcamlDependencyRegistryData;
+use self::python::PythonDependencyRegistryData;
+use self::ruby::RubyDependencyRegistryData;
+use self::rust::{RustDependencyData, RustDependencyRegistryData};
+use self::swift::SwiftDependencyRegistryData;
 
+pub(crate) mod cpp;
+pub(crate) mod crystal;
+pub(crate) mod go;
+pub(crate) mod julia;
+pub(crate) mod lua;
+pub(crate) mod nim;
+pub(crate) mod node;
+pub(crate) mod ocaml;
+pub(crate) mod python;
+pub(crate) mod ruby;
 pub(crate) mod rust;
+pub(crate) mod swift;
 
-const DEPENDENCY_REGISTRY_REMOTE_URL: &str =
+pub(crate) const DEPENDENCY_REGISTRY_REMOTE_URL: &str =
     "https://registry.riff.determinate.systems/riff-registry.json";
+/// The cache of the default remote registry; others are cached next to it, by the hash of their URL
 const DEPENDENCY_REGISTRY_CACHE_PATH: &str = "registry.json";
+/// A registry layer in `$XDG_CONFIG_HOME/riff`, such as one an organization installs on its machines
+const ORG_REGISTRY_LAYER_PATH: &str = "registry.json";
+/// A registry layer in the project directory, which takes precedence over every other registry
+pub(crate) const PROJECT_REGISTRY_LAYER_PATH: &str = ".riff/registry.json";
+/// Used when there is no usable cache, such as on a fresh install which can't reach the remote
 const DEPENDENCY_REGISTRY_FALLBACK: &str = include_str!("../../registry/registry.json");
 
+/// How sure riff is that a rule's inputs are the ones a dependency needs
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Confidence {
+    /// Guessed, such as from the library a `-sys` crate links
+    Heuristic,
+    /// Maintained in the registry
+    #[default]
+    Curated,
+    /// Declared by the project or the dependency itself
+    Exact,
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Confidence::Heuristic => "heuristic",
+            Confidence::Curated => "curated",
+            Confidence::Exact => "exact",
+        })
+    }
+}
+
+/// Packages which clash when an environment has more than one of them
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Conflict {
+    /// The Nix attribute paths of the packages, like `postgresql_14` and `postgresql_15`
+    pub(crate) packages: BTreeSet<String>,
+    /// What goes wrong when an environment has several, like "they each set `CC`"
+    pub(crate) reason: String,
+    #[serde(default)]
+    pub(crate) severity: ConflictSeverity,
+}
+
+/// What riff does about a conflict in an environment
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictSeverity {
+    /// Warn, as the environment mostly works
+    #[default]
+    Warn,
+    /// Refuse to build the environment, as it can't work
+    Error,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum DependencyRegistryError {
     #[error("XDG base directories error")]
@@ -35,121 +114,154 @@ pub enum DependencyRegistryError {
     Json(#[from] serde_json::Error),
     #[error("Request error")]
     Reqwest(#[from] reqwest::Error),
+    #[error(transparent)]
+    NetworkDisabled(#[from] NetworkDisabled),
+    #[error("The registry at {0} has never been fetched, and `--no-network` keeps riff from fetching it. Run `riff registry update` with network access first.")]
+    NotCached(String),
     #[error("Wrong registry data version: 1 (expected) != {0} (got)")]
     WrongVersion(usize),
+    #[error("`{0}` is not an http or https URL, so it can't be a registry URL")]
+    InvalidRemoteUrl(String),
+    #[error("Could not use the registry layer `{}`: {source}", path.display())]
+    Layer {
+        path: PathBuf,
+        source: Box<DependencyRegistryError>,
+    },
+}
+
+/// Where a registry's data was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrySource {
+    /// The copy of the remote registry in the XDG cache
+    Cache,
+    /// The snapshot of `registry/registry.json` embedded in riff when it was built
+    Bundled,
 }
 
 #[derive(Debug)]
 pub struct DependencyRegistry {
     data: Arc<RwLock<DependencyRegistryData>>,
+    source: RegistrySource,
+    remote_url: String,
     offline: bool,
     refresh_handle: Option<JoinHandle<()>>,
 }
 
 impl DependencyRegistry {
-    #[tracing::instrument(skip_all, fields(%offline))]
-    pub async fn new(offline: bool) -> Result<Self, DependencyRegistryError> {
-        let xdg_dirs = BaseDirectories::with_prefix(RIFF_XDG_PREFIX)?;
-        // Create the directory if needed
-        let cached_registry_pathbuf =
-            xdg_dirs.place_cache_file(Path::new(DEPENDENCY_REGISTRY_CACHE_PATH))?;
-        // Create the file if needed.
-        let mut cached_registry_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .truncate(false)
-            .create(true) // We do this proactively to avoid the user seeing a non-fatal error later when we freshen the cache.
-            .open(cached_registry_pathbuf.clone())
-            .await?;
-        let mut cached_registry_content = Default::default();
-        cached_registry_file
-            .read_to_string(&mut cached_registry_content)
-            .await
-            .map_err(DependencyRegistryError::ReadCachedRegistry)?;
-        drop(cached_registry_file);
+    /// Open the registry the project in `project_dir` uses
+    ///
+    /// That is the one at `registry_url`, such as from `--registry-url`, or else the one named by
+    /// the project's `riff.toml`, or else riff's own. The project's `.riff/registry.json` is layered
+    /// on top of it.
+    pub async fn for_project(
+        offline: bool,
+        registry_url: Option<&str>,
+        project_dir: &Path,
+    ) -> color_eyre::Result<Self> {
+        let project_registry_url = match registry_url {
+            Some(_) => None,
+            None => ProjectConfig::read(project_dir)
+                .await?
+                .and_then(|config| config.registry_url),
+        };
+        Ok(Self::layered(
+            offline,
+            registry_url.or(project_registry_url.as_deref()),
+            Some(project_dir),
+        )
+        .await?)
+    }
+
+    /// Open the registry at `remote_url`, or riff's own if it is `None`
+    pub async fn new(
+        offline: bool,
+        remote_url: Option<&str>,
+    ) -> Result<Self, DependencyRegistryError> {
+        Self::layered(offline, remote_url, None).await
+    }
+
+    /// Open the registry at `remote_url`, with the layers which take precedence over it
+    ///
+    /// The base is the remote registry, or the one bundled with riff until it has been fetched.
+    /// On top of it go the organization's `$XDG_CONFIG_HOME/riff/registry.json` and then the
+    /// `.riff/registry.json` in `project_dir`. A layer's rule for a dependency replaces any rule
+    /// for it from the layers below.
+    #[tracing::instrument(skip_all, fields(%offline, ?remote_url, ?project_dir))]
+    async fn layered(
+        offline: bool,
+        remote_url: Option<&str>,
+        project_dir: Option<&Path>,
+    ) -> Result<Self, DependencyRegistryError> {
+        let remote_url = match remote_url {
+            Some(remote_url) => check_remote_url(remote_url)?,
+            None => DEPENDENCY_REGISTRY_REMOTE_URL.to_string(),
+        };
+        // Without anywhere to cache it, the registry is only kept in memory
+        let cached_registry_pathbuf = match riff_dirs::cache_dir("") {
+            Ok(dir) => Some(dir.join(cache_file_name(&remote_url))),
+            Err(err) => {
+                tracing::debug!(%err, "Not caching the registry");
+                None
+            }
+        };
+        let cached_registry_content = match &cached_registry_pathbuf {
+            Some(cached_registry_pathbuf) => read_cache(cached_registry_pathbuf).await?,
+            None => String::new(),
+        };
 
-        cached_registry_content = if cached_registry_content.is_empty() {
-            DEPENDENCY_REGISTRY_FALLBACK.to_string()
+        let offline = offline || network::disabled();
+        // An organization's registry can't be stood in for by riff's own
+        if cached_registry_content.is_empty()
+            && network::disabled()
+            && remote_url != DEPENDENCY_REGISTRY_REMOTE_URL
+        {
+            return Err(DependencyRegistryError::NotCached(remote_url));
+        }
+        let (remote_data, source) = if cached_registry_content.is_empty() {
+            tracing::debug!("No cached registry, using the registry bundled with riff");
+            (None, RegistrySource::Bundled)
         } else {
-            cached_registry_content
+            match parse_data(&cached_registry_content) {
+                Ok(data) => (Some(data), RegistrySource::Cache),
+                Err(err) => {
+                    tracing::warn!(
+                        err = %eyre::eyre!(err),
+                        path = ?cached_registry_pathbuf,
+                        "Could not use the cached registry, using the registry bundled with riff"
+                    );
+                    (None, RegistrySource::Bundled)
+                }
+            }
         };
 
-        let data: DependencyRegistryData = serde_json::from_str(&cached_registry_content)?;
-        if data.version != 1 {
-            return Err(DependencyRegistryError::WrongVersion(data.version));
+        let mut overlays = Vec::new();
+        let layer_paths = BaseDirectories::with_prefix(RIFF_XDG_PREFIX)
+            .ok()
+            .and_then(|xdg_dirs| xdg_dirs.find_config_file(ORG_REGISTRY_LAYER_PATH))
+            .into_iter()
+            .chain(project_dir.map(|dir| dir.join(PROJECT_REGISTRY_LAYER_PATH)));
+        for path in layer_paths {
+            if let Some(layer) = read_layer(&path).await? {
+                tracing::debug!(path = %path.display(), "Layering registry");
+                overlays.push(layer);
+            }
         }
 
-        let data = Arc::new(RwLock::new(data));
+        let data = Arc::new(RwLock::new(layer(remote_data, &overlays)?));
         // We detach the join handle as we don't actually care when/if this finishes
         let data_clone = Arc::clone(&data);
         let refresh_handle = if !offline {
+            let remote_url = remote_url.clone();
             let handle = tokio::spawn(async move {
-                // Refresh the cache
-                let http_client = reqwest::Client::new();
-                let req = http_client.get(DEPENDENCY_REGISTRY_REMOTE_URL);
-                tracing::trace!("Fetching new registry data from {DEPENDENCY_REGISTRY_REMOTE_URL}");
-                let res = match req.send().await {
-                    Ok(res) => res,
+                match fetch_remote(&remote_url, cached_registry_pathbuf.as_deref()).await {
+                    Ok(fresh_data) => match layer(Some(fresh_data), &overlays) {
+                        Ok(fresh_data) => *data_clone.write().await = fresh_data,
+                        Err(err) => {
+                            tracing::error!(err = ?err, "Could not layer registry data from {remote_url}")
+                        }
+                    },
                     Err(err) => {
-                        tracing::error!(err = %eyre::eyre!(err), "Could not fetch new registry data from {DEPENDENCY_REGISTRY_REMOTE_URL}");
-                        return;
-                    }
-                };
-                let content = match res.text().await {
-                    Ok(content) => content,
-                    Err(err) => {
-                        tracing::error!(err = %eyre::eyre!(err), "Could not fetch new registry data body from {DEPENDENCY_REGISTRY_REMOTE_URL}");
-                        return;
-                    }
-                };
-                let fresh_data: DependencyRegistryData = match serde_json::from_str(&content) {
-                    Ok(data) => data,
-                    Err(err) => {
-                        tracing::error!(err = %eyre::eyre!(err), "Could not parse new registry data from {DEPENDENCY_REGISTRY_REMOTE_URL}");
-                        return;
-                    }
-                };
-                *data_clone.write().await = fresh_data;
-                // Write out the update
-                let new_registry_pathbuf = match xdg_dirs.place_cache_file(PathBuf::from(
-                    DEPENDENCY_REGISTRY_CACHE_PATH.to_string()
-                        + ".new"
-                        + &std::process::id().to_string(),
-                )) {
-                    Ok(new_registry_pathbuf) => new_registry_pathbuf,
-                    Err(err) => {
-                        tracing::error!(err = %eyre::eyre!(err), "Could not place new registry file in XDG cache directory");
-                        return;
-                    }
-                };
-                let mut new_registry_file = match OpenOptions::new()
-                    .truncate(true)
-                    .create(true)
-                    .write(true)
-                    .open(new_registry_pathbuf.clone())
-                    .await
-                {
-                    Ok(new_registry_file) => new_registry_file,
-                    Err(err) => {
-                        tracing::error!(err = %eyre::eyre!(err), path = %new_registry_pathbuf.display(), "Could not truncate XDG cached registry file to empty");
-                        return;
-                    }
-                };
-                match new_registry_file.write_all(content.trim().as_bytes()).await {
-                    Ok(_) => {
-                        tracing::debug!(path = %new_registry_pathbuf.display(), "Refreshed remote registry into XDG cache")
-                    }
-                    Err(err) => {
-                        tracing::error!(err = %eyre::eyre!(err), "Could not write to {}", new_registry_pathbuf.display());
-                        return;
-                    }
-                };
-                match tokio::fs::rename(&new_registry_pathbuf, &cached_registry_pathbuf).await {
-                    Ok(_) => {
-                        tracing::debug!(new = %new_registry_pathbuf.display(), current = %cached_registry_pathbuf.display(), "Renamed new registry to replace cached registry")
-                    }
-                    Err(err) => {
-                        tracing::error!(new = %new_registry_pathbuf.display(), current = %cached_registry_pathbuf.display(), err = %eyre::eyre!(err), "Could not persist the registry update");
+                        tracing::error!(err = ?err, "Could not refresh registry data from {remote_url}")
                     }
                 }
             });
@@ -160,11 +272,27 @@ impl DependencyRegistry {
 
         Ok(Self {
             data,
+            source,
+            remote_url,
             offline,
             refresh_handle,
         })
     }
 
+    /// A registry serving `data`, which is never refreshed
+    pub fn from_data(data: DependencyRegistryData) -> Result<Self, DependencyRegistryError> {
+        if data.version != 1 {
+            return Err(DependencyRegistryError::WrongVersion(data.version));
+        }
+        Ok(Self {
+            data: Arc::new(RwLock::new(data)),
+            source: RegistrySource::Bundled,
+            remote_url: DEPENDENCY_REGISTRY_REMOTE_URL.to_string(),
+            offline: true,
+            refresh_handle: None,
+        })
+    }
+
     pub fn fresh(&self) -> bool {
         if let Some(ref handle) = self.refresh_handle {
             handle.is_finished()
@@ -178,19 +306,199 @@ impl DependencyRegistry {
         self.offline
     }
 
+    /// The URL the registry is refreshed from
+    pub fn remote_url(&self) -> &str {
+        &self.remote_url
+    }
+
+    /// Where the registry was read from when riff started, before any refresh
+    pub fn source(&self) -> RegistrySource {
+        self.source
+    }
+
     pub async fn language(&self) -> RwLockReadGuard<DependencyRegistryLanguageData> {
         RwLockReadGuard::map(self.data.read().await, |v| &v.language)
     }
 
+    /// The packages which clash when an environment has more than one of them
+    pub async fn conflicts(&self) -> RwLockReadGuard<'_, Vec<Conflict>> {
+        RwLockReadGuard::map(self.data.read().await, |v| &v.conflicts)
+    }
+
     pub async fn latest_riff_version(&self) -> RwLockReadGuard<Option<String>> {
         RwLockReadGuard::map(self.data.read().await, |v| &v.latest_riff_version)
     }
+
+    /// The SHA-256 of the registry's contents, as recorded in `riff.lock`
+    pub async fn hash(&self) -> String {
+        self.data.read().await.hash.clone()
+    }
+
+    /// Fetch the registry at `remote_url` into the XDG cache now, rather than in the background
+    pub async fn update(
+        remote_url: &str,
+    ) -> Result<DependencyRegistryData, DependencyRegistryError> {
+        let remote_url = check_remote_url(remote_url)?;
+        let cached_registry_pathbuf = riff_dirs::cache_dir("")?.join(cache_file_name(&remote_url));
+        fetch_remote(&remote_url, Some(&cached_registry_pathbuf)).await
+    }
+
+    /// Delete the cache of the registry at `remote_url`, so the next run starts from the registry bundled with riff
+    ///
+    /// Returns the path of the deleted cache, if there was one.
+    pub async fn clear_cache(remote_url: &str) -> Result<Option<PathBuf>, DependencyRegistryError> {
+        let cached_registry_pathbuf = riff_dirs::cache_dir("")?.join(cache_file_name(remote_url));
+        if !cached_registry_pathbuf.exists() {
+            return Ok(None);
+        }
+        tokio::fs::remove_file(&cached_registry_pathbuf).await?;
+        Ok(Some(cached_registry_pathbuf))
+    }
+}
+
+/// Check that `remote_url` is a URL riff can fetch a registry from
+pub(crate) fn check_remote_url(remote_url: &str) -> Result<String, DependencyRegistryError> {
+    match reqwest::Url::parse(remote_url) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => Ok(url.to_string()),
+        _ => Err(DependencyRegistryError::InvalidRemoteUrl(
+            remote_url.to_string(),
+        )),
+    }
+}
+
+/// The name of the file in the XDG cache directory holding the registry at `remote_url`
+fn cache_file_name(remote_url: &str) -> String {
+    if remote_url == DEPENDENCY_REGISTRY_REMOTE_URL {
+        DEPENDENCY_REGISTRY_CACHE_PATH.to_string()
+    } else {
+        format!("registry-{}.json", &content_hash(remote_url)[..16])
+    }
+}
+
+/// Read the cached registry at `cached_registry_pathbuf`, which is empty if it wasn't cached yet
+async fn read_cache(cached_registry_pathbuf: &Path) -> Result<String, DependencyRegistryError> {
+    // Create the file if needed.
+    let mut cached_registry_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .create(true) // We do this proactively to avoid the user seeing a non-fatal error later when we freshen the cache.
+        .open(cached_registry_pathbuf)
+        .await?;
+    let mut cached_registry_content = Default::default();
+    cached_registry_file
+        .read_to_string(&mut cached_registry_content)
+        .await
+        .map_err(DependencyRegistryError::ReadCachedRegistry)?;
+    Ok(cached_registry_content)
+}
+
+/// Fetch the registry at `remote_url` and atomically replace the cache at `cached_registry_pathbuf`,
+/// if it is cached
+#[tracing::instrument(skip(cached_registry_pathbuf))]
+async fn fetch_remote(
+    remote_url: &str,
+    cached_registry_pathbuf: Option<&Path>,
+) -> Result<DependencyRegistryData, DependencyRegistryError> {
+    tracing::trace!("Fetching new registry data from {remote_url}");
+    let content = network::client("fetch the registry")?
+        .get(remote_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    // Parsed as it is written to the cache, so the hash matches on the next run
+    let fresh_data = parse_data(content.trim())?;
+    let cached_registry_pathbuf = match cached_registry_pathbuf {
+        Some(cached_registry_pathbuf) => cached_registry_pathbuf,
+        None => return Ok(fresh_data),
+    };
+
+    // Written next to the cache and renamed over it, so concurrent runs never read half of it
+    let new_registry_pathbuf = cached_registry_pathbuf
+        .with_file_name(cache_file_name(remote_url) + ".new" + &std::process::id().to_string());
+    let mut new_registry_file = OpenOptions::new()
+        .truncate(true)
+        .create(true)
+        .write(true)
+        .open(&new_registry_pathbuf)
+        .await?;
+    new_registry_file
+        .write_all(content.trim().as_bytes())
+        .await?;
+    tracing::debug!(path = %new_registry_pathbuf.display(), "Refreshed remote registry into XDG cache");
+    tokio::fs::rename(&new_registry_pathbuf, cached_registry_pathbuf).await?;
+    tracing::debug!(new = %new_registry_pathbuf.display(), current = %cached_registry_pathbuf.display(), "Renamed new registry to replace cached registry");
+    Ok(fresh_data)
+}
+
+fn parse_data(content: &str) -> Result<DependencyRegistryData, DependencyRegistryError> {
+    let mut data: DependencyRegistryData = serde_json::from_str(content)?;
+    if data.version != 1 {
+        return Err(DependencyRegistryError::WrongVersion(data.version));
+    }
+    data.hash = content_hash(content);
+    data.language
+        .identify_rules(&serde_json::from_str(content)?);
+    Ok(data)
+}
+
+/// Read the registry layer at `path`, if there is one
+async fn read_layer(
+    path: &Path,
+) -> Result<Option<DependencyRegistryData>, DependencyRegistryError> {
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => Err(err)?,
+    };
+    parse_data(&content)
+        .map(Some)
+        .map_err(|err| DependencyRegistryError::Layer {
+            path: path.to_path_buf(),
+            source: Box::new(err),
+        })
+}
+
+/// Stack `overlays` on top of the remote registry, or the bundled one if there is no remote data
+fn layer(
+    remote_data: Option<DependencyRegistryData>,
+    overlays: &[DependencyRegistryData],
+) -> Result<DependencyRegistryData, DependencyRegistryError> {
+    let mut data = match remote_data {
+        Some(data) => data,
+        None => bundled_data()?,
+    };
+    for overlay in overlays {
+        data.overlay(overlay.clone());
+    }
+    Ok(data)
+}
+
+/// The SHA-256 of the registry embedded in riff, which identifies the snapshot
+pub(crate) fn bundled_registry_hash() -> String {
+    content_hash(DEPENDENCY_REGISTRY_FALLBACK)
+}
+
+/// The registry embedded in riff, so it works on machines which never reached the remote one
+fn bundled_data() -> Result<DependencyRegistryData, DependencyRegistryError> {
+    parse_data(DEPENDENCY_REGISTRY_FALLBACK)
+}
+
+fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
 }
 
 impl Drop for DependencyRegistry {
     fn drop(&mut self) {
         let Self {
             data: _,
+            source: _,
+            remote_url: _,
             offline: _,
             refresh_handle,
         } = self;
@@ -204,6 +512,8 @@ impl Clone for DependencyRegistry {
     fn clone(&self) -> Self {
         Self {
             data: self.data.clone(),
+            source: self.source,
+            remote_url: self.remote_url.clone(),
             offline: self.offline,
             refresh_handle: None,
         }
@@ -216,9 +526,245 @@ pub struct DependencyRegistryData {
     pub(crate) latest_riff_version: Option<String>,
     pub(crate) version: usize, // Checked for ABI compat
     pub(crate) language: DependencyRegistryLanguageData,
+    #[serde(default)]
+    pub(crate) conflicts: Vec<Conflict>,
+    /// The SHA-256 of the JSON the data was read from, if it was read from JSON
+    #[serde(skip)]
+    pub(crate) hash: String,
+}
+
+impl DependencyRegistryData {
+    /// Let the rules of `overlay` replace the rules for the same dependencies in `self`
+    ///
+    /// The settings every project of a language gets come from the lowest layer, so overlays can
+    /// stay small. The conflicts of every layer apply.
+    fn overlay(&mut self, overlay: DependencyRegistryData) {
+        if overlay.latest_riff_version.is_some() {
+            self.latest_riff_version = overlay.latest_riff_version;
+        }
+        self.conflicts.extend(overlay.conflicts);
+        let language = &mut self.language;
+        language
+            .rust
+            .dependencies
+            .extend(overlay.language.rust.dependencies);
+        language
+            .node
+            .dependencies
+            .extend(overlay.language.node.dependencies);
+        language
+            .python
+            .dependencies
+            .extend(overlay.language.python.dependencies);
+        language
+            .go
+            .dependencies
+            .extend(overlay.language.go.dependencies);
+        language
+            .ruby
+            .dependencies
+            .extend(overlay.language.ruby.dependencies);
+        language
+            .cpp
+            .dependencies
+            .extend(overlay.language.cpp.dependencies);
+        language
+            .ocaml
+            .dependencies
+            .extend(overlay.language.ocaml.dependencies);
+        language
+            .swift
+            .dependencies
+            .extend(overlay.language.swift.dependencies);
+        language
+            .lua
+            .dependencies
+            .extend(overlay.language.lua.dependencies);
+        language
+            .julia
+            .dependencies
+            .extend(overlay.language.julia.dependencies);
+        language
+            .nim
+            .dependencies
+            .extend(overlay.language.nim.dependencies);
+        language
+            .crystal
+            .dependencies
+            .extend(overlay.language.crystal.dependencies);
+        self.hash = content_hash(&(self.hash.clone() + &overlay.hash));
+    }
+}
+
+/// Which registry rule a [`RustDependencyData`] is, so `riff.lock` can tell when it changes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleId {
+    /// The language and the dependency of the rule, like `rust/openssl-sys`
+    pub(crate) name: String,
+    /// The SHA-256 of the rule's JSON
+    pub(crate) hash: String,
 }
 
 #[derive(Deserialize, Default, Clone, Debug)]
 pub struct DependencyRegistryLanguageData {
+    #[serde(default)]
     pub(crate) rust: RustDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) node: NodeDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) python: PythonDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) go: GoDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) ruby: RubyDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) cpp: CppDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) ocaml: OcamlDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) swift: SwiftDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) lua: LuaDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) julia: JuliaDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) nim: NimDependencyRegistryData,
+    #[serde(default)]
+    pub(crate) crystal: CrystalDependencyRegistryData,
+}
+
+impl DependencyRegistryLanguageData {
+    /// The rules of each language, by the language's key in the registry
+    fn rules_mut(&mut self) -> [(&'static str, &mut HashMap<String, RustDependencyData>); 12] {
+        [
+            ("rust", &mut self.rust.dependencies),
+            ("node", &mut self.node.dependencies),
+            ("python", &mut self.python.dependencies),
+            ("go", &mut self.go.dependencies),
+            ("ruby", &mut self.ruby.dependencies),
+            ("cpp", &mut self.cpp.dependencies),
+            ("ocaml", &mut self.ocaml.dependencies),
+            ("swift", &mut self.swift.dependencies),
+            ("lua", &mut self.lua.dependencies),
+            ("julia", &mut self.julia.dependencies),
+            ("nim", &mut self.nim.dependencies),
+            ("crystal", &mut self.crystal.dependencies),
+        ]
+    }
+
+    /// Name each rule, and hash its JSON in `registry`, the registry this was parsed from
+    fn identify_rules(&mut self, registry: &serde_json::Value) {
+        for (language, rules) in self.rules_mut() {
+            for (dependency, rule) in rules.iter_mut() {
+                let json = &registry["language"][language]["dependencies"][dependency.as_str()];
+                rule.rule = Some(RuleId {
+                    name: format!("{language}/{dependency}"),
+                    hash: content_hash(&json.to_string()),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn falls_back_to_bundled_registry() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+
+        let registry = DependencyRegistry::new(true, None).await?;
+        assert_eq!(registry.source(), RegistrySource::Bundled);
+        assert!(registry
+            .language()
+            .await
+            .rust
+            .dependencies
+            .contains_key("openssl-sys"));
+
+        // A cache cut short by a crash shouldn't leave riff without any rules
+        let cache = cache_dir
+            .path()
+            .join(RIFF_XDG_PREFIX)
+            .join(DEPENDENCY_REGISTRY_CACHE_PATH);
+        tokio::fs::write(&cache, "{\"language\": {").await?;
+        let registry = DependencyRegistry::new(true, None).await?;
+        assert_eq!(registry.source(), RegistrySource::Bundled);
+        assert_eq!(
+            registry.hash().await,
+            content_hash(DEPENDENCY_REGISTRY_FALLBACK)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn project_layer_overrides_rules() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let config_dir = TempDir::new()?;
+        std::env::set_var("XDG_CONFIG_HOME", config_dir.path());
+        let project_dir = TempDir::new()?;
+        tokio::fs::create_dir(project_dir.path().join(".riff")).await?;
+        tokio::fs::write(
+            project_dir.path().join(PROJECT_REGISTRY_LAYER_PATH),
+            r#"{
+                "version": 1,
+                "language": {
+                    "rust": {
+                        "dependencies": {
+                            "openssl-sys": { "build-inputs": ["openssl_3"] }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .await?;
+
+        let registry = DependencyRegistry::layered(true, None, Some(project_dir.path())).await?;
+        let language = registry.language().await;
+        assert_eq!(
+            language.rust.dependencies["openssl-sys"].build_inputs(),
+            ["openssl_3".to_string()].into_iter().collect()
+        );
+        // Rules the layer doesn't mention, and the defaults, still come from the registry below it
+        assert!(language.rust.dependencies.contains_key("libsqlite3-sys"));
+        assert!(language.rust.default.build_inputs.contains("cargo"));
+        // Each rule is named, and hashed from the layer it comes from
+        let openssl = language.rust.dependencies["openssl-sys"].rule.clone();
+        assert_eq!(
+            openssl.as_ref().map(|rule| rule.name.as_str()),
+            Some("rust/openssl-sys")
+        );
+        assert_eq!(
+            openssl.map(|rule| rule.hash),
+            Some(content_hash(r#"{"build-inputs":["openssl_3"]}"#))
+        );
+        assert_ne!(
+            registry.hash().await,
+            content_hash(DEPENDENCY_REGISTRY_FALLBACK)
+        );
+
+        tokio::fs::write(project_dir.path().join(PROJECT_REGISTRY_LAYER_PATH), "{}").await?;
+        assert!(matches!(
+            DependencyRegistry::layered(true, None, Some(project_dir.path())).await,
+            Err(DependencyRegistryError::Layer { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn remote_urls() {
+        assert_eq!(
+            cache_file_name(DEPENDENCY_REGISTRY_REMOTE_URL),
+            DEPENDENCY_REGISTRY_CACHE_PATH
+        );
+        let internal = "https://riff.example.com/registry.json";
+        assert_eq!(check_remote_url(internal).unwrap(), internal);
+        assert_ne!(cache_file_name(internal), DEPENDENCY_REGISTRY_CACHE_PATH);
+        assert!(check_remote_url("file:///etc/registry.json").is_err());
+        assert!(check_remote_url("registry.json").is_err());
+    }
 }
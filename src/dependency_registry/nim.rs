@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of Nimble packages to riff settings
+///
+/// They use the same settings (including per-target settings) as crates do. Nim loads many
+/// libraries with `dynlib` when the program starts, so those are `runtime-inputs`.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct NimDependencyRegistryData {
+    /// Settings which are needed for every Nim project (Eg `nim`)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of dependencies (by Nimble package name) to configuration
+    ///
+    /// The system packages named by `foreignDep`, like `openssl`, are looked up here and then in
+    /// the C and C++ registry.
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of npm packages to riff settings
+///
+/// Packages use the same settings (including per-target settings) as crates do.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct NodeDependencyRegistryData {
+    /// Settings which are needed for every Node.js project (Eg `nodejs`)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of dependencies (by npm package name) to configuration
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
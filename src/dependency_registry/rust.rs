@@ -1,16 +1,21 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use serde::Deserialize;
 
+use super::{Confidence, RuleId};
 use crate::dev_env::{DevEnvironment, DevEnvironmentAppliable};
+use crate::nix_expr;
+use crate::platform;
 
 /// A language specific registry of dependencies to riff settings
 #[derive(Deserialize, Default, Clone, Debug)]
 pub struct RustDependencyRegistryData {
     /// Settings which are needed for every instance of this language (Eg `cargo` for Rust)
+    #[serde(default)]
     pub(crate) default: RustDependencyTargetData,
     /// A mapping of dependencies (by crate name) to configuration
     // TODO(@hoverbear): How do we handle crates with conflicting names? eg a `rocksdb-sys` crate from one repo and another from another having different requirements?
+    #[serde(default)]
     pub(crate) dependencies: HashMap<String, RustDependencyData>,
 }
 
@@ -21,9 +26,74 @@ pub struct RustDependencyData {
     // Keep the key a `String` since users can make custom targets.
     #[serde(default)]
     pub(crate) targets: HashMap<String, RustDependencyTargetData>,
+    /// Settings which only apply on a platform, like `linux` or `macos-aarch64`
+    ///
+    /// Unlike `targets`, these go by the machine riff runs on, and their build inputs are guarded
+    /// by the platform in the generated flake.
+    #[serde(default)]
+    pub(crate) platforms: HashMap<String, RustDependencyTargetData>,
+    /// Settings which only apply when the crate is built with a feature, by feature name
+    #[serde(default)]
+    pub(crate) features: HashMap<String, RustDependencyTargetData>,
+    /// How sure the registry is of the rule, which `--conservative` requires to be curated
+    #[serde(default)]
+    pub(crate) confidence: Confidence,
+    /// Which registry rule this is, unless it's declared by the project or a manifest
+    #[serde(skip)]
+    pub(crate) rule: Option<RuleId>,
 }
 
 impl RustDependencyData {
+    /// The settings for the crate built with the `enabled` features
+    ///
+    /// The settings of enabled features are folded into the default ones. When the features the
+    /// crate is built with are unknown, as in `cargo metadata` output without a resolved
+    /// dependency graph, every feature's settings apply, so nothing the crate might need is left
+    /// out.
+    pub(crate) fn with_features(&self, enabled: Option<&BTreeSet<String>>) -> Self {
+        let mut data = self.clone();
+        for (feature, feature_config) in &self.features {
+            let feature_enabled = match enabled {
+                Some(enabled) => enabled.contains(feature),
+                None => true,
+            };
+            if feature_enabled {
+                data.default.merge(feature_config);
+            }
+        }
+        data.features.clear();
+        data
+    }
+
+    /// Remove the shell hooks of every target and feature, returning whether there were any
+    pub(crate) fn remove_shell_hooks(&mut self) -> bool {
+        let mut removed = false;
+        for target_data in std::iter::once(&mut self.default)
+            .chain(self.targets.values_mut())
+            .chain(self.platforms.values_mut())
+            .chain(self.features.values_mut())
+        {
+            removed |= target_data.shell_hook.take().is_some();
+        }
+        removed
+    }
+
+    /// Remove the flake inputs and overlays of every target and feature, returning whether there
+    /// were any
+    pub(crate) fn remove_flake_inputs(&mut self) -> bool {
+        let mut removed = false;
+        for target_data in std::iter::once(&mut self.default)
+            .chain(self.targets.values_mut())
+            .chain(self.platforms.values_mut())
+            .chain(self.features.values_mut())
+        {
+            removed |= !target_data.flake_inputs.is_empty() || !target_data.overlays.is_empty();
+            target_data.flake_inputs.clear();
+            target_data.overlays.clear();
+        }
+        removed
+    }
+
     #[tracing::instrument(skip_all)]
     pub(crate) fn build_inputs(&self) -> HashSet<String> {
         let target = format!("{}", target_lexicon::HOST);
@@ -67,12 +137,23 @@ impl RustDependencyData {
 impl DevEnvironmentAppliable for RustDependencyData {
     #[tracing::instrument(skip_all)]
     fn apply(&self, dev_env: &mut DevEnvironment) {
+        dev_env.applied_rules += 1;
+        if let Some(rule) = &self.rule {
+            dev_env
+                .registry_rules
+                .insert(rule.name.clone(), rule.hash.clone());
+        }
         self.default.apply(dev_env);
-        let target = format!("{}", target_lexicon::HOST);
+        let target = dev_env.target();
         // Importantly: These come after, they are more specific.
         if let Some(target_config) = self.targets.get(&target) {
             target_config.apply(dev_env);
         }
+        for (platform, platform_config) in &self.platforms {
+            if platform::matches_host(platform) {
+                platform_config.apply_on_platform(platform, dev_env);
+            }
+        }
     }
 }
 
@@ -80,14 +161,108 @@ impl DevEnvironmentAppliable for RustDependencyData {
 #[derive(Deserialize, Default, Clone, Debug)]
 pub struct RustDependencyTargetData {
     /// The Nix `buildInputs` needed
-    #[serde(default, rename = "build-inputs")]
+    #[serde(
+        default,
+        rename = "build-inputs",
+        deserialize_with = "nix_expr::deserialize_attr_paths"
+    )]
     pub(crate) build_inputs: HashSet<String>,
     /// Any packaging specific environment variables that need to be set
     #[serde(default, rename = "environment-variables")]
     pub(crate) environment_variables: HashMap<String, String>,
     /// The Nix packages which should have the result of `lib.getLib` run on them placed on the `LD_LIBRARY_PATH`
-    #[serde(default, rename = "runtime-inputs")]
+    #[serde(
+        default,
+        rename = "runtime-inputs",
+        deserialize_with = "nix_expr::deserialize_attr_paths"
+    )]
     pub(crate) runtime_inputs: HashSet<String>,
+    /// Shell code to run when the environment activates, in its `shellHook`
+    #[serde(default, rename = "shell-hook")]
+    pub(crate) shell_hook: Option<String>,
+    /// The Apple SDK frameworks needed on macOS, like `Security`
+    #[serde(default, rename = "darwin-frameworks")]
+    pub(crate) darwin_frameworks: HashSet<String>,
+    /// Flakes to add to the generated flake's inputs by name, like
+    /// `"fenix": "github:nix-community/fenix"`
+    #[serde(
+        default,
+        rename = "flake-inputs",
+        deserialize_with = "deserialize_flake_inputs"
+    )]
+    pub(crate) flake_inputs: HashMap<String, String>,
+    /// Overlays to apply to nixpkgs, as attribute paths of the flake inputs, like
+    /// `fenix.overlays.default`
+    #[serde(default, deserialize_with = "nix_expr::deserialize_attr_paths")]
+    pub(crate) overlays: HashSet<String>,
+}
+
+/// The names generated flakes already give their own inputs
+const RESERVED_FLAKE_INPUTS: [&str; 4] = ["self", "nixpkgs", "crane", "naersk"];
+
+/// Deserialize flake inputs, checking their names can be used in the generated flake
+fn deserialize_flake_inputs<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let inputs = HashMap::<String, String>::deserialize(deserializer)?;
+    for (name, url) in &inputs {
+        if !nix_expr::is_identifier(name) || RESERVED_FLAKE_INPUTS.contains(&name.as_str()) {
+            return Err(serde::de::Error::custom(format!(
+                "`{name}` can't be the name of a flake input"
+            )));
+        }
+        if url.is_empty() {
+            return Err(serde::de::Error::custom(format!(
+                "The flake input `{name}` has no flake reference"
+            )));
+        }
+    }
+    Ok(inputs)
+}
+
+impl RustDependencyTargetData {
+    /// Add `other`'s inputs to these, with its environment variables taking precedence
+    fn merge(&mut self, other: &RustDependencyTargetData) {
+        self.build_inputs.extend(other.build_inputs.iter().cloned());
+        self.environment_variables.extend(
+            other
+                .environment_variables
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        self.runtime_inputs
+            .extend(other.runtime_inputs.iter().cloned());
+        self.darwin_frameworks
+            .extend(other.darwin_frameworks.iter().cloned());
+        self.flake_inputs.extend(
+            other
+                .flake_inputs
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        self.overlays.extend(other.overlays.iter().cloned());
+        if let Some(other_hook) = &other.shell_hook {
+            self.shell_hook = Some(match self.shell_hook.take() {
+                Some(hook) => format!("{hook}\n{other_hook}"),
+                None => other_hook.clone(),
+            });
+        }
+    }
+}
+
+impl RustDependencyTargetData {
+    /// Apply the settings, keeping the build inputs to `platform` in the generated flake
+    fn apply_on_platform(&self, platform: &str, dev_env: &mut DevEnvironment) {
+        let mut settings = self.clone();
+        let build_inputs = std::mem::take(&mut settings.build_inputs);
+        settings.apply(dev_env);
+        dev_env
+            .platform_build_inputs
+            .entry(platform.to_string())
+            .or_default()
+            .extend(build_inputs);
+    }
 }
 
 impl DevEnvironmentAppliable for RustDependencyTargetData {
@@ -116,6 +291,23 @@ impl DevEnvironmentAppliable for RustDependencyTargetData {
             .union(&self.runtime_inputs)
             .cloned()
             .collect();
+        dev_env
+            .darwin_frameworks
+            .extend(self.darwin_frameworks.iter().cloned());
+        for (name, url) in &self.flake_inputs {
+            if let Some(existing_url) = dev_env.flake_inputs.insert(name.clone(), url.clone()) {
+                tracing::debug!(
+                    name,
+                    existing_url,
+                    new_url = url,
+                    "Overriding previously declared flake input"
+                )
+            }
+        }
+        dev_env.overlays.extend(self.overlays.iter().cloned());
+        if let Some(shell_hook) = &self.shell_hook {
+            dev_env.add_shell_hook(shell_hook);
+        }
     }
 }
 
@@ -129,7 +321,7 @@ mod test {
     async fn try_apply() -> eyre::Result<()> {
         let cache_dir = TempDir::new()?;
         std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
-        let registry = DependencyRegistry::new(true).await?;
+        let registry = DependencyRegistry::new(true, None).await?;
         let mut dev_env = DevEnvironment::new(&registry);
 
         let target = format!("{}", target_lexicon::HOST);
@@ -143,6 +335,10 @@ mod test {
                 .into_iter()
                 .collect(),
                 runtime_inputs: vec!["default".into()].into_iter().collect(),
+                shell_hook: None,
+                darwin_frameworks: Default::default(),
+                flake_inputs: Default::default(),
+                overlays: Default::default(),
             },
             targets: {
                 let mut map = HashMap::default();
@@ -157,10 +353,18 @@ mod test {
                         .into_iter()
                         .collect(),
                         runtime_inputs: vec!["target_specific".into()].into_iter().collect(),
+                        shell_hook: None,
+                        darwin_frameworks: Default::default(),
+                        flake_inputs: Default::default(),
+                        overlays: Default::default(),
                     },
                 );
                 map
             },
+            platforms: Default::default(),
+            features: Default::default(),
+            confidence: Default::default(),
+            rule: None,
         };
 
         data.apply(&mut dev_env);
@@ -210,6 +414,10 @@ mod test {
                 );
                 map
             },
+            platforms: Default::default(),
+            features: Default::default(),
+            confidence: Default::default(),
+            rule: None,
         };
         let merged = data.build_inputs();
         assert_eq!(
@@ -250,6 +458,10 @@ mod test {
                 );
                 map
             },
+            platforms: Default::default(),
+            features: Default::default(),
+            confidence: Default::default(),
+            rule: None,
         };
         let merged = data.environment_variables();
         assert_eq!(
@@ -271,6 +483,7 @@ mod test {
         let data = RustDependencyData {
             default: RustDependencyTargetData {
                 runtime_inputs: vec!["default".into()].into_iter().collect(),
+                shell_hook: None,
                 ..Default::default()
             },
             targets: {
@@ -279,11 +492,16 @@ mod test {
                     target,
                     RustDependencyTargetData {
                         runtime_inputs: vec!["target_specific".into()].into_iter().collect(),
+                        shell_hook: None,
                         ..Default::default()
                     },
                 );
                 map
             },
+            platforms: Default::default(),
+            features: Default::default(),
+            confidence: Default::default(),
+            rule: None,
         };
         let merged = data.runtime_inputs();
         assert_eq!(
@@ -294,4 +512,79 @@ mod test {
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn platform_apply() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let data: RustDependencyData = serde_json::from_value(serde_json::json!({
+            "platforms": {
+                std::env::consts::OS: {
+                    "build-inputs": ["host-input"],
+                    "environment-variables": {"HOST_VAR": "host"},
+                },
+                "plan9": {"build-inputs": ["plan9-input"]},
+            },
+        }))?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        data.apply(&mut dev_env);
+        assert!(dev_env.build_inputs.is_empty());
+        assert_eq!(
+            dev_env.platform_build_inputs,
+            [(
+                std::env::consts::OS.to_string(),
+                ["host-input".to_string()].into_iter().collect()
+            )]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            dev_env.environment_variables.get("HOST_VAR"),
+            Some(&"host".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_attribute_paths() -> eyre::Result<()> {
+        let data: RustDependencyData = serde_json::from_value(serde_json::json!({
+            "build-inputs": ["llvmPackages_15.libclang"],
+            "runtime-inputs": ["xorg.libX11"],
+        }))?;
+        assert!(data.build_inputs().contains("llvmPackages_15.libclang"));
+        assert!(data.runtime_inputs().contains("xorg.libX11"));
+
+        let typo = serde_json::from_value::<RustDependencyData>(serde_json::json!({
+            "build-inputs": ["llvmPackages_15..libclang"],
+        }));
+        assert!(typo.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn flake_inputs() -> eyre::Result<()> {
+        let data: RustDependencyData = serde_json::from_value(serde_json::json!({
+            "flake-inputs": { "fenix": "github:nix-community/fenix" },
+            "overlays": ["fenix.overlays.default"],
+        }))?;
+        assert_eq!(
+            data.default.flake_inputs["fenix"],
+            "github:nix-community/fenix"
+        );
+        assert!(data.default.overlays.contains("fenix.overlays.default"));
+
+        for inputs in [
+            serde_json::json!({ "nixpkgs": "github:NixOS/nixpkgs" }),
+            serde_json::json!({ "bad name": "github:me/flake" }),
+            serde_json::json!({ "fenix": "" }),
+        ] {
+            let data = serde_json::from_value::<RustDependencyData>(
+                serde_json::json!({ "flake-inputs": inputs }),
+            );
+            assert!(data.is_err(), "{inputs}");
+        }
+        Ok(())
+    }
 }
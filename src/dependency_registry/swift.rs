@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use super::rust::{RustDependencyData, RustDependencyTargetData};
+
+/// A registry of Swift system libraries to riff settings
+///
+/// They use the same settings (including per-target settings) as crates do.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct SwiftDependencyRegistryData {
+    /// Settings which are needed for every Swift package (Eg `swift`)
+    #[serde(default)]
+    pub(crate) default: RustDependencyTargetData,
+    /// A mapping of system libraries (by pkg-config name) to configuration
+    ///
+    /// Libraries missing here are looked up in the C and C++ registry.
+    #[serde(default)]
+    pub(crate) dependencies: HashMap<String, RustDependencyData>,
+}
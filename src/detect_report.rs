@@ -0,0 +1,216 @@
+//! Detection reports, which capture what riff reads from a project so its environment can be
+//! reproduced without the project itself.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, WrapErr};
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+
+use crate::build_info::BuildInfo;
+use crate::cargo_metadata;
+use crate::dependency_registry::DependencyRegistry;
+use crate::dev_env::DevEnvironment;
+use crate::language_backend::BACKENDS;
+use crate::project_config;
+use crate::task_runner::TaskRunner;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DetectReport {
+    /// The version of riff which captured the report
+    #[serde(rename = "riff-version")]
+    pub riff_version: String,
+    /// How the riff which captured the report was built, for bug reports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<BuildInfo>,
+    /// The telemetry labels of the languages riff detected
+    pub languages: BTreeSet<String>,
+    /// The contents of the manifests and task runner files riff read, by file name
+    pub files: BTreeMap<String, String>,
+    /// The output of `cargo metadata`, with machine-specific paths scrubbed
+    #[serde(
+        rename = "cargo-metadata",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cargo_metadata: Option<serde_json::Value>,
+}
+
+impl DetectReport {
+    /// Detect the project in `project_dir`, capturing the files detection reads
+    ///
+    /// `cargo_metadata_snapshot` replaces running `cargo metadata`, as `--from-metadata` does.
+    pub async fn capture(
+        project_dir: &Path,
+        registry: &DependencyRegistry,
+        cargo_metadata_snapshot: Option<PathBuf>,
+    ) -> color_eyre::Result<Self> {
+        let cargo_metadata = match &cargo_metadata_snapshot {
+            Some(snapshot) => Some(cargo_metadata::read_snapshot_document(snapshot).await?),
+            None if project_dir.join("Cargo.toml").exists() => {
+                let output = cargo_metadata::run(project_dir, registry.offline()).await?;
+                Some(
+                    serde_json::from_str(&output)
+                        .wrap_err("Output produced by `cargo metadata` was not valid JSON")?,
+                )
+            }
+            None => None,
+        };
+        let cargo_metadata = cargo_metadata.map(|mut document| {
+            cargo_metadata::scrub_paths(&mut document);
+            document
+        });
+
+        // Detect from the captured metadata, so `cargo metadata` only runs once
+        let snapshot_dir = TempDir::new()?;
+        let snapshot = match &cargo_metadata {
+            Some(document) => {
+                let snapshot = snapshot_dir.path().join("cargo-metadata.json");
+                tokio::fs::write(&snapshot, serde_json::to_string(document)?).await?;
+                Some(snapshot)
+            }
+            None => None,
+        };
+        let mut dev_env = DevEnvironment::new(registry).with_cargo_metadata_snapshot(snapshot);
+        dev_env.detect(project_dir).await?;
+
+        let mut files = BTreeMap::new();
+        for file_name in known_files() {
+            let path = project_dir.join(file_name);
+            if !path.is_file() {
+                continue;
+            }
+            let contents = tokio::fs::read_to_string(&path)
+                .await
+                .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+            files.insert(file_name.to_string(), contents);
+        }
+
+        Ok(Self {
+            riff_version: env!("CARGO_PKG_VERSION").to_string(),
+            build: Some(BuildInfo::current()),
+            languages: dev_env
+                .detected_languages
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            files,
+            cargo_metadata,
+        })
+    }
+
+    pub async fn read(path: &Path) -> color_eyre::Result<Self> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("`{}` is not a detection report", path.display()))
+    }
+
+    /// Recreate the captured files in a temporary project directory
+    pub async fn unpack(&self) -> color_eyre::Result<ReplayedProject> {
+        let dir = TempDir::new()?;
+        let project_dir = dir.path().join("project");
+        tokio::fs::create_dir(&project_dir).await?;
+
+        let known_files = known_files().collect::<BTreeSet<_>>();
+        for (file_name, contents) in &self.files {
+            // Only ever write the files riff reads, and never outside of the project directory
+            if !known_files.contains(file_name.as_str()) {
+                return Err(eyre!(
+                    "The detection report contains `{file_name}`, which riff does not read"
+                ));
+            }
+            let path = project_dir.join(file_name);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(path, contents).await?;
+        }
+
+        let cargo_metadata = match &self.cargo_metadata {
+            Some(document) => {
+                let path = dir.path().join("cargo-metadata.json");
+                tokio::fs::write(&path, serde_json::to_string(document)?).await?;
+                Some(path)
+            }
+            None => None,
+        };
+
+        Ok(ReplayedProject {
+            dir,
+            cargo_metadata,
+        })
+    }
+}
+
+/// A project recreated from a detection report, deleted when dropped
+#[derive(Debug)]
+pub struct ReplayedProject {
+    dir: TempDir,
+    cargo_metadata: Option<PathBuf>,
+}
+
+impl ReplayedProject {
+    pub fn project_dir(&self) -> PathBuf {
+        self.dir.path().join("project")
+    }
+
+    /// The captured `cargo metadata` output, to be used instead of running `cargo metadata`
+    pub fn cargo_metadata(&self) -> Option<PathBuf> {
+        self.cargo_metadata.clone()
+    }
+}
+
+/// The files any language backend or task runner reads, and the project's configuration
+pub(crate) fn known_files() -> impl Iterator<Item = &'static str> {
+    BACKENDS
+        .iter()
+        .flat_map(|backend| backend.manifest_files())
+        .chain(
+            [TaskRunner::Just, TaskRunner::Make]
+                .iter()
+                .flat_map(|runner| runner.file_names()),
+        )
+        .chain(&project_config::CONFIG_FILES)
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::fs::write;
+
+    #[tokio::test]
+    async fn replay_detect_report() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("Gemfile"),
+            "source 'https://rubygems.org'\ngem 'pg'\n",
+        )
+        .await?;
+        write(project_dir.path().join("secret.txt"), "hunter2").await?;
+
+        let report = DetectReport::capture(project_dir.path(), &registry, None).await?;
+        assert_eq!(report.languages, ["Ruby".to_string()].into_iter().collect());
+        assert_eq!(
+            report.files.keys().collect::<Vec<_>>(),
+            vec!["Gemfile"],
+            "only the files riff reads are captured"
+        );
+
+        let replayed = report.unpack().await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(&replayed.project_dir()).await?;
+        assert!(dev_env.build_inputs.contains("postgresql"));
+
+        let mut report = report;
+        report.files.insert("../escape".to_string(), String::new());
+        assert!(report.unpack().await.is_err());
+        Ok(())
+    }
+}
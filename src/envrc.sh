@@ -0,0 +1,3 @@
+# Load the environment riff detects for this project. The environment is cached in
+# `.direnv`, and evaluated again when a manifest or lockfile changes.
+eval "$(riff direnv)"
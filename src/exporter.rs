@@ -0,0 +1,556 @@
+//! The formats `riff export` writes a project's environment in.
+//!
+//! Each format is an [`Exporter`] in [`EXPORTERS`], which `riff export` offers as a subcommand
+//! taking the format's own options alongside the ones all exports share. Exporters which builds
+//! can leave out, like those needing dependencies riff doesn't otherwise have, are added to the
+//! table behind a cargo feature, as `sbom` is, so only builds which enable it pay for them.
+
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use toml_edit::Document;
+
+use crate::dev_env::{DevEnvironment, RustBuilder};
+use crate::{cargo_config, tool_wrappers};
+#[cfg(feature = "sbom")]
+use crate::{flake_generator, riff_lock::RiffLock, sbom};
+
+/// The formats `riff export` offers, in the order its help lists them
+pub static EXPORTERS: &[&dyn Exporter] = &[
+    &FlakeExporter,
+    &OciExporter,
+    &DevcontainerExporter,
+    &GithubActionsExporter,
+    &CargoConfigExporter,
+    #[cfg(feature = "sbom")]
+    &SbomExporter,
+];
+
+/// The exporter of the format `name`
+pub fn find(name: &str) -> Option<&'static dyn Exporter> {
+    EXPORTERS
+        .iter()
+        .copied()
+        .find(|exporter| exporter.name() == name)
+}
+
+/// A format a project's environment can be exported in
+#[async_trait]
+pub trait Exporter: Sync {
+    /// The name of the format, which is the name of its `riff export` subcommand
+    fn name(&self) -> &'static str;
+
+    /// The one line summary of the format in `riff export --help`
+    fn about(&self) -> &'static str;
+
+    /// The description of the format in `riff export <format> --help`, if it has more to say
+    fn long_about(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Add the format's own options to its subcommand, which [`render`](Self::render) reads back
+    /// from [`ExportContext::matches`]
+    fn augment_args<'help>(&self, cmd: Command<'help>) -> Command<'help> {
+        cmd
+    }
+
+    /// The directory in the project the files are written to, if they aren't printed by default
+    fn default_dir(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Where the file `name` is written when neither `--output` nor `--output-dir` is given, if
+    /// somewhere other than [`default_dir`](Self::default_dir) or stdout
+    fn default_path(&self, _name: &str) -> color_eyre::Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Whether the files are used on their own, so the environment shouldn't be layered on top of
+    /// the project's `shell.nix`
+    fn standalone(&self) -> bool {
+        true
+    }
+
+    /// The files of the format for `dev_env`
+    async fn render(
+        &self,
+        dev_env: &DevEnvironment<'_>,
+        context: &ExportContext<'_>,
+    ) -> color_eyre::Result<Vec<ExportFile>>;
+}
+
+/// What an exporter renders files for, besides the environment
+pub struct ExportContext<'a> {
+    pub project_dir: &'a Path,
+    /// The arguments of the format's subcommand
+    pub matches: &'a ArgMatches,
+}
+
+/// A file an exporter writes, named relative to the output directory
+#[derive(Debug, Clone)]
+pub struct ExportFile {
+    pub name: &'static str,
+    pub contents: String,
+    /// Merges the contents into an existing file, keeping what was in it, so overwriting it loses
+    /// nothing
+    pub merge: Option<fn(&str, &str) -> color_eyre::Result<String>>,
+}
+
+impl ExportFile {
+    pub fn new(name: &'static str, contents: String) -> Self {
+        Self {
+            name,
+            contents,
+            merge: None,
+        }
+    }
+}
+
+/// The options of `riff export flake`
+#[derive(Debug, Args)]
+struct FlakeOptions {
+    /// Also emit a `packages.default` output which builds the project
+    ///
+    /// The flake must then be placed in the project root, next to `Cargo.lock`.
+    #[clap(long)]
+    package: bool,
+    /// The Nix library used to build the `packages.default` output, instead of the one the
+    /// project's `riff.toml` picks [default: build-rust-package]
+    #[clap(long, value_enum, requires = "package")]
+    builder: Option<RustBuilder>,
+}
+
+pub struct FlakeExporter;
+
+#[async_trait]
+impl Exporter for FlakeExporter {
+    fn name(&self) -> &'static str {
+        "flake"
+    }
+
+    fn about(&self) -> &'static str {
+        "Export a `flake.nix` providing your project's development shell"
+    }
+
+    fn long_about(&self) -> Option<&'static str> {
+        Some(
+            "Export a `flake.nix` providing your project's development shell
+
+For example, write a flake which can also build the project with `nix build`:
+
+    $ riff export flake --package --output flake.nix
+
+Use crane instead of `rustPlatform.buildRustPackage` to build it:
+
+    $ riff export flake --package --builder crane",
+        )
+    }
+
+    fn augment_args<'help>(&self, cmd: Command<'help>) -> Command<'help> {
+        FlakeOptions::augment_args(cmd)
+    }
+
+    async fn render(
+        &self,
+        dev_env: &DevEnvironment<'_>,
+        context: &ExportContext<'_>,
+    ) -> color_eyre::Result<Vec<ExportFile>> {
+        let options = FlakeOptions::from_arg_matches(context.matches)?;
+        let flake_nix = if options.package {
+            dev_env.to_buildable_flake(options.builder)?
+        } else {
+            dev_env.to_flake()
+        };
+        Ok(vec![ExportFile::new("flake.nix", flake_nix)])
+    }
+}
+
+/// The options of `riff export oci`
+#[derive(Debug, Args)]
+struct OciOptions {
+    /// The name of the image, which defaults to the name of the project's package or directory
+    #[clap(long)]
+    name: Option<String>,
+}
+
+pub struct OciExporter;
+
+#[async_trait]
+impl Exporter for OciExporter {
+    fn name(&self) -> &'static str {
+        "oci"
+    }
+
+    fn about(&self) -> &'static str {
+        "Export a flake whose `oci` package is a container image of your project's environment"
+    }
+
+    fn long_about(&self) -> Option<&'static str> {
+        Some(
+            "Export a flake whose `oci` package is a container image of your project's environment
+
+The image contains the same native dependencies as `riff shell`, so CI systems without Nix can \
+build the project with them. Build it on Linux and load it into Docker:
+
+    $ riff export oci --output oci/flake.nix
+    $ nix build ./oci#oci && docker load < result",
+        )
+    }
+
+    fn augment_args<'help>(&self, cmd: Command<'help>) -> Command<'help> {
+        OciOptions::augment_args(cmd)
+    }
+
+    async fn render(
+        &self,
+        dev_env: &DevEnvironment<'_>,
+        context: &ExportContext<'_>,
+    ) -> color_eyre::Result<Vec<ExportFile>> {
+        let options = OciOptions::from_arg_matches(context.matches)?;
+        let name = options
+            .name
+            .unwrap_or_else(|| project_name(dev_env, context.project_dir));
+        Ok(vec![ExportFile::new(
+            "flake.nix",
+            dev_env.to_oci_flake(&name, source_date()?.as_deref()),
+        )])
+    }
+}
+
+pub struct DevcontainerExporter;
+
+#[async_trait]
+impl Exporter for DevcontainerExporter {
+    fn name(&self) -> &'static str {
+        "devcontainer"
+    }
+
+    fn about(&self) -> &'static str {
+        "Export a `.devcontainer` directory which loads your project's environment"
+    }
+
+    fn long_about(&self) -> Option<&'static str> {
+        Some(
+            "Export a `.devcontainer` directory which loads your project's environment
+
+VS Code and GitHub Codespaces then start a container with Nix installed, and the same environment \
+as `riff shell` in its terminals. This writes `.devcontainer/devcontainer.json` and the \
+`.devcontainer/flake.nix` it loads the environment from.",
+        )
+    }
+
+    fn default_dir(&self) -> Option<&'static str> {
+        Some(".devcontainer")
+    }
+
+    async fn render(
+        &self,
+        dev_env: &DevEnvironment<'_>,
+        context: &ExportContext<'_>,
+    ) -> color_eyre::Result<Vec<ExportFile>> {
+        let devcontainer = dev_env.to_devcontainer(&project_name(dev_env, context.project_dir));
+        Ok(vec![
+            ExportFile::new("flake.nix", dev_env.to_flake()),
+            ExportFile::new(
+                "devcontainer.json",
+                serde_json::to_string_pretty(&devcontainer)? + "\n",
+            ),
+        ])
+    }
+}
+
+pub struct GithubActionsExporter;
+
+#[async_trait]
+impl Exporter for GithubActionsExporter {
+    fn name(&self) -> &'static str {
+        "github-actions"
+    }
+
+    fn about(&self) -> &'static str {
+        "Set your project's environment up for the later steps of a GitHub Actions job"
+    }
+
+    fn long_about(&self) -> Option<&'static str> {
+        Some(
+            "Set your project's environment up for the later steps of a GitHub Actions job
+
+This appends the variables to the file `$GITHUB_ENV` names, and the directories to put in front \
+of `PATH` to the one `$GITHUB_PATH` names, which GitHub Actions reads after each step:
+
+    - run: riff export github-actions
+    - run: cargo build
+
+The shell hook is bash code, so it's left out. `--output-dir` writes the files as `env` and \
+`path` instead.",
+        )
+    }
+
+    // The paths are those of the job's machine, which does use its `shell.nix`
+    fn standalone(&self) -> bool {
+        false
+    }
+
+    fn default_path(&self, name: &str) -> color_eyre::Result<Option<PathBuf>> {
+        match name {
+            "env" => github_file("GITHUB_ENV").map(Some),
+            "path" => github_file("GITHUB_PATH").map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    async fn render(
+        &self,
+        dev_env: &DevEnvironment<'_>,
+        _context: &ExportContext<'_>,
+    ) -> color_eyre::Result<Vec<ExportFile>> {
+        let activation_script = dev_env.to_activation_script().await?;
+        if activation_script.shell_hook.is_some() {
+            eprintln!(
+                "{warning} The environment's shell hook is bash code, so it's left out of the job",
+                warning = "⚠️".yellow(),
+            );
+        }
+        let (env, path) = activation_script.to_github();
+        Ok(vec![
+            ExportFile {
+                merge: Some(append),
+                ..ExportFile::new("env", env)
+            },
+            ExportFile {
+                merge: Some(append),
+                ..ExportFile::new("path", path)
+            },
+        ])
+    }
+}
+
+/// The file GitHub Actions names in the variable `var`
+pub fn github_file(var: &str) -> color_eyre::Result<PathBuf> {
+    match std::env::var_os(var) {
+        Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => Err(eyre!(
+            "`{var}` isn't set, so this isn't a GitHub Actions step",
+            var = var.cyan(),
+        )),
+    }
+}
+
+/// `contents` after the `existing` lines, as GitHub Actions reads its files
+fn append(existing: &str, contents: &str) -> color_eyre::Result<String> {
+    let separator = if existing.is_empty() || existing.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    Ok(format!("{existing}{separator}{contents}"))
+}
+
+pub struct CargoConfigExporter;
+
+#[async_trait]
+impl Exporter for CargoConfigExporter {
+    fn name(&self) -> &'static str {
+        "cargo-config"
+    }
+
+    fn about(&self) -> &'static str {
+        "Export cargo settings which point builds at your project's environment"
+    }
+
+    fn long_about(&self) -> Option<&'static str> {
+        Some(
+            "Export cargo settings which point builds at your project's environment
+
+This sets the variables `pkg-config` and Nix's compiler wrappers read in cargo's `[env]` table, \
+and uses the environment's C compiler as the linker, so builds run by tools outside of \
+`riff shell`, like an IDE, find the same native libraries. The paths are specific to this \
+machine, so don't commit the result.
+
+Merge the settings into the project's `.cargo/config.toml`, keeping any others:
+
+    $ riff export cargo-config --output .cargo/config.toml",
+        )
+    }
+
+    // The settings are for builds on this machine, which do use its `shell.nix`
+    fn standalone(&self) -> bool {
+        false
+    }
+
+    async fn render(
+        &self,
+        dev_env: &DevEnvironment<'_>,
+        _context: &ExportContext<'_>,
+    ) -> color_eyre::Result<Vec<ExportFile>> {
+        let activation_script = dev_env.to_activation_script().await?;
+        let linker = tool_wrappers::resolve_tool(&activation_script, "cc").ok();
+        let fragment = cargo_config::fragment(
+            &activation_script,
+            dev_env.environment_variables.keys().map(String::as_str),
+            linker.as_deref(),
+        );
+        Ok(vec![ExportFile {
+            merge: Some(merge_cargo_config),
+            ..ExportFile::new("config.toml", fragment.to_string())
+        }])
+    }
+}
+
+fn merge_cargo_config(existing: &str, fragment: &str) -> color_eyre::Result<String> {
+    let fragment = fragment
+        .parse::<Document>()
+        .wrap_err("Could not parse the exported cargo configuration")?;
+    cargo_config::merge(existing, &fragment)
+}
+
+#[cfg(feature = "sbom")]
+pub struct SbomExporter;
+
+#[cfg(feature = "sbom")]
+#[async_trait]
+impl Exporter for SbomExporter {
+    fn name(&self) -> &'static str {
+        "sbom"
+    }
+
+    fn about(&self) -> &'static str {
+        "Export a CycloneDX software bill of materials of your project's environment"
+    }
+
+    fn long_about(&self) -> Option<&'static str> {
+        Some(
+            "Export a CycloneDX software bill of materials of your project's environment
+
+It lists the packages riff adds to the environment, at the versions the nixpkgs revision \
+`riff lock` would record resolves them to, for tools which audit what builds depend on. The \
+project's own dependencies are left to the tools which read its lock files. Set \
+`SOURCE_DATE_EPOCH` to record when it was made:
+
+    $ riff export sbom --output sbom.cdx.json",
+        )
+    }
+
+    async fn render(
+        &self,
+        dev_env: &DevEnvironment<'_>,
+        context: &ExportContext<'_>,
+    ) -> color_eyre::Result<Vec<ExportFile>> {
+        let flake = flake_generator::write_locked_flake(dev_env).await?;
+        if flake.legacy() {
+            return Err(eyre!(
+                "`riff export sbom` resolves the versions of the packages with flakes, which this Nix doesn't support"
+            ));
+        }
+        let lock = RiffLock::new(dev_env, &flake).await?;
+        let bom = sbom::cyclonedx(
+            &project_name(dev_env, context.project_dir),
+            &lock,
+            source_date()?.as_deref(),
+        );
+        Ok(vec![ExportFile::new(
+            "sbom.cdx.json",
+            serde_json::to_string_pretty(&bom)? + "\n",
+        )])
+    }
+}
+
+/// The date exports which record one use, from `SOURCE_DATE_EPOCH`, as an RFC 3339 UTC date
+///
+/// See <https://reproducible-builds.org/specs/source-date-epoch/>. Exports without it use no date
+/// at all, or the Unix epoch, so they are the same whenever they're made.
+pub fn source_date() -> color_eyre::Result<Option<String>> {
+    let epoch = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) if !epoch.is_empty() => epoch,
+        _ => return Ok(None),
+    };
+    let seconds = epoch
+        .parse::<u64>()
+        .map_err(|_| eyre!("`SOURCE_DATE_EPOCH` is `{epoch}`, not a number of seconds"))?;
+    Ok(Some(rfc3339(seconds)))
+}
+
+/// `seconds` since the Unix epoch as an RFC 3339 UTC date, like `2022-09-01T12:00:00Z`
+fn rfc3339(seconds: u64) -> String {
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+    // Howard Hinnant's `civil_from_days`, for days since 1970-01-01
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// The name of the project's package, or of its directory
+fn project_name(dev_env: &DevEnvironment<'_>, project_dir: &Path) -> String {
+    dev_env
+        .project_package
+        .as_ref()
+        .map(|package| package.name.clone())
+        .or_else(|| {
+            project_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().to_lowercase())
+        })
+        .unwrap_or_else(|| "riff-env".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exporters() {
+        for name in [
+            "flake",
+            "oci",
+            "devcontainer",
+            "github-actions",
+            "cargo-config",
+        ] {
+            assert!(find(name).is_some(), "{name}");
+        }
+        assert_eq!(find("sbom").is_some(), cfg!(feature = "sbom"));
+        assert!(find("docker").is_none());
+        let names = EXPORTERS
+            .iter()
+            .map(|exporter| exporter.name())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(names.len(), EXPORTERS.len(), "exporter names are unique");
+    }
+
+    #[test]
+    fn github_files() -> eyre::Result<()> {
+        assert_eq!(append("", "A=1\n")?, "A=1\n");
+        assert_eq!(append("A=1\n", "B=2\n")?, "A=1\nB=2\n");
+        assert_eq!(append("A=1", "B=2\n")?, "A=1\nB=2\n");
+        assert_eq!(GithubActionsExporter.default_path("flake.nix")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1662033600), "2022-09-01T12:00:00Z");
+        assert_eq!(rfc3339(4102444799), "2099-12-31T23:59:59Z");
+    }
+}
@@ -0,0 +1,319 @@
+//! Locked flakes kept in `$XDG_CACHE_HOME/riff/flakes`, so an unchanged environment isn't locked
+//! again on every run.
+//!
+//! Each flake is in a directory named after the hash of its `flake.nix`, with a manifest of the
+//! hashes of its files and the Nix which locked them. Flakes are written to a temporary directory
+//! and moved into place once locked, so a run which is interrupted leaves nothing behind. A
+//! directory which doesn't match its manifest anyway, because it was half-copied, edited, or
+//! cleaned up by something else, is regenerated instead of being handed to `nix`. Flakes which
+//! weren't used for a week are pruned, going by a stamp file touched each time one is reused.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::riff_dirs;
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+const FLAKES_DIR: &str = "flakes";
+const MANIFEST: &str = "riff-manifest.json";
+/// The file whose modification time is when the flake was last used
+const LAST_USED: &str = "riff-last-used";
+/// The prefix of flakes which are still being written
+const PARTIAL_PREFIX: &str = ".partial-";
+/// How long a lock is reused for, matching how long Nix caches branches like `nixpkgs-unstable`
+/// by default (its `tarball-ttl`)
+const LOCK_TTL: Duration = Duration::from_secs(60 * 60);
+/// How long flakes which weren't used are kept
+const PRUNE_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Manifest {
+    flake_nix: String,
+    flake_lock: String,
+    /// The version of the Nix which locked the flake, if it reported one
+    nix_version: Option<String>,
+    /// When the flake was locked, in seconds since the Unix epoch
+    locked_at: u64,
+}
+
+/// What a cached flake directory holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheEntry {
+    Fresh,
+    Missing,
+    /// Locked longer ago than a lock is reused for
+    Expired,
+    /// Locked by another version of Nix, so the lock may be incompatible
+    Stale(String),
+    Corrupted(String),
+}
+
+/// The directory cached flakes are kept in, created if needed
+pub fn flakes_dir() -> color_eyre::Result<PathBuf> {
+    riff_dirs::cache_dir(FLAKES_DIR).wrap_err("Could not create the directory for cached flakes")
+}
+
+/// Where the locked flake of `flake_nix` is cached, in `flakes_dir`
+pub fn flake_dir(flakes_dir: &Path, flake_nix: &str) -> PathBuf {
+    flakes_dir.join(&hash(flake_nix.as_bytes())[..32])
+}
+
+/// A temporary directory in `flakes_dir` to write and lock a flake in, before [`store`] moves it
+/// into place
+pub fn partial_dir(flakes_dir: &Path) -> color_eyre::Result<TempDir> {
+    tempfile::Builder::new()
+        .prefix(PARTIAL_PREFIX)
+        .tempdir_in(flakes_dir)
+        .wrap_err_with(|| format!("Could not create a directory in `{}`", flakes_dir.display()))
+}
+
+/// Whether the flake of `flake_nix` cached in `dir` can be used as it is
+///
+/// An expired lock is still used if `allow_expired`, like when riff is offline. A directory
+/// which isn't usable is removed, and a corrupted one is reported, so it's regenerated.
+pub async fn reusable(
+    dir: &Path,
+    flake_nix: &str,
+    nix_version: Option<&semver::Version>,
+    allow_expired: bool,
+) -> bool {
+    let reason = match check(dir, flake_nix, nix_version, SystemTime::now()).await {
+        CacheEntry::Fresh => return touch(dir).await,
+        CacheEntry::Expired if allow_expired => return touch(dir).await,
+        CacheEntry::Missing => return false,
+        CacheEntry::Expired => {
+            tracing::debug!(dir = %dir.display(), "Locking the cached flake again");
+            None
+        }
+        CacheEntry::Stale(reason) => {
+            tracing::info!(dir = %dir.display(), %reason, "Regenerating a stale cached flake");
+            None
+        }
+        CacheEntry::Corrupted(reason) => {
+            tracing::info!(dir = %dir.display(), %reason, "Regenerating a corrupted cached flake");
+            Some(reason)
+        }
+    };
+    if let Some(reason) = reason {
+        eprintln!(
+            "{warning} The cached flake in `{dir}` {reason}, so Riff is generating it again.",
+            warning = "⚠️".yellow(),
+            dir = dir.display(),
+        );
+    }
+    if let Err(err) = tokio::fs::remove_dir_all(dir).await {
+        tracing::debug!(%err, dir = %dir.display(), "Could not remove the cached flake");
+    }
+    false
+}
+
+/// Check the flake of `flake_nix` cached in `dir` against its manifest, at `now`
+pub async fn check(
+    dir: &Path,
+    flake_nix: &str,
+    nix_version: Option<&semver::Version>,
+    now: SystemTime,
+) -> CacheEntry {
+    if !dir.exists() {
+        return CacheEntry::Missing;
+    }
+    let manifest = match tokio::fs::read_to_string(dir.join(MANIFEST)).await {
+        Ok(manifest) => manifest,
+        Err(_) => return CacheEntry::Corrupted(format!("has no `{MANIFEST}`")),
+    };
+    let manifest: Manifest = match serde_json::from_str(&manifest) {
+        Ok(manifest) => manifest,
+        Err(_) => return CacheEntry::Corrupted(format!("has an unreadable `{MANIFEST}`")),
+    };
+    for (file, expected) in [
+        ("flake.nix", &manifest.flake_nix),
+        ("flake.lock", &manifest.flake_lock),
+    ] {
+        match tokio::fs::read(dir.join(file)).await {
+            Ok(contents) if hash(&contents) == *expected => (),
+            Ok(_) => return CacheEntry::Corrupted(format!("has a `{file}` which was changed")),
+            Err(_) => return CacheEntry::Corrupted(format!("is missing its `{file}`")),
+        }
+    }
+    if manifest.flake_nix != hash(flake_nix.as_bytes()) {
+        return CacheEntry::Corrupted("holds a different `flake.nix`".to_string());
+    }
+    let installed = nix_version.map(ToString::to_string);
+    if manifest.nix_version != installed {
+        return CacheEntry::Stale(format!(
+            "was locked by Nix {}, not the installed {}",
+            manifest.nix_version.as_deref().unwrap_or("(unknown)"),
+            installed.as_deref().unwrap_or("(unknown)"),
+        ));
+    }
+    let locked_at = SystemTime::UNIX_EPOCH + Duration::from_secs(manifest.locked_at);
+    match now.duration_since(locked_at) {
+        Ok(age) if age > LOCK_TTL => CacheEntry::Expired,
+        _ => CacheEntry::Fresh,
+    }
+}
+
+/// Record the locked flake of `flake_nix` in `partial` and move it to `dir`
+pub async fn store(
+    partial: TempDir,
+    dir: &Path,
+    flake_nix: &str,
+    nix_version: Option<&semver::Version>,
+) -> color_eyre::Result<()> {
+    let flake_lock = tokio::fs::read(partial.path().join("flake.lock"))
+        .await
+        .wrap_err("Could not read the generated `flake.lock`")?;
+    let manifest = Manifest {
+        flake_nix: hash(flake_nix.as_bytes()),
+        flake_lock: hash(&flake_lock),
+        nix_version: nix_version.map(ToString::to_string),
+        locked_at: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs(),
+    };
+    tokio::fs::write(
+        partial.path().join(MANIFEST),
+        serde_json::to_string_pretty(&manifest)?,
+    )
+    .await
+    .wrap_err_with(|| format!("Could not write `{MANIFEST}`"))?;
+
+    tokio::fs::write(partial.path().join(LAST_USED), "")
+        .await
+        .wrap_err_with(|| format!("Could not write `{LAST_USED}`"))?;
+
+    if let Some(flakes_dir) = dir.parent() {
+        prune(flakes_dir).await;
+    }
+    let partial = partial.into_path();
+    match tokio::fs::rename(&partial, dir).await {
+        Ok(()) => Ok(()),
+        // Another riff cached the same flake in the meantime, and may be using it, so it's kept
+        Err(_) if dir.join(MANIFEST).exists() => {
+            tracing::debug!(dir = %dir.display(), "Using the flake another riff cached");
+            tokio::fs::remove_dir_all(&partial).await.ok();
+            Ok(())
+        }
+        Err(err) => {
+            tokio::fs::remove_dir_all(&partial).await.ok();
+            Err(err)
+                .wrap_err_with(|| format!("Could not move the locked flake to `{}`", dir.display()))
+        }
+    }
+}
+
+/// Record that the flake in `dir` was used just now, so it isn't pruned
+async fn touch(dir: &Path) -> bool {
+    if let Err(err) = tokio::fs::write(dir.join(LAST_USED), "").await {
+        tracing::debug!(%err, dir = %dir.display(), "Could not record the use of the cached flake");
+    }
+    true
+}
+
+/// Remove the flakes in `flakes_dir` which weren't used for a while, and the remains of
+/// interrupted runs
+async fn prune(flakes_dir: &Path) {
+    let mut entries = match tokio::fs::read_dir(flakes_dir).await {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        // Flakes cached before riff kept the stamp only have the time they were locked
+        let metadata = match tokio::fs::metadata(entry.path().join(LAST_USED)).await {
+            Ok(metadata) => Ok(metadata),
+            Err(_) => entry.metadata().await,
+        };
+        let unused_for = metadata
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        if matches!(unused_for, Some(unused_for) if unused_for > PRUNE_AFTER) {
+            tracing::debug!(dir = %entry.path().display(), "Pruning a cached flake");
+            tokio::fs::remove_dir_all(entry.path()).await.ok();
+        }
+    }
+}
+
+fn hash(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::fs::write;
+
+    const FLAKE_NIX: &str = "{ outputs = { self }: { }; }";
+
+    async fn cached_flake(flakes_dir: &Path, version: &semver::Version) -> eyre::Result<PathBuf> {
+        let partial = partial_dir(flakes_dir)?;
+        write(partial.path().join("flake.nix"), FLAKE_NIX).await?;
+        write(partial.path().join("flake.lock"), r#"{"version":7}"#).await?;
+        let dir = flake_dir(flakes_dir, FLAKE_NIX);
+        store(partial, &dir, FLAKE_NIX, Some(version)).await?;
+        Ok(dir)
+    }
+
+    #[tokio::test]
+    async fn cache_entries() -> eyre::Result<()> {
+        let flakes_dir = TempDir::new()?;
+        let version = semver::Version::new(2, 11, 1);
+        let now = SystemTime::now();
+        let dir = flake_dir(flakes_dir.path(), FLAKE_NIX);
+        assert_eq!(
+            check(&dir, FLAKE_NIX, Some(&version), now).await,
+            CacheEntry::Missing
+        );
+
+        let dir = cached_flake(flakes_dir.path(), &version).await?;
+        assert_eq!(
+            check(&dir, FLAKE_NIX, Some(&version), now).await,
+            CacheEntry::Fresh
+        );
+        assert_eq!(
+            check(&dir, FLAKE_NIX, Some(&version), now + LOCK_TTL * 2).await,
+            CacheEntry::Expired
+        );
+        assert!(matches!(
+            check(&dir, FLAKE_NIX, Some(&semver::Version::new(2, 12, 0)), now).await,
+            CacheEntry::Stale(_)
+        ));
+        // Nothing but the finished flake is left in the cache
+        assert_eq!(std::fs::read_dir(flakes_dir.path())?.count(), 1);
+        let last_used = std::fs::metadata(dir.join(LAST_USED))?.modified()?;
+        assert!(reusable(&dir, FLAKE_NIX, Some(&version), false).await);
+        assert!(std::fs::metadata(dir.join(LAST_USED))?.modified()? >= last_used);
+
+        // Caching a flake which another riff just cached leaves theirs in place
+        assert_eq!(cached_flake(flakes_dir.path(), &version).await?, dir);
+        assert_eq!(
+            check(&dir, FLAKE_NIX, Some(&version), now).await,
+            CacheEntry::Fresh
+        );
+        assert_eq!(std::fs::read_dir(flakes_dir.path())?.count(), 1);
+
+        write(dir.join("flake.lock"), r#"{"version":"#).await?;
+        assert_eq!(
+            check(&dir, FLAKE_NIX, Some(&version), now).await,
+            CacheEntry::Corrupted("has a `flake.lock` which was changed".to_string())
+        );
+        assert!(!reusable(&dir, FLAKE_NIX, Some(&version), true).await);
+        assert!(!dir.exists());
+
+        let dir = cached_flake(flakes_dir.path(), &version).await?;
+        tokio::fs::remove_file(dir.join(MANIFEST)).await?;
+        assert_eq!(
+            check(&dir, FLAKE_NIX, Some(&version), now).await,
+            CacheEntry::Corrupted(format!("has no `{MANIFEST}`"))
+        );
+        Ok(())
+    }
+}
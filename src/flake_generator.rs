@@ -1,14 +1,161 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use eyre::{eyre, WrapErr};
 use owo_colors::OwoColorize;
 use tempfile::TempDir;
 use tokio::process::Command;
 
+use crate::conflicts;
+use crate::cross_target;
 use crate::dependency_registry::DependencyRegistry;
-use crate::dev_env::DevEnvironment;
+use crate::detect_report::{DetectReport, ReplayedProject};
+use crate::dev_env::{DevEnvironment, DevEnvironmentAppliable};
+use crate::environment_overrides::EnvironmentOverrides;
+use crate::flake_cache;
+use crate::gc_roots;
+use crate::legacy_nix;
+use crate::network;
+use crate::nix_passthrough;
+use crate::progress;
+use crate::riff_lock::{RiffLock, LOCK_FILE};
 use crate::spinner::SimpleSpinner;
-use crate::telemetry::Telemetry;
+use crate::telemetry::{self, Phase, Telemetry};
+
+/// A generated and locked flake, deleted when dropped unless it's cached
+#[derive(Debug)]
+pub struct GeneratedFlake {
+    dir: FlakeDir,
+    /// Whether evaluating the flake reads files outside of it, such as an existing `shell.nix`
+    impure: bool,
+    /// The profile which keeps the evaluated environment in the Nix store, if there is one
+    gc_root: Option<PathBuf>,
+    /// Whether the directory holds a `shell.nix` for `nix-shell` instead, as the installed Nix
+    /// doesn't support flakes
+    legacy: bool,
+}
+
+#[derive(Debug)]
+enum FlakeDir {
+    Temporary(TempDir),
+    /// In the flake cache, see [`flake_cache`]
+    Cached(PathBuf),
+}
+
+impl GeneratedFlake {
+    pub fn path(&self) -> &Path {
+        match &self.dir {
+            FlakeDir::Temporary(dir) => dir.path(),
+            FlakeDir::Cached(dir) => dir,
+        }
+    }
+
+    pub fn gc_root(&self) -> Option<&Path> {
+        self.gc_root.as_deref()
+    }
+
+    pub fn legacy(&self) -> bool {
+        self.legacy
+    }
+
+    /// Keep the environment evaluated from the flake in the Nix store while `project_dir` exists
+    ///
+    /// Failing to do so only costs a rebuild later, so it is not an error.
+    pub async fn root_for_project(&mut self, project_dir: &Path) {
+        // `nix-shell` can't register a profile
+        if self.legacy {
+            return;
+        }
+        match gc_roots::profile_for(project_dir, self.path()).await {
+            Ok(profile) => self.gc_root = Some(profile),
+            Err(err) => tracing::debug!(%err, "Could not register a garbage collector root"),
+        }
+    }
+
+    /// Extra arguments `nix` needs to evaluate the flake
+    pub fn nix_args(&self) -> Vec<&'static str> {
+        let mut args = network::nix_args().to_vec();
+        if self.impure {
+            args.push("--impure");
+        }
+        args.extend(nix_passthrough::args().iter().map(String::as_str));
+        args
+    }
+}
+
+/// How to detect a project's environment, beyond where the project is
+#[derive(Debug, Clone, Default, Hash)]
+pub struct DetectOptions {
+    /// Add the tools required by `justfile`/`Makefile` recipes
+    pub task_runner_tools: bool,
+    /// Don't layer the environment on top of an existing `shell.nix` or `default.nix`
+    pub ignore_existing_nix: bool,
+    /// Saved `cargo metadata` output to use instead of running `cargo metadata`
+    pub from_metadata: Option<PathBuf>,
+    /// A detection report to replay instead of reading the project
+    pub from_detect_report: Option<PathBuf>,
+    /// Don't guess the inputs of `-sys` crates the registry doesn't know
+    pub no_sys_heuristics: bool,
+    /// Only apply curated or declared rules, skipping any heuristics
+    pub conservative: bool,
+    /// Leave out dev-dependencies
+    pub no_dev: bool,
+    /// Leave out build-dependencies
+    pub no_build_deps: bool,
+    /// Fail when any of the project's languages fails to be detected
+    pub strict_detect: bool,
+    /// Refuse to use an environment which differs from the one in `riff.lock`
+    pub locked: bool,
+    /// Additions from the command line, applied after detection
+    pub overrides: EnvironmentOverrides,
+    /// The registry to use instead of the one the project's `riff.toml` names, or riff's own
+    pub registry_url: Option<String>,
+    /// The target triple to cross-compile to, instead of the host
+    pub target: Option<String>,
+}
+
+/// The global options which shape detection, for the commands which detect a project to
+/// `#[clap(flatten)]`
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct DetectArgs {
+    #[clap(from_global)]
+    pub registry_url: Option<String>,
+    #[clap(from_global)]
+    pub task_runner_tools: bool,
+    #[clap(from_global)]
+    pub ignore_existing_nix: bool,
+    #[clap(from_global)]
+    pub no_sys_heuristics: bool,
+    #[clap(from_global)]
+    pub conservative: bool,
+    #[clap(from_global)]
+    pub no_dev: bool,
+    #[clap(from_global)]
+    pub no_build_deps: bool,
+    #[clap(from_global)]
+    pub strict_detect: bool,
+    #[clap(from_global, value_parser)]
+    pub from_metadata: Option<PathBuf>,
+    #[clap(from_global, value_parser)]
+    pub from_detect_report: Option<PathBuf>,
+}
+
+impl From<&DetectArgs> for DetectOptions {
+    fn from(args: &DetectArgs) -> Self {
+        Self {
+            registry_url: args.registry_url.clone(),
+            task_runner_tools: args.task_runner_tools,
+            ignore_existing_nix: args.ignore_existing_nix,
+            no_sys_heuristics: args.no_sys_heuristics,
+            conservative: args.conservative,
+            no_dev: args.no_dev,
+            no_build_deps: args.no_build_deps,
+            strict_detect: args.strict_detect,
+            from_metadata: args.from_metadata.clone(),
+            from_detect_report: args.from_detect_report.clone(),
+            ..Default::default()
+        }
+    }
+}
 
 /// Generates a `flake.nix` by inspecting the specified `project_dir` for supported project types.
 #[tracing::instrument(skip(disable_telemetry))]
@@ -16,76 +163,65 @@ pub async fn generate_flake_from_project_dir(
     project_dir: Option<PathBuf>,
     offline: bool,
     disable_telemetry: bool,
-) -> color_eyre::Result<TempDir> {
+    options: DetectOptions,
+) -> color_eyre::Result<GeneratedFlake> {
     let project_dir = match project_dir {
         Some(dir) => dir,
         None => std::env::current_dir().wrap_err("Current working directory was invalid")?,
     };
     tracing::debug!("Project directory is '{}'.", project_dir.display());
 
-    let registry = DependencyRegistry::new(offline).await?;
-    let mut dev_env = DevEnvironment::new(&registry);
-
-    match dev_env.detect(&project_dir).await {
-        Ok(_) => {}
-        err @ Err(_) => {
-            let wrapped_err = err
-                .wrap_err_with(|| {
-                    format!(
-                        "\
-                            `{colored_project_dir}` doesn't contain a project recognized by Riff.\n\
-                            Try running `{riff_shell}` in a Rust project directory.\
-                    ",
-                        colored_project_dir = &project_dir.display().to_string().green(),
-                        riff_shell = "riff shell".cyan(),
-                    )
-                })
-                .unwrap_err();
-            eprintln!("{wrapped_err}");
-            std::process::exit(1);
-        }
-    };
+    let registry =
+        DependencyRegistry::for_project(offline, options.registry_url.as_deref(), &project_dir)
+            .await?;
+    let dev_env = detect_dev_env(&project_dir, &registry, disable_telemetry, options).await?;
 
-    // If the user is using an old version of `riff`, we want to let them know.
-    // We do it after detecting the dependencies because we'd prefer the user's first
-    // output from the program not to be a scary error, especially when it's neither scary or an
-    // error.
-    let latest_riff_version = registry.latest_riff_version().await;
-    // We don't want to error anywhere here
-    if latest_riff_version
-        .as_ref()
-        .and_then(|v| semver::Version::parse(v).ok())
-        .and_then(|registry_version| {
-            semver::Version::parse(env!("CARGO_PKG_VERSION"))
-                .ok()
-                .map(|current_version| registry_version > current_version)
-        })
-        .unwrap_or(false)
-    {
-        eprintln!(
-            "📦 A new version of `{riff}` ({latest_riff_version_colored}) is available! {riff_download_url}",
-            riff = "riff".cyan(),
-            latest_riff_version_colored = latest_riff_version.as_ref().cloned().unwrap_or_else(|| "unknown".to_string()).yellow(),
-            riff_download_url = "https://github.com/DeterminateSystems/riff/releases".blue().underline(),
-        );
-    }
+    write_locked_flake(&dev_env).await
+}
 
-    if !(disable_telemetry || offline) {
-        match Telemetry::new()
-            .await
-            .with_detected_languages(&dev_env.detected_languages)
-            .send()
-            .await
-        {
-            Ok(_) => (),
-            Err(err) => tracing::debug!(%err, "Could not send telemetry"),
-        };
+/// Writes the flake for `dev_env` to the flake cache and locks it, unless it's already there.
+///
+/// Without a cache directory, the flake is written to a temporary directory instead.
+#[tracing::instrument(skip_all)]
+pub async fn write_locked_flake(
+    dev_env: &DevEnvironment<'_>,
+) -> color_eyre::Result<GeneratedFlake> {
+    if !legacy_nix::flakes_supported().await {
+        return write_shell_nix(dev_env).await;
     }
 
     let flake_nix = dev_env.to_flake();
     tracing::trace!("Generated 'flake.nix':\n{}", flake_nix);
 
-    let flake_dir = TempDir::new()?;
+    let offline = dev_env.registry.offline() || network::disabled();
+    let nix_version = legacy_nix::nix_version().await;
+    let flakes_dir = match flake_cache::flakes_dir() {
+        Ok(flakes_dir) => Some(flakes_dir),
+        Err(err) => {
+            tracing::debug!(%err, "Not caching the generated flake");
+            None
+        }
+    };
+    let cached_dir = flakes_dir
+        .as_ref()
+        .map(|flakes_dir| flake_cache::flake_dir(flakes_dir, &flake_nix));
+    if let Some(cached_dir) = &cached_dir {
+        // Offline, an old lock is better than none
+        if flake_cache::reusable(cached_dir, &flake_nix, nix_version, offline).await {
+            tracing::debug!(dir = %cached_dir.display(), "Using the cached flake");
+            return Ok(GeneratedFlake {
+                dir: FlakeDir::Cached(cached_dir.clone()),
+                impure: dev_env.base_shell.is_some(),
+                gc_root: None,
+                legacy: false,
+            });
+        }
+    }
+
+    let flake_dir = match &flakes_dir {
+        Some(flakes_dir) => flake_cache::partial_dir(flakes_dir)?,
+        None => TempDir::new()?,
+    };
     let flake_nix_path = flake_dir.path().join("flake.nix");
 
     tokio::fs::write(&flake_nix_path, &flake_nix)
@@ -98,7 +234,8 @@ pub async fn generate_flake_from_project_dir(
         .arg("lock")
         .args(&["--extra-experimental-features", "flakes nix-command"])
         .arg("-L")
-        .arg(format!("path://{}", flake_dir.path().to_str().unwrap()));
+        .arg(format!("path://{}", flake_dir.path().to_str().unwrap()))
+        .kill_on_drop(true);
 
     if offline {
         nix_lock_command.arg("--offline");
@@ -110,49 +247,310 @@ pub async fn generate_flake_from_project_dir(
         nix_flake_lock = "nix flake lock".cyan()
     )))
     .context("Failed to construct progress spinner")?;
+    let lock_progress = progress::start(progress::Phase::Lock, "Running `nix flake lock`");
 
     let nix_lock_exit = match nix_lock_command.output().await {
         Ok(nix_lock_exit) => nix_lock_exit,
         err @ Err(_) => {
+            lock_progress.finish(false);
             let wrapped_err = err
                 .wrap_err_with(|| {
                     format!(
                         "\
                     Could not execute `{nix_lock}`. Is `{nix}` installed?\n\n\
                     Get instructions for installing Nix: {nix_install_url}\n\
+                    Run `{riff_doctor}` to check your setup.\n\
                     Underlying error\
                     ",
                         nix_lock = "nix flake lock".cyan(),
                         nix = "nix".cyan(),
                         nix_install_url = "https://nixos.org/download.html".blue().underline(),
+                        riff_doctor = "riff doctor".cyan(),
                     )
                 })
                 .unwrap_err();
+            record_failure_telemetry(dev_env, Phase::Lock).await;
             eprintln!("{wrapped_err:#}");
-            std::process::exit(1);
+            telemetry::exit(1).await;
         }
     };
 
     spinner.finish_and_clear();
+    lock_progress.finish(nix_lock_exit.status.success());
 
     if !nix_lock_exit.status.success() {
+        record_failure_telemetry(dev_env, Phase::Lock).await;
         return Err(eyre!(
-            "`nix flake lock` exited with code {}:\n{}",
+            "`nix flake lock` exited with code {}:\n{}{}",
             nix_lock_exit
                 .status
                 .code()
                 .map(|x| x.to_string())
                 .unwrap_or_else(|| "unknown".to_string()),
             std::str::from_utf8(&nix_lock_exit.stderr)?,
+            network::missing_hint(),
         ));
     }
 
-    Ok(flake_dir)
+    let dir = match cached_dir {
+        Some(cached_dir) => {
+            flake_cache::store(flake_dir, &cached_dir, &flake_nix, nix_version).await?;
+            FlakeDir::Cached(cached_dir)
+        }
+        None => FlakeDir::Temporary(flake_dir),
+    };
+
+    Ok(GeneratedFlake {
+        dir,
+        impure: dev_env.base_shell.is_some(),
+        gc_root: None,
+        legacy: false,
+    })
+}
+
+/// Writes a `shell.nix` for `dev_env` to a temporary directory, for Nix without flakes
+#[tracing::instrument(skip_all)]
+async fn write_shell_nix(dev_env: &DevEnvironment<'_>) -> color_eyre::Result<GeneratedFlake> {
+    eprintln!(
+        "{warning} The installed Nix doesn't support flakes, so Riff is using `{nix_shell}`. \
+         Commands which build, lock, or export flakes need Nix {minimum_version} or later.",
+        warning = "⚠️".yellow(),
+        nix_shell = "nix-shell".cyan(),
+        minimum_version = legacy_nix::MINIMUM_NIX_VERSION,
+    );
+
+    if !dev_env.flake_inputs.is_empty() || !dev_env.overlays.is_empty() {
+        eprintln!(
+            "{warning} `{nix_shell}` can't use flakes, so the environment leaves out the `{flake_inputs}` and `{overlays}` of the project.",
+            warning = "⚠️".yellow(),
+            nix_shell = "nix-shell".cyan(),
+            flake_inputs = "flake-inputs".cyan(),
+            overlays = "overlays".cyan(),
+        );
+    }
+    let shell_nix = dev_env.to_shell_nix();
+    tracing::trace!("Generated 'shell.nix':\n{}", shell_nix);
+
+    let shell_dir = TempDir::new()?;
+    tokio::fs::write(shell_dir.path().join(legacy_nix::SHELL_NIX), &shell_nix)
+        .await
+        .wrap_err("Unable to write shell.nix")?;
+
+    Ok(GeneratedFlake {
+        dir: FlakeDir::Temporary(shell_dir),
+        impure: false,
+        gc_root: None,
+        legacy: true,
+    })
+}
+
+/// Detects the environment of the project in `project_dir` again, for commands which follow its
+/// changes.
+///
+/// Unlike [`detect_dev_env`], a project which isn't recognized is an error rather than the end
+/// of riff, and there's no telemetry or notice of new versions of riff.
+pub async fn redetect_dev_env<'a>(
+    project_dir: &Path,
+    registry: &'a DependencyRegistry,
+    options: &DetectOptions,
+) -> color_eyre::Result<DevEnvironment<'a>> {
+    let replayed = replay(options).await?;
+    let (detect_dir, from_metadata) = detect_source(project_dir, options, replayed.as_ref());
+    let mut dev_env = new_dev_env(registry, options, from_metadata);
+    dev_env.detect(&detect_dir).await?;
+    apply_options(project_dir, options, &mut dev_env).await?;
+    Ok(dev_env)
+}
+
+/// The project recreated from the detection report `options` replays, if it names one
+///
+/// It's deleted when dropped, so it must be kept until detection finishes.
+async fn replay(options: &DetectOptions) -> color_eyre::Result<Option<ReplayedProject>> {
+    match &options.from_detect_report {
+        Some(report) => Ok(Some(DetectReport::read(report).await?.unpack().await?)),
+        None => Ok(None),
+    }
+}
+
+/// The directory to detect the project in, and the `cargo metadata` output to use for it
+fn detect_source(
+    project_dir: &Path,
+    options: &DetectOptions,
+    replayed: Option<&ReplayedProject>,
+) -> (PathBuf, Option<PathBuf>) {
+    match replayed {
+        Some(replayed) => (replayed.project_dir(), replayed.cargo_metadata()),
+        None => (project_dir.to_path_buf(), options.from_metadata.clone()),
+    }
+}
+
+/// An environment to detect a project in with `options`
+fn new_dev_env<'a>(
+    registry: &'a DependencyRegistry,
+    options: &DetectOptions,
+    from_metadata: Option<PathBuf>,
+) -> DevEnvironment<'a> {
+    DevEnvironment::new(registry)
+        .with_task_runner_tools(options.task_runner_tools)
+        .with_existing_nix(!options.ignore_existing_nix)
+        .with_sys_heuristics(!options.no_sys_heuristics)
+        .with_conservative(options.conservative)
+        .with_no_dev(options.no_dev)
+        .with_no_build_deps(options.no_build_deps)
+        .with_strict_detect(options.strict_detect)
+        .with_cargo_metadata_snapshot(from_metadata)
+        .with_target(options.target.clone())
+}
+
+/// Apply the `options` which change the detected `dev_env`: the lock, the target and overrides
+async fn apply_options(
+    project_dir: &Path,
+    options: &DetectOptions,
+    dev_env: &mut DevEnvironment<'_>,
+) -> color_eyre::Result<()> {
+    if options.locked {
+        let lock = RiffLock::read(project_dir).await?.ok_or_else(|| {
+            eyre!(
+                "`{lock_file}` does not exist, so the environment can't be locked. Create it with `{riff_lock}`.",
+                lock_file = LOCK_FILE,
+                riff_lock = "riff lock".cyan(),
+            )
+        })?;
+        lock.enforce(&dev_env.registry.hash().await, dev_env)?;
+    }
+
+    if let Some(target) = &options.target {
+        cross_target::apply(target, dev_env)?;
+    }
+    options.overrides.apply(dev_env);
+    Ok(())
+}
+
+/// Detects the environment of the project in `project_dir`, exiting if it is not recognized.
+///
+/// This also lets the user know about new versions of riff, and records the telemetry riff sends
+/// as it exits.
+#[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+pub async fn detect_dev_env<'a>(
+    project_dir: &Path,
+    registry: &'a DependencyRegistry,
+    disable_telemetry: bool,
+    options: DetectOptions,
+) -> color_eyre::Result<DevEnvironment<'a>> {
+    let replayed = replay(&options).await?;
+    let (detect_dir, from_metadata) = detect_source(project_dir, &options, replayed.as_ref());
+
+    let mut dev_env = new_dev_env(registry, &options, from_metadata);
+    dev_env.telemetry_disabled |= disable_telemetry;
+
+    let detect_progress = progress::start(progress::Phase::Detect, "Detecting the project");
+    match dev_env.detect(&detect_dir).await {
+        Ok(_) => detect_progress.finish(true),
+        Err(err) if !dev_env.detected_languages.is_empty() => {
+            detect_progress.finish(false);
+            // A language was recognized, so the cause is what the user needs to see
+            let wrapped_err = err.wrap_err(format!(
+                "Riff recognized the project in `{colored_project_dir}`, but could not detect its environment",
+                colored_project_dir = &project_dir.display().to_string().green(),
+            ));
+            record_failure_telemetry(&dev_env, Phase::Detect).await;
+            eprintln!("{wrapped_err:#}");
+            telemetry::exit(1).await;
+        }
+        err @ Err(_) => {
+            detect_progress.finish(false);
+            let wrapped_err = err
+                .wrap_err_with(|| {
+                    format!(
+                        "\
+                            `{colored_project_dir}` doesn't contain a project recognized by Riff.\n\
+                            Try running `{riff_shell}` in a Rust, Node.js, Python, Go, Ruby, C/C++, OCaml, Swift, Lua, Julia, Nim, Crystal, Bazel, or Buck2 project directory.\
+                    ",
+                        colored_project_dir = &project_dir.display().to_string().green(),
+                        riff_shell = "riff shell".cyan(),
+                    )
+                })
+                .unwrap_err();
+            record_failure_telemetry(&dev_env, Phase::Detect).await;
+            eprintln!("{wrapped_err}");
+            telemetry::exit(1).await;
+        }
+    };
+
+    apply_options(project_dir, &options, &mut dev_env).await?;
+    conflicts::check(&dev_env).await?;
+
+    if dev_env.impure_build {
+        eprintln!(
+            "{notice} The build scripts of this project use the network (`{impure_build}` in `{riff_toml}`), \
+             so building it with Nix needs `{relaxed}`",
+            notice = "🌐".bold(),
+            impure_build = "impure-build".cyan(),
+            riff_toml = "riff.toml".cyan(),
+            relaxed = "sandbox = relaxed".cyan(),
+        );
+        if options.locked || is_ci::cached() {
+            eprintln!(
+                "{warning} Builds of this project are not reproducible: its build scripts download \
+                 files which neither `{riff_lock}` nor Nix can pin.",
+                warning = "⚠️".yellow(),
+                riff_lock = LOCK_FILE.cyan(),
+            );
+        }
+    }
+
+    // If the user is using an old version of `riff`, we want to let them know.
+    // We do it after detecting the dependencies because we'd prefer the user's first
+    // output from the program not to be a scary error, especially when it's neither scary or an
+    // error.
+    let latest_riff_version = registry.latest_riff_version().await;
+    // We don't want to error anywhere here
+    if latest_riff_version
+        .as_ref()
+        .and_then(|v| semver::Version::parse(v).ok())
+        .and_then(|registry_version| {
+            semver::Version::parse(env!("CARGO_PKG_VERSION"))
+                .ok()
+                .map(|current_version| registry_version > current_version)
+        })
+        .unwrap_or(false)
+    {
+        eprintln!(
+            "📦 A new version of `{riff}` ({latest_riff_version_colored}) is available! {riff_download_url}",
+            riff = "riff".cyan(),
+            latest_riff_version_colored = latest_riff_version.as_ref().cloned().unwrap_or_else(|| "unknown".to_string()).yellow(),
+            riff_download_url = "https://github.com/DeterminateSystems/riff/releases".blue().underline(),
+        );
+    }
+
+    // Sent as riff exits, with how long the command took and how it ended
+    if !(dev_env.telemetry_disabled || registry.offline()) {
+        Telemetry::new()
+            .await
+            .with_detected_languages(&dev_env.detected_languages)
+            .with_registry_fresh(registry.fresh())
+            .record();
+    }
+
+    Ok(dev_env)
+}
+
+/// Record that `phase` failed for `dev_env`, unless telemetry is disabled
+async fn record_failure_telemetry(dev_env: &DevEnvironment<'_>, phase: Phase) {
+    if dev_env.telemetry_disabled || dev_env.registry.offline() {
+        return;
+    }
+    Telemetry::new()
+        .await
+        .with_detected_languages(&dev_env.detected_languages)
+        .with_failed_phase(phase)
+        .with_registry_fresh(dev_env.registry.fresh())
+        .record();
 }
 
 #[cfg(test)]
 mod tests {
-    use super::generate_flake_from_project_dir;
+    use super::{generate_flake_from_project_dir, DetectOptions};
     use tempfile::TempDir;
     use tokio::fs::{read_to_string, write};
 
@@ -182,8 +580,13 @@ path = "lib.rs"
         )
         .await?;
 
-        let flake_dir =
-            generate_flake_from_project_dir(Some(temp_dir.path().to_owned()), true, true).await?;
+        let flake_dir = generate_flake_from_project_dir(
+            Some(temp_dir.path().to_owned()),
+            true,
+            true,
+            DetectOptions::default(),
+        )
+        .await?;
         let flake = read_to_string(flake_dir.path().join("flake.nix")).await?;
 
         assert!(
@@ -0,0 +1,166 @@
+//! Garbage collector roots which keep the environments of projects in the Nix store.
+//!
+//! Each root is a directory in `$XDG_STATE_HOME/riff/gcroots`, named after the hash of the
+//! generated flake. It holds the profile `nix print-dev-env --profile` evaluates the environment
+//! into, which Nix registers as an indirect root, and the path of the project it belongs to.
+
+use std::path::{Path, PathBuf};
+
+use crate::riff_dirs;
+use eyre::WrapErr;
+use sha2::{Digest, Sha256};
+
+const GC_ROOTS_DIR: &str = "gcroots";
+/// The file in a root holding the path of its project
+const PROJECT_FILE: &str = "project";
+const PROFILE: &str = "profile";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcRoot {
+    pub dir: PathBuf,
+    /// The hash of the flake the rooted environment was generated from
+    pub flake_hash: String,
+    /// The project the environment belongs to, if the root records one
+    pub project_dir: Option<PathBuf>,
+}
+
+impl GcRoot {
+    /// Whether the project the root belongs to no longer exists
+    pub fn is_stale(&self) -> bool {
+        match &self.project_dir {
+            Some(project_dir) => !project_dir.is_dir(),
+            None => true,
+        }
+    }
+
+    pub async fn remove(&self) -> color_eyre::Result<()> {
+        tokio::fs::remove_dir_all(&self.dir)
+            .await
+            .wrap_err_with(|| format!("Could not remove `{}`", self.dir.display()))
+    }
+}
+
+/// The profile to evaluate the environment of the flake in `flake_dir` into, so it stays in the
+/// Nix store as long as `project_dir` exists
+///
+/// The roots of older environments of the project are removed, so each project only keeps its
+/// latest environment alive.
+pub async fn profile_for(project_dir: &Path, flake_dir: &Path) -> color_eyre::Result<PathBuf> {
+    let roots_dir = roots_dir()?;
+    let project_dir = tokio::fs::canonicalize(project_dir)
+        .await
+        .wrap_err_with(|| format!("Could not resolve `{}`", project_dir.display()))?;
+    let flake_hash = flake_hash(flake_dir).await?;
+
+    for root in read_roots(&roots_dir).await? {
+        if root.project_dir.as_ref() == Some(&project_dir) && root.flake_hash != flake_hash {
+            tracing::debug!(root = %root.dir.display(), "Removing the root of an older environment");
+            root.remove().await?;
+        }
+    }
+
+    let root_dir = roots_dir.join(&flake_hash);
+    tokio::fs::create_dir_all(&root_dir).await?;
+    tokio::fs::write(
+        root_dir.join(PROJECT_FILE),
+        project_dir.to_string_lossy().as_bytes(),
+    )
+    .await?;
+    Ok(root_dir.join(PROFILE))
+}
+
+/// Remove the root holding `profile`, so the next `nix store gc` can delete its environment
+pub async fn remove_root_of(profile: &Path) -> color_eyre::Result<()> {
+    let roots_dir = roots_dir()?;
+    match profile.parent() {
+        Some(dir) if dir.parent() == Some(roots_dir.as_path()) => tokio::fs::remove_dir_all(dir)
+            .await
+            .wrap_err_with(|| format!("Could not remove `{}`", dir.display())),
+        _ => Ok(()),
+    }
+}
+
+/// All the roots riff registered
+pub async fn roots() -> color_eyre::Result<Vec<GcRoot>> {
+    read_roots(&roots_dir()?).await
+}
+
+fn roots_dir() -> color_eyre::Result<PathBuf> {
+    Ok(riff_dirs::state_dir(GC_ROOTS_DIR)?)
+}
+
+async fn read_roots(roots_dir: &Path) -> color_eyre::Result<Vec<GcRoot>> {
+    let mut roots = Vec::new();
+    let mut entries = tokio::fs::read_dir(roots_dir)
+        .await
+        .wrap_err_with(|| format!("Could not read `{}`", roots_dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let project_dir = tokio::fs::read_to_string(entry.path().join(PROJECT_FILE))
+            .await
+            .ok()
+            .map(PathBuf::from);
+        roots.push(GcRoot {
+            dir: entry.path(),
+            flake_hash: entry.file_name().to_string_lossy().into_owned(),
+            project_dir,
+        });
+    }
+    roots.sort_by(|a, b| a.dir.cmp(&b.dir));
+    Ok(roots)
+}
+
+/// The hash of the `flake.nix` and `flake.lock` in `flake_dir`
+async fn flake_hash(flake_dir: &Path) -> color_eyre::Result<String> {
+    let mut hasher = Sha256::new();
+    for file in ["flake.nix", "flake.lock"] {
+        let path = flake_dir.join(file);
+        match tokio::fs::read(&path).await {
+            Ok(contents) => hasher.update(contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("Could not read `{}`", path.display()))
+            }
+        }
+    }
+    Ok(hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::fs::write;
+
+    #[tokio::test]
+    async fn one_root_per_project() -> eyre::Result<()> {
+        let state_dir = TempDir::new()?;
+        std::env::set_var("XDG_STATE_HOME", state_dir.path());
+        let project_dir = TempDir::new()?;
+        let flake_dir = TempDir::new()?;
+
+        write(flake_dir.path().join("flake.nix"), "{ }").await?;
+        let profile = profile_for(project_dir.path(), flake_dir.path()).await?;
+        assert_eq!(profile.file_name(), Some(PROFILE.as_ref()));
+        write(flake_dir.path().join("flake.nix"), "{ outputs = _: { }; }").await?;
+        let updated_profile = profile_for(project_dir.path(), flake_dir.path()).await?;
+        assert_ne!(profile, updated_profile);
+
+        let roots = roots().await?;
+        assert_eq!(roots.len(), 1);
+        assert_eq!(Some(roots[0].dir.as_path()), updated_profile.parent());
+        assert!(!roots[0].is_stale());
+
+        drop(project_dir);
+        assert!(roots[0].is_stale());
+
+        remove_root_of(&updated_profile).await?;
+        assert!(read_roots(&roots_dir()?).await?.is_empty());
+        Ok(())
+    }
+}
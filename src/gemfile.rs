@@ -0,0 +1,95 @@
+//! The parts of `Gemfile` and `Gemfile.lock` riff cares about.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use eyre::WrapErr;
+
+/// Read the names of the gems used by the project in `project_dir`
+///
+/// `Gemfile.lock` also lists transitive dependencies, so it is used as well when present.
+pub async fn read_gem_names(project_dir: &Path) -> color_eyre::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for (file_name, parse) in [
+        ("Gemfile", gemfile_gems as fn(&str) -> BTreeSet<String>),
+        ("Gemfile.lock", gemfile_lock_gems),
+    ] {
+        let path = project_dir.join(file_name);
+        if !path.exists() {
+            continue;
+        }
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        names.extend(parse(&contents));
+    }
+    Ok(names)
+}
+
+/// The gems named by `gem` lines in a `Gemfile`
+pub fn gemfile_gems(contents: &str) -> BTreeSet<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("gem "))
+        .filter_map(|rest| {
+            let rest = rest.trim_start();
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let name = rest[1..].split(quote).next()?;
+            Some(name.to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// The gems listed in the `specs` of a `Gemfile.lock`
+pub fn gemfile_lock_gems(contents: &str) -> BTreeSet<String> {
+    contents
+        .lines()
+        // Specs are indented by four spaces, their own dependencies by six
+        .filter(|line| line.starts_with("    ") && !line.starts_with("     "))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gems() {
+        let gemfile = r#"
+source "https://rubygems.org"
+
+gem "rails", "~> 7.0"
+gem 'pg'
+  gem "puma", require: false
+"#;
+        assert_eq!(
+            gemfile_gems(gemfile),
+            ["pg", "puma", "rails"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+
+        let gemfile_lock = "\
+GEM
+  remote: https://rubygems.org/
+  specs:
+    nokogiri (1.13.8-x86_64-linux)
+      racc (~> 1.4)
+    racc (1.6.0)
+
+PLATFORMS
+  x86_64-linux
+";
+        assert_eq!(
+            gemfile_lock_gems(gemfile_lock),
+            ["nokogiri", "racc"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! The parts of `go.mod` riff cares about.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use eyre::WrapErr;
+
+/// Read the paths of the modules required by the `go.mod` in `project_dir`
+pub async fn read_required_modules(project_dir: &Path) -> color_eyre::Result<BTreeSet<String>> {
+    let path = project_dir.join("go.mod");
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+    Ok(required_modules(&contents))
+}
+
+/// The module paths in the `require` directives of a `go.mod`, including indirect ones
+pub fn required_modules(contents: &str) -> BTreeSet<String> {
+    let mut modules = BTreeSet::new();
+    let mut in_require_block = false;
+    for line in contents.lines() {
+        let line = match line.split_once("//") {
+            Some((line, _comment)) => line,
+            None => line,
+        }
+        .trim();
+
+        let requirement = if in_require_block {
+            if line == ")" {
+                in_require_block = false;
+                continue;
+            }
+            line
+        } else if let Some(rest) = line.strip_prefix("require") {
+            let rest = rest.trim();
+            if rest == "(" {
+                in_require_block = true;
+                continue;
+            }
+            rest
+        } else {
+            continue;
+        };
+
+        if let Some(module) = requirement.split_whitespace().next() {
+            modules.insert(module.trim_matches('"').to_string());
+        }
+    }
+    modules
+}
+
+/// Strip a major version suffix, so `example.com/mod/v3` is looked up as `example.com/mod`
+pub fn without_major_version(module: &str) -> &str {
+    match module.rsplit_once("/v") {
+        Some((base, version))
+            if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => module,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn go_mod_requirements() {
+        let go_mod = r#"
+module example.com/riff-test
+
+go 1.19
+
+require github.com/mattn/go-sqlite3 v1.14.15
+
+require (
+	github.com/shirou/gopsutil/v3 v3.22.8
+	golang.org/x/sys v0.0.0-20220919091848-fb04ddd9f9c8 // indirect
+)
+"#;
+        assert_eq!(
+            required_modules(go_mod),
+            [
+                "github.com/mattn/go-sqlite3",
+                "github.com/shirou/gopsutil/v3",
+                "golang.org/x/sys",
+            ]
+            .into_iter()
+            .map(ToString::to_string)
+            .collect()
+        );
+        assert_eq!(
+            without_major_version("github.com/shirou/gopsutil/v3"),
+            "github.com/shirou/gopsutil"
+        );
+        assert_eq!(
+            without_major_version("github.com/mattn/go-sqlite3"),
+            "github.com/mattn/go-sqlite3"
+        );
+        assert_eq!(
+            without_major_version("gopkg.in/gographics/imagick.v3"),
+            "gopkg.in/gographics/imagick.v3"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! The parts of Julia projects riff cares about: the packages `Project.toml` and `Manifest.toml`
+//! list.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use eyre::WrapErr;
+
+/// The project files Julia looks for, in order of preference
+pub const PROJECT_FILES: [&str; 2] = ["JuliaProject.toml", "Project.toml"];
+/// The manifest files Julia looks for, in order of preference
+pub const MANIFEST_FILES: [&str; 2] = ["JuliaManifest.toml", "Manifest.toml"];
+
+/// Shell code pointing Julia at a package depot in the project's `.riff` directory, keeping the
+/// bundled standard library depots after it
+pub const ISOLATED_DEPOT_HOOK: &str =
+    r#"export JULIA_DEPOT_PATH="${RIFF_PROJECT_DIR:-$PWD}/.riff/julia-depot:""#;
+
+/// Whether `project_dir` contains a Julia project
+pub fn detect(project_dir: &Path) -> bool {
+    PROJECT_FILES
+        .iter()
+        .chain(MANIFEST_FILES.iter())
+        .any(|file| project_dir.join(file).is_file())
+}
+
+/// The packages the project in `project_dir` uses: its direct dependencies, and every package in
+/// its manifest if it has been resolved
+pub async fn package_names(project_dir: &Path) -> color_eyre::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    if let Some(contents) = read_first(project_dir, &PROJECT_FILES).await? {
+        names.extend(project_dependencies(&contents)?);
+    }
+    if let Some(contents) = read_first(project_dir, &MANIFEST_FILES).await? {
+        names.extend(manifest_packages(&contents)?);
+    }
+    Ok(names)
+}
+
+async fn read_first(project_dir: &Path, files: &[&str]) -> color_eyre::Result<Option<String>> {
+    let path = match files
+        .iter()
+        .map(|file| project_dir.join(file))
+        .find(|path| path.is_file())
+    {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    tokio::fs::read_to_string(&path)
+        .await
+        .map(Some)
+        .wrap_err_with(|| format!("Could not read `{}`", path.display()))
+}
+
+/// The keys of the `[deps]` and `[weakdeps]` tables of a `Project.toml`
+pub fn project_dependencies(contents: &str) -> color_eyre::Result<BTreeSet<String>> {
+    let project: toml::Value = toml::from_str(contents).wrap_err("Could not parse Project.toml")?;
+    Ok(["deps", "weakdeps"]
+        .into_iter()
+        .filter_map(|key| project.get(key)?.as_table())
+        .flat_map(|table| table.keys().cloned())
+        .collect())
+}
+
+/// The packages of a `Manifest.toml`, which nests them in a `[deps]` table since format 2.0 and
+/// lists them at the top level before it
+pub fn manifest_packages(contents: &str) -> color_eyre::Result<BTreeSet<String>> {
+    let manifest: toml::Value =
+        toml::from_str(contents).wrap_err("Could not parse Manifest.toml")?;
+    let packages = match manifest.get("manifest_format") {
+        Some(_) => manifest.get("deps").and_then(toml::Value::as_table),
+        None => manifest.as_table(),
+    };
+    Ok(packages
+        .into_iter()
+        .flatten()
+        .filter(|(_, entries)| entries.is_array())
+        .map(|(name, _)| name.clone())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_and_manifest() -> eyre::Result<()> {
+        let project_toml = r#"
+name = "RiffTest"
+uuid = "b8f0b5bb-8a0b-4c79-9c53-6a24d1e0c4a4"
+
+[deps]
+HDF5 = "f67ccb44-e63f-5c2f-98bd-6dc0ccc4ba2f"
+PyCall = "438e738f-606a-5dbb-bf0a-cddfbfd45ab0"
+
+[compat]
+julia = "1.9"
+"#;
+        assert_eq!(
+            project_dependencies(project_toml)?,
+            ["HDF5", "PyCall"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+
+        let manifest_v2 = r#"
+julia_version = "1.9.3"
+manifest_format = "2.0"
+
+[[deps.MPI]]
+deps = ["MPIPreferences"]
+uuid = "da04e1cc-30fd-572f-bb4f-1f8673147195"
+
+[[deps.MPIPreferences]]
+uuid = "3da0fdf6-3ccc-4f1b-acd9-58baa6c99267"
+"#;
+        assert_eq!(
+            manifest_packages(manifest_v2)?,
+            ["MPI", "MPIPreferences"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+
+        let manifest_v1 = r#"
+[[RCall]]
+uuid = "6f49c342-dc21-5d91-9882-a32aef131414"
+"#;
+        assert_eq!(
+            manifest_packages(manifest_v1)?,
+            ["RCall".to_string()].into_iter().collect()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,866 @@
+//! The languages riff can detect, each providing part of the developer environment.
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+
+use crate::bazel_project::BuildTool;
+use crate::cargo_metadata;
+use crate::cpp_project::{self, BuildSystem};
+use crate::crystal_shard::{self, Shard};
+use crate::dev_env::{DevEnvironment, DevEnvironmentAppliable};
+use crate::gemfile;
+use crate::go_module;
+use crate::julia_project;
+use crate::lua_project::{self, LuaProject};
+use crate::nim_project::{self, NimbleProject};
+use crate::opam_project;
+use crate::package_json::{PackageJson, PackageLock};
+use crate::python_project::{self, PythonProject};
+use crate::swift_package::{self, SwiftPackage};
+
+/// The backends `DevEnvironment::detect` runs, in the order their summaries are printed
+pub static BACKENDS: &[&dyn LanguageBackend] = &[
+    &RustBackend,
+    &NodeBackend,
+    &PythonBackend,
+    &GoBackend,
+    &RubyBackend,
+    &CppBackend,
+    &OcamlBackend,
+    &SwiftBackend,
+    &LuaBackend,
+    &JuliaBackend,
+    &NimBackend,
+    &CrystalBackend,
+    &BazelBackend,
+    &BuckBackend,
+];
+
+/// Support for the projects of one language
+///
+/// Each backend collects into its own `DevEnvironment`, which is summarized and then merged into
+/// the project's environment.
+#[async_trait]
+pub trait LanguageBackend: Sync {
+    /// The name of the language in telemetry, such as `Rust`
+    fn telemetry_label(&self) -> &'static str;
+
+    /// The label in front of the summary of what the language needs
+    fn summary_label(&self) -> String;
+
+    /// The files in the project directory the backend reads, which detection reports capture
+    fn manifest_files(&self) -> &'static [&'static str];
+
+    /// Whether `project_dir` contains a project in this language
+    async fn detect(&self, project_dir: &Path, dev_env: &DevEnvironment<'_>) -> bool;
+
+    /// Add the inputs and environment variables the project needs to `dev_env`
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()>;
+}
+
+pub struct RustBackend;
+
+#[async_trait]
+impl LanguageBackend for RustBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Rust"
+    }
+
+    fn summary_label(&self) -> String {
+        "🦀 rust".bold().red().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &["Cargo.toml"]
+    }
+
+    async fn detect(&self, project_dir: &Path, dev_env: &DevEnvironment<'_>) -> bool {
+        dev_env.cargo_metadata_snapshot.is_some() || project_dir.join("Cargo.toml").exists()
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Cargo dependencies...");
+
+        // Infer offline-ness from our stored registry
+        let (metadata, root_manifest_path) = cargo_metadata::load(
+            project_dir,
+            dev_env.cargo_metadata_snapshot.as_deref(),
+            dev_env.registry.offline(),
+        )
+        .await?;
+
+        tracing::debug!(fresh = %dev_env.registry.fresh(), "Cache freshness");
+        dev_env
+            .add_deps_from_cargo_metadata(metadata, root_manifest_path.as_deref())
+            .await;
+
+        Ok(())
+    }
+}
+
+pub struct NodeBackend;
+
+#[async_trait]
+impl LanguageBackend for NodeBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Node"
+    }
+
+    fn summary_label(&self) -> String {
+        "⬢ node".bold().green().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[
+            "package.json",
+            "package-lock.json",
+            "yarn.lock",
+            "pnpm-lock.yaml",
+        ]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        project_dir.join("package.json").exists()
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding npm dependencies...");
+
+        let package_json = PackageJson::read(project_dir).await?;
+        let mut dependency_names = package_json.dependency_names();
+        if let Some(package_lock) = PackageLock::read(project_dir).await? {
+            dependency_names.extend(package_lock.dependency_names());
+        }
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.node.default.apply(dev_env);
+
+        if project_dir.join("yarn.lock").exists() {
+            dev_env.build_inputs.insert("yarn".to_string());
+        } else if project_dir.join("pnpm-lock.yaml").exists() {
+            dev_env.build_inputs.insert("nodePackages.pnpm".to_string());
+        }
+
+        for name in &dependency_names {
+            if let Some(dep_config) = language_registry.node.dependencies.get(name) {
+                tracing::debug!(
+                    package_name = %name,
+                    "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                    "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                    "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                    "Detected known npm package information"
+                );
+                dep_config.apply(dev_env);
+            }
+        }
+
+        if let Some(dep_config) = &package_json.riff {
+            tracing::debug!(
+                package = ?package_json.name,
+                "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                "Detected `riff` in `package.json`"
+            );
+            dep_config.apply(dev_env);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct PythonBackend;
+
+#[async_trait]
+impl LanguageBackend for PythonBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Python"
+    }
+
+    fn summary_label(&self) -> String {
+        "🐍 python".bold().yellow().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[
+            "pyproject.toml",
+            "setup.py",
+            "requirements.txt",
+            "poetry.lock",
+        ]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        python_project::PYTHON_PROJECT_FILES
+            .iter()
+            .any(|file| project_dir.join(file).exists())
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Python dependencies...");
+
+        let python_project = PythonProject::read(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.python.default.apply(dev_env);
+
+        if python_project.uses_poetry {
+            dev_env.build_inputs.insert("poetry".to_string());
+        }
+
+        for name in &python_project.dependency_names {
+            if let Some(dep_config) = language_registry.python.dependencies.get(name) {
+                tracing::debug!(
+                    package_name = %name,
+                    "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                    "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                    "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                    "Detected known Python package information"
+                );
+                dep_config.apply(dev_env);
+            }
+        }
+
+        if let Some(dep_config) = &python_project.riff {
+            tracing::debug!(
+                manifest = "pyproject.toml",
+                "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                "Detected `tool.riff` in `pyproject.toml`"
+            );
+            dep_config.apply(dev_env);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct GoBackend;
+
+#[async_trait]
+impl LanguageBackend for GoBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Go"
+    }
+
+    fn summary_label(&self) -> String {
+        "🐹 go".bold().cyan().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &["go.mod"]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        project_dir.join("go.mod").exists()
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Go dependencies...");
+
+        let modules = go_module::read_required_modules(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.go.default.apply(dev_env);
+
+        for module in &modules {
+            let dep_config = language_registry.go.dependencies.get(module).or_else(|| {
+                language_registry
+                    .go
+                    .dependencies
+                    .get(go_module::without_major_version(module))
+            });
+            if let Some(dep_config) = dep_config {
+                tracing::debug!(
+                    module = %module,
+                    "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                    "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                    "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                    "Detected known Go module information"
+                );
+                dep_config.apply(dev_env);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct RubyBackend;
+
+#[async_trait]
+impl LanguageBackend for RubyBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Ruby"
+    }
+
+    fn summary_label(&self) -> String {
+        "💎 ruby".bold().magenta().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &["Gemfile", "Gemfile.lock"]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        project_dir.join("Gemfile").exists()
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Ruby dependencies...");
+
+        let gems = gemfile::read_gem_names(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.ruby.default.apply(dev_env);
+
+        for gem in &gems {
+            if let Some(dep_config) = language_registry.ruby.dependencies.get(gem) {
+                tracing::debug!(
+                    gem = %gem,
+                    "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                    "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                    "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                    "Detected known gem information"
+                );
+                dep_config.apply(dev_env);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct CppBackend;
+
+#[async_trait]
+impl LanguageBackend for CppBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Cpp"
+    }
+
+    fn summary_label(&self) -> String {
+        "⚙️ c/c++".bold().blue().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &["CMakeLists.txt", "meson.build"]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        [BuildSystem::CMake, BuildSystem::Meson]
+            .iter()
+            .any(|build_system| project_dir.join(build_system.file_name()).is_file())
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding C/C++ dependencies...");
+
+        let build_systems = cpp_project::detect(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.cpp.default.apply(dev_env);
+
+        for (build_system, dependencies) in &build_systems {
+            dev_env.build_inputs.extend(
+                build_system
+                    .nixpkgs_attributes()
+                    .iter()
+                    .map(ToString::to_string),
+            );
+            for name in dependencies {
+                if let Some(dep_config) = language_registry.cpp.dependencies.get(name) {
+                    tracing::debug!(
+                        package_name = %name,
+                        "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                        "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                        "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                        "Detected known C/C++ package information"
+                    );
+                    dep_config.apply(dev_env);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct OcamlBackend;
+
+#[async_trait]
+impl LanguageBackend for OcamlBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "OCaml"
+    }
+
+    fn summary_label(&self) -> String {
+        "🐫 ocaml".bold().yellow().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[opam_project::DUNE_PROJECT]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        opam_project::detect(project_dir)
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding opam dependencies...");
+
+        let packages = opam_project::read_package_names(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.ocaml.default.apply(dev_env);
+
+        for package in &packages {
+            if let Some(dep_config) = language_registry.ocaml.dependencies.get(package) {
+                tracing::debug!(
+                    package_name = %package,
+                    "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                    "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                    "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                    "Detected known opam package information"
+                );
+                dep_config.apply(dev_env);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct SwiftBackend;
+
+#[async_trait]
+impl LanguageBackend for SwiftBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Swift"
+    }
+
+    fn summary_label(&self) -> String {
+        "🐦 swift".bold().red().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[swift_package::PACKAGE_SWIFT]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        project_dir.join(swift_package::PACKAGE_SWIFT).is_file()
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Swift dependencies...");
+
+        let package = SwiftPackage::read(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.swift.default.apply(dev_env);
+
+        for name in &package.pkg_config_names {
+            let dep_config = language_registry
+                .swift
+                .dependencies
+                .get(name)
+                .or_else(|| language_registry.cpp.dependencies.get(name));
+            if let Some(dep_config) = dep_config {
+                tracing::debug!(
+                    pkg_config_name = %name,
+                    "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                    "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                    "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                    "Detected known system library information"
+                );
+                dep_config.apply(dev_env);
+            }
+        }
+
+        dev_env.darwin_frameworks.extend(package.frameworks);
+
+        Ok(())
+    }
+}
+
+pub struct LuaBackend;
+
+#[async_trait]
+impl LanguageBackend for LuaBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Lua"
+    }
+
+    fn summary_label(&self) -> String {
+        "🌙 lua".bold().blue().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[lua_project::LAZY_LOCK]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        lua_project::detect(project_dir)
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Lua dependencies...");
+
+        let project = LuaProject::read(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.lua.default.apply(dev_env);
+
+        // Neovim embeds LuaJIT, so plugins are written for it rather than the latest Lua
+        if project.neovim {
+            dev_env
+                .build_inputs
+                .extend(["luajit".to_string(), "neovim".to_string()]);
+        } else {
+            dev_env.build_inputs.insert("lua".to_string());
+        }
+
+        let external_dependencies = project.external_dependencies.iter().filter_map(|name| {
+            language_registry
+                .lua
+                .dependencies
+                .get(name)
+                .or_else(|| language_registry.cpp.dependencies.get(name))
+                .map(|dep_config| (name, dep_config))
+        });
+        let dependencies = project
+            .dependency_names
+            .iter()
+            .filter_map(|name| {
+                language_registry
+                    .lua
+                    .dependencies
+                    .get(name)
+                    .map(|dep_config| (name, dep_config))
+            })
+            .chain(external_dependencies);
+        for (name, dep_config) in dependencies {
+            tracing::debug!(
+                package_name = %name,
+                "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                "Detected known Lua package information"
+            );
+            dep_config.apply(dev_env);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct JuliaBackend;
+
+#[async_trait]
+impl LanguageBackend for JuliaBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Julia"
+    }
+
+    fn summary_label(&self) -> String {
+        "🟣 julia".bold().purple().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[
+            "JuliaProject.toml",
+            "Project.toml",
+            "JuliaManifest.toml",
+            "Manifest.toml",
+        ]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        julia_project::detect(project_dir)
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Julia dependencies...");
+
+        let package_names = julia_project::package_names(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.julia.default.apply(dev_env);
+
+        for name in &package_names {
+            if let Some(dep_config) = language_registry.julia.dependencies.get(name) {
+                tracing::debug!(
+                    package_name = %name,
+                    "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                    "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                    "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                    "Detected known Julia package information"
+                );
+                dep_config.apply(dev_env);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct NimBackend;
+
+#[async_trait]
+impl LanguageBackend for NimBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Nim"
+    }
+
+    fn summary_label(&self) -> String {
+        "👑 nim".bold().yellow().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        nim_project::detect(project_dir)
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Nim dependencies...");
+
+        let project = NimbleProject::read(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.nim.default.apply(dev_env);
+
+        let foreign_dependencies = project.foreign_dependencies.iter().filter_map(|name| {
+            language_registry
+                .nim
+                .dependencies
+                .get(name)
+                .or_else(|| language_registry.cpp.dependencies.get(name))
+                .map(|dep_config| (name, dep_config))
+        });
+        let dependencies = project
+            .dependency_names
+            .iter()
+            .filter_map(|name| {
+                language_registry
+                    .nim
+                    .dependencies
+                    .get(name)
+                    .map(|dep_config| (name, dep_config))
+            })
+            .chain(foreign_dependencies);
+        for (name, dep_config) in dependencies {
+            tracing::debug!(
+                package_name = %name,
+                "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                "Detected known Nim package information"
+            );
+            dep_config.apply(dev_env);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct CrystalBackend;
+
+#[async_trait]
+impl LanguageBackend for CrystalBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Crystal"
+    }
+
+    fn summary_label(&self) -> String {
+        "🔮 crystal".bold().white().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        &[crystal_shard::SHARD_YML]
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        project_dir.join(crystal_shard::SHARD_YML).is_file()
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Crystal dependencies...");
+
+        let shard = Shard::read(project_dir).await?;
+
+        let language_registry = dev_env.registry.language().await.clone();
+        language_registry.crystal.default.apply(dev_env);
+
+        let libraries = shard.libraries.iter().filter_map(|name| {
+            let unprefixed = name.strip_prefix("lib").unwrap_or(name);
+            [name.as_str(), unprefixed]
+                .into_iter()
+                .find_map(|name| {
+                    language_registry
+                        .crystal
+                        .dependencies
+                        .get(name)
+                        .or_else(|| language_registry.cpp.dependencies.get(name))
+                })
+                .map(|dep_config| (name, dep_config))
+        });
+        let dependencies = shard
+            .dependency_names
+            .iter()
+            .filter_map(|name| {
+                language_registry
+                    .crystal
+                    .dependencies
+                    .get(name)
+                    .map(|dep_config| (name, dep_config))
+            })
+            .chain(libraries);
+        for (name, dep_config) in dependencies {
+            tracing::debug!(
+                shard_name = %name,
+                "build-inputs" = %dep_config.build_inputs().iter().join(", "),
+                "environment-variables" = %dep_config.environment_variables().iter().map(|(k, v)| format!("{k}={v}")).join(", "),
+                "runtime-inputs" = %dep_config.runtime_inputs().iter().join(", "),
+                "Detected known Crystal shard information"
+            );
+            dep_config.apply(dev_env);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct BazelBackend;
+
+#[async_trait]
+impl LanguageBackend for BazelBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Bazel"
+    }
+
+    fn summary_label(&self) -> String {
+        "🌿 bazel".bold().green().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        BuildTool::Bazel.root_files()
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        BuildTool::Bazel.detect(project_dir)
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Bazel toolchain...");
+        BuildTool::Bazel.apply(dev_env);
+        Ok(())
+    }
+}
+
+pub struct BuckBackend;
+
+#[async_trait]
+impl LanguageBackend for BuckBackend {
+    fn telemetry_label(&self) -> &'static str {
+        "Buck"
+    }
+
+    fn summary_label(&self) -> String {
+        "🪣 buck2".bold().yellow().to_string()
+    }
+
+    fn manifest_files(&self) -> &'static [&'static str] {
+        BuildTool::Buck2.root_files()
+    }
+
+    async fn detect(&self, project_dir: &Path, _dev_env: &DevEnvironment<'_>) -> bool {
+        BuildTool::Buck2.detect(project_dir)
+    }
+
+    #[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+    async fn collect_deps(
+        &self,
+        project_dir: &Path,
+        dev_env: &mut DevEnvironment<'_>,
+    ) -> color_eyre::Result<()> {
+        tracing::debug!("Adding Buck2 toolchain...");
+        BuildTool::Buck2.apply(dev_env);
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! Support for Nix installations without flakes, like the Nix 2.3 many distributions package,
+//! which get a classic `shell.nix` evaluated by `nix-shell` instead of a flake.
+
+use once_cell::sync::Lazy;
+use tokio::process::Command;
+use tokio::sync::OnceCell;
+
+use crate::nix_expr;
+
+/// The first version of Nix with flakes
+pub(crate) const MINIMUM_NIX_VERSION: semver::Version = semver::Version::new(2, 4, 0);
+
+/// The file written instead of `flake.nix` when flakes aren't supported
+pub const SHELL_NIX: &str = "shell.nix";
+
+/// The version of the `nix` on the `PATH`, if it runs and reports one
+pub async fn installed_nix_version() -> Option<semver::Version> {
+    let output = Command::new("nix")
+        .arg("--version")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_nix_version(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+/// Like [`installed_nix_version`], checked once per run
+pub async fn nix_version() -> Option<&'static semver::Version> {
+    static VERSION: Lazy<OnceCell<Option<semver::Version>>> = Lazy::new(OnceCell::new);
+    VERSION
+        .get_or_init(|| async {
+            let version = installed_nix_version().await;
+            if let Some(version) = &version {
+                tracing::debug!(%version, "Detected the installed Nix");
+            }
+            version
+        })
+        .await
+        .as_ref()
+}
+
+/// Whether the installed Nix can evaluate flakes
+///
+/// An unknown version is assumed to support them, so any error comes from the flake commands.
+pub async fn flakes_supported() -> bool {
+    match nix_version().await {
+        Some(version) => *version >= MINIMUM_NIX_VERSION,
+        None => true,
+    }
+}
+
+/// The version in the output of `nix --version`, like `nix (Nix) 2.11.1`
+pub(crate) fn parse_nix_version(version_line: &str) -> Option<semver::Version> {
+    let version = version_line.split_whitespace().last()?;
+    // Pre-release builds look like `2.12.0pre20221005_abcdef`
+    let release = version
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .next()?;
+    let mut parts = release.split('.').map(str::parse::<u64>);
+    Some(semver::Version::new(
+        parts.next()?.ok()?,
+        parts.next().unwrap_or(Ok(0)).ok()?,
+        parts.next().unwrap_or(Ok(0)).ok()?,
+    ))
+}
+
+/// A Nix expression for the nixpkgs of `flake_ref`, which `shell.nix` imports
+///
+/// GitHub references are fetched as tarballs and paths are used as they are. Anything else
+/// falls back to the `<nixpkgs>` channel.
+pub fn nixpkgs_expr(flake_ref: &str) -> String {
+    let flake_ref = flake_ref.split('?').next().unwrap_or_default();
+    if let Some(repo) = flake_ref.strip_prefix("github:") {
+        let mut parts = repo.splitn(3, '/');
+        if let (Some(owner), Some(name)) = (parts.next(), parts.next()) {
+            let rev = parts.next().unwrap_or("HEAD");
+            return format!(
+                "builtins.fetchTarball {}",
+                nix_expr::string(&format!(
+                    "https://github.com/{owner}/{name}/archive/{rev}.tar.gz"
+                ))
+            );
+        }
+    }
+    match flake_ref.strip_prefix("path:").unwrap_or(flake_ref) {
+        path if path.starts_with('/') => format!("/. + {}", nix_expr::string(path)),
+        _ => {
+            tracing::debug!(%flake_ref, "Using the `nixpkgs` channel for a flake reference");
+            "<nixpkgs>".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nixpkgs_exprs() {
+        assert_eq!(
+            nixpkgs_expr("github:NixOS/nixpkgs/nixos-22.11"),
+            "builtins.fetchTarball \"https://github.com/NixOS/nixpkgs/archive/nixos-22.11.tar.gz\""
+        );
+        assert_eq!(
+            nixpkgs_expr("github:NixOS/nixpkgs"),
+            "builtins.fetchTarball \"https://github.com/NixOS/nixpkgs/archive/HEAD.tar.gz\""
+        );
+        assert_eq!(
+            nixpkgs_expr("path:/home/riff/nixpkgs"),
+            "/. + \"/home/riff/nixpkgs\""
+        );
+        assert_eq!(nixpkgs_expr("nixpkgs"), "<nixpkgs>");
+    }
+}
@@ -0,0 +1,210 @@
+//! The parts of Lua projects riff cares about: the rocks `*.rockspec` files depend on, and the
+//! plugins of Neovim plugin repositories.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use eyre::WrapErr;
+
+/// The lock file of the lazy.nvim plugin manager, listing the plugins a configuration uses
+pub const LAZY_LOCK: &str = "lazy-lock.json";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LuaProject {
+    /// Whether the project is a Neovim plugin or configuration, which runs on LuaJIT
+    pub neovim: bool,
+    /// The rocks and Neovim plugins the project depends on
+    pub dependency_names: BTreeSet<String>,
+    /// The lowercased names of the `external_dependencies` of its rockspecs, like `openssl`
+    pub external_dependencies: BTreeSet<String>,
+}
+
+impl LuaProject {
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Self> {
+        let mut project = Self {
+            neovim: is_neovim_project(project_dir),
+            ..Default::default()
+        };
+        for path in rockspecs(project_dir) {
+            let contents = read(&path).await?;
+            project
+                .dependency_names
+                .extend(rockspec_dependencies(&contents));
+            project
+                .external_dependencies
+                .extend(rockspec_external_dependencies(&contents));
+        }
+        let lazy_lock = project_dir.join(LAZY_LOCK);
+        if lazy_lock.is_file() {
+            let plugins: BTreeMap<String, serde_json::Value> =
+                serde_json::from_str(&read(&lazy_lock).await?)
+                    .wrap_err_with(|| format!("Could not parse `{}`", lazy_lock.display()))?;
+            project.dependency_names.extend(plugins.into_keys());
+        }
+        Ok(project)
+    }
+}
+
+/// Whether `project_dir` contains a Lua project
+pub fn detect(project_dir: &Path) -> bool {
+    !rockspecs(project_dir).is_empty() || is_neovim_project(project_dir)
+}
+
+/// A Neovim configuration managed by lazy.nvim, or a plugin with the usual `lua` and `plugin`
+/// directories
+fn is_neovim_project(project_dir: &Path) -> bool {
+    project_dir.join(LAZY_LOCK).is_file()
+        || (project_dir.join("lua").is_dir() && project_dir.join("plugin").is_dir())
+}
+
+/// The `*.rockspec` files in `project_dir`
+fn rockspecs(project_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = std::fs::read_dir(project_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension() == Some("rockspec".as_ref()) && path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+async fn read(path: &Path) -> color_eyre::Result<String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Could not read `{}`", path.display()))
+}
+
+/// The rocks in the `dependencies`, `build_dependencies` and `test_dependencies` tables of a
+/// rockspec, without their version constraints
+pub fn rockspec_dependencies(contents: &str) -> BTreeSet<String> {
+    ["dependencies", "build_dependencies", "test_dependencies"]
+        .into_iter()
+        .flat_map(|key| tables(contents, key))
+        .flat_map(|table| top_level_strings(table))
+        .filter_map(|dependency| {
+            dependency
+                .split(|c: char| c.is_whitespace() || "<>=~".contains(c))
+                .next()
+                .map(ToString::to_string)
+        })
+        // Every rock depends on Lua itself
+        .filter(|name| !name.is_empty() && name != "lua")
+        .collect()
+}
+
+/// The keys of the `external_dependencies` table of a rockspec, lowercased
+pub fn rockspec_external_dependencies(contents: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for table in tables(contents, "external_dependencies") {
+        let mut depth = 0;
+        let mut key = String::new();
+        for c in table.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '=' if depth == 0 && !key.is_empty() => {
+                    names.insert(key.to_ascii_lowercase());
+                }
+                c if c.is_alphanumeric() || c == '_' => {
+                    if depth == 0 {
+                        key.push(c);
+                    }
+                    continue;
+                }
+                c if c.is_whitespace() => continue,
+                _ => (),
+            }
+            key.clear();
+        }
+    }
+    names
+}
+
+/// The contents of the tables assigned to `key`, like `key = { ... }`
+fn tables<'a>(contents: &'a str, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    contents.match_indices(key).filter_map(move |(start, _)| {
+        // Skip longer names ending in `key`, like `build_dependencies` for `dependencies`
+        let before = contents[..start].chars().next_back();
+        if let Some(c) = before {
+            if c.is_alphanumeric() || c == '_' {
+                return None;
+            }
+        }
+        let rest = contents[start + key.len()..]
+            .trim_start()
+            .strip_prefix('=')?;
+        let table = rest.trim_start().strip_prefix('{')?;
+        let mut depth = 0;
+        for (index, c) in table.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Some(&table[..index]),
+                '}' => depth -= 1,
+                _ => (),
+            }
+        }
+        None
+    })
+}
+
+/// The string literals directly inside a table
+fn top_level_strings(table: &str) -> Vec<&str> {
+    let mut strings = Vec::new();
+    let mut depth = 0;
+    let mut rest = table;
+    while let Some(index) = rest.find(['{', '}', '"', '\'']) {
+        let c = rest[index..].chars().next().unwrap_or_default();
+        rest = &rest[index + 1..];
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            quote => {
+                let end = rest.find(quote).unwrap_or(rest.len());
+                if depth == 0 {
+                    strings.push(&rest[..end]);
+                }
+                rest = &rest[(end + 1).min(rest.len())..];
+            }
+        }
+    }
+    strings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rockspec() {
+        let rockspec = r#"
+package = "riff-test"
+version = "0.1-1"
+dependencies = {
+   "lua >= 5.1, < 5.5",
+   "luasec ~> 1.2",
+   'lpeg',
+}
+build_dependencies = { "luarocks-build-rust-mlua" }
+external_dependencies = {
+   OPENSSL = { header = "openssl/ssl.h" },
+   ZLIB = { library = "z" },
+}
+"#;
+        assert_eq!(
+            rockspec_dependencies(rockspec),
+            ["lpeg", "luarocks-build-rust-mlua", "luasec"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+        assert_eq!(
+            rockspec_external_dependencies(rockspec),
+            ["openssl", "zlib"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+    }
+}
@@ -1,25 +1,77 @@
+mod bazel_project;
+mod build_info;
+mod cargo_config;
 mod cargo_metadata;
+mod closure_size;
 mod cmds;
+mod config;
+mod conflicts;
+mod contribution_graph;
+mod cpp_project;
+mod cross_target;
+mod crystal_shard;
+mod daemon;
 mod dependency_registry;
+mod detect_report;
 mod dev_env;
+mod env_cache;
+mod environment_overrides;
+mod exporter;
+mod flake_cache;
 mod flake_generator;
+mod gc_roots;
+mod gemfile;
+mod go_module;
+mod julia_project;
+mod language_backend;
+mod legacy_nix;
+mod lua_project;
+mod metadata_editor;
+mod network;
+mod nim_project;
 mod nix_dev_env;
+mod nix_expr;
+mod nix_passthrough;
+mod opam_project;
+mod otel;
+mod package_json;
+mod platform;
+mod progress;
+mod project_config;
+mod project_fingerprint;
+mod python_project;
+mod registry_test;
+mod resource_limits;
+mod riff_dirs;
+mod riff_lock;
+#[cfg(feature = "sbom")]
+mod sbom;
 mod spinner;
+mod swift_package;
+mod sys_heuristic;
+mod task_runner;
 mod telemetry;
+mod timeouts;
+mod tool_wrappers;
+mod wsl;
 
 use std::error::Error;
 use std::io::Write;
+use std::path::PathBuf;
 use std::process::ExitCode;
 
 use atty::Stream;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use eyre::WrapErr;
 use owo_colors::OwoColorize;
+use tracing::Instrument;
 use tracing_error::ErrorLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+use build_info::BuildInfo;
 use cmds::Commands;
-use telemetry::Telemetry;
+use otel::OtelExporter;
+use telemetry::{Phase, Telemetry};
 
 const RIFF_XDG_PREFIX: &str = "riff";
 
@@ -36,42 +88,214 @@ struct Cli {
     // TODO(@hoverbear): Can we disable that, too?
     #[clap(long, global = true, env = "RIFF_OFFLINE")]
     offline: bool,
+    /// Make no network connections at all, running `nix` offline and failing if something isn't cached
+    #[clap(long, global = true, env = "RIFF_NO_NETWORK")]
+    no_network: bool,
+    /// Fetch the dependency registry from this URL, such as an internal one, instead of riff's
+    #[clap(long, global = true, env = "RIFF_REGISTRY_URL")]
+    registry_url: Option<String>,
+    /// Add the tools required by `justfile`/`Makefile` recipes to the environment
+    #[clap(long, global = true, env = "RIFF_TASK_RUNNER_TOOLS")]
+    task_runner_tools: bool,
+    /// Don't layer the environment on top of an existing `shell.nix` or `default.nix`
+    #[clap(long, global = true, env = "RIFF_IGNORE_EXISTING_NIX")]
+    ignore_existing_nix: bool,
+    /// Don't guess the inputs of `-sys` crates riff doesn't know from the libraries they link
+    #[clap(long, global = true, env = "RIFF_NO_SYS_HEURISTICS")]
+    no_sys_heuristics: bool,
+    /// Only apply rules curated in the registry or declared by the project, never guessed ones
+    #[clap(long, global = true, env = "RIFF_CONSERVATIVE")]
+    conservative: bool,
+    /// Leave out the native inputs of dev-dependencies, which only tests, examples and benchmarks use
+    #[clap(long, global = true, env = "RIFF_NO_DEV")]
+    no_dev: bool,
+    /// Leave out the native inputs of build-dependencies and their dependencies
+    #[clap(long, global = true, env = "RIFF_NO_BUILD_DEPS")]
+    no_build_deps: bool,
+    /// Fail when detecting any of the project's languages fails, instead of leaving that language out
+    #[clap(long, global = true, env = "RIFF_STRICT_DETECT")]
+    strict_detect: bool,
+    /// Milliseconds an operation runs before riff shows a spinner for it
+    #[clap(
+        long,
+        global = true,
+        env = "RIFF_SPINNER_DELAY",
+        value_parser,
+        default_value_t = spinner::DEFAULT_DELAY.as_millis() as u64
+    )]
+    spinner_delay: u64,
+    /// How to show progress: spinners, or newline-delimited JSON events for tools wrapping riff
+    #[clap(
+        long,
+        global = true,
+        env = "RIFF_PROGRESS",
+        value_enum,
+        default_value_t = progress::ProgressFormat::Human
+    )]
+    progress: progress::ProgressFormat,
+    /// The file descriptor `--progress json` writes its events to
+    #[clap(
+        long,
+        global = true,
+        env = "RIFF_PROGRESS_FD",
+        value_name = "FD",
+        default_value_t = 2
+    )]
+    progress_fd: i32,
+    /// Replay saved `cargo metadata` output, such as from `riff dev snapshot-metadata`, instead of running `cargo metadata`
+    #[clap(long, global = true, value_parser, env = "RIFF_FROM_METADATA")]
+    from_metadata: Option<PathBuf>,
+    /// Replay a detection report from `riff dev detect-report` instead of reading the project
+    #[clap(long, global = true, value_parser, env = "RIFF_FROM_DETECT_REPORT")]
+    from_detect_report: Option<PathBuf>,
+    /// Pass this argument on to the `nix` commands which evaluate the environment, like `--nix-arg=--impure`
+    #[clap(long, global = true, value_name = "ARG", allow_hyphen_values = true)]
+    nix_arg: Vec<String>,
+    /// Pass `--option NAME VALUE` on to the `nix` commands which evaluate the environment
+    #[clap(
+        long,
+        global = true,
+        value_name = "NAME=VALUE",
+        value_parser = nix_passthrough::parse_option
+    )]
+    nix_option: Vec<(String, String)>,
+    /// Export riff's tracing spans to this OpenTelemetry collector, like `http://localhost:4318`
+    #[clap(long, global = true, value_name = "URL", env = "RIFF_TRACE_OTEL")]
+    trace_otel: Option<String>,
+    /// Read settings from this file too, over those of the system, user and project configuration
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        value_parser,
+        env = "RIFF_CONFIG"
+    )]
+    config: Option<PathBuf>,
 }
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<std::process::ExitCode> {
+    telemetry::start_clock();
     color_eyre::config::HookBuilder::default()
         .issue_url(concat!(env!("CARGO_PKG_REPOSITORY"), "/issues/new"))
         .install()?;
 
-    setup_tracing().await?;
+    let mut maybe_args = Cli::try_parse();
+    if let Ok(args) = &maybe_args {
+        // Configuration files set global options through their environment variables, so parse
+        // again to let clap read them, under any set by the user or given as flags
+        config::set_file(args.config.clone());
+        if config::export_global_options().await? {
+            maybe_args = Cli::try_parse();
+        }
+    }
 
-    let maybe_args = Cli::try_parse();
+    let otel_exporter = setup_tracing(
+        maybe_args
+            .as_ref()
+            .ok()
+            .and_then(|args| args.trace_otel.as_deref()),
+    )
+    .await?;
 
-    let args = match maybe_args {
-        Ok(args) => args,
-        Err(e) => {
-            let telemetry_ok_via_env = match std::env::var("RIFF_DISABLE_TELEMETRY")
-                .or_else(|_| std::env::var("RIFF_OFFLINE"))
-            {
-                Ok(val) if val == "false" || val == "0" || val.is_empty() => true,
-                Err(_) => true,
-                _ => false,
-            };
-            let telemetry_ok_via_flag = !std::env::args()
-                .take_while(|v| v != "--")
-                .any(|v| v == *"--disable-telemetry" || v == *"--offline");
-            if telemetry_ok_via_env && telemetry_ok_via_flag {
-                Telemetry::new().await.send().await.ok();
+    // clap's version flag can't take options, so `--version --verbose` is handled before it
+    let raw_args = std::env::args().skip(1).collect::<Vec<_>>();
+    if raw_args.len() == 2
+        && raw_args.iter().any(|arg| arg == "--version" || arg == "-V")
+        && raw_args.iter().any(|arg| arg == "--verbose")
+    {
+        println!("{}", BuildInfo::current());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let args =
+        match maybe_args {
+            Ok(args) => args,
+            Err(e) => {
+                let telemetry_ok_via_env = ["RIFF_OFFLINE", "RIFF_NO_NETWORK"].iter().all(|var| {
+                    match std::env::var(var) {
+                        Ok(val) if val == "false" || val == "0" || val.is_empty() => true,
+                        Err(_) => true,
+                        _ => false,
+                    }
+                }) && !telemetry::disabled_by_env();
+                let telemetry_ok_via_flag = !std::env::args().take_while(|v| v != "--").any(|v| {
+                    v == *"--disable-telemetry" || v == *"--offline" || v == *"--no-network"
+                });
+                if telemetry_ok_via_env && telemetry_ok_via_flag {
+                    let mut telemetry = Telemetry::new().await;
+                    // `--help` and `--version` are reported as errors by clap, but aren't failures
+                    if e.use_stderr() {
+                        telemetry = telemetry.with_failed_phase(Phase::Parse);
+                    }
+                    telemetry.send().await.ok();
+                }
+                e.exit() // Dead!
             }
-            e.exit() // Dead!
-        }
+        };
+    spinner::set_delay(std::time::Duration::from_millis(args.spinner_delay));
+    if args.no_network {
+        network::disable();
+    }
+    nix_passthrough::set(args.nix_arg.clone(), args.nix_option.clone());
+    if args.progress == progress::ProgressFormat::Json {
+        progress::emit_json_to(args.progress_fd).wrap_err_with(|| {
+            format!(
+                "Could not write progress events to file descriptor {}",
+                args.progress_fd
+            )
+        })?;
+    }
+
+    let subcommand = Cli::command()
+        .try_get_matches()
+        .ok()
+        .and_then(|matches| matches.subcommand_name().map(String::from));
+    let span = tracing::info_span!("riff", command = subcommand.as_deref().unwrap_or_default());
+    let result = run(args.command).instrument(span).await;
+    // Errors are reported by exiting with 1
+    let exit_status = match &result {
+        Ok(status) => status.unwrap_or(0),
+        Err(_) => 1,
     };
-    match args.command {
-        Commands::PrintDevEnv(print_dev_env) => {
-            Ok(exit_status_to_exit_code(print_dev_env.cmd().await?))
+    telemetry::send_recorded(exit_status).await;
+    if let Some(otel_exporter) = otel_exporter {
+        if let Err(err) = otel_exporter.flush().await {
+            tracing::warn!(err = %err, "Could not export tracing spans");
         }
-        Commands::Shell(shell) => Ok(exit_status_to_exit_code(shell.cmd().await?)),
+    }
+    result.map(exit_status_to_exit_code)
+}
+
+async fn run(command: Commands) -> color_eyre::Result<Option<i32>> {
+    match command {
+        Commands::PrintDevEnv(print_dev_env) => print_dev_env.cmd().await,
+        Commands::Env(env) => env.cmd().await,
+        Commands::Shell(shell) => shell.cmd().await,
+        Commands::Reload(reload) => reload.cmd().await,
+        Commands::Watch(watch) => watch.cmd().await,
+        Commands::Add(add) => add.cmd().await,
+        Commands::Remove(remove) => remove.cmd().await,
+        Commands::Pin(pin) => pin.cmd().await,
+        Commands::Lock(lock) => lock.cmd().await,
+        Commands::ExecWrapper(exec_wrapper) => exec_wrapper.cmd().await,
+        Commands::Direnv(direnv) => direnv.cmd().await,
+        Commands::Export(export) => export.cmd().await,
+        Commands::VerifyExports(verify_exports) => verify_exports.cmd().await,
+        Commands::Gc(gc) => gc.cmd().await,
+        Commands::Graph(graph) => graph.cmd().await,
+        Commands::Detect(detect) => detect.cmd().await,
+        Commands::Explain(explain) => explain.cmd().await,
+        Commands::Du(du) => du.cmd().await,
+        Commands::Init(init) => init.cmd().await,
+        Commands::New(new) => new.cmd().await,
+        Commands::Try(try_package) => try_package.cmd().await,
+        Commands::Doctor(doctor) => doctor.cmd().await,
+        Commands::Daemon(daemon) => daemon.cmd().await,
+        Commands::Registry(registry) => registry.cmd().await,
+        Commands::Telemetry(telemetry) => telemetry.cmd().await,
+        Commands::Config(config) => config.cmd().await,
+        Commands::Dev(dev) => dev.cmd().await,
         Commands::Run(run) => {
             let code = run.cmd().await?;
             if let Some(code) = code {
@@ -87,7 +311,7 @@ Try running it in a shell; for example:
                 }
             }
 
-            Ok(exit_status_to_exit_code(code))
+            Ok(code)
         }
     }
 }
@@ -98,8 +322,8 @@ fn exit_status_to_exit_code(status: Option<i32>) -> ExitCode {
         .unwrap_or(ExitCode::SUCCESS)
 }
 
-#[tracing::instrument]
-async fn setup_tracing() -> eyre::Result<()> {
+/// Set up logging, and the export of spans to the OpenTelemetry collector at `otel_endpoint`
+async fn setup_tracing(otel_endpoint: Option<&str>) -> color_eyre::Result<Option<OtelExporter>> {
     let filter_layer = match EnvFilter::try_from_default_env() {
         Ok(layer) => layer,
         Err(e) => {
@@ -120,11 +344,20 @@ async fn setup_tracing() -> eyre::Result<()> {
         .with_writer(std::io::stderr)
         .pretty();
 
+    let (otel_exporter, otel_layer) = match otel_endpoint {
+        Some(endpoint) => {
+            let (exporter, layer) = OtelExporter::new(endpoint)?;
+            (Some(exporter), Some(layer))
+        }
+        None => (None, None),
+    };
+
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt_layer)
         .with(ErrorLayer::default())
+        .with(otel_layer)
         .try_init()?;
 
-    Ok(())
+    Ok(otel_exporter)
 }
@@ -0,0 +1,347 @@
+//! Programmatic, formatting-preserving edits of the `package.metadata.riff` table.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, WrapErr};
+use toml_edit::{Array, Document, InlineTable, Item, Table, TableLike};
+
+/// Which list of inputs an edit applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Build,
+    Runtime,
+}
+
+impl InputKind {
+    pub fn key(&self) -> &'static str {
+        match self {
+            InputKind::Build => "build-inputs",
+            InputKind::Runtime => "runtime-inputs",
+        }
+    }
+
+    /// The kind named by a command's first argument, like `build-input` in
+    /// `riff add build-input openssl`, and the inputs after it
+    ///
+    /// Without one, the inputs are build inputs unless `runtime` (from `--runtime`) is set.
+    pub fn from_args(args: &[String], runtime: bool) -> color_eyre::Result<(Self, &[String])> {
+        let named = args.split_first().and_then(|(first, rest)| {
+            let kind = match first.trim_end_matches('s') {
+                "build-input" => InputKind::Build,
+                "runtime-input" => InputKind::Runtime,
+                _ => return None,
+            };
+            Some((kind, rest))
+        });
+        match named {
+            Some((InputKind::Build, _)) if runtime => Err(eyre!(
+                "`--runtime` can't be used with `build-input`, use `runtime-input` instead"
+            )),
+            Some(named) => Ok(named),
+            None if runtime => Ok((InputKind::Runtime, args)),
+            None => Ok((InputKind::Build, args)),
+        }
+    }
+}
+
+const ENVIRONMENT_VARIABLES_KEY: &str = "environment-variables";
+
+pub struct MetadataEditor {
+    manifest_path: PathBuf,
+    document: Document,
+}
+
+impl MetadataEditor {
+    /// Open the `Cargo.toml` in `project_dir` for editing
+    pub async fn open(project_dir: &Path) -> color_eyre::Result<Self> {
+        let manifest_path = project_dir.join("Cargo.toml");
+        let contents = tokio::fs::read_to_string(&manifest_path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", manifest_path.display()))?;
+        Self::from_contents(manifest_path, &contents)
+    }
+
+    pub fn from_contents(manifest_path: PathBuf, contents: &str) -> color_eyre::Result<Self> {
+        let document = contents
+            .parse::<Document>()
+            .wrap_err_with(|| format!("Could not parse `{}`", manifest_path.display()))?;
+        if !matches!(document.get("package"), Some(package) if package.is_table_like()) {
+            return Err(eyre!(
+                "`{}` has no `[package]` table to store riff metadata in",
+                manifest_path.display()
+            ));
+        }
+        Ok(Self {
+            manifest_path,
+            document,
+        })
+    }
+
+    /// Add `input` to the `kind` inputs, returning `false` if it was already present
+    pub fn add_input(&mut self, kind: InputKind, input: &str) -> color_eyre::Result<bool> {
+        let riff = riff_table(&mut self.document, &self.manifest_path)?;
+        let riff = table_like(riff, "package.metadata.riff", &self.manifest_path)?;
+        let array = riff.entry(kind.key()).or_insert_with(|| {
+            let mut array = Array::new();
+            array.set_trailing_comma(false);
+            Item::Value(array.into())
+        });
+        let array = array.as_array_mut().ok_or_else(|| {
+            eyre!(
+                "`package.metadata.riff.{key}` in `{manifest}` is not a list",
+                key = kind.key(),
+                manifest = self.manifest_path.display()
+            )
+        })?;
+        if array.iter().any(|v| v.as_str() == Some(input)) {
+            return Ok(false);
+        }
+        array.push(input);
+        Ok(true)
+    }
+
+    /// Remove `input` from the `kind` inputs, returning `false` if it was not present
+    pub fn remove_input(&mut self, kind: InputKind, input: &str) -> bool {
+        let array = match self
+            .existing_riff_table()
+            .and_then(|riff| riff.get_mut(kind.key()))
+            .and_then(Item::as_array_mut)
+        {
+            Some(array) => array,
+            None => return false,
+        };
+        let before = array.len();
+        array.retain(|v| v.as_str() != Some(input));
+        before != array.len()
+    }
+
+    /// Set an environment variable, returning the previous value if there was one
+    pub fn set_environment_variable(
+        &mut self,
+        name: &str,
+        value: &str,
+    ) -> color_eyre::Result<Option<String>> {
+        let riff = riff_table(&mut self.document, &self.manifest_path)?;
+        let environment_variables = child_table(
+            riff,
+            "package.metadata.riff",
+            ENVIRONMENT_VARIABLES_KEY,
+            &self.manifest_path,
+        )?;
+        let environment_variables = table_like(
+            environment_variables,
+            "package.metadata.riff.environment-variables",
+            &self.manifest_path,
+        )?;
+        let previous = environment_variables
+            .insert(name, toml_edit::value(value))
+            .as_ref()
+            .and_then(Item::as_str)
+            .map(ToString::to_string);
+        Ok(previous)
+    }
+
+    /// Remove an environment variable, returning `false` if it was not present
+    pub fn remove_environment_variable(&mut self, name: &str) -> bool {
+        self.existing_riff_table()
+            .and_then(|riff| riff.get_mut(ENVIRONMENT_VARIABLES_KEY))
+            .and_then(Item::as_table_like_mut)
+            .and_then(|environment_variables| environment_variables.remove(name))
+            .is_some()
+    }
+
+    pub async fn save(&self) -> color_eyre::Result<()> {
+        tokio::fs::write(&self.manifest_path, self.to_string())
+            .await
+            .wrap_err_with(|| format!("Could not write `{}`", self.manifest_path.display()))
+    }
+
+    fn existing_riff_table(&mut self) -> Option<&mut dyn TableLike> {
+        self.document
+            .get_mut("package")?
+            .get_mut("metadata")?
+            .get_mut("riff")?
+            .as_table_like_mut()
+    }
+}
+
+/// The `package.metadata.riff` table in `document`, created if needed
+fn riff_table<'a>(
+    document: &'a mut Document,
+    manifest_path: &Path,
+) -> color_eyre::Result<&'a mut Item> {
+    let metadata = child_table(
+        &mut document["package"],
+        "package",
+        "metadata",
+        manifest_path,
+    )?;
+    child_table(metadata, "package.metadata", "riff", manifest_path)
+}
+
+/// The table `key` in the table `parent` at `path`, created if needed
+///
+/// A new table is inline when `parent` is, as in `metadata = { riff = { ... } }`. Otherwise it's
+/// implicit, so `[package.metadata.riff]` doesn't bring an empty `[package.metadata]` with it.
+fn child_table<'a>(
+    parent: &'a mut Item,
+    path: &str,
+    key: &str,
+    manifest_path: &Path,
+) -> color_eyre::Result<&'a mut Item> {
+    let inline = parent.is_inline_table();
+    Ok(table_like(parent, path, manifest_path)?
+        .entry(key)
+        .or_insert_with(|| {
+            if inline {
+                Item::Value(InlineTable::new().into())
+            } else {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            }
+        }))
+}
+
+/// `item` as a table, which is either a `[table]` or an inline one
+fn table_like<'a>(
+    item: &'a mut Item,
+    path: &str,
+    manifest_path: &Path,
+) -> color_eyre::Result<&'a mut dyn TableLike> {
+    item.as_table_like_mut().ok_or_else(|| {
+        eyre!(
+            "`{path}` in `{manifest}` is not a table",
+            manifest = manifest_path.display()
+        )
+    })
+}
+
+impl std::fmt::Display for MetadataEditor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.document.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"# A comment that should survive
+[package]
+name = "riff-test"
+version = "0.1.0"
+
+[dependencies]
+"#;
+
+    #[test]
+    fn add_and_remove_inputs() -> eyre::Result<()> {
+        let mut editor = MetadataEditor::from_contents("Cargo.toml".into(), MANIFEST)?;
+        assert!(editor.add_input(InputKind::Build, "openssl")?);
+        assert!(!editor.add_input(InputKind::Build, "openssl")?);
+        assert!(editor.add_input(InputKind::Runtime, "libGL")?);
+        assert_eq!(editor.set_environment_variable("HI", "BYE")?, None);
+
+        let edited = editor.to_string();
+        assert!(edited.starts_with("# A comment that should survive\n"));
+        assert!(edited.contains("[package.metadata.riff]"));
+        assert!(edited.contains(r#"build-inputs = ["openssl"]"#));
+        assert!(edited.contains(r#"runtime-inputs = ["libGL"]"#));
+        assert!(edited.contains("[package.metadata.riff.environment-variables]"));
+        assert!(edited.contains(r#"HI = "BYE""#));
+        assert!(!edited.contains("[package.metadata]\n"));
+
+        assert!(editor.remove_input(InputKind::Build, "openssl"));
+        assert!(!editor.remove_input(InputKind::Build, "openssl"));
+        assert!(editor.remove_environment_variable("HI"));
+        assert!(!editor.remove_environment_variable("HI"));
+        assert!(!editor.to_string().contains("openssl"));
+        Ok(())
+    }
+
+    #[test]
+    fn inline_tables() -> eyre::Result<()> {
+        let mut editor = MetadataEditor::from_contents(
+            "Cargo.toml".into(),
+            "[package]\nname = \"riff-test\"\nmetadata.riff = { build-inputs = [\"a\"] }\n",
+        )?;
+        assert!(editor.add_input(InputKind::Build, "b")?);
+        assert!(editor.add_input(InputKind::Runtime, "c")?);
+        assert_eq!(editor.set_environment_variable("HI", "BYE")?, None);
+        assert_eq!(
+            editor.set_environment_variable("HI", "AGAIN")?,
+            Some("BYE".to_string())
+        );
+        let edited = editor.to_string();
+        let manifest: toml::Value = toml::from_str(&edited)?;
+        let riff = &manifest["package"]["metadata"]["riff"];
+        assert_eq!(
+            riff["build-inputs"],
+            toml::Value::from(vec!["a", "b"]),
+            "{edited}"
+        );
+        assert_eq!(
+            riff["runtime-inputs"],
+            toml::Value::from(vec!["c"]),
+            "{edited}"
+        );
+        assert_eq!(riff["environment-variables"]["HI"].as_str(), Some("AGAIN"));
+        assert!(editor.remove_input(InputKind::Build, "a"));
+
+        let mut editor = MetadataEditor::from_contents(
+            "Cargo.toml".into(),
+            "package = { name = \"riff-test\", metadata = { riff = {} } }\n",
+        )?;
+        assert!(editor.add_input(InputKind::Build, "openssl")?);
+        let manifest: toml::Value = toml::from_str(&editor.to_string())?;
+        assert_eq!(
+            manifest["package"]["metadata"]["riff"]["build-inputs"],
+            toml::Value::from(vec!["openssl"])
+        );
+
+        // A key which isn't a list of inputs is an error, not an input that's already there
+        let mut editor = MetadataEditor::from_contents(
+            "Cargo.toml".into(),
+            "[package]\nmetadata = { riff = { build-inputs = \"openssl\" } }\n",
+        )?;
+        assert!(editor.add_input(InputKind::Build, "openssl").is_err());
+        let mut editor =
+            MetadataEditor::from_contents("Cargo.toml".into(), "[package]\nmetadata = \"riff\"\n")?;
+        assert!(editor.add_input(InputKind::Build, "openssl").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn input_kind_from_args() -> eyre::Result<()> {
+        let args = |args: &[&str]| args.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        let openssl = args(&["openssl"]);
+        assert_eq!(
+            InputKind::from_args(&openssl, false)?,
+            (InputKind::Build, &openssl[..])
+        );
+        assert_eq!(
+            InputKind::from_args(&openssl, true)?,
+            (InputKind::Runtime, &openssl[..])
+        );
+        assert_eq!(
+            InputKind::from_args(&args(&["build-input", "openssl"]), false)?,
+            (InputKind::Build, &openssl[..])
+        );
+        let libgl = args(&["libGL"]);
+        assert_eq!(
+            InputKind::from_args(&args(&["runtime-inputs", "libGL"]), true)?,
+            (InputKind::Runtime, &libgl[..])
+        );
+        assert!(InputKind::from_args(&args(&["build-input", "openssl"]), true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn virtual_manifest_is_rejected() {
+        let editor =
+            MetadataEditor::from_contents("Cargo.toml".into(), "[workspace]\nmembers = []\n");
+        assert!(editor.is_err());
+    }
+}
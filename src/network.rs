@@ -0,0 +1,59 @@
+//! Riff's own network access, which `--no-network` turns off for locked-down build machines.
+//!
+//! Every HTTP client riff uses comes from [`client_builder`], which refuses to make one once the
+//! network is disabled, and [`nix_args`] puts the `nix` commands riff runs in offline mode.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, thiserror::Error)]
+#[error("Riff needs the network to {purpose}, but `--no-network` disables it")]
+pub struct NetworkDisabled {
+    purpose: &'static str,
+}
+
+/// Refuse all network access for the rest of the run
+pub fn disable() {
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// Whether `--no-network` was given
+pub fn disabled() -> bool {
+    DISABLED.load(Ordering::SeqCst)
+}
+
+/// An HTTP client builder to `purpose`, like "fetch the registry", unless the network is disabled
+pub fn client_builder(purpose: &'static str) -> Result<reqwest::ClientBuilder, NetworkDisabled> {
+    if disabled() {
+        tracing::debug!(purpose, "Refusing network access");
+        return Err(NetworkDisabled { purpose });
+    }
+    Ok(reqwest::Client::builder())
+}
+
+/// Like [`client_builder`], for a client with the default settings
+pub fn client(purpose: &'static str) -> Result<reqwest::Client, NetworkDisabled> {
+    client_builder(purpose).map(|_| reqwest::Client::new())
+}
+
+/// Arguments which keep `nix` from using substituters or fetching anything, if the network is
+/// disabled
+pub fn nix_args() -> &'static [&'static str] {
+    if disabled() {
+        &["--offline"]
+    } else {
+        &[]
+    }
+}
+
+/// A paragraph for errors from `nix`, explaining what to do if it failed because something isn't
+/// in the Nix store and the network is disabled
+pub fn missing_hint() -> &'static str {
+    if disabled() {
+        "\n\nWith `--no-network`, everything the environment needs must already be in the Nix \
+         store. Run the same command once with network access to fetch it."
+    } else {
+        ""
+    }
+}
@@ -0,0 +1,125 @@
+//! The parts of Nim projects riff cares about: the packages and system libraries their
+//! `*.nimble` files depend on.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use eyre::WrapErr;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct NimbleProject {
+    /// The Nimble packages the project `requires`
+    pub dependency_names: BTreeSet<String>,
+    /// The system packages the project declares with `foreignDep`, like `openssl`
+    pub foreign_dependencies: BTreeSet<String>,
+}
+
+impl NimbleProject {
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Self> {
+        let mut project = Self::default();
+        for path in nimble_files(project_dir) {
+            let contents = tokio::fs::read_to_string(&path)
+                .await
+                .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+            let parsed = Self::parse(&contents);
+            project.dependency_names.extend(parsed.dependency_names);
+            project
+                .foreign_dependencies
+                .extend(parsed.foreign_dependencies);
+        }
+        Ok(project)
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut project = Self::default();
+        for line in contents.lines().map(str::trim_start) {
+            if let Some(arguments) = line.strip_prefix("requires") {
+                project.dependency_names.extend(
+                    string_literals(arguments)
+                        .into_iter()
+                        .filter_map(package_name)
+                        // Every package depends on Nim itself
+                        .filter(|name| name != "nim"),
+                );
+            } else if let Some(arguments) = line.strip_prefix("foreignDep") {
+                project.foreign_dependencies.extend(
+                    string_literals(arguments)
+                        .into_iter()
+                        .map(|name| name.trim().to_string()),
+                );
+            }
+        }
+        project
+    }
+}
+
+/// Whether `project_dir` contains a Nim project
+pub fn detect(project_dir: &Path) -> bool {
+    !nimble_files(project_dir).is_empty()
+}
+
+/// The `*.nimble` files in `project_dir`
+fn nimble_files(project_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = std::fs::read_dir(project_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension() == Some("nimble".as_ref()) && path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+/// The name in a requirement like `jester >= 0.5.0` or `https://github.com/owner/repo#head`
+fn package_name(requirement: &str) -> Option<String> {
+    let name = requirement
+        .split(|c: char| c.is_whitespace() || "<>=~^#".contains(c))
+        .next()?;
+    let name = name.trim_end_matches('/').rsplit('/').next()?;
+    (!name.is_empty()).then(|| name.to_ascii_lowercase())
+}
+
+/// The contents of the double-quoted string literals in `code`
+fn string_literals(code: &str) -> Vec<&str> {
+    let mut literals = Vec::new();
+    for (index, part) in code.split('"').enumerate() {
+        if index % 2 == 1 {
+            literals.push(part);
+        } else if part.contains('#') {
+            // The rest of the line is a comment
+            break;
+        }
+    }
+    literals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nimble_file() {
+        let nimble = r#"
+version       = "0.1.0"
+author        = "Riff"
+srcDir        = "src"
+
+requires "nim >= 1.6.0", "jester >= 0.5.0"
+requires "https://github.com/GULPF/tiny_sqlite#head"
+requires("db_connector") # For the tests
+
+foreignDep "openssl"
+"#;
+        assert_eq!(
+            NimbleProject::parse(nimble),
+            NimbleProject {
+                dependency_names: ["db_connector", "jester", "tiny_sqlite"]
+                    .into_iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                foreign_dependencies: ["openssl".to_string()].into_iter().collect(),
+            }
+        );
+    }
+}
@@ -1,14 +1,22 @@
-use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
-use eyre::WrapErr;
+use eyre::{eyre, WrapErr};
 use owo_colors::OwoColorize;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 
-pub async fn get_nix_dev_env(flake_dir: &Path) -> color_eyre::Result<NixDevEnv> {
-    let output = get_raw_nix_dev_env(flake_dir).await?;
+use crate::flake_generator::GeneratedFlake;
+use crate::legacy_nix;
+use crate::network;
+use crate::progress;
+
+pub async fn get_nix_dev_env(flake: &GeneratedFlake) -> color_eyre::Result<NixDevEnv> {
+    if flake.legacy() {
+        return get_nix_shell_env(flake.path()).await;
+    }
+    let output = get_raw_nix_dev_env(flake.path(), &flake.nix_args(), flake.gc_root()).await?;
 
     serde_json::from_str(&output).wrap_err(
         "Unable to parse output produced by `nix print-dev-env` into our desired structure",
@@ -16,12 +24,12 @@ pub async fn get_nix_dev_env(flake_dir: &Path) -> color_eyre::Result<NixDevEnv>
 }
 
 /// The output schema of `nix print-dev-env --json`.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NixDevEnv {
     variables: HashMap<String, Variable>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type", content = "value")]
 pub enum Variable {
     #[serde(rename = "exported")]
@@ -34,17 +42,305 @@ pub enum Variable {
     Associative(HashMap<String, String>),
 }
 
-pub async fn get_raw_nix_dev_env(flake_dir: &Path) -> color_eyre::Result<String> {
+impl NixDevEnv {
+    /// The environment in the NUL separated output of `env -0` inside `nix-shell --pure`,
+    /// leaving out what it kept from riff's own environment and the variables of the shell itself
+    fn from_env_dump(dump: &str) -> Self {
+        let variables = dump
+            .split('\0')
+            .filter_map(|entry| entry.split_once('='))
+            .filter(|(name, _)| !NIX_SHELL_VARS.contains(name))
+            .filter(|(name, value)| std::env::var(name).ok().as_deref() != Some(*value))
+            .map(|(name, value)| (name.to_string(), Variable::Exported(value.to_string())))
+            .collect();
+        Self { variables }
+    }
+
+    /// The value of an exported variable, such as `PATH`
+    pub fn exported(&self, name: &str) -> Option<&str> {
+        match self.variables.get(name) {
+            Some(Variable::Exported(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The `shellHook` of the environment, if it has one
+    pub fn shell_hook(&self) -> Option<&str> {
+        match self.variables.get(SHELL_HOOK_VAR) {
+            Some(Variable::Var(hook) | Variable::Exported(hook)) if !hook.trim().is_empty() => {
+                Some(hook)
+            }
+            _ => None,
+        }
+    }
+
+    /// The exported variables, excluding those `nix develop` would not pass through
+    pub fn exported_variables(&self) -> BTreeMap<String, String> {
+        self.variables
+            .iter()
+            .filter(|(name, _)| !IGNORED_VARS.contains(&name.as_str()))
+            .filter_map(|(name, value)| match value {
+                Variable::Exported(value) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+const SHELL_HOOK_VAR: &str = "shellHook";
+
+/// Runs the `shellHook` in `RIFF_SHELL_HOOK`, then replaces itself with the command in its
+/// arguments, so variables the hook exports reach the command
+const SHELL_HOOK_WRAPPER: &str = r#"eval "$RIFF_SHELL_HOOK"; exec "$0" "$@""#;
+
+/// Variables which are prepended to the existing value rather than replacing it
+const PREPENDED_VARS: [&str; 2] = ["PATH", "XDG_DATA_DIRS"];
+
+/// A resolved environment, ready to apply without evaluating anything with Nix
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ActivationScript {
+    /// Variables to set, replacing any existing value
+    pub set: BTreeMap<String, String>,
+    /// Colon separated lists to prepend to variables like `PATH`
+    pub prepend: BTreeMap<String, String>,
+    /// Shell code to run once the variables are set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell_hook: Option<String>,
+}
+
+impl ActivationScript {
+    /// The directories to prepend to `PATH`, in order
+    pub fn path(&self) -> Vec<PathBuf> {
+        self.prepend
+            .get("PATH")
+            .map(|path| std::env::split_paths(path).collect())
+            .unwrap_or_default()
+    }
+
+    /// POSIX shell code exporting the environment, prepending to the existing values
+    pub fn to_shell(&self) -> String {
+        let set = self
+            .set
+            .iter()
+            .map(|(name, value)| format!("export {name}={}\n", shell_quote(value)));
+        let prepend = self.prepend.iter().map(|(name, value)| {
+            format!(
+                "export {name}={}\"${{{name}:+:${name}}}\"\n",
+                shell_quote(value)
+            )
+        });
+        set.chain(prepend)
+            .chain(self.shell_hook.iter().map(|hook| format!("{hook}\n")))
+            .collect()
+    }
+
+    /// fish shell code exporting the environment, prepending to the existing values
+    ///
+    /// The shell hook is bash code, so it's left out.
+    pub fn to_fish(&self) -> String {
+        let set = self
+            .set
+            .iter()
+            .map(|(name, value)| format!("set -gx {name} {}\n", backslash_quote(value)));
+        // Path variables split their values on `:`, and keep their other entries as list items
+        let prepend = self.prepend.iter().map(|(name, value)| {
+            format!("set -gx --path {name} {} ${name}\n", backslash_quote(value))
+        });
+        set.chain(prepend).collect()
+    }
+
+    /// A `.env` file of the environment, with prepended values resolved against the current
+    /// process's environment
+    ///
+    /// The shell hook can't be expressed in one, so it's left out.
+    pub fn to_dotenv(&self) -> String {
+        self.set
+            .iter()
+            .map(|(name, value)| (name, value.clone()))
+            .chain(self.resolved_prepends(|_| true))
+            .map(|(name, value)| format!("{name}={}\n", backslash_quote(&value)))
+            .collect()
+    }
+
+    /// The files GitHub Actions reads from `$GITHUB_ENV` and `$GITHUB_PATH` to set up the steps
+    /// after the current one, as `(env, path)`
+    ///
+    /// `PATH` goes in the path file, one directory per line. Actions prepends each line to what
+    /// came before, so they're written last first. The other prepended values are resolved
+    /// against the current process's environment, and the shell hook is left out.
+    pub fn to_github(&self) -> (String, String) {
+        let env = self
+            .set
+            .iter()
+            .map(|(name, value)| (name, value.clone()))
+            .chain(self.resolved_prepends(|name| name != "PATH"))
+            .map(|(name, value)| {
+                if value.contains('\n') {
+                    // A multiline value goes between delimiters, which mustn't be in the value
+                    let mut delimiter = "RIFF_EOF".to_string();
+                    while value.contains(&delimiter) {
+                        delimiter.push('_');
+                    }
+                    format!("{name}<<{delimiter}\n{value}\n{delimiter}\n")
+                } else {
+                    format!("{name}={value}\n")
+                }
+            })
+            .collect();
+        let mut path = self.path();
+        path.reverse();
+        let path = path
+            .iter()
+            .map(|dir| format!("{}\n", dir.display()))
+            .collect();
+        (env, path)
+    }
+
+    /// The prepended variables `include` accepts, prepended to their values in the current
+    /// process's environment
+    fn resolved_prepends<'a>(
+        &'a self,
+        include: impl Fn(&str) -> bool + 'a,
+    ) -> impl Iterator<Item = (&'a String, String)> + 'a {
+        self.prepend
+            .iter()
+            .filter(move |(name, _)| include(name))
+            .map(|(name, value)| match std::env::var(name) {
+                Ok(old_value) if !old_value.is_empty() => {
+                    (name, format!("{}:{}", value, old_value))
+                }
+                _ => (name, value.clone()),
+            })
+    }
+
+    /// Apply the environment to `command`, prepending to the current process's values if
+    /// `inherit` is set
+    ///
+    /// This doesn't run the shell hook, which `run_in_dev_env` does by wrapping the command.
+    pub fn apply(&self, command: &mut Command, inherit: bool) {
+        for (name, value) in &self.set {
+            command.env(name, value);
+        }
+        for (name, value) in &self.prepend {
+            match std::env::var(name).ok().filter(|_| inherit) {
+                Some(old_value) => command.env(name, format!("{}:{}", value, old_value)),
+                None => command.env(name, value),
+            };
+        }
+    }
+}
+
+impl From<&NixDevEnv> for ActivationScript {
+    fn from(dev_env: &NixDevEnv) -> Self {
+        let (prepend, set) = dev_env
+            .exported_variables()
+            .into_iter()
+            .partition(|(name, _)| PREPENDED_VARS.contains(&name.as_str()));
+        Self {
+            set,
+            prepend,
+            shell_hook: dev_env.shell_hook().map(ToString::to_string),
+        }
+    }
+}
+
+/// Quote `value` as a single POSIX shell word
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quote `value` in single quotes, escaping quotes and backslashes with a backslash, as fish and
+/// `.env` files read them
+fn backslash_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Evaluate the `shell.nix` in `shell_dir` with `nix-shell`, for Nix without flakes
+///
+/// `nix-shell` runs the shell hook before dumping the environment, so the variables it exports
+/// are included and it isn't run again.
+#[tracing::instrument(skip_all, fields(shell_dir = %shell_dir.display()))]
+async fn get_nix_shell_env(shell_dir: &Path) -> color_eyre::Result<NixDevEnv> {
+    let env_file = shell_dir.join("env");
+    let mut nix_shell_command = Command::new("nix-shell");
+    nix_shell_command.kill_on_drop(true);
+    if network::disabled() {
+        // `nix-shell` predates `--offline`
+        nix_shell_command.args(["--option", "substitute", "false"]);
+    }
+    nix_shell_command.args(crate::nix_passthrough::args());
+    nix_shell_command
+        .arg("--pure")
+        .arg(shell_dir.join(legacy_nix::SHELL_NIX))
+        .arg("--run")
+        .arg(format!(
+            "env -0 > {}",
+            shell_quote(&env_file.display().to_string())
+        ))
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    tracing::trace!(command = ?nix_shell_command.as_std(), "Running");
+
+    let output = nix_shell_command
+        .spawn()
+        .wrap_err_with(|| {
+            format!(
+                "Could not execute `{nix_shell}`. Is `{nix}` installed?",
+                nix_shell = "nix-shell".cyan(),
+                nix = "nix".cyan(),
+            )
+        })?
+        .wait_with_output()
+        .await?;
+    // Anything the shell hook prints goes to stderr, keeping stdout for riff's own output
+    eprint!("{}", String::from_utf8_lossy(&output.stdout));
+    if !output.status.success() {
+        return Err(eyre!(
+            "`nix-shell` exited with code {}{}",
+            output
+                .status
+                .code()
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            network::missing_hint(),
+        ));
+    }
+
+    let dump = tokio::fs::read_to_string(&env_file)
+        .await
+        .wrap_err("Could not read the environment `nix-shell` dumped")?;
+    Ok(NixDevEnv::from_env_dump(&dump))
+}
+
+#[tracing::instrument(skip_all, fields(flake_dir = %flake_dir.display()))]
+pub async fn get_raw_nix_dev_env(
+    flake_dir: &Path,
+    nix_args: &[&str],
+    profile: Option<&Path>,
+) -> color_eyre::Result<String> {
     let mut nix_command = Command::new("nix");
     nix_command
         .arg("print-dev-env")
         .arg("--json")
         .args(&["--extra-experimental-features", "flakes nix-command"])
         .arg("-L")
+        .args(nix_args)
         .arg(format!("path://{}", flake_dir.to_str().unwrap()))
         .stdin(Stdio::inherit())
         .stdout(Stdio::piped())
-        .stderr(Stdio::inherit());
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true);
+    // Nix's own log says what it's building, for `--progress json`
+    if progress::enabled() {
+        nix_command
+            .args(["--log-format", "internal-json"])
+            .stderr(Stdio::piped());
+    }
+    // Nix registers the profile as a garbage collector root
+    if let Some(profile) = profile {
+        nix_command.arg("--profile").arg(profile);
+    }
     tracing::trace!(command = ?nix_command.as_std(), "Running");
 
     // TODO(@hoverbear): Try to enable this somehow. Right now since we don't keep the lock
@@ -56,85 +352,135 @@ pub async fn get_raw_nix_dev_env(flake_dir: &Path) -> color_eyre::Result<String>
     //     nix_develop_command.arg("--offline");
     // }
 
-    let nix_command_exit = match nix_command
+    let evaluate_progress =
+        progress::start(progress::Phase::Evaluate, "Running `nix print-dev-env`");
+    let mut nix_command_child = nix_command
         .spawn()
-        .wrap_err("Failed to spawn `nix develop`")? // This could throw a `EWOULDBLOCK`
-        .wait_with_output()
-        .await
-    {
+        .wrap_err("Failed to spawn `nix develop`")?; // This could throw a `EWOULDBLOCK`
+    let nix_log = nix_command_child
+        .stderr
+        .take()
+        .map(|stderr| tokio::spawn(progress::forward_nix_log(progress::Phase::Evaluate, stderr)));
+    let nix_command_exit = nix_command_child.wait_with_output().await;
+    if let Some(nix_log) = nix_log {
+        nix_log.await.ok();
+    }
+    let nix_command_exit = match nix_command_exit {
         Ok(nix_command_exit) => nix_command_exit,
         err @ Err(_) => {
+            evaluate_progress.finish(false);
             let wrapped_err = err
                 .wrap_err_with(|| {
                     format!(
                         "\
                         Could not execute `{nix_print_dev_env}`. Is `{nix}` installed?\n\n\
                         Get instructions for installing Nix: {nix_install_url}\n\
+                        Run `{riff_doctor}` to check your setup.\n\
                         Underlying error\
                         ",
                         nix_print_dev_env = "nix print-dev-env".cyan(),
                         nix = "nix".cyan(),
                         nix_install_url = "https://nixos.org/download.html".blue().underline(),
+                        riff_doctor = "riff doctor".cyan(),
                     )
                 })
                 .unwrap_err();
             eprintln!("{wrapped_err:#}");
-            std::process::exit(1);
+            crate::telemetry::exit(1).await;
         }
     };
 
+    evaluate_progress.finish(nix_command_exit.status.success());
+    if !nix_command_exit.status.success() {
+        return Err(eyre!(
+            "`nix print-dev-env` exited with code {}{}",
+            nix_command_exit
+                .status
+                .code()
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            network::missing_hint(),
+        ));
+    }
+
     String::from_utf8(nix_command_exit.stdout)
         .wrap_err("Output produced by `nix print-dev-env` was not valid UTF8")
 }
 
+// TODO(@edolstra): Copied from develop.cc, would be nice to
+// keep these in sync somehow (e.g. `nix print-dev-env --json`
+// could output them).
+const IGNORED_VARS: [&str; 16] = [
+    "BASHOPTS",
+    "HOME",
+    "NIX_BUILD_TOP",
+    "NIX_ENFORCE_PURITY",
+    "NIX_LOG_FD",
+    "NIX_REMOTE",
+    "PPID",
+    "SHELLOPTS",
+    "SSL_CERT_FILE",
+    "TEMP",
+    "TEMPDIR",
+    "TERM",
+    "TMP",
+    "TMPDIR",
+    "TZ",
+    "UID",
+];
+
+/// Variables `nix-shell` and the shell it starts set, which `nix print-dev-env` leaves out
+const NIX_SHELL_VARS: [&str; 5] = ["IN_NIX_SHELL", "OLDPWD", "PWD", "SHLVL", "_"];
+
+/// The value of `IN_NIX_SHELL` when riff runs inside `nix develop` or `nix-shell`
+pub fn outer_nix_shell() -> Option<String> {
+    std::env::var("IN_NIX_SHELL").ok().filter(|v| !v.is_empty())
+}
+
+/// Warn that the environment is nested in another Nix shell, returning whether it is
+pub fn warn_if_nested() -> bool {
+    match outer_nix_shell() {
+        Some(outer) => {
+            eprintln!(
+                "{warning} riff is running inside another Nix shell (`{in_nix_shell}`). \
+                 Tools from that shell stay on the `PATH` after riff's, which can shadow them.",
+                warning = "⚠️".yellow(),
+                in_nix_shell = format!("IN_NIX_SHELL={outer}").cyan(),
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+/// Prepare `command_name` to run in `dev_env`, without any of riff's own environment if
+/// `clear_env` is set
 pub async fn run_in_dev_env(
     dev_env: &NixDevEnv,
     command_name: &str,
+    clear_env: bool,
 ) -> color_eyre::Result<Command> {
-    let mut command = Command::new(&command_name);
-
-    // TODO(@edolstra): Copied from develop.cc, would be nice to
-    // keep these in sync somehow (e.g. `nix print-dev-env --json`
-    // could output them).
-    let prepended_vars = HashSet::from(["PATH".to_owned(), "XDG_DATA_DIRS".to_owned()]);
-
-    let ignored_vars = HashSet::from(
-        [
-            "BASHOPTS",
-            "HOME",
-            "NIX_BUILD_TOP",
-            "NIX_ENFORCE_PURITY",
-            "NIX_LOG_FD",
-            "NIX_REMOTE",
-            "PPID",
-            "SHELLOPTS",
-            "SSL_CERT_FILE",
-            "TEMP",
-            "TEMPDIR",
-            "TERM",
-            "TMP",
-            "TMPDIR",
-            "TZ",
-            "UID",
-        ]
-        .map(str::to_owned),
-    );
-
-    for (name, value) in &dev_env.variables {
-        if let Variable::Exported(value) = value {
-            if ignored_vars.contains(name) {
-                continue;
-            }
-            let mut value = value.clone();
-            if prepended_vars.contains(name) {
-                if let Ok(old_value) = std::env::var(&name) {
-                    value = format!("{}:{}", value, old_value);
-                }
-            }
-            command.env(name, value);
+    // The hook is shell code, so it runs in the environment's `bash` before the command
+    let mut command = match dev_env.shell_hook() {
+        Some(shell_hook) => {
+            let mut command = Command::new("bash");
+            command
+                .args(["-c", SHELL_HOOK_WRAPPER, command_name])
+                .env("RIFF_SHELL_HOOK", shell_hook);
+            command
         }
+        None => Command::new(&command_name),
+    };
+
+    if clear_env {
+        command.env_clear();
+    } else if warn_if_nested() && dev_env.exported("LD_LIBRARY_PATH").is_none() {
+        // The outer shell's libraries were built against its own inputs, not ours
+        command.env_remove("LD_LIBRARY_PATH");
     }
 
+    ActivationScript::from(dev_env).apply(&mut command, !clear_env);
+
     // Increment $IN_RIFF.
     command.env(
         "IN_RIFF",
@@ -149,6 +495,23 @@ pub async fn run_in_dev_env(
     Ok(command)
 }
 
+/// Pass the current process's values of the variables `names` on to `command`, which runs in
+/// `dev_env` with a cleared environment, unless `dev_env` sets them itself
+pub fn keep_variables<'a>(
+    command: &mut Command,
+    dev_env: &NixDevEnv,
+    names: impl IntoIterator<Item = &'a str>,
+) {
+    for name in names {
+        if dev_env.exported(name).is_some() {
+            continue;
+        }
+        if let Some(value) = std::env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 pub async fn get_shell() -> color_eyre::Result<String> {
     // Use $SHELL, the user's shell from /etc/passwd, or bash.
@@ -170,3 +533,194 @@ pub async fn get_shell() -> color_eyre::Result<String> {
         .await?
         .unwrap_or_else(|| "bash".to_owned()))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    #[test]
+    fn activation_script() -> eyre::Result<()> {
+        let dev_env: NixDevEnv = serde_json::from_str(
+            r#"{"variables": {
+                "PATH": {"type": "exported", "value": "/nix/store/a/bin:/nix/store/b/bin"},
+                "HOME": {"type": "exported", "value": "/homeless-shelter"},
+                "GREETING": {"type": "exported", "value": "it's riff"},
+                "stdenv": {"type": "var", "value": "/nix/store/stdenv"}
+            }}"#,
+        )?;
+        let activation_script = ActivationScript::from(&dev_env);
+        assert_eq!(
+            activation_script.set,
+            [("GREETING".to_string(), "it's riff".to_string())]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            activation_script.path(),
+            vec![
+                PathBuf::from("/nix/store/a/bin"),
+                PathBuf::from("/nix/store/b/bin")
+            ]
+        );
+        assert_eq!(
+            activation_script.to_shell(),
+            "export GREETING='it'\\''s riff'\n\
+             export PATH='/nix/store/a/bin:/nix/store/b/bin'\"${PATH:+:$PATH}\"\n"
+        );
+        assert_eq!(
+            activation_script.to_fish(),
+            "set -gx GREETING 'it\\'s riff'\n\
+             set -gx --path PATH '/nix/store/a/bin:/nix/store/b/bin' $PATH\n"
+        );
+
+        let activation_script = ActivationScript {
+            set: [("GREETING".to_string(), r"it's C:\riff".to_string())]
+                .into_iter()
+                .collect(),
+            prepend: [("RIFF_TEST_DIRS".to_string(), "/nix/store/a".to_string())]
+                .into_iter()
+                .collect(),
+            shell_hook: None,
+        };
+        std::env::set_var("RIFF_TEST_DIRS", "/usr/share");
+        assert_eq!(
+            activation_script.to_dotenv(),
+            "GREETING='it\\'s C:\\\\riff'\n\
+             RIFF_TEST_DIRS='/nix/store/a:/usr/share'\n"
+        );
+
+        let activation_script = ActivationScript {
+            set: [
+                ("GREETING".to_string(), "it's riff".to_string()),
+                ("MOTD".to_string(), "hello\nRIFF_EOF".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            prepend: [
+                (
+                    "PATH".to_string(),
+                    "/nix/store/a/bin:/nix/store/b/bin".to_string(),
+                ),
+                ("RIFF_TEST_DIRS".to_string(), "/nix/store/a".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            shell_hook: None,
+        };
+        assert_eq!(
+            activation_script.to_github(),
+            (
+                "GREETING=it's riff\n\
+                 MOTD<<RIFF_EOF_\nhello\nRIFF_EOF\nRIFF_EOF_\n\
+                 RIFF_TEST_DIRS=/nix/store/a:/usr/share\n"
+                    .to_string(),
+                "/nix/store/b/bin\n/nix/store/a/bin\n".to_string()
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn kept_variables() -> eyre::Result<()> {
+        let dev_env: NixDevEnv = serde_json::from_str(
+            r#"{"variables": {
+                "RIFF_TEST_GREETING": {"type": "exported", "value": "from the environment"}
+            }}"#,
+        )?;
+        std::env::set_var("RIFF_TEST_KEPT", "kept");
+        std::env::set_var("RIFF_TEST_GREETING", "from the host");
+        std::env::set_var("RIFF_TEST_DROPPED", "dropped");
+
+        let mut command = run_in_dev_env(&dev_env, "bash", true).await?;
+        keep_variables(
+            &mut command,
+            &dev_env,
+            ["RIFF_TEST_KEPT", "RIFF_TEST_GREETING", "RIFF_TEST_UNSET"],
+        );
+        let envs = command
+            .as_std()
+            .get_envs()
+            .filter_map(|(name, value)| Some((name.to_str()?, value?.to_str()?)))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(envs.get("RIFF_TEST_KEPT"), Some(&"kept"));
+        assert_eq!(
+            envs.get("RIFF_TEST_GREETING"),
+            Some(&"from the environment")
+        );
+        assert!(!envs.contains_key("RIFF_TEST_DROPPED"));
+        assert!(!envs.contains_key("RIFF_TEST_UNSET"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shell_hook() -> eyre::Result<()> {
+        let dev_env: NixDevEnv = serde_json::from_str(
+            r#"{"variables": {
+                "PATH": {"type": "exported", "value": "/nix/store/riff/bin"},
+                "shellHook": {"type": "var", "value": "export GREETING=hello\n"}
+            }}"#,
+        )?;
+        assert_eq!(
+            ActivationScript::from(&dev_env).to_shell(),
+            "export PATH='/nix/store/riff/bin'\"${PATH:+:$PATH}\"\n\
+             export GREETING=hello\n\n"
+        );
+
+        let command = run_in_dev_env(&dev_env, "cargo", true).await?;
+        let command = command.as_std();
+        assert_eq!(command.get_program(), "bash");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-c", SHELL_HOOK_WRAPPER, "cargo"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nix_shell_env() {
+        std::env::set_var("RIFF_TEST_INHERITED", "kept");
+        let dev_env = NixDevEnv::from_env_dump(
+            "PATH=/nix/store/riff/bin\0RIFF_TEST_INHERITED=kept\0SHLVL=2\0\
+             GREETING=hello\nworld\0",
+        );
+        assert_eq!(
+            dev_env.exported_variables(),
+            [
+                ("GREETING".to_string(), "hello\nworld".to_string()),
+                ("PATH".to_string(), "/nix/store/riff/bin".to_string()),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[tokio::test]
+    async fn nested_in_nix_shell() -> eyre::Result<()> {
+        let dev_env: NixDevEnv = serde_json::from_str(
+            r#"{"variables": {"PATH": {"type": "exported", "value": "/nix/store/riff/bin"}}}"#,
+        )?;
+        std::env::set_var("LD_LIBRARY_PATH", "/nix/store/outer/lib");
+
+        std::env::remove_var("IN_NIX_SHELL");
+        let command = run_in_dev_env(&dev_env, "true", false).await?;
+        assert!(!command
+            .as_std()
+            .get_envs()
+            .any(|(name, _)| name == OsStr::new("LD_LIBRARY_PATH")));
+
+        std::env::set_var("IN_NIX_SHELL", "impure");
+        assert_eq!(outer_nix_shell(), Some("impure".to_string()));
+        let command = run_in_dev_env(&dev_env, "true", false).await?;
+        let envs = command.as_std().get_envs().collect::<HashMap<_, _>>();
+        assert_eq!(envs.get(OsStr::new("LD_LIBRARY_PATH")), Some(&None));
+        assert!(envs
+            .get(OsStr::new("PATH"))
+            .and_then(|path| *path)
+            .map(|path| path.to_string_lossy().starts_with("/nix/store/riff/bin:"))
+            .unwrap_or(false));
+        std::env::remove_var("IN_NIX_SHELL");
+        Ok(())
+    }
+}
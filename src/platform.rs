@@ -0,0 +1,70 @@
+//! Platforms registry rules can be limited to, named like `linux` or `macos-aarch64`.
+//!
+//! The names are an operating system, and optionally an architecture, as Rust spells them in
+//! `std::env::consts::OS` and `std::env::consts::ARCH`.
+
+/// Whether the platform `name` is the one riff runs on
+pub fn matches_host(name: &str) -> bool {
+    matches(name, std::env::consts::OS, std::env::consts::ARCH)
+}
+
+fn matches(name: &str, os: &str, arch: &str) -> bool {
+    match name.split_once('-') {
+        Some((name_os, name_arch)) => name_os == os && name_arch == arch,
+        None => name == os,
+    }
+}
+
+/// The Nix condition, in scope of a `pkgs`, under which the platform `name` is being built for
+pub fn nix_condition(name: &str) -> String {
+    let (os, arch) = match name.split_once('-') {
+        Some((os, arch)) => (os, Some(arch)),
+        None => (name, None),
+    };
+    let os_condition = match os {
+        "linux" => "stdenv.isLinux".to_string(),
+        "macos" => "stdenv.isDarwin".to_string(),
+        "freebsd" => "stdenv.isFreeBSD".to_string(),
+        os => format!(
+            "stdenv.hostPlatform.parsed.kernel.name == {}",
+            crate::nix_expr::string(os)
+        ),
+    };
+    match arch {
+        None => os_condition,
+        Some(arch) => {
+            let arch_condition = match arch {
+                "x86_64" => "stdenv.hostPlatform.isx86_64".to_string(),
+                "aarch64" => "stdenv.hostPlatform.isAarch64".to_string(),
+                arch => format!(
+                    "stdenv.hostPlatform.parsed.cpu.name == {}",
+                    crate::nix_expr::string(arch)
+                ),
+            };
+            format!("{os_condition} && {arch_condition}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_names() {
+        assert!(matches("linux", "linux", "x86_64"));
+        assert!(matches("linux-x86_64", "linux", "x86_64"));
+        assert!(!matches("linux-aarch64", "linux", "x86_64"));
+        assert!(!matches("macos", "linux", "x86_64"));
+
+        assert_eq!(nix_condition("linux"), "stdenv.isLinux");
+        assert_eq!(
+            nix_condition("macos-aarch64"),
+            "stdenv.isDarwin && stdenv.hostPlatform.isAarch64"
+        );
+        assert_eq!(
+            nix_condition("linux-riscv64"),
+            r#"stdenv.isLinux && stdenv.hostPlatform.parsed.cpu.name == "riscv64""#
+        );
+    }
+}
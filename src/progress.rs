@@ -0,0 +1,321 @@
+//! Structured progress events for programs wrapping riff, like editor extensions.
+//!
+//! With `--progress json`, riff writes one JSON object per line to `--progress-fd` instead of
+//! drawing spinners:
+//!
+//! ```json
+//! {"event":"phase-started","phase":"lock","message":"Running `nix flake lock`"}
+//! {"event":"phase-finished","phase":"lock","success":true,"elapsed_ms":1204}
+//! {"event":"building","phase":"evaluate","derivation":"/nix/store/...-openssl-3.0.7.drv"}
+//! {"event":"progress","phase":"evaluate","done":3,"expected":12,"percent":25.0}
+//! ```
+//!
+//! `building` and `progress` events come from Nix's own log while it builds or downloads the
+//! environment.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::FromRawFd;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use clap::ValueEnum;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+static SINK: OnceCell<Mutex<File>> = OnceCell::new();
+
+/// How riff shows what it's doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ProgressFormat {
+    /// Spinners on the terminal
+    #[default]
+    Human,
+    /// Newline-delimited JSON events
+    Json,
+}
+
+/// The parts of riff's work progress is reported for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Reading the crate graph with `cargo metadata`
+    CargoMetadata,
+    /// Detecting the project's environment
+    Detect,
+    /// Locking the generated flake with `nix flake lock`
+    Lock,
+    /// Evaluating, and building or downloading, the environment with Nix
+    Evaluate,
+    /// Measuring the closure size of an input, for `riff du`
+    Measure,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    PhaseStarted {
+        phase: Phase,
+        message: &'a str,
+    },
+    PhaseFinished {
+        phase: Phase,
+        success: bool,
+        elapsed_ms: u128,
+    },
+    /// Nix started building a derivation
+    Building {
+        phase: Phase,
+        derivation: &'a str,
+    },
+    /// Nix finished `done` of the `expected` builds or downloads
+    Progress {
+        phase: Phase,
+        done: u64,
+        expected: u64,
+        percent: f64,
+    },
+}
+
+/// Write events to the file descriptor `fd` for the rest of the run
+pub fn emit_json_to(fd: i32) -> std::io::Result<()> {
+    // Duplicated, so the events don't close stdout or stderr behind riff's back
+    let duplicate = unsafe { libc::dup(fd) };
+    if duplicate < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    SINK.set(Mutex::new(unsafe { File::from_raw_fd(duplicate) }))
+        .ok();
+    Ok(())
+}
+
+/// Whether events are being written, in which case spinners are not drawn
+pub fn enabled() -> bool {
+    SINK.get().is_some()
+}
+
+pub fn emit(event: &Event) {
+    let sink = match SINK.get() {
+        Some(sink) => sink,
+        None => return,
+    };
+    let line = match serde_json::to_string(event) {
+        Ok(line) => line + "\n",
+        Err(err) => {
+            tracing::debug!(%err, "Could not serialize a progress event");
+            return;
+        }
+    };
+    let mut sink = sink.lock().expect("poisoned progress sink");
+    if let Err(err) = sink.write_all(line.as_bytes()) {
+        tracing::debug!(%err, "Could not write a progress event");
+    }
+}
+
+/// A phase in progress, reported as failed if dropped before it finishes
+#[derive(Debug)]
+pub struct PhaseProgress {
+    phase: Phase,
+    started: Instant,
+    finished: bool,
+}
+
+/// Report that `phase` started
+pub fn start(phase: Phase, message: &str) -> PhaseProgress {
+    emit(&Event::PhaseStarted { phase, message });
+    PhaseProgress {
+        phase,
+        started: Instant::now(),
+        finished: false,
+    }
+}
+
+impl PhaseProgress {
+    pub fn finish(mut self, success: bool) {
+        self.report_finished(success);
+    }
+
+    fn report_finished(&mut self, success: bool) {
+        self.finished = true;
+        emit(&Event::PhaseFinished {
+            phase: self.phase,
+            success,
+            elapsed_ms: self.started.elapsed().as_millis(),
+        });
+    }
+}
+
+impl Drop for PhaseProgress {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.report_finished(false);
+        }
+    }
+}
+
+// The activity and result types of Nix's `--log-format internal-json`
+const ACT_COPY_PATHS: u64 = 103;
+const ACT_BUILDS: u64 = 104;
+const ACT_BUILD: u64 = 105;
+const RES_PROGRESS: u64 = 105;
+/// The most verbose level of Nix's messages shown to the user, `info`
+const MAX_MESSAGE_LEVEL: u64 = 3;
+
+/// The activities in a Nix log whose progress is reported
+#[derive(Debug, Default)]
+pub struct NixLog {
+    counted_activities: HashSet<u64>,
+}
+
+/// What a line of Nix's log means for riff's output
+#[derive(Debug, Clone, PartialEq)]
+pub enum NixLogLine {
+    Building(String),
+    Progress {
+        done: u64,
+        expected: u64,
+    },
+    /// A message for the user
+    Message(String),
+    Ignored,
+}
+
+impl NixLog {
+    /// Interpret a line of `--log-format internal-json` output
+    pub fn parse_line(&mut self, line: &str) -> NixLogLine {
+        let json = match line.strip_prefix("@nix ") {
+            Some(json) => json,
+            // Build logs and anything else Nix didn't encode
+            None => return NixLogLine::Message(line.to_string()),
+        };
+        let entry: serde_json::Value = match serde_json::from_str(json) {
+            Ok(entry) => entry,
+            Err(_) => return NixLogLine::Message(line.to_string()),
+        };
+        let number = |key: &str| entry.get(key).and_then(serde_json::Value::as_u64);
+        match entry.get("action").and_then(serde_json::Value::as_str) {
+            Some("msg") if number("level").unwrap_or(0) <= MAX_MESSAGE_LEVEL => {
+                match entry.get("msg").and_then(serde_json::Value::as_str) {
+                    Some(msg) => NixLogLine::Message(msg.to_string()),
+                    None => NixLogLine::Ignored,
+                }
+            }
+            Some("start") => match (number("id"), number("type")) {
+                (Some(id), Some(ACT_BUILDS | ACT_COPY_PATHS)) => {
+                    self.counted_activities.insert(id);
+                    NixLogLine::Ignored
+                }
+                (_, Some(ACT_BUILD)) => {
+                    match entry["fields"].get(0).and_then(serde_json::Value::as_str) {
+                        Some(derivation) => NixLogLine::Building(derivation.to_string()),
+                        None => NixLogLine::Ignored,
+                    }
+                }
+                _ => NixLogLine::Ignored,
+            },
+            Some("result") if number("type") == Some(RES_PROGRESS) => {
+                let counted = number("id")
+                    .map(|id| self.counted_activities.contains(&id))
+                    .unwrap_or(false);
+                let field = |index: usize| entry["fields"].get(index)?.as_u64();
+                match (counted, field(0), field(1)) {
+                    (true, Some(done), Some(expected)) if expected > 0 => {
+                        NixLogLine::Progress { done, expected }
+                    }
+                    _ => NixLogLine::Ignored,
+                }
+            }
+            _ => NixLogLine::Ignored,
+        }
+    }
+}
+
+/// Report the progress in the `--log-format internal-json` log Nix writes to `log`, passing its
+/// messages on to stderr
+pub async fn forward_nix_log(phase: Phase, log: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(log).lines();
+    let mut nix_log = NixLog::default();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match nix_log.parse_line(&line) {
+            NixLogLine::Building(derivation) => emit(&Event::Building {
+                phase,
+                derivation: &derivation,
+            }),
+            NixLogLine::Progress { done, expected } => emit(&Event::Progress {
+                phase,
+                done,
+                expected,
+                percent: (done as f64 / expected as f64 * 1000.0).round() / 10.0,
+            }),
+            NixLogLine::Message(message) => eprintln!("{message}"),
+            NixLogLine::Ignored => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events() -> eyre::Result<()> {
+        assert_eq!(
+            serde_json::to_string(&Event::PhaseStarted {
+                phase: Phase::CargoMetadata,
+                message: "Running `cargo metadata`",
+            })?,
+            r#"{"event":"phase-started","phase":"cargo-metadata","message":"Running `cargo metadata`"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::Progress {
+                phase: Phase::Evaluate,
+                done: 1,
+                expected: 4,
+                percent: 25.0,
+            })?,
+            r#"{"event":"progress","phase":"evaluate","done":1,"expected":4,"percent":25.0}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nix_log_lines() {
+        let mut nix_log = NixLog::default();
+        assert_eq!(
+            nix_log.parse_line(r#"@nix {"action":"start","id":7,"level":0,"type":104,"text":"","fields":[],"parent":0}"#),
+            NixLogLine::Ignored
+        );
+        assert_eq!(
+            nix_log.parse_line(r#"@nix {"action":"start","id":8,"level":3,"type":105,"text":"building '/nix/store/abc-hello.drv'","fields":["/nix/store/abc-hello.drv","",1,1],"parent":7}"#),
+            NixLogLine::Building("/nix/store/abc-hello.drv".to_string())
+        );
+        assert_eq!(
+            nix_log.parse_line(r#"@nix {"action":"result","id":7,"type":105,"fields":[1,4,1,0]}"#),
+            NixLogLine::Progress {
+                done: 1,
+                expected: 4
+            }
+        );
+        // Progress of activities which aren't counted, like single downloads
+        assert_eq!(
+            nix_log.parse_line(
+                r#"@nix {"action":"result","id":9,"type":105,"fields":[512,1024,0,0]}"#
+            ),
+            NixLogLine::Ignored
+        );
+        assert_eq!(
+            nix_log.parse_line(r#"@nix {"action":"msg","level":0,"msg":"error: build failed"}"#),
+            NixLogLine::Message("error: build failed".to_string())
+        );
+        assert_eq!(
+            nix_log.parse_line(r#"@nix {"action":"msg","level":5,"msg":"evaluating file"}"#),
+            NixLogLine::Ignored
+        );
+        assert_eq!(
+            nix_log.parse_line("warning: Git tree is dirty"),
+            NixLogLine::Message("warning: Git tree is dirty".to_string())
+        );
+    }
+}
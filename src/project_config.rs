@@ -0,0 +1,238 @@
+//! Project-level configuration in `riff.toml`, for settings which don't belong in a manifest
+//! like `Cargo.toml`, or for projects which don't have one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use eyre::{eyre, WrapErr};
+use serde::Deserialize;
+use toml::value::{Table, Value};
+
+use crate::config::{self, Config};
+use crate::dependency_registry::check_remote_url;
+use crate::dev_env::{nixpkgs_flake_ref, DevEnvironment, DevEnvironmentAppliable, RustBuilder};
+use crate::julia_project;
+use crate::nix_expr;
+use crate::resource_limits::ResourceLimits;
+
+/// Where the configuration is read from, in order of preference
+pub const CONFIG_FILES: [&str; 2] = ["riff.toml", ".riff/config.toml"];
+
+/// The contents of `riff.toml`
+///
+/// ```toml
+/// build-inputs = ["protobuf"]
+/// remove-build-inputs = ["openssl"]
+/// nixpkgs = "github:NixOS/nixpkgs/nixos-22.11"
+/// registry-url = "https://riff.example.com/registry.json"
+/// disable-telemetry = true
+/// impure-build = true
+/// builder = "crane"
+/// shell-hook = "export PROTOC_INCLUDE=\"$PWD/proto\""
+/// isolate-julia-depot = true
+/// keep-environment = ["SSH_AUTH_SOCK"]
+///
+/// [environment-variables]
+/// PROTOC_NO_VENDOR = "1"
+///
+/// [resource-limits]
+/// memory = "4G"
+/// ```
+#[derive(Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Nix `buildInputs` to add
+    #[serde(default, deserialize_with = "nix_expr::deserialize_attr_paths")]
+    pub(crate) build_inputs: BTreeSet<String>,
+    /// Nix `buildInputs` to remove, even if the registry or a manifest adds them
+    #[serde(default, deserialize_with = "nix_expr::deserialize_attr_paths")]
+    pub(crate) remove_build_inputs: BTreeSet<String>,
+    /// Nix packages to add to the `LD_LIBRARY_PATH`
+    #[serde(default, deserialize_with = "nix_expr::deserialize_attr_paths")]
+    pub(crate) runtime_inputs: BTreeSet<String>,
+    /// Nix packages to remove from the `LD_LIBRARY_PATH`
+    #[serde(default, deserialize_with = "nix_expr::deserialize_attr_paths")]
+    pub(crate) remove_runtime_inputs: BTreeSet<String>,
+    /// Environment variables to set, overriding any others
+    #[serde(default)]
+    pub(crate) environment_variables: BTreeMap<String, String>,
+    /// The nixpkgs to take packages from, as a flake reference or a nixpkgs commit
+    pub(crate) nixpkgs: Option<String>,
+    /// The registry to use instead of riff's, unless `--registry-url` names another
+    pub(crate) registry_url: Option<String>,
+    /// Never send telemetry for this project
+    #[serde(default)]
+    pub(crate) disable_telemetry: bool,
+    /// The project's build scripts need the network, such as to download models or headers
+    #[serde(default)]
+    pub(crate) impure_build: bool,
+    /// The Nix library `riff export flake --package` builds the project with
+    pub(crate) builder: Option<RustBuilder>,
+    /// Shell code to run when the environment activates, after any other hooks
+    pub(crate) shell_hook: Option<String>,
+    /// Install Julia packages into `.riff/julia-depot` rather than the user's `~/.julia`
+    #[serde(default)]
+    pub(crate) isolate_julia_depot: bool,
+    /// Variables `riff shell --pure` keeps from the outer environment, besides `HOME` and `TERM`
+    #[serde(default)]
+    pub(crate) keep_environment: BTreeSet<String>,
+    /// Limits on the memory, CPU and processes of the commands `riff run` starts
+    #[serde(default)]
+    pub(crate) resource_limits: ResourceLimits,
+}
+
+impl ProjectConfig {
+    /// Read the configuration of the project in `project_dir`, if it or the system, the user or
+    /// `--config` has any
+    ///
+    /// The project's settings are layered over the system's and the user's, as described in
+    /// [`config`](crate::config).
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Option<Self>> {
+        let config = Config::load(project_dir).await?;
+        if config.layers.is_empty() {
+            return Ok(None);
+        }
+        // Each file is checked on its own first, so errors name the file they're in
+        for layer in &config.layers {
+            Self::from_table(config::project_settings(&layer.table))
+                .wrap_err_with(|| format!("Could not parse the {}", layer.origin))?;
+        }
+        Ok(Some(Self::from_table(config::project_settings(
+            &config.merged(),
+        ))?))
+    }
+
+    /// Whether the project in `project_dir` has a configuration file of its own
+    pub fn exists(project_dir: &Path) -> bool {
+        CONFIG_FILES
+            .iter()
+            .any(|file| project_dir.join(file).is_file())
+    }
+
+    #[cfg(test)]
+    fn parse(contents: &str) -> color_eyre::Result<Self> {
+        Self::from_table(toml::from_str(contents)?)
+    }
+
+    pub(crate) fn from_table(table: Table) -> color_eyre::Result<Self> {
+        let mut config: Self = Value::Table(table).try_into()?;
+        if let Some(nixpkgs) = &config.nixpkgs {
+            config.nixpkgs = Some(nixpkgs_flake_ref(nixpkgs).map_err(|e| eyre!(e))?);
+        }
+        if let Some(registry_url) = &config.registry_url {
+            config.registry_url = Some(check_remote_url(registry_url)?);
+        }
+        Ok(config)
+    }
+}
+
+impl DevEnvironmentAppliable for ProjectConfig {
+    #[tracing::instrument(skip_all)]
+    fn apply(&self, dev_env: &mut DevEnvironment) {
+        dev_env
+            .build_inputs
+            .extend(self.build_inputs.iter().cloned());
+        dev_env
+            .build_inputs
+            .retain(|input| !self.remove_build_inputs.contains(input));
+        dev_env
+            .runtime_inputs
+            .extend(self.runtime_inputs.iter().cloned());
+        dev_env
+            .runtime_inputs
+            .retain(|input| !self.remove_runtime_inputs.contains(input));
+        for (name, value) in &self.environment_variables {
+            if let Some(existing_value) = dev_env
+                .environment_variables
+                .insert(name.clone(), value.clone())
+            {
+                tracing::debug!(
+                    key = name,
+                    existing_value,
+                    new_value = value,
+                    "Overriding previously declared environment variable"
+                )
+            }
+        }
+        if let Some(nixpkgs) = &self.nixpkgs {
+            dev_env.nixpkgs_url = Some(nixpkgs.clone());
+        }
+        dev_env.telemetry_disabled |= self.disable_telemetry;
+        dev_env.impure_build |= self.impure_build;
+        if let Some(builder) = self.builder {
+            dev_env.builder = builder;
+        }
+        if self.isolate_julia_depot {
+            dev_env.add_shell_hook(julia_project::ISOLATED_DEPOT_HOOK);
+        }
+        if let Some(shell_hook) = &self.shell_hook {
+            dev_env.add_shell_hook(shell_hook);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependency_registry::DependencyRegistry;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn apply_project_config() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.insert("openssl".to_string());
+        dev_env
+            .environment_variables
+            .insert("HELLO".to_string(), "WORLD".to_string());
+
+        let config = ProjectConfig::parse(
+            r#"
+build-inputs = ["protobuf"]
+remove-build-inputs = ["openssl"]
+nixpkgs = "github:NixOS/nixpkgs/nixos-22.11"
+disable-telemetry = true
+impure-build = true
+builder = "naersk"
+shell-hook = "mkdir -p .cache"
+keep-environment = ["SSH_AUTH_SOCK"]
+
+[environment-variables]
+HELLO = "riff"
+"#,
+        )?;
+        config.apply(&mut dev_env);
+        assert_eq!(
+            dev_env.build_inputs,
+            ["protobuf".to_string()].into_iter().collect()
+        );
+        assert_eq!(
+            dev_env
+                .environment_variables
+                .get("HELLO")
+                .map(String::as_str),
+            Some("riff")
+        );
+        assert!(dev_env
+            .to_flake()
+            .contains(r#"inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-22.11";"#));
+        assert!(dev_env.telemetry_disabled);
+        assert!(dev_env.impure_build);
+        assert_eq!(dev_env.builder, RustBuilder::Naersk);
+        assert_eq!(dev_env.shell_hooks, ["mkdir -p .cache"]);
+        assert!(config.keep_environment.contains("SSH_AUTH_SOCK"));
+
+        assert!(ProjectConfig::parse("nixpkgs = \"${builtins.abort}\"").is_err());
+        assert_eq!(
+            ProjectConfig::parse("nixpkgs = \"4a2b6b1\"")?
+                .nixpkgs
+                .as_deref(),
+            Some("github:NixOS/nixpkgs/4a2b6b1")
+        );
+        assert!(ProjectConfig::parse("build-input = [\"typo\"]").is_err());
+        assert!(ProjectConfig::parse("registry-url = \"registry.json\"").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Fingerprints of the files riff reads from a project, to notice when its environment needs to
+//! be detected again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use eyre::WrapErr;
+
+use crate::detect_report;
+use crate::dev_env::EXISTING_NIX_FILES;
+use crate::riff_lock;
+
+/// Files which affect detection through `cargo metadata`, rather than being read by riff itself
+const LOCK_FILES: [&str; 1] = ["Cargo.lock"];
+
+/// The files in `project_dir` which detection reads, like `Cargo.toml` and `Cargo.lock`, and the
+/// manifests of its workspace members, relative to it
+pub fn watched_files(project_dir: &Path) -> Vec<PathBuf> {
+    read_files()
+        .map(PathBuf::from)
+        .filter(|file_name| project_dir.join(file_name).is_file())
+        .chain(workspace_manifests(project_dir))
+        .collect()
+}
+
+/// Whether a change to `path` can change the environment of `project_dir`
+///
+/// That's one of the files detection reads, or the manifest of a workspace member anywhere but
+/// the build directory and hidden directories, like `target/package/app-0.1.0/Cargo.toml`.
+pub fn affects(project_dir: &Path, path: &Path) -> bool {
+    let relative = match path.strip_prefix(project_dir) {
+        Ok(relative) => relative,
+        Err(_) => return false,
+    };
+    if read_files().any(|file_name| relative == Path::new(file_name)) {
+        return true;
+    }
+    relative.file_name() == Some("Cargo.toml".as_ref())
+        && relative.components().all(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            name != "target" && !name.starts_with('.')
+        })
+}
+
+/// Whether detection reads the file named `file_name` in a project directory, if it's there
+pub fn reads(file_name: &str) -> bool {
+    read_files().any(|read| read == file_name)
+}
+
+fn read_files() -> impl Iterator<Item = &'static str> {
+    detect_report::known_files()
+        .chain(LOCK_FILES)
+        .chain([riff_lock::LOCK_FILE])
+        .chain(EXISTING_NIX_FILES)
+}
+
+/// The manifests of the members of the Cargo workspace in `project_dir`, relative to it, like
+/// `crates/app/Cargo.toml`
+///
+/// Members are listed in the root `Cargo.toml`, where `*` and `?` in a name match directories as
+/// they do for Cargo, like `members = ["crates/*"]`.
+fn workspace_manifests(project_dir: &Path) -> BTreeSet<PathBuf> {
+    let manifest = match std::fs::read_to_string(project_dir.join("Cargo.toml")) {
+        Ok(manifest) => manifest,
+        Err(_) => return BTreeSet::new(),
+    };
+    let manifest = match manifest.parse::<toml::Value>() {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            tracing::debug!(%err, "Could not read the workspace members");
+            return BTreeSet::new();
+        }
+    };
+    let paths = |key: &str| {
+        manifest
+            .get("workspace")
+            .and_then(|workspace| workspace.get(key))
+            .and_then(toml::Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(toml::Value::as_str)
+            .collect::<Vec<_>>()
+    };
+    let excluded = paths("exclude");
+    paths("members")
+        .into_iter()
+        .flat_map(|pattern| expand(project_dir, pattern))
+        .filter(|dir| !excluded.iter().any(|excluded| dir.starts_with(excluded)))
+        .map(|dir| dir.join("Cargo.toml"))
+        .filter(|manifest| project_dir.join(manifest).is_file())
+        .collect()
+}
+
+/// The directories in `project_dir` which `pattern` names, relative to it
+fn expand(project_dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::new()];
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        if !component.contains(['*', '?']) {
+            dirs.iter_mut().for_each(|dir| dir.push(component));
+            continue;
+        }
+        dirs = dirs
+            .into_iter()
+            .flat_map(|dir| {
+                std::fs::read_dir(project_dir.join(&dir))
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Result::ok)
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .filter(|name| matches_wildcard(component, name))
+                    .map(|name| dir.join(name))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+    dirs
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters and `?` any one
+fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => name.is_empty(),
+        Some('*') => name
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain([name.len()])
+            .any(|index| matches_wildcard(chars.as_str(), &name[index..])),
+        Some(c) => {
+            let mut name_chars = name.chars();
+            match name_chars.next() {
+                Some(n) if c == '?' || c == n => {
+                    matches_wildcard(chars.as_str(), name_chars.as_str())
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+/// A hash which changes whenever the version of riff or one of `watched_files` does
+pub async fn fingerprint(project_dir: &Path, watched_files: &[PathBuf]) -> color_eyre::Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    for file_name in watched_files {
+        let path = project_dir.join(file_name);
+        let contents = tokio::fs::read(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        (file_name, contents).hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::fs::write;
+
+    #[tokio::test]
+    async fn fingerprint_follows_lockfile() -> eyre::Result<()> {
+        let project_dir = TempDir::new()?;
+        write(project_dir.path().join("Cargo.toml"), "[package]\n").await?;
+        write(project_dir.path().join("Cargo.lock"), "version = 3\n").await?;
+        write(project_dir.path().join("README.md"), "# riff\n").await?;
+        let files = watched_files(project_dir.path());
+        assert_eq!(files, [Path::new("Cargo.toml"), Path::new("Cargo.lock")]);
+
+        let before = fingerprint(project_dir.path(), &files).await?;
+        assert_eq!(before, fingerprint(project_dir.path(), &files).await?);
+
+        write(
+            project_dir.path().join("Cargo.lock"),
+            "version = 3\n\n[[package]]\nname = \"libz-sys\"\n",
+        )
+        .await?;
+        assert_ne!(before, fingerprint(project_dir.path(), &files).await?);
+
+        assert!(reads("Cargo.lock"));
+        assert!(reads("riff.toml"));
+        assert!(!reads("README.md"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn workspace_members() -> eyre::Result<()> {
+        let project_dir = TempDir::new()?;
+        write(
+            project_dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"cli\", \"crates/*\"]\nexclude = [\"crates/old\"]\n",
+        )
+        .await?;
+        for member in [
+            "cli",
+            "crates/core",
+            "crates/old",
+            "crates/docs",
+            "target/package/cli",
+        ] {
+            let dir = project_dir.path().join(member);
+            tokio::fs::create_dir_all(&dir).await?;
+            if member != "crates/docs" {
+                write(dir.join("Cargo.toml"), "[package]\n").await?;
+            }
+        }
+        assert_eq!(
+            watched_files(project_dir.path()),
+            [
+                Path::new("Cargo.toml"),
+                Path::new("cli/Cargo.toml"),
+                Path::new("crates/core/Cargo.toml"),
+            ]
+        );
+
+        let affected = |path: &str| affects(project_dir.path(), &project_dir.path().join(path));
+        assert!(affected("riff.toml"));
+        assert!(affected(".riff/config.toml"));
+        assert!(affected("crates/core/Cargo.toml"));
+        assert!(!affected("crates/core/src/main.rs"));
+        assert!(!affected("target/package/cli/Cargo.toml"));
+        assert!(!affected(".git/Cargo.toml"));
+
+        assert!(matches_wildcard("*", "core"));
+        assert!(matches_wildcard("riff-*", "riff-cli"));
+        assert!(matches_wildcard("c?re", "core"));
+        assert!(!matches_wildcard("riff-*", "core"));
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! The parts of `pyproject.toml`, `setup.py` and `requirements.txt` riff cares about.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use eyre::WrapErr;
+
+use crate::dependency_registry::rust::RustDependencyData;
+
+/// The files which mark a directory as a Python project
+pub const PYTHON_PROJECT_FILES: [&str; 3] = ["pyproject.toml", "setup.py", "requirements.txt"];
+
+#[derive(serde::Deserialize, Default)]
+pub struct PyProject {
+    pub project: Option<PyProjectProject>,
+    #[serde(default)]
+    pub tool: PyProjectTool,
+}
+
+/// The PEP 621 `[project]` table
+#[derive(serde::Deserialize, Default)]
+pub struct PyProjectProject {
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    #[serde(default, rename = "optional-dependencies")]
+    pub optional_dependencies: HashMap<String, Vec<String>>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct PyProjectTool {
+    pub poetry: Option<Poetry>,
+    /// Riff settings for the project, like `package.metadata.riff` in a `Cargo.toml`
+    pub riff: Option<RustDependencyData>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct Poetry {
+    #[serde(default)]
+    pub dependencies: HashMap<String, toml::Value>,
+    #[serde(default, rename = "dev-dependencies")]
+    pub dev_dependencies: HashMap<String, toml::Value>,
+    #[serde(default)]
+    pub group: HashMap<String, PoetryGroup>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct PoetryGroup {
+    #[serde(default)]
+    pub dependencies: HashMap<String, toml::Value>,
+}
+
+/// Everything riff learned about a Python project
+#[derive(Debug, Default)]
+pub struct PythonProject {
+    /// The normalized names of the project's dependencies
+    pub dependency_names: BTreeSet<String>,
+    pub riff: Option<RustDependencyData>,
+    pub uses_poetry: bool,
+}
+
+impl PythonProject {
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Self> {
+        let mut project = Self::default();
+
+        if let Some(contents) = read_if_exists(&project_dir.join("pyproject.toml")).await? {
+            let pyproject: PyProject =
+                toml::from_str(&contents).wrap_err("Could not parse `pyproject.toml`")?;
+            project.add_pyproject(pyproject);
+        }
+        if let Some(contents) = read_if_exists(&project_dir.join("setup.py")).await? {
+            project
+                .dependency_names
+                .extend(setup_py_requirements(&contents));
+        }
+        if let Some(contents) = read_if_exists(&project_dir.join("requirements.txt")).await? {
+            project
+                .dependency_names
+                .extend(requirements_txt_names(&contents));
+        }
+        project.uses_poetry |= project_dir.join("poetry.lock").exists();
+
+        Ok(project)
+    }
+
+    fn add_pyproject(&mut self, pyproject: PyProject) {
+        if let Some(project) = pyproject.project {
+            let requirements = project
+                .dependencies
+                .iter()
+                .chain(project.optional_dependencies.values().flatten());
+            self.dependency_names
+                .extend(requirements.filter_map(|requirement| requirement_name(requirement)));
+        }
+        if let Some(poetry) = pyproject.tool.poetry {
+            self.uses_poetry = true;
+            let names = poetry
+                .dependencies
+                .keys()
+                .chain(poetry.dev_dependencies.keys())
+                .chain(
+                    poetry
+                        .group
+                        .values()
+                        .flat_map(|group| group.dependencies.keys()),
+                )
+                // Poetry lists the interpreter version alongside the dependencies
+                .filter(|name| name.as_str() != "python")
+                .map(|name| normalize_name(name));
+            self.dependency_names.extend(names);
+        }
+        self.riff = pyproject.tool.riff;
+    }
+}
+
+async fn read_if_exists(path: &Path) -> color_eyre::Result<Option<String>> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).wrap_err_with(|| format!("Could not read `{}`", path.display())),
+    }
+}
+
+/// Normalize a project name as PEP 503 does, so `Foo_Bar` and `foo-bar` match
+pub fn normalize_name(name: &str) -> String {
+    name.split(['-', '_', '.'])
+        .filter(|part| !part.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The normalized project name of a PEP 508 requirement such as `lxml[html]>=4.9; python_version > "3"`
+pub fn requirement_name(requirement: &str) -> Option<String> {
+    let requirement = requirement.trim();
+    let end = requirement
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        .unwrap_or(requirement.len());
+    match &requirement[..end] {
+        "" => None,
+        name => Some(normalize_name(name)),
+    }
+}
+
+/// The project names in a `requirements.txt`, skipping options such as `-r` and `-e`
+pub fn requirements_txt_names(contents: &str) -> BTreeSet<String> {
+    contents
+        .lines()
+        .map(|line| match line.find(" #") {
+            Some(comment) => &line[..comment],
+            None => line,
+        })
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#') && !line.starts_with('-'))
+        .filter_map(requirement_name)
+        .collect()
+}
+
+/// The project names in the `install_requires` list of a `setup.py`
+///
+/// We can't run `setup.py`, so this only understands a literal list of strings.
+pub fn setup_py_requirements(contents: &str) -> BTreeSet<String> {
+    let list = contents
+        .split_once("install_requires")
+        .and_then(|(_, rest)| rest.split_once('['))
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(list, _)| list)
+        .unwrap_or_default();
+
+    let mut names = BTreeSet::new();
+    let mut chars = list.chars();
+    while let Some(c) = chars.next() {
+        if c != '"' && c != '\'' {
+            continue;
+        }
+        let string: String = chars.by_ref().take_while(|&next| next != c).collect();
+        names.extend(requirement_name(&string));
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn pyproject_dependencies() -> eyre::Result<()> {
+        let pyproject: PyProject = toml::from_str(
+            r#"
+[project]
+name = "riff-test"
+dependencies = ["lxml[html]>=4.9", "Psycopg2 ; python_version > '3'"]
+
+[project.optional-dependencies]
+crypto = ["cryptography"]
+
+[tool.poetry.dependencies]
+python = "^3.10"
+Pillow = "^9"
+
+[tool.poetry.group.dev.dependencies]
+pytest = "*"
+
+[tool.riff]
+build-inputs = ["hello"]
+"#,
+        )?;
+        let mut project = PythonProject::default();
+        project.add_pyproject(pyproject);
+        assert_eq!(
+            project.dependency_names,
+            names(&["cryptography", "lxml", "pillow", "psycopg2", "pytest"])
+        );
+        assert!(project.uses_poetry);
+        assert!(project
+            .riff
+            .map(|riff| riff.build_inputs().contains("hello"))
+            .unwrap_or(false));
+        Ok(())
+    }
+
+    #[test]
+    fn requirements_and_setup_py() {
+        let requirements = "\
+# A comment
+-r base.txt
+-e .
+psycopg2==2.9.3  # pinned
+zope.interface
+";
+        assert_eq!(
+            requirements_txt_names(requirements),
+            names(&["psycopg2", "zope-interface"])
+        );
+
+        let setup_py = r#"
+setup(
+    name="riff-test",
+    install_requires=[
+        "lxml>=4",
+        'PyZMQ',
+    ],
+)
+"#;
+        assert_eq!(setup_py_requirements(setup_py), names(&["lxml", "pyzmq"]));
+    }
+}
@@ -0,0 +1,179 @@
+//! Regression tests for registry rules, run against fixture `cargo metadata` documents.
+//!
+//! A fixture pairs the output of `cargo metadata --format-version 1` with the settings the
+//! registry is expected to produce for it. Only the expectations which are present are checked:
+//!
+//! ```json
+//! {
+//!   "metadata": {"packages": [{"name": "libsqlite3-sys"}]},
+//!   "expect": {"build-inputs": ["cargo", "rustc", "rustfmt", "sqlite"]}
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+
+use crate::cargo_metadata::CargoMetadata;
+use crate::dependency_registry::{DependencyRegistry, DependencyRegistryData};
+use crate::dev_env::DevEnvironment;
+
+#[derive(Deserialize, Serialize)]
+pub struct Fixture {
+    pub metadata: serde_json::Value,
+    pub expect: Expectation,
+}
+
+/// The settings a fixture should produce
+#[derive(Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Expectation {
+    #[serde(rename = "build-inputs", skip_serializing_if = "Option::is_none")]
+    pub build_inputs: Option<BTreeSet<String>>,
+    #[serde(
+        rename = "environment-variables",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub environment_variables: Option<BTreeMap<String, String>>,
+    #[serde(rename = "runtime-inputs", skip_serializing_if = "Option::is_none")]
+    pub runtime_inputs: Option<BTreeSet<String>>,
+}
+
+/// Read a registry document, such as `registry/registry.json`
+pub async fn read_registry(path: &Path) -> color_eyre::Result<DependencyRegistry> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+    let data: DependencyRegistryData = serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("Could not parse `{}`", path.display()))?;
+    Ok(DependencyRegistry::from_data(data)?)
+}
+
+pub async fn read_fixture(path: &Path) -> color_eyre::Result<Fixture> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+    serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("Could not parse `{}`", path.display()))
+}
+
+/// The settings `registry` produces for the packages in `metadata`
+pub async fn resolve(registry: &DependencyRegistry, metadata: CargoMetadata) -> Expectation {
+    let mut dev_env = DevEnvironment::new(registry);
+    dev_env.add_deps_from_cargo_metadata(metadata, None).await;
+    Expectation {
+        build_inputs: Some(dev_env.build_inputs.into_iter().collect()),
+        environment_variables: Some(dev_env.environment_variables.into_iter().collect()),
+        runtime_inputs: Some(dev_env.runtime_inputs.into_iter().collect()),
+    }
+}
+
+/// Apply `registry` to the fixture's metadata, describing each way the result differs from the
+/// expectation
+pub async fn check_fixture(
+    registry: &DependencyRegistry,
+    fixture: Fixture,
+) -> color_eyre::Result<Vec<String>> {
+    let metadata = serde_json::from_value(fixture.metadata)
+        .wrap_err("The fixture's `metadata` is not the output of `cargo metadata`")?;
+    let actual = resolve(registry, metadata).await;
+    let expected = fixture.expect;
+
+    let mut mismatches = Vec::new();
+    if let (Some(expected), Some(actual)) = (&expected.build_inputs, &actual.build_inputs) {
+        mismatches.extend(set_mismatch("build-inputs", expected, actual));
+    }
+    if let (Some(expected), Some(actual)) = (&expected.runtime_inputs, &actual.runtime_inputs) {
+        mismatches.extend(set_mismatch("runtime-inputs", expected, actual));
+    }
+    if let (Some(expected), Some(actual)) = (
+        &expected.environment_variables,
+        &actual.environment_variables,
+    ) {
+        if expected != actual {
+            mismatches.push(format!(
+                "environment-variables: expected {expected:?}, got {actual:?}"
+            ));
+        }
+    }
+    Ok(mismatches)
+}
+
+fn set_mismatch(
+    key: &str,
+    expected: &BTreeSet<String>,
+    actual: &BTreeSet<String>,
+) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+    let mut mismatch = format!("{key}:");
+    let missing = expected.difference(actual).collect::<Vec<_>>();
+    if !missing.is_empty() {
+        write!(mismatch, " missing {missing:?}").ok();
+    }
+    let unexpected = actual.difference(expected).collect::<Vec<_>>();
+    if !unexpected.is_empty() {
+        write!(mismatch, " unexpected {unexpected:?}").ok();
+    }
+    Some(mismatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every fixture in `registry/tests` passes against the bundled registry
+    #[tokio::test]
+    async fn bundled_registry_fixtures() -> eyre::Result<()> {
+        let registry_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("registry");
+        let registry = read_registry(&registry_dir.join("registry.json")).await?;
+
+        let mut fixtures = 0;
+        let mut entries = tokio::fs::read_dir(registry_dir.join("tests")).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let fixture = read_fixture(&entry.path()).await?;
+            let mismatches = check_fixture(&registry, fixture).await?;
+            assert!(
+                mismatches.is_empty(),
+                "{}: {mismatches:?}",
+                entry.path().display()
+            );
+            fixtures += 1;
+        }
+        assert!(fixtures > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mismatches_are_described() -> eyre::Result<()> {
+        let registry = DependencyRegistry::from_data(serde_json::from_str(
+            r#"{
+                "version": 1,
+                "language": {"rust": {
+                    "default": {"build-inputs": ["cargo"]},
+                    "dependencies": {"openssl-sys": {
+                        "build-inputs": ["openssl"],
+                        "environment-variables": {"OPENSSL_NO_VENDOR": "1"}
+                    }}
+                }}
+            }"#,
+        )?)?;
+        let fixture: Fixture = serde_json::from_str(
+            r#"{
+                "metadata": {"packages": [{"name": "openssl-sys"}]},
+                "expect": {
+                    "build-inputs": ["cargo", "pkg-config"],
+                    "environment-variables": {"OPENSSL_NO_VENDOR": "1"}
+                }
+            }"#,
+        )?;
+        assert_eq!(
+            check_fixture(&registry, fixture).await?,
+            vec![r#"build-inputs: missing ["pkg-config"] unexpected ["openssl"]"#.to_string()]
+        );
+        Ok(())
+    }
+}
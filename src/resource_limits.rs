@@ -0,0 +1,228 @@
+//! Limits on the memory, CPU and processes of the commands `riff run` starts.
+//!
+//! A project sets them in the `resource-limits` table of its `riff.toml`, and `riff run` then
+//! starts its command in a transient systemd scope with `systemd-run --scope`, whose cgroup the
+//! kernel holds the command and everything it starts to. The scope runs the command directly, so
+//! it keeps the terminal and the environment riff gives it.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Deserializer};
+use tokio::process::Command;
+
+/// The variables `systemd-run --user` finds the user's manager with, which are kept for it even
+/// when the command's environment is cleared
+pub const MANAGER_VARIABLES: [&str; 2] = ["DBUS_SESSION_BUS_ADDRESS", "XDG_RUNTIME_DIR"];
+
+/// The `resource-limits` table of `riff.toml`
+///
+/// ```toml
+/// [resource-limits]
+/// memory = "4G"
+/// cpu = "200%"
+/// tasks = 512
+/// ```
+#[derive(Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// The most memory the command may use, like `512M` or `4G`, as systemd's `MemoryMax`
+    #[serde(default, deserialize_with = "deserialize_memory")]
+    pub(crate) memory: Option<String>,
+    /// The CPU time the command may use, like `200%` for two cores, as systemd's `CPUQuota`
+    #[serde(default, deserialize_with = "deserialize_cpu")]
+    pub(crate) cpu: Option<String>,
+    /// How many processes and threads the command may have at once, as systemd's `TasksMax`
+    pub(crate) tasks: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Whether no limit is set
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu.is_none() && self.tasks.is_none()
+    }
+
+    /// The systemd properties which set the limits, like `MemoryMax=4G`
+    fn properties(&self) -> Vec<String> {
+        let memory = self
+            .memory
+            .iter()
+            .map(|memory| format!("MemoryMax={memory}"));
+        let cpu = self.cpu.iter().map(|cpu| format!("CPUQuota={cpu}"));
+        let tasks = self.tasks.iter().map(|tasks| format!("TasksMax={tasks}"));
+        memory.chain(cpu).chain(tasks).collect()
+    }
+
+    /// `command`, run by `systemd-run` in a scope with the limits
+    ///
+    /// This fails if there's no systemd manager `systemd-run` can reach to start the scope, like
+    /// in a session started with `su`, rather than leaving `systemd-run` to fail as the command.
+    pub async fn wrap(&self, command: &[String]) -> color_eyre::Result<Vec<String>> {
+        if !cfg!(target_os = "linux") {
+            return Err(eyre!(
+                "The project's `{resource_limits}` need systemd, which only Linux has",
+                resource_limits = "resource-limits".cyan(),
+            ));
+        }
+        let systemd_run =
+            find_on_path(std::env::var_os("PATH"), "systemd-run").ok_or_else(|| {
+                eyre!(
+                "The project's `{resource_limits}` need `{systemd_run}`, which isn't on the `PATH`",
+                resource_limits = "resource-limits".cyan(),
+                systemd_run = "systemd-run".cyan(),
+            )
+            })?;
+        // Only root can start scopes in the system's manager
+        let user = unsafe { libc::getuid() } != 0;
+        check_manager(user).await?;
+        Ok(self.command_line(&systemd_run, user, command))
+    }
+
+    fn command_line(&self, systemd_run: &Path, user: bool, command: &[String]) -> Vec<String> {
+        let mut args = vec![systemd_run.display().to_string()];
+        if user {
+            args.push("--user".to_string());
+        }
+        args.extend(["--scope", "--quiet", "--collect"].map(String::from));
+        for property in self.properties() {
+            args.push("--property".to_string());
+            args.push(property);
+        }
+        args.push("--".to_string());
+        args.extend(command.iter().cloned());
+        args
+    }
+}
+
+/// Check that `systemd-run` can reach the manager it starts scopes with, the user's if `user`
+async fn check_manager(user: bool) -> color_eyre::Result<()> {
+    let mut systemctl = Command::new("systemctl");
+    if user {
+        systemctl.arg("--user");
+    }
+    systemctl.args(["show", "--property=Version"]);
+    tracing::trace!(command = ?systemctl.as_std(), "Running");
+    let output = systemctl.output().await.wrap_err_with(|| {
+        format!(
+            "The project's `{resource_limits}` need `{systemctl}`, which couldn't be run",
+            resource_limits = "resource-limits".cyan(),
+            systemctl = "systemctl".cyan(),
+        )
+    })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(eyre!(
+        "The project's `{resource_limits}` need {manager}, which `{systemctl}` couldn't reach:\n{}\
+         A session started with `su` or in a container may have none; log in with `{machinectl}` instead.",
+        String::from_utf8_lossy(&output.stderr).trim(),
+        resource_limits = "resource-limits".cyan(),
+        manager = if user { "your systemd user manager" } else { "systemd" },
+        systemctl = if user { "systemctl --user" } else { "systemctl" }.cyan(),
+        machinectl = "machinectl shell $USER@".cyan(),
+    ))
+}
+
+/// The file `name` in one of the directories on `path`, a `PATH`
+pub fn find_on_path(path: Option<impl AsRef<OsStr>>, name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&path?)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// A size like `4G`, or a percentage of the machine's memory like `50%`
+fn check_memory(memory: &str) -> Result<(), String> {
+    let digits = memory.trim_end_matches(['K', 'M', 'G', 'T', '%']);
+    let suffix = &memory[digits.len()..];
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) || suffix.len() > 1 {
+        return Err(format!(
+            "`{memory}` is not an amount of memory like `512M`, `4G` or `50%`"
+        ));
+    }
+    Ok(())
+}
+
+/// A percentage of one core like `200%`
+fn check_cpu(cpu: &str) -> Result<(), String> {
+    match cpu.strip_suffix('%') {
+        Some(digits) if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) => Ok(()),
+        _ => Err(format!(
+            "`{cpu}` is not a share of CPU time like `200%` for two cores"
+        )),
+    }
+}
+
+fn deserialize_memory<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let memory = Option::<String>::deserialize(deserializer)?;
+    if let Some(memory) = &memory {
+        check_memory(memory).map_err(serde::de::Error::custom)?;
+    }
+    Ok(memory)
+}
+
+fn deserialize_cpu<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let cpu = Option::<String>::deserialize(deserializer)?;
+    if let Some(cpu) = &cpu {
+        check_cpu(cpu).map_err(serde::de::Error::custom)?;
+    }
+    Ok(cpu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() -> eyre::Result<()> {
+        let limits: ResourceLimits =
+            toml::from_str("memory = \"4G\"\ncpu = \"200%\"\ntasks = 512\n")?;
+        assert_eq!(
+            limits.command_line(
+                Path::new("/usr/bin/systemd-run"),
+                true,
+                &["cargo".to_string(), "build".to_string()]
+            ),
+            [
+                "/usr/bin/systemd-run",
+                "--user",
+                "--scope",
+                "--quiet",
+                "--collect",
+                "--property",
+                "MemoryMax=4G",
+                "--property",
+                "CPUQuota=200%",
+                "--property",
+                "TasksMax=512",
+                "--",
+                "cargo",
+                "build",
+            ]
+        );
+        assert!(ResourceLimits::default().is_empty());
+
+        assert!(check_memory("512M").is_ok());
+        assert!(check_memory("1073741824").is_ok());
+        assert!(check_memory("50%").is_ok());
+        assert!(check_memory("4GB").is_err());
+        assert!(check_memory("G").is_err());
+        assert!(check_cpu("150%").is_ok());
+        assert!(check_cpu("2").is_err());
+        assert!(toml::from_str::<ResourceLimits>("memory = \"lots\"").is_err());
+
+        let bin = tempfile::TempDir::new()?;
+        std::fs::write(bin.path().join("cargo"), "")?;
+        let path = std::env::join_paths(["/does/not/exist", bin.path().to_str().unwrap()])?;
+        assert_eq!(
+            find_on_path(Some(&path), "cargo"),
+            Some(bin.path().join("cargo"))
+        );
+        assert_eq!(find_on_path(Some(&path), "rustc"), None);
+        assert_eq!(find_on_path(None::<&OsStr>, "cargo"), None);
+        Ok(())
+    }
+}
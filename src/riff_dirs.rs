@@ -0,0 +1,123 @@
+//! The directories riff keeps its caches and state in.
+//!
+//! These are `$XDG_CACHE_HOME/riff` and `$XDG_STATE_HOME/riff`. Where they can't be written, like
+//! in a container with a read-only home, riff uses `riff-<uid>` in the temporary directory
+//! instead, and warns once that what it keeps there won't last.
+
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+
+use owo_colors::OwoColorize;
+use xdg::BaseDirectories;
+
+use crate::RIFF_XDG_PREFIX;
+
+/// The directory `name` in riff's cache directory, created if needed
+pub fn cache_dir(name: &str) -> io::Result<PathBuf> {
+    let xdg_dir = BaseDirectories::with_prefix(RIFF_XDG_PREFIX)
+        .map(|dirs| dirs.get_cache_home().join(name))
+        .map_err(|err| err.to_string());
+    writable_or_fallback(xdg_dir, || Ok(fallback_home()?.join("cache").join(name)))
+}
+
+/// The directory `name` in riff's state directory, created if needed
+pub fn state_dir(name: &str) -> io::Result<PathBuf> {
+    let xdg_dir = BaseDirectories::with_prefix(RIFF_XDG_PREFIX)
+        .map(|dirs| dirs.get_state_home().join(name))
+        .map_err(|err| err.to_string());
+    writable_or_fallback(xdg_dir, || Ok(fallback_home()?.join("state").join(name)))
+}
+
+/// `dir`, created if needed, or else `fallback` if `dir` can't be written
+fn writable_or_fallback(
+    dir: Result<PathBuf, String>,
+    fallback: impl FnOnce() -> io::Result<PathBuf>,
+) -> io::Result<PathBuf> {
+    let err = match dir {
+        Ok(dir) => match create_writable(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(err) => format!("`{}`: {err}", dir.display()),
+        },
+        Err(err) => err,
+    };
+    let fallback = fallback()?;
+    create_writable(&fallback)?;
+    warn_fallback(&err);
+    Ok(fallback)
+}
+
+/// Create `dir` if needed, and check a file can be created in it, as a read-only mount lets the
+/// directory exist
+fn create_writable(dir: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    tempfile::tempfile_in(dir).map(drop)
+}
+
+/// The directory in the temporary directory for this user's caches and state
+///
+/// It's only readable by the user, and isn't used if someone else made it first.
+fn fallback_home() -> io::Result<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("riff-{uid}"));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(err) => return Err(err),
+    }
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("`{}` belongs to another user", dir.display()),
+        ));
+    }
+    Ok(dir)
+}
+
+fn warn_fallback(err: &str) {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| {
+        eprintln!(
+            "{warning} riff can't write to its directories ({err}), so it keeps its caches in `{dir}`, \
+             which may not last across restarts. Point `{cache_home}` and `{state_home}` at writable \
+             directories to keep them.",
+            warning = "⚠️".yellow(),
+            dir = std::env::temp_dir().display(),
+            cache_home = "XDG_CACHE_HOME".cyan(),
+            state_home = "XDG_STATE_HOME".cyan(),
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn fallback() -> eyre::Result<()> {
+        let dir = TempDir::new()?;
+        let writable = dir.path().join("cache");
+        let fallback = dir.path().join("fallback");
+
+        let used = writable_or_fallback(Ok(writable.clone()), || Ok(fallback.clone()))?;
+        assert_eq!(used, writable);
+        assert!(writable.is_dir());
+        assert!(!fallback.exists());
+
+        // Unlike a read-only directory, a file stops even root from creating the directory
+        let file = dir.path().join("file");
+        std::fs::write(&file, "")?;
+        let used = writable_or_fallback(Ok(file.join("cache")), || Ok(fallback.clone()))?;
+        assert_eq!(used, fallback);
+        assert!(fallback.is_dir());
+
+        let used =
+            writable_or_fallback(Err("$HOME is not set".to_string()), || Ok(fallback.clone()))?;
+        assert_eq!(used, fallback);
+        Ok(())
+    }
+}
@@ -0,0 +1,638 @@
+//! `riff.lock`, which records what a project's environment was resolved from, so every developer
+//! on the same commit gets the same environment.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, WrapErr};
+use itertools::Itertools;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::dev_env::{nixpkgs_flake_ref, DevEnvironment, DEFAULT_NIXPKGS_URL};
+use crate::flake_generator::GeneratedFlake;
+use crate::nix_expr;
+
+/// Where the lock is written, relative to the project directory
+pub const LOCK_FILE: &str = "riff.lock";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RiffLock {
+    /// The SHA-256 of the registry the environment was detected with
+    pub registry: String,
+    pub nixpkgs: LockedNixpkgs,
+    pub build_inputs: BTreeSet<String>,
+    pub runtime_inputs: BTreeSet<String>,
+    /// The version each input resolved to, or its store path if it has no version
+    #[serde(default)]
+    pub versions: BTreeMap<String, String>,
+    /// The registry rules the environment was detected with, by name, with the hash of each
+    ///
+    /// Locks written before riff recorded them have none, rather than no rules.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<BTreeMap<String, String>>,
+    /// The store paths `riff pin` pinned tools to, by tool name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tools: BTreeMap<String, PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockedNixpkgs {
+    /// The flake reference the project asked for, like `github:NixOS/nixpkgs/nixos-unstable`
+    pub url: String,
+    /// The flake reference of the exact revision it resolved to
+    pub locked: String,
+}
+
+impl RiffLock {
+    /// Record `dev_env`, whose nixpkgs resolved to the one `flake` is locked to
+    pub async fn new(
+        dev_env: &DevEnvironment<'_>,
+        flake: &GeneratedFlake,
+    ) -> color_eyre::Result<Self> {
+        let flake_lock_path = flake.path().join("flake.lock");
+        let flake_lock: serde_json::Value = serde_json::from_str(
+            &tokio::fs::read_to_string(&flake_lock_path)
+                .await
+                .wrap_err("Could not read the generated `flake.lock`")?,
+        )
+        .wrap_err("The generated `flake.lock` was not valid JSON")?;
+        let locked = flake_lock
+            .pointer("/nodes/nixpkgs/locked")
+            .ok_or_else(|| eyre!("The generated `flake.lock` has no `nixpkgs` input"))?;
+
+        let inputs = dev_env
+            .build_inputs
+            .iter()
+            .chain(&dev_env.runtime_inputs)
+            .cloned()
+            .collect();
+        Ok(Self {
+            registry: dev_env.registry.hash().await,
+            nixpkgs: LockedNixpkgs {
+                url: dev_env
+                    .nixpkgs_url
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_NIXPKGS_URL.to_string()),
+                locked: locked_flake_ref(locked)?,
+            },
+            build_inputs: dev_env.build_inputs.iter().cloned().collect(),
+            runtime_inputs: dev_env.runtime_inputs.iter().cloned().collect(),
+            versions: resolve_versions(flake, &inputs).await?,
+            rules: Some(dev_env.registry_rules.clone()),
+            tools: Default::default(),
+        })
+    }
+
+    /// Read the lock of the project in `project_dir`, if it has one
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Option<Self>> {
+        let path = project_dir.join(LOCK_FILE);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .wrap_err_with(|| format!("Could not parse `{}`", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).wrap_err_with(|| format!("Could not read `{}`", path.display())),
+        }
+    }
+
+    pub async fn write(&self, project_dir: &Path) -> color_eyre::Result<()> {
+        let path = project_dir.join(LOCK_FILE);
+        tokio::fs::write(&path, serde_json::to_string_pretty(self)? + "\n")
+            .await
+            .wrap_err_with(|| format!("Could not write `{}`", path.display()))
+    }
+
+    /// Check that `dev_env`, detected with the registry whose hash is `registry`, is the locked
+    /// environment, and pin it to the locked nixpkgs
+    pub fn enforce(&self, registry: &str, dev_env: &mut DevEnvironment) -> color_eyre::Result<()> {
+        let mut changes = Vec::new();
+        if registry != self.registry {
+            changes.push("the registry changed".to_string());
+        }
+        let requested_nixpkgs = dev_env
+            .nixpkgs_url
+            .as_deref()
+            .unwrap_or(DEFAULT_NIXPKGS_URL);
+        if requested_nixpkgs != self.nixpkgs.url {
+            changes.push(format!(
+                "nixpkgs is now `{requested_nixpkgs}`, not `{}`",
+                self.nixpkgs.url
+            ));
+        }
+        for (kind, locked, current) in [
+            ("build", &self.build_inputs, &dev_env.build_inputs),
+            ("runtime", &self.runtime_inputs, &dev_env.runtime_inputs),
+        ] {
+            let current = current.iter().cloned().collect::<BTreeSet<_>>();
+            let added = current.difference(locked).join(", ");
+            if !added.is_empty() {
+                changes.push(format!("{kind} inputs {added} were added"));
+            }
+            let removed = locked.difference(&current).join(", ");
+            if !removed.is_empty() {
+                changes.push(format!("{kind} inputs {removed} were removed"));
+            }
+        }
+        if !changes.is_empty() {
+            return Err(eyre!(
+                "The environment no longer matches `{lock_file}`: {changes}.\n\
+                 Run `{riff_lock}` to update the lock if this is expected.",
+                lock_file = LOCK_FILE,
+                changes = changes.join("; "),
+                riff_lock = "riff lock --update".cyan(),
+            ));
+        }
+
+        dev_env.nixpkgs_url = Some(self.nixpkgs.locked.clone());
+        Ok(())
+    }
+
+    /// Pin `tool` to `store_path`, refusing to silently change an existing pin
+    pub fn pin_tool(
+        &mut self,
+        tool: &str,
+        store_path: PathBuf,
+        update: bool,
+    ) -> color_eyre::Result<()> {
+        match self.tools.get(tool) {
+            Some(previous) if previous != &store_path && !update => Err(eyre!(
+                "`{tool}` is pinned to `{previous}` but the environment now provides `{current}`.\n\
+                 Rerun with `{update_flag}` if this change is expected.",
+                previous = previous.display(),
+                current = store_path.display(),
+                update_flag = "--update".cyan(),
+            )),
+            _ => {
+                self.tools.insert(tool.to_string(), store_path);
+                Ok(())
+            }
+        }
+    }
+
+    /// What changes when `self` is replaced by `updated`
+    pub fn diff(&self, updated: &Self) -> Vec<LockChange> {
+        let mut changes = Vec::new();
+        if self.registry != updated.registry {
+            changes.push(LockChange::Registry {
+                from: self.registry.clone(),
+                to: updated.registry.clone(),
+            });
+        }
+        if self.nixpkgs.locked != updated.nixpkgs.locked {
+            changes.push(LockChange::Nixpkgs {
+                from: self.nixpkgs.locked.clone(),
+                to: updated.nixpkgs.locked.clone(),
+            });
+        }
+        for (kind, old, new) in [
+            ("build", &self.build_inputs, &updated.build_inputs),
+            ("runtime", &self.runtime_inputs, &updated.runtime_inputs),
+        ] {
+            changes.extend(old.difference(new).map(|input| LockChange::Removed {
+                kind,
+                input: input.clone(),
+                version: self.versions.get(input).cloned(),
+            }));
+            changes.extend(new.difference(old).map(|input| LockChange::Added {
+                kind,
+                input: input.clone(),
+                version: updated.versions.get(input).cloned(),
+            }));
+            for input in old.intersection(new) {
+                if let (Some(from), Some(to)) =
+                    (self.versions.get(input), updated.versions.get(input))
+                {
+                    if from != to {
+                        changes.push(LockChange::Version {
+                            input: input.clone(),
+                            from: from.clone(),
+                            to: to.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        if let (Some(old), Some(new)) = (&self.rules, &updated.rules) {
+            for rule in old.keys().chain(new.keys()).sorted().dedup() {
+                let change = match (old.get(rule), new.get(rule)) {
+                    (Some(_), None) => RuleChange::NoLongerApplies,
+                    (None, Some(_)) => RuleChange::Applies,
+                    (Some(from), Some(to)) if from != to => RuleChange::Changed,
+                    _ => continue,
+                };
+                changes.push(LockChange::Rule {
+                    rule: rule.clone(),
+                    change,
+                });
+            }
+        }
+        changes
+    }
+}
+
+/// One difference between a lock and its update, as shown by `riff lock --update`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockChange {
+    Registry {
+        from: String,
+        to: String,
+    },
+    Nixpkgs {
+        from: String,
+        to: String,
+    },
+    Added {
+        kind: &'static str,
+        input: String,
+        version: Option<String>,
+    },
+    Removed {
+        kind: &'static str,
+        input: String,
+        version: Option<String>,
+    },
+    /// An input resolved to another version, or store path
+    Version {
+        input: String,
+        from: String,
+        to: String,
+    },
+    Rule {
+        rule: String,
+        change: RuleChange,
+    },
+}
+
+/// How a registry rule changed between a lock and its update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleChange {
+    /// The rule now applies to the project, such as after a dependency was added
+    Applies,
+    /// The rule no longer applies to the project
+    NoLongerApplies,
+    /// The registry changed what the rule adds
+    Changed,
+}
+
+impl std::fmt::Display for LockChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockChange::Registry { from, to } => write!(
+                f,
+                "  registry  {} → {}",
+                short_hash(from).red(),
+                short_hash(to).green()
+            ),
+            LockChange::Nixpkgs { from, to } => {
+                write!(f, "  nixpkgs   {} → {}", from.red(), to.green())
+            }
+            LockChange::Added {
+                kind,
+                input,
+                version,
+            } => write!(
+                f,
+                "{} {input}{} ({kind} input)",
+                "+".green(),
+                version_suffix(version)
+            ),
+            LockChange::Removed {
+                kind,
+                input,
+                version,
+            } => write!(
+                f,
+                "{} {input}{} ({kind} input)",
+                "-".red(),
+                version_suffix(version)
+            ),
+            LockChange::Version { input, from, to } => {
+                write!(
+                    f,
+                    "{} {input} {} → {}",
+                    "~".yellow(),
+                    from.red(),
+                    to.green()
+                )
+            }
+            LockChange::Rule { rule, change } => {
+                let change = match change {
+                    RuleChange::Applies => "now applies",
+                    RuleChange::NoLongerApplies => "no longer applies",
+                    RuleChange::Changed => "changed",
+                };
+                write!(f, "{} registry rule {} {change}", "~".yellow(), rule.cyan())
+            }
+        }
+    }
+}
+
+fn version_suffix(version: &Option<String>) -> String {
+    match version {
+        Some(version) => format!(" {version}"),
+        None => String::new(),
+    }
+}
+
+/// The version each of `inputs` resolved to in the package set of `flake`, with its overlays, or
+/// the store path of those without a version
+async fn resolve_versions(
+    flake: &GeneratedFlake,
+    inputs: &BTreeSet<String>,
+) -> color_eyre::Result<BTreeMap<String, String>> {
+    // `nix-shell` has no flake to evaluate the package set of
+    if flake.legacy() || inputs.is_empty() {
+        return Ok(Default::default());
+    }
+    let bindings = inputs
+        .iter()
+        .map(|input| {
+            format!(
+                "{name} = let package = pkgs.{path} or null; in \
+                 if package == null then null else package.version or package.outPath;",
+                name = nix_expr::string(input),
+                path = nix_expr::attr_path(input),
+            )
+        })
+        .join(" ");
+    let mut command = Command::new("nix");
+    command
+        .arg("eval")
+        .arg("--json")
+        .args(["--extra-experimental-features", "flakes nix-command"])
+        .args(flake.nix_args())
+        .arg(format!(
+            "path://{}#legacyPackages.{}",
+            flake.path().display(),
+            current_system().await?
+        ))
+        .arg("--apply")
+        .arg(format!("pkgs: {{ {bindings} }}"))
+        .kill_on_drop(true);
+    tracing::trace!(command = ?command.as_std(), "Running");
+    let output = command
+        .output()
+        .await
+        .wrap_err("Could not execute `nix eval` to resolve the versions of the inputs")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Could not resolve the versions of the inputs:\n{}",
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    let versions: BTreeMap<String, Option<String>> = serde_json::from_slice(&output.stdout)
+        .wrap_err("`nix eval` printed the versions of the inputs as invalid JSON")?;
+    Ok(versions
+        .into_iter()
+        .filter_map(|(input, version)| Some((input, version?)))
+        .collect())
+}
+
+/// The Nix system riff runs on, like `x86_64-linux`
+async fn current_system() -> color_eyre::Result<String> {
+    let output = Command::new("nix")
+        .args([
+            "eval",
+            "--impure",
+            "--raw",
+            "--expr",
+            "builtins.currentSystem",
+        ])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .wrap_err("Could not execute `nix eval` to find the system")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Could not find the system:\n{}",
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+fn short_hash(hash: &str) -> &str {
+    hash.get(..12).unwrap_or(hash)
+}
+
+/// A flake reference to exactly the source described by a `locked` node of a `flake.lock`
+fn locked_flake_ref(locked: &serde_json::Value) -> color_eyre::Result<String> {
+    let field = |name: &str| {
+        locked
+            .get(name)
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| eyre!("The locked nixpkgs has no `{name}`"))
+    };
+    let flake_ref = match field("type")? {
+        "github" => format!(
+            "github:{}/{}/{}",
+            field("owner")?,
+            field("repo")?,
+            field("rev")?
+        ),
+        "gitlab" => format!(
+            "gitlab:{}/{}/{}",
+            field("owner")?,
+            field("repo")?,
+            field("rev")?
+        ),
+        "git" => format!("git+{}?rev={}", field("url")?, field("rev")?),
+        "tarball" => format!("{}?narHash={}", field("url")?, field("narHash")?),
+        "path" => format!("path:{}?narHash={}", field("path")?, field("narHash")?),
+        other => return Err(eyre!("nixpkgs from a `{other}` input can't be locked")),
+    };
+    nixpkgs_flake_ref(&flake_ref).map_err(|e| eyre!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependency_registry::DependencyRegistry;
+    use tempfile::TempDir;
+
+    #[test]
+    fn lock_nixpkgs() -> eyre::Result<()> {
+        let locked = serde_json::json!({
+            "lastModified": 1665732960,
+            "narHash": "sha256-WBZ+uSHKFyjvd0w4inbm0cNExYTn8lpYFcHEes8tmec=",
+            "owner": "NixOS",
+            "repo": "nixpkgs",
+            "rev": "4428e23312933a196724da2df7ab78eb5e67a88e",
+            "type": "github"
+        });
+        assert_eq!(
+            locked_flake_ref(&locked)?,
+            "github:NixOS/nixpkgs/4428e23312933a196724da2df7ab78eb5e67a88e"
+        );
+        assert!(locked_flake_ref(&serde_json::json!({"type": "indirect"})).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn enforce_lock() -> eyre::Result<()> {
+        let cache_dir = TempDir::new()?;
+        std::env::set_var("XDG_CACHE_HOME", cache_dir.path());
+        let registry = DependencyRegistry::new(true, None).await?;
+        let mut dev_env = DevEnvironment::new(&registry);
+        dev_env.build_inputs.insert("openssl".to_string());
+
+        let lock = RiffLock {
+            registry: registry.hash().await,
+            nixpkgs: LockedNixpkgs {
+                url: DEFAULT_NIXPKGS_URL.to_string(),
+                locked: "github:NixOS/nixpkgs/4428e23312933a196724da2df7ab78eb5e67a88e".to_string(),
+            },
+            build_inputs: ["openssl".to_string()].into_iter().collect(),
+            runtime_inputs: Default::default(),
+            versions: Default::default(),
+            rules: None,
+            tools: Default::default(),
+        };
+        let project_dir = TempDir::new()?;
+        lock.write(project_dir.path()).await?;
+        assert_eq!(
+            RiffLock::read(project_dir.path()).await?,
+            Some(lock.clone())
+        );
+
+        let mut changed = dev_env.clone();
+        changed.build_inputs.insert("sqlite".to_string());
+        let err = lock
+            .enforce(&registry.hash().await, &mut changed)
+            .unwrap_err();
+        assert!(err.to_string().contains("build inputs sqlite were added"));
+        assert!(lock.enforce("0000", &mut dev_env.clone()).is_err());
+
+        lock.enforce(&registry.hash().await, &mut dev_env)?;
+        assert!(dev_env
+            .to_flake()
+            .contains("github:NixOS/nixpkgs/4428e23312933a196724da2df7ab78eb5e67a88e"));
+        Ok(())
+    }
+
+    #[test]
+    fn changed_pin_requires_update() -> eyre::Result<()> {
+        let mut lock = RiffLock {
+            registry: "aaaa".to_string(),
+            nixpkgs: LockedNixpkgs {
+                url: DEFAULT_NIXPKGS_URL.to_string(),
+                locked: "github:NixOS/nixpkgs/4428e23".to_string(),
+            },
+            build_inputs: Default::default(),
+            runtime_inputs: Default::default(),
+            versions: Default::default(),
+            rules: None,
+            tools: Default::default(),
+        };
+        assert!(!serde_json::to_string(&lock)?.contains("tools"));
+        lock.pin_tool("cargo", "/nix/store/aaa-cargo/bin/cargo".into(), false)?;
+        lock.pin_tool("cargo", "/nix/store/aaa-cargo/bin/cargo".into(), false)?;
+        assert!(lock
+            .pin_tool("cargo", "/nix/store/bbb-cargo/bin/cargo".into(), false)
+            .is_err());
+        lock.pin_tool("cargo", "/nix/store/bbb-cargo/bin/cargo".into(), true)?;
+        assert_eq!(
+            lock.tools.get("cargo"),
+            Some(&PathBuf::from("/nix/store/bbb-cargo/bin/cargo"))
+        );
+        let read: RiffLock = serde_json::from_str(&serde_json::to_string(&lock)?)?;
+        assert_eq!(read, lock);
+        Ok(())
+    }
+
+    #[test]
+    fn diff_locks() {
+        let lock = RiffLock {
+            registry: "aaaa".to_string(),
+            nixpkgs: LockedNixpkgs {
+                url: DEFAULT_NIXPKGS_URL.to_string(),
+                locked: "github:NixOS/nixpkgs/4428e23".to_string(),
+            },
+            build_inputs: ["openssl".to_string(), "sqlite".to_string()]
+                .into_iter()
+                .collect(),
+            runtime_inputs: Default::default(),
+            versions: [("openssl", "1.1.1t"), ("sqlite", "3.40.1")]
+                .into_iter()
+                .map(|(input, version)| (input.to_string(), version.to_string()))
+                .collect(),
+            rules: Some(
+                [
+                    ("rust/openssl-sys", "1111"),
+                    ("rust/libsqlite3-sys", "2222"),
+                ]
+                .into_iter()
+                .map(|(rule, hash)| (rule.to_string(), hash.to_string()))
+                .collect(),
+            ),
+            tools: Default::default(),
+        };
+        assert!(lock.diff(&lock).is_empty());
+
+        let mut updated = lock.clone();
+        updated.nixpkgs.locked = "github:NixOS/nixpkgs/97b8d9c".to_string();
+        updated.build_inputs = ["openssl_3".to_string(), "sqlite".to_string()]
+            .into_iter()
+            .collect();
+        updated.runtime_inputs = ["libGL".to_string()].into_iter().collect();
+        updated.versions = [("openssl_3", "3.0.8"), ("sqlite", "3.41.0")]
+            .into_iter()
+            .map(|(input, version)| (input.to_string(), version.to_string()))
+            .collect();
+        updated.rules = Some(
+            [("rust/openssl-sys", "3333"), ("rust/wayland-sys", "4444")]
+                .into_iter()
+                .map(|(rule, hash)| (rule.to_string(), hash.to_string()))
+                .collect(),
+        );
+        assert_eq!(
+            lock.diff(&updated),
+            vec![
+                LockChange::Nixpkgs {
+                    from: "github:NixOS/nixpkgs/4428e23".to_string(),
+                    to: "github:NixOS/nixpkgs/97b8d9c".to_string(),
+                },
+                LockChange::Removed {
+                    kind: "build",
+                    input: "openssl".to_string(),
+                    version: Some("1.1.1t".to_string()),
+                },
+                LockChange::Added {
+                    kind: "build",
+                    input: "openssl_3".to_string(),
+                    version: Some("3.0.8".to_string()),
+                },
+                LockChange::Version {
+                    input: "sqlite".to_string(),
+                    from: "3.40.1".to_string(),
+                    to: "3.41.0".to_string(),
+                },
+                LockChange::Added {
+                    kind: "runtime",
+                    input: "libGL".to_string(),
+                    version: None,
+                },
+                LockChange::Rule {
+                    rule: "rust/libsqlite3-sys".to_string(),
+                    change: RuleChange::NoLongerApplies,
+                },
+                LockChange::Rule {
+                    rule: "rust/openssl-sys".to_string(),
+                    change: RuleChange::Changed,
+                },
+                LockChange::Rule {
+                    rule: "rust/wayland-sys".to_string(),
+                    change: RuleChange::Applies,
+                },
+            ]
+        );
+
+        // Locks from before rules were recorded don't list every rule as new
+        let mut old = lock.clone();
+        old.rules = None;
+        assert!(old.diff(&lock).is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! A software bill of materials of a riff environment, in CycloneDX's JSON format, for tools which
+//! audit what a project's builds depend on.
+//!
+//! It lists the packages riff adds to the environment, at the versions its locked nixpkgs resolves
+//! them to. The project's own dependencies, like those in `Cargo.lock`, are left to the tools
+//! which read its lock files.
+
+use serde_json::json;
+
+use crate::riff_lock::RiffLock;
+
+/// The version of the CycloneDX specification the bill of materials follows
+const SPEC_VERSION: &str = "1.4";
+
+/// The bill of materials of the environment recorded in `lock`, for the project `name`
+///
+/// `created` is recorded as the time it was made, if given.
+pub fn cyclonedx(name: &str, lock: &RiffLock, created: Option<&str>) -> serde_json::Value {
+    let mut metadata = json!({
+        "tools": [{ "name": "riff", "version": env!("CARGO_PKG_VERSION") }],
+        "component": { "type": "application", "bom-ref": name, "name": name },
+    });
+    if let Some(created) = created {
+        metadata["timestamp"] = json!(created);
+    }
+
+    let mut components = vec![json!({
+        "type": "library",
+        "bom-ref": "nixpkgs",
+        "name": "nixpkgs",
+        "externalReferences": [{ "type": "vcs", "url": lock.nixpkgs.locked }],
+    })];
+    for (kind, inputs) in [
+        ("build", &lock.build_inputs),
+        ("runtime", &lock.runtime_inputs),
+    ] {
+        for input in inputs {
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": format!("nixpkgs#{input}"),
+                "name": input,
+                "properties": [{ "name": "riff:input", "value": kind }],
+            });
+            // Packages without a version are recorded by their store path instead
+            match lock.versions.get(input) {
+                Some(path) if path.starts_with("/nix/store/") => {
+                    component["properties"]
+                        .as_array_mut()
+                        .expect("The properties are a list")
+                        .push(json!({ "name": "nix:store-path", "value": path }));
+                }
+                Some(version) => component["version"] = json!(version),
+                None => (),
+            }
+            components.push(component);
+        }
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": SPEC_VERSION,
+        "version": 1,
+        "metadata": metadata,
+        "components": components,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::riff_lock::LockedNixpkgs;
+
+    #[test]
+    fn bill_of_materials() {
+        let lock = RiffLock {
+            registry: "abc".to_string(),
+            nixpkgs: LockedNixpkgs {
+                url: "github:NixOS/nixpkgs/nixpkgs-unstable".to_string(),
+                locked: "github:NixOS/nixpkgs/0123456789abcdef".to_string(),
+            },
+            build_inputs: ["openssl", "pkg-config"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            runtime_inputs: ["libGL".to_string()].into_iter().collect(),
+            versions: [
+                ("openssl", "3.0.7"),
+                ("libGL", "/nix/store/aaa-libglvnd-1.5.0"),
+            ]
+            .into_iter()
+            .map(|(input, version)| (input.to_string(), version.to_string()))
+            .collect(),
+            rules: None,
+            tools: Default::default(),
+        };
+
+        let bom = cyclonedx("riff-test", &lock, Some("2022-09-01T12:00:00Z"));
+        assert_eq!(bom["bomFormat"], "CycloneDX");
+        assert_eq!(bom["metadata"]["component"]["name"], "riff-test");
+        assert_eq!(bom["metadata"]["timestamp"], "2022-09-01T12:00:00Z");
+        let components = bom["components"].as_array().expect("components");
+        assert_eq!(components.len(), 4);
+        assert_eq!(
+            components[0]["externalReferences"][0]["url"],
+            "github:NixOS/nixpkgs/0123456789abcdef"
+        );
+        let component = |name: &str| {
+            components
+                .iter()
+                .find(|component| component["name"] == name)
+                .unwrap_or_else(|| panic!("no `{name}` component"))
+        };
+        assert_eq!(component("openssl")["version"], "3.0.7");
+        assert_eq!(component("openssl")["properties"][0]["value"], "build");
+        assert!(component("pkg-config").get("version").is_none());
+        assert!(component("libGL").get("version").is_none());
+        assert_eq!(component("libGL")["properties"][0]["value"], "runtime");
+        assert_eq!(
+            component("libGL")["properties"][1]["value"],
+            "/nix/store/aaa-libglvnd-1.5.0"
+        );
+
+        assert!(cyclonedx("riff-test", &lock, None)["metadata"]
+            .get("timestamp")
+            .is_none());
+    }
+}
@@ -1,17 +1,42 @@
 use std::time::Duration;
 
-use indicatif::{ProgressBar, ProgressStyle};
+use atty::Stream;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use once_cell::sync::OnceCell;
 
 const LEADER: char = ' ';
 const DRUM: char = '🥁';
 const QUARTER_NOTE: char = '♩';
 const EIGHTH_NOTE: char = '♪';
 
+/// How long an operation runs before its spinner appears, unless `--spinner-delay` says otherwise
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(200);
+
+static DELAY: OnceCell<Duration> = OnceCell::new();
+
+/// Set how long operations run before their spinners appear, so quick ones don't flicker
+pub fn set_delay(delay: Duration) {
+    DELAY.set(delay).ok();
+}
+
 pub struct SimpleSpinner;
 
 impl SimpleSpinner {
     pub fn new_with_message(msg: Option<&str>) -> color_eyre::Result<ProgressBar> {
-        let spinner = ProgressBar::new_spinner();
+        Self::new_with_options(
+            msg,
+            *DELAY.get().unwrap_or(&DEFAULT_DELAY),
+            atty::is(Stream::Stderr) && !crate::progress::enabled(),
+        )
+    }
+
+    fn new_with_options(
+        msg: Option<&str>,
+        delay: Duration,
+        is_terminal: bool,
+    ) -> color_eyre::Result<ProgressBar> {
+        // Drawn nowhere until it has run for `delay`
+        let spinner = ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden());
         spinner.enable_steady_tick(Duration::from_millis(260));
         spinner.set_style(
             ProgressStyle::with_template("{msg}{spinner}")?.tick_strings(&[
@@ -36,6 +61,67 @@ impl SimpleSpinner {
             spinner.set_message(msg.to_owned());
         }
 
+        match reveal_after(delay, is_terminal) {
+            Some(delay) if delay.is_zero() => {
+                spinner.set_draw_target(ProgressDrawTarget::stderr());
+            }
+            Some(delay) => {
+                let weak_spinner = spinner.downgrade();
+                std::thread::spawn(move || {
+                    std::thread::sleep(delay);
+                    if let Some(spinner) = weak_spinner.upgrade() {
+                        if !spinner.is_finished() {
+                            spinner.set_draw_target(ProgressDrawTarget::stderr());
+                        }
+                    }
+                });
+            }
+            None => (),
+        }
+
         Ok(spinner)
     }
 }
+
+/// How long to wait before drawing a spinner, or `None` if it should never be drawn
+///
+/// Spinners only make sense on a terminal; elsewhere, such as in CI logs, every frame would end
+/// up as a line of output.
+fn reveal_after(delay: Duration, is_terminal: bool) -> Option<Duration> {
+    if is_terminal {
+        Some(delay)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reveal_only_on_terminals() {
+        assert_eq!(
+            reveal_after(DEFAULT_DELAY, true),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(reveal_after(Duration::ZERO, true), Some(Duration::ZERO));
+        assert_eq!(reveal_after(DEFAULT_DELAY, false), None);
+        assert_eq!(reveal_after(Duration::ZERO, false), None);
+    }
+
+    #[test]
+    fn quick_operations_stay_hidden() -> eyre::Result<()> {
+        let spinner =
+            SimpleSpinner::new_with_options(Some("quick"), Duration::from_millis(50), true)?;
+        assert!(spinner.is_hidden());
+        spinner.finish_and_clear();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(spinner.is_hidden());
+
+        let spinner = SimpleSpinner::new_with_options(Some("piped"), Duration::ZERO, false)?;
+        assert!(spinner.is_hidden());
+        spinner.finish_and_clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+//! The parts of a Swift package's `Package.swift` riff cares about.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use eyre::WrapErr;
+
+pub const PACKAGE_SWIFT: &str = "Package.swift";
+
+/// The native dependencies a Swift package declares
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SwiftPackage {
+    /// The pkg-config names of its `.systemLibrary` targets
+    pub pkg_config_names: BTreeSet<String>,
+    /// The frameworks its targets link with `.linkedFramework`
+    pub frameworks: BTreeSet<String>,
+}
+
+impl SwiftPackage {
+    pub async fn read(project_dir: &Path) -> color_eyre::Result<Self> {
+        let path = project_dir.join(PACKAGE_SWIFT);
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .wrap_err_with(|| format!("Could not read `{}`", path.display()))?;
+        Ok(Self::parse(&contents))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let pkg_config_names = contents
+            .split(".systemLibrary(")
+            .skip(1)
+            .filter_map(|call| {
+                // The arguments end at the first `)`, as none of them are calls themselves
+                let arguments = call.split(')').next()?;
+                let pkg_config = arguments.split("pkgConfig:").nth(1)?;
+                string_literal(pkg_config)
+            })
+            .collect();
+        let frameworks = contents
+            .split(".linkedFramework(")
+            .skip(1)
+            .filter_map(string_literal)
+            .collect();
+        Self {
+            pkg_config_names,
+            frameworks,
+        }
+    }
+}
+
+/// The Nixpkgs attribute of an Apple SDK framework, like `Security`
+pub fn darwin_framework_attribute(framework: &str) -> String {
+    format!("darwin.apple_sdk.frameworks.{framework}")
+}
+
+/// The contents of the string literal at the start of `code`
+fn string_literal(code: &str) -> Option<String> {
+    let literal = code.trim_start().strip_prefix('"')?;
+    let contents = literal.split('"').next()?;
+    (!contents.is_empty()).then(|| contents.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_swift() {
+        let package_swift = r#"
+// swift-tools-version:5.7
+import PackageDescription
+
+let package = Package(
+    name: "RiffTest",
+    targets: [
+        .systemLibrary(name: "CSQLite", pkgConfig: "sqlite3", providers: [.apt(["libsqlite3-dev"])]),
+        .systemLibrary(name: "CZlib"),
+        .executableTarget(
+            name: "RiffTest",
+            dependencies: ["CSQLite", "CZlib"],
+            linkerSettings: [.linkedFramework( "Security", .when(platforms: [.macOS])), .linkedLibrary("z")]
+        ),
+    ]
+)
+"#;
+        assert_eq!(
+            SwiftPackage::parse(package_swift),
+            SwiftPackage {
+                pkg_config_names: ["sqlite3".to_string()].into_iter().collect(),
+                frameworks: ["Security".to_string()].into_iter().collect(),
+            }
+        );
+    }
+}
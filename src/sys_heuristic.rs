@@ -0,0 +1,199 @@
+//! Guesses at the native dependencies of `-sys` crates the registry doesn't know, from the
+//! library they link or the pkg-config packages they look for.
+
+use itertools::Itertools;
+
+use crate::cargo_metadata::CargoMetadataPackage;
+
+/// Native library and pkg-config names whose nixpkgs attribute is not the same name
+const NIXPKGS_ALIASES: [(&str, &str); 40] = [
+    ("X11", "xorg.libX11"),
+    ("asound", "alsa-lib"),
+    ("alsa", "alsa-lib"),
+    ("bz2", "bzip2"),
+    ("crypto", "openssl"),
+    ("dbus-1", "dbus"),
+    ("ffi", "libffi"),
+    ("freetype2", "freetype"),
+    ("gio-2.0", "glib"),
+    ("git2", "libgit2"),
+    ("glfw3", "glfw"),
+    ("glib-2.0", "glib"),
+    ("gobject-2.0", "glib"),
+    ("gpg-error", "libgpg-error"),
+    ("libcurl", "curl"),
+    ("liblzma", "xz"),
+    ("libmagic", "file"),
+    ("libpcre2-8", "pcre2"),
+    ("libpq", "postgresql"),
+    ("libpulse", "libpulseaudio"),
+    ("libsystemd", "systemd"),
+    ("libudev", "udev"),
+    ("libusb-1.0", "libusb1"),
+    ("libxml-2.0", "libxml2"),
+    ("libzmq", "zeromq"),
+    ("libzstd", "zstd"),
+    ("lzma", "xz"),
+    ("magic", "file"),
+    ("onig", "oniguruma"),
+    ("pcap", "libpcap"),
+    ("pq", "postgresql"),
+    ("sodium", "libsodium"),
+    ("sqlite3", "sqlite"),
+    ("ssh2", "libssh2"),
+    ("ssl", "openssl"),
+    ("usb-1.0", "libusb1"),
+    ("vulkan", "vulkan-loader"),
+    ("xml2", "libxml2"),
+    ("z", "zlib"),
+    ("zmq", "zeromq"),
+];
+
+/// A nixpkgs attribute guessed for a `-sys` crate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Guess {
+    /// Where the guess came from, like `links = "zmq"`
+    pub source: String,
+    pub nixpkgs_attribute: String,
+}
+
+/// Guess the nixpkgs attributes `package` needs, if it is a `-sys` crate
+///
+/// The pkg-config packages of a `package.metadata.system-deps` table are preferred, as they are
+/// more specific than the library named by the `links` key.
+pub fn guess(package: &CargoMetadataPackage) -> Vec<Guess> {
+    if !package.name.ends_with("-sys") {
+        return Vec::new();
+    }
+    let system_deps = package
+        .metadata
+        .as_ref()
+        .map(|metadata| metadata.system_deps())
+        .unwrap_or_default();
+    let native_names = if !system_deps.is_empty() {
+        system_deps
+            .into_iter()
+            .map(|name| (format!("system-deps {name:?}"), name))
+            .collect()
+    } else if let Some(links) = &package.links {
+        vec![(format!("links = {links:?}"), links.clone())]
+    } else {
+        Vec::new()
+    };
+
+    native_names
+        .into_iter()
+        .filter_map(|(source, name)| {
+            Some(Guess {
+                source,
+                nixpkgs_attribute: nixpkgs_attribute(&name)?,
+            })
+        })
+        .collect()
+}
+
+/// Whether `package` builds against a native library, rather than only declaring bindings
+pub fn links_native(package: &CargoMetadataPackage) -> bool {
+    package.links.is_some()
+        || package
+            .metadata
+            .as_ref()
+            .map(|metadata| !metadata.system_deps.is_empty())
+            .unwrap_or(false)
+}
+
+/// nixpkgs attributes which likely provide the library of the `-sys` crate `package`, to suggest
+/// when nothing declares its inputs
+///
+/// Besides the [guesses](guess) from the library it links, these are looked up from its name,
+/// so `sodium-sys` suggests `libsodium`, and `tidy-sys` suggests `tidy` and `libtidy`.
+pub fn suggest(package: &CargoMetadataPackage) -> Vec<String> {
+    let stem = match package.name.strip_suffix("-sys") {
+        Some(stem) => stem,
+        None => return Vec::new(),
+    };
+    let bare = stem.strip_prefix("lib").unwrap_or(stem);
+    let names = [stem.to_string(), bare.to_string(), format!("lib{bare}")];
+    guess(package)
+        .into_iter()
+        .map(|guess| guess.nixpkgs_attribute)
+        .chain(names.iter().filter_map(|name| nixpkgs_attribute(name)))
+        .unique()
+        .collect()
+}
+
+/// The nixpkgs attribute the native library or pkg-config package `name` is likely packaged as
+fn nixpkgs_attribute(name: &str) -> Option<String> {
+    match NIXPKGS_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        Some((_, attribute)) => Some(attribute.to_string()),
+        // Most libraries are packaged under their own name
+        None if is_plain_attribute(name) => Some(name.to_string()),
+        None => None,
+    }
+}
+
+/// Whether `name` can be spliced into the generated flake as a nixpkgs attribute
+fn is_plain_attribute(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(json: &str) -> CargoMetadataPackage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn guess_sys_crates() {
+        let guesses = guess(&package(
+            r#"{"name": "zmq-sys", "links": "zmq", "metadata": null}"#,
+        ));
+        assert_eq!(
+            guesses,
+            vec![Guess {
+                source: r#"links = "zmq""#.to_string(),
+                nixpkgs_attribute: "zeromq".to_string(),
+            }]
+        );
+
+        let guesses = guess(&package(
+            r#"{"name": "gtk-sys", "links": "gtk-3",
+                "metadata": {"system-deps": {"gtk": {"name": "gtk+-3.0", "version": "3.22"},
+                                             "glib_2_0": {"name": "glib-2.0"}}}}"#,
+        ));
+        assert_eq!(
+            guesses
+                .iter()
+                .map(|guess| guess.nixpkgs_attribute.as_str())
+                .collect::<Vec<_>>(),
+            vec!["glib"],
+            "names which aren't nixpkgs attributes are not guessed"
+        );
+
+        assert!(guess(&package(
+            r#"{"name": "serde", "links": "z", "metadata": null}"#
+        ))
+        .is_empty());
+    }
+
+    #[test]
+    fn suggest_sys_crates() {
+        let zmq = package(r#"{"name": "zmq-sys", "links": "zmq", "metadata": null}"#);
+        assert!(links_native(&zmq));
+        assert_eq!(suggest(&zmq), vec!["zeromq"]);
+
+        let sodium = package(r#"{"name": "libsodium-sys", "metadata": null}"#);
+        assert!(!links_native(&sodium));
+        assert_eq!(suggest(&sodium), vec!["libsodium"]);
+
+        let tidy = package(r#"{"name": "tidy-sys", "metadata": null}"#);
+        assert_eq!(suggest(&tidy), vec!["tidy", "libtidy"]);
+
+        assert!(suggest(&package(r#"{"name": "openssl", "metadata": null}"#)).is_empty());
+    }
+}
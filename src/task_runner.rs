@@ -0,0 +1,214 @@
+//! Detection of the tools required by `justfile` and `Makefile` recipes.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Tools we're confident about, mapped to the Nixpkgs attribute which provides them
+///
+/// We don't guess at attributes for anything else, as a wrong guess would break the environment.
+const KNOWN_TOOLS: &[(&str, &str)] = &[
+    ("bash", "bash"),
+    ("deno", "deno"),
+    ("docker-compose", "docker-compose"),
+    ("fish", "fish"),
+    ("gh", "gh"),
+    ("jq", "jq"),
+    ("just", "just"),
+    ("make", "gnumake"),
+    ("node", "nodejs"),
+    ("npm", "nodejs"),
+    ("perl", "perl"),
+    ("protoc", "protobuf"),
+    ("python", "python3"),
+    ("python3", "python3"),
+    ("ruby", "ruby"),
+    ("shellcheck", "shellcheck"),
+    ("sqlx", "sqlx-cli"),
+    ("wasm-pack", "wasm-pack"),
+    ("yq", "yq"),
+    ("zsh", "zsh"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskRunner {
+    Just,
+    Make,
+}
+
+impl TaskRunner {
+    /// The file names each task runner reads, in the order they look for them
+    pub fn file_names(&self) -> &'static [&'static str] {
+        match self {
+            TaskRunner::Just => &["justfile", "Justfile", ".justfile"],
+            TaskRunner::Make => &["GNUmakefile", "makefile", "Makefile"],
+        }
+    }
+
+    /// The Nixpkgs attribute providing the task runner itself
+    pub fn nixpkgs_attribute(&self) -> &'static str {
+        match self {
+            TaskRunner::Just => "just",
+            TaskRunner::Make => "gnumake",
+        }
+    }
+}
+
+/// Find the Nixpkgs attributes needed by the task runner files in `project_dir`
+#[tracing::instrument(skip_all, fields(project_dir = %project_dir.display()))]
+pub async fn detect_required_tools(project_dir: &Path) -> std::io::Result<BTreeSet<String>> {
+    let mut required = BTreeSet::new();
+    for runner in [TaskRunner::Just, TaskRunner::Make] {
+        for file_name in runner.file_names() {
+            let path = project_dir.join(file_name);
+            if !path.is_file() {
+                continue;
+            }
+            let contents = tokio::fs::read_to_string(&path).await?;
+            let tools = required_tools(runner, &contents);
+            tracing::debug!(path = %path.display(), tools = ?tools, "Scanned task runner file");
+            required.insert(runner.nixpkgs_attribute().to_string());
+            required.extend(tools);
+            break;
+        }
+    }
+    Ok(required)
+}
+
+/// Find the Nixpkgs attributes obviously needed by the recipes in `contents`
+///
+/// This looks at shebangs, the configured shell, `REQUIRED_TOOLS`/`required_tools` variables and
+/// the first word of recipe lines, keeping only tools we know how to provide.
+pub fn required_tools(runner: TaskRunner, contents: &str) -> BTreeSet<String> {
+    let mut commands = Vec::new();
+    for line in contents.lines() {
+        let is_recipe_line = match runner {
+            TaskRunner::Make => line.starts_with('\t'),
+            TaskRunner::Just => line.starts_with(|c: char| c.is_whitespace()),
+        };
+        let line = line.trim();
+
+        if let Some(interpreter) = line.strip_prefix("#!") {
+            commands.extend(shebang_command(interpreter));
+        } else if let Some(tools) = required_tools_assignment(line) {
+            commands.extend(tools);
+        } else if let Some(shell) = shell_setting(runner, line) {
+            commands.push(shell);
+        } else if is_recipe_line {
+            let command = line
+                .trim_start_matches(['@', '-', '+'])
+                .split_whitespace()
+                .next();
+            commands.extend(command.map(ToString::to_string));
+        }
+    }
+
+    commands
+        .iter()
+        .filter_map(|command| {
+            let command = command.rsplit('/').next().unwrap_or(command);
+            KNOWN_TOOLS
+                .iter()
+                .find(|(tool, _)| *tool == command)
+                .map(|(_, attribute)| attribute.to_string())
+        })
+        .collect()
+}
+
+fn shebang_command(interpreter: &str) -> Option<String> {
+    let mut words = interpreter.split_whitespace();
+    let program = words.next()?;
+    if program.ends_with("/env") {
+        words
+            .find(|word| !word.starts_with('-'))
+            .map(ToString::to_string)
+    } else {
+        Some(program.to_string())
+    }
+}
+
+fn required_tools_assignment(line: &str) -> Option<Vec<String>> {
+    let (name, value) = line
+        .split_once(":=")
+        .or_else(|| line.split_once("?="))
+        .or_else(|| line.split_once('='))?;
+    let name = name.trim().trim_start_matches("export ").trim();
+    if !name.eq_ignore_ascii_case("required_tools") {
+        return None;
+    }
+    Some(
+        value
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .map(|tool| tool.trim_matches(|c| c == '"' || c == '\''))
+            .filter(|tool| !tool.is_empty())
+            .map(ToString::to_string)
+            .collect(),
+    )
+}
+
+fn shell_setting(runner: TaskRunner, line: &str) -> Option<String> {
+    match runner {
+        // set shell := ["zsh", "-cu"]
+        TaskRunner::Just => {
+            let value = line.strip_prefix("set shell")?.trim().strip_prefix(":=")?;
+            let value = value.trim().trim_start_matches('[');
+            let shell = value.split(',').next()?;
+            Some(shell.trim().trim_matches('"').to_string())
+        }
+        // SHELL := /usr/bin/env zsh
+        TaskRunner::Make => {
+            let (name, value) = line.split_once('=')?;
+            if name.trim_end_matches([':', '?', ' ']) != "SHELL" {
+                return None;
+            }
+            shebang_command(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn justfile_tools() {
+        let justfile = r#"
+set shell := ["zsh", "-cu"]
+required_tools := "jq, yq"
+
+test:
+    cargo test
+    @protoc --version
+
+script:
+    #!/usr/bin/env python3
+    print("hello")
+"#;
+        assert_eq!(
+            required_tools(TaskRunner::Just, justfile),
+            ["jq", "protobuf", "python3", "yq", "zsh"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+    }
+
+    #[test]
+    fn makefile_tools() {
+        let makefile = "\
+SHELL := /bin/bash
+REQUIRED_TOOLS = shellcheck unknown-tool
+
+lint:
+\t-shellcheck scripts/*.sh
+\tnode build.js
+\tcargo build
+";
+        assert_eq!(
+            required_tools(TaskRunner::Make, makefile),
+            ["bash", "nodejs", "shellcheck"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect()
+        );
+    }
+}
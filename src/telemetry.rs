@@ -1,7 +1,13 @@
-use std::{collections::HashSet, path::Path, time::Duration};
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, ValueSource};
 use eyre::eyre;
+use once_cell::sync::OnceCell;
 use reqwest::Response;
 use serde::Serialize;
 use tokio::{
@@ -11,17 +17,28 @@ use tokio::{
 };
 use uuid::Uuid;
 
-use crate::{cmds::Commands, dev_env::DetectedLanguage, Cli, RIFF_XDG_PREFIX};
+use crate::build_info::BuildInfo;
+use crate::{cmds::Commands, Cli, RIFF_XDG_PREFIX};
 
 static TELEMETRY_DISTINCT_ID_PATH: &str = "distinct_id";
+static TELEMETRY_DISABLED_PATH: &str = "telemetry_disabled";
+static TELEMETRY_DISABLED_DESCRIPTION: &str =
+    "riff does not send telemetry from this machine while this file exists.
+Run `riff telemetry enable` or delete this file to send it again.
+";
 static TELEMETRY_IDENTIFIER_DESCRIPTION: &str =  "This is a randomly generated version 4 UUID.
 Determinate Systems uses this ID to know how many people use the tool and to focus our limited research and development.
 This ID is completely random and contains no personally identifiable information about you.
 You can delete this file at any time to create a new ID.
-You can also disable ID generation; see the documentation on telemetry to see how to do so.";
+You can also disable telemetry, and this ID, with `riff telemetry disable`.";
 static TELEMETRY_REMOTE_URL: &str = "https://registry.riff.determinate.systems/telemetry";
 pub static TELEMETRY_HEADER_NAME: &str = "X-RIFF-Client-Info";
 
+/// When riff started, to report how long the command took
+static STARTED: OnceCell<Instant> = OnceCell::new();
+/// The telemetry of the command, recorded once the project is detected and sent as riff exits
+static RECORDED: Mutex<Option<Telemetry>> = Mutex::new(None);
+
 #[derive(Debug, Serialize)]
 pub(crate) struct Telemetry {
     /// Stored in `$XDG_DATA_HOME/riff/distinct_id` as a UUIDv4
@@ -32,31 +49,73 @@ pub(crate) struct Telemetry {
     os_release_name: Option<String>,
     /// `VERSION_ID` from `/etc/os-release`
     os_release_version_id: Option<String>,
-    /// The `CARGO_PKG_VERSION` from a `riff` build
-    riff_version: String,
+    /// The version of riff, and how it was built
+    riff_version: BuildInfo,
     /// The version output of `nix --version`
     nix_version: Option<String>,
     /// If the exit code of `test -t 0` is 0, then this is true, otherwise false
     is_tty: bool,
+    /// The version of WSL riff runs under, if it does, as `os_release_name` is then the Linux
+    /// distribution inside it
+    wsl_version: Option<u8>,
     /// The command given to riff (eg "shell")
     subcommand: Option<String>,
-    detected_languages: HashSet<DetectedLanguage>,
+    detected_languages: HashSet<&'static str>,
     in_ci: bool,
+    /// The names of the flags given to riff (eg "offline"), but never their values
+    flags: BTreeSet<String>,
+    /// Where riff failed, if it did
+    failed_phase: Option<Phase>,
+    /// Whether the registry had been refreshed by the time the project was detected
+    registry_fresh: Option<bool>,
+    /// How long the command took, in milliseconds, from riff starting to exiting
+    duration_ms: Option<u64>,
+    /// The exit status of riff, which is that of the command `riff run` or `riff shell` ran
+    exit_status: Option<i32>,
+}
+
+/// The part of riff's work which failed, reported in telemetry to find where users get stuck
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Phase {
+    /// Parsing the command line
+    Parse,
+    /// Detecting the project's environment
+    Detect,
+    /// Locking the generated flake with `nix flake lock`
+    Lock,
 }
 
 impl Telemetry {
-    pub(crate) async fn from_clap_parse_result(command: Option<&crate::Commands>) -> Self {
-        let distinct_id = match distinct_id().await {
-            Ok(distinct_id) => Some(distinct_id),
-            Err(err) => {
-                tracing::debug!(err = %eyre::eyre!(err), "Could get distinct ID for telemetry");
-                None
+    pub(crate) async fn from_clap_parse_result(matches: Option<&ArgMatches>) -> Self {
+        let disabled = disabled();
+        let command = matches.and_then(|matches| Cli::from_arg_matches(matches).ok());
+        let command = command.as_ref().map(|cli| &cli.command);
+        let flags = matches
+            .map(|matches| {
+                // Building adds the arguments clap generates, such as `--help`, as in parsing
+                let mut cli_command = Cli::command();
+                cli_command.build();
+                flags_used(&cli_command, matches)
+            })
+            .unwrap_or_default();
+
+        let distinct_id = if disabled {
+            // Don't create an ID for a user who has opted out
+            None
+        } else {
+            match distinct_id().await {
+                Ok(distinct_id) => Some(distinct_id),
+                Err(err) => {
+                    tracing::debug!(err = %eyre::eyre!(err), "Could get distinct ID for telemetry");
+                    None
+                }
             }
         };
 
         let system_os = std::env::consts::OS.to_string();
         let system_arch = std::env::consts::ARCH.to_string();
-        let riff_version = env!("CARGO_PKG_VERSION").to_string();
+        let riff_version = BuildInfo::current();
         let nix_version = match nix_version().await {
             Ok(nix_version) => nix_version,
             Err(err) => {
@@ -73,8 +132,33 @@ impl Telemetry {
 
         let subcommand = match command {
             Some(Commands::Shell(_)) => Some("shell".to_string()),
+            Some(Commands::Reload(_)) => Some("reload".to_string()),
+            Some(Commands::Watch(_)) => Some("watch".to_string()),
             Some(Commands::Run(_)) => Some("run".to_string()),
             Some(Commands::PrintDevEnv(_)) => Some("print-dev-env".to_string()),
+            Some(Commands::Env(_)) => Some("env".to_string()),
+            Some(Commands::Detect(_)) => Some("detect".to_string()),
+            Some(Commands::Add(_)) => Some("add".to_string()),
+            Some(Commands::Remove(_)) => Some("remove".to_string()),
+            Some(Commands::Pin(_)) => Some("pin".to_string()),
+            Some(Commands::Lock(_)) => Some("lock".to_string()),
+            Some(Commands::ExecWrapper(_)) => Some("exec-wrapper".to_string()),
+            Some(Commands::Direnv(_)) => Some("direnv".to_string()),
+            Some(Commands::Export(_)) => Some("export".to_string()),
+            Some(Commands::VerifyExports(_)) => Some("verify-exports".to_string()),
+            Some(Commands::Gc(_)) => Some("gc".to_string()),
+            Some(Commands::Graph(_)) => Some("graph".to_string()),
+            Some(Commands::Explain(_)) => Some("explain".to_string()),
+            Some(Commands::Du(_)) => Some("du".to_string()),
+            Some(Commands::Init(_)) => Some("init".to_string()),
+            Some(Commands::New(_)) => Some("new".to_string()),
+            Some(Commands::Try(_)) => Some("try".to_string()),
+            Some(Commands::Doctor(_)) => Some("doctor".to_string()),
+            Some(Commands::Daemon(_)) => Some("daemon".to_string()),
+            Some(Commands::Registry(_)) => Some("registry".to_string()),
+            Some(Commands::Telemetry(_)) => Some("telemetry".to_string()),
+            Some(Commands::Config(_)) => Some("config".to_string()),
+            Some(Commands::Dev(_)) => Some("dev".to_string()),
             None => None,
         };
 
@@ -87,9 +171,15 @@ impl Telemetry {
             riff_version,
             nix_version,
             is_tty,
+            wsl_version: crate::wsl::version(),
             subcommand,
             detected_languages: Default::default(),
             in_ci: is_ci::cached(),
+            flags,
+            failed_phase: None,
+            registry_fresh: None,
+            duration_ms: None,
+            exit_status: None,
         }
     }
 
@@ -97,28 +187,54 @@ impl Telemetry {
     ///
     /// This is not very performant and may do things like re-invoke `nix` or reparse the `$ARG`s.
     pub(crate) async fn new() -> Self {
-        let cli = Cli::try_parse().ok().map(|c| c.command);
+        let matches = Cli::command().try_get_matches().ok();
 
-        Self::from_clap_parse_result(cli.as_ref()).await
+        Self::from_clap_parse_result(matches.as_ref()).await
+    }
+
+    pub(crate) fn with_detected_languages(mut self, languages: &HashSet<&'static str>) -> Self {
+        self.detected_languages = languages.clone();
+        self
+    }
+
+    pub(crate) fn with_failed_phase(mut self, phase: Phase) -> Self {
+        self.failed_phase = Some(phase);
+        self
     }
 
-    pub(crate) fn with_detected_languages(mut self, languages: &HashSet<DetectedLanguage>) -> Self {
-        self.detected_languages = languages.iter().cloned().collect();
+    pub(crate) fn with_registry_fresh(mut self, fresh: bool) -> Self {
+        self.registry_fresh = Some(fresh);
         self
     }
 
+    pub(crate) fn with_outcome(mut self, duration: Duration, exit_status: i32) -> Self {
+        self.duration_ms = Some(duration.as_millis().try_into().unwrap_or(u64::MAX));
+        self.exit_status = Some(exit_status);
+        self
+    }
+
+    /// Keep the telemetry to send as riff exits, in place of any recorded before
+    pub(crate) fn record(self) {
+        *RECORDED.lock().unwrap_or_else(PoisonError::into_inner) = Some(self);
+    }
+
+    /// Send the telemetry, unless it is disabled on this machine or with `RIFF_DISABLE_TELEMETRY`
     #[tracing::instrument(skip_all)]
-    pub(crate) async fn send(&self) -> eyre::Result<Response> {
+    pub(crate) async fn send(&self) -> eyre::Result<Option<Response>> {
+        if disabled() || crate::network::disabled() {
+            tracing::debug!("Telemetry is disabled, not sending it");
+            return Ok(None);
+        }
         let header_data = self.as_header_data()?;
         tracing::trace!(data = %self.redact_header_data(header_data.clone()), "Sending telemetry data to {TELEMETRY_REMOTE_URL}");
-        let http_client = reqwest::Client::new();
+        let http_client = crate::network::client("send telemetry")?;
         let req = http_client
             .post(TELEMETRY_REMOTE_URL)
             .header(TELEMETRY_HEADER_NAME, &header_data)
             .timeout(Duration::from_millis(250));
         let res = req.send().await?;
         tracing::debug!(telemetry = %self.redact_header_data(header_data.clone()), "Sent telemetry data to {TELEMETRY_REMOTE_URL}");
-        Ok(res)
+        Ok(Some(res))
     }
 
     pub(crate) fn as_header_data(&self) -> Result<String, serde_json::Error> {
@@ -134,6 +250,105 @@ impl Telemetry {
     }
 }
 
+/// Note when riff started, to report how long the command took
+pub(crate) fn start_clock() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// Send the telemetry recorded for the command, if any, with how long it took and `exit_status`
+pub(crate) async fn send_recorded(exit_status: i32) {
+    let recorded = RECORDED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    let telemetry = match recorded {
+        Some(telemetry) => telemetry,
+        None => return,
+    };
+    let duration = STARTED
+        .get()
+        .map(Instant::elapsed)
+        .unwrap_or(Duration::ZERO);
+    if let Err(err) = telemetry.with_outcome(duration, exit_status).send().await {
+        tracing::debug!(%err, "Could not send telemetry");
+    }
+}
+
+/// Send the telemetry recorded for the command, then exit with `exit_status`
+pub(crate) async fn exit(exit_status: i32) -> ! {
+    send_recorded(exit_status).await;
+    std::process::exit(exit_status)
+}
+
+/// Whether telemetry is disabled on this machine, or with `RIFF_DISABLE_TELEMETRY`
+///
+/// A project's `riff.toml` can disable it too, which the commands reading the project check.
+pub(crate) fn disabled() -> bool {
+    disabled_by_env() || disabled_path().is_some()
+}
+
+/// Whether `RIFF_DISABLE_TELEMETRY` is set to anything other than `false`, `0` or nothing
+pub(crate) fn disabled_by_env() -> bool {
+    match std::env::var("RIFF_DISABLE_TELEMETRY") {
+        Ok(val) => !(val == "false" || val == "0" || val.is_empty()),
+        Err(_) => false,
+    }
+}
+
+/// The file which disables telemetry on this machine, if it exists
+pub(crate) fn disabled_path() -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix(RIFF_XDG_PREFIX)
+        .ok()?
+        .find_config_file(TELEMETRY_DISABLED_PATH)
+}
+
+/// Disable telemetry on this machine, and delete its distinct ID
+///
+/// Returns the file which disables it.
+pub(crate) async fn disable() -> eyre::Result<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(RIFF_XDG_PREFIX)?;
+    let disabled_path = xdg_dirs.place_config_file(TELEMETRY_DISABLED_PATH)?;
+    tokio::fs::write(&disabled_path, TELEMETRY_DISABLED_DESCRIPTION).await?;
+    if let Some(distinct_id_path) = xdg_dirs.find_config_file(TELEMETRY_DISTINCT_ID_PATH) {
+        tokio::fs::remove_file(distinct_id_path).await?;
+    }
+    Ok(disabled_path)
+}
+
+/// Enable telemetry on this machine again, returning whether it was disabled
+pub(crate) async fn enable() -> eyre::Result<bool> {
+    match disabled_path() {
+        Some(disabled_path) => {
+            tokio::fs::remove_file(disabled_path).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// The long names of the flags given to `command` or its subcommands in `matches`
+///
+/// Positional arguments and the values of flags are left out, as they may identify the user.
+fn flags_used(command: &clap::Command, matches: &ArgMatches) -> BTreeSet<String> {
+    let mut flags = command
+        .get_arguments()
+        .filter_map(|arg| Some((arg.get_id(), arg.get_long()?)))
+        .filter(|(id, _)| {
+            matches!(
+                matches.value_source(*id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        })
+        .map(|(_, long)| long.to_string())
+        .collect::<BTreeSet<_>>();
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        if let Some(subcommand) = command.find_subcommand(name) {
+            flags.extend(flags_used(subcommand, sub_matches));
+        }
+    }
+    flags
+}
+
 async fn distinct_id() -> eyre::Result<Uuid> {
     let xdg_dirs = xdg::BaseDirectories::with_prefix(RIFF_XDG_PREFIX)?;
     let distinct_id_path = xdg_dirs.place_config_file(Path::new(TELEMETRY_DISTINCT_ID_PATH))?;
@@ -191,3 +406,31 @@ async fn nix_version() -> eyre::Result<Option<String>> {
         Err(err) => Err(err.into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_flag_names_only() {
+        let matches = Cli::command()
+            .try_get_matches_from([
+                "riff",
+                "--offline",
+                "shell",
+                "--project-dir",
+                "/home/someone/secret-project",
+                "--locked",
+            ])
+            .unwrap();
+        let mut cli_command = Cli::command();
+        cli_command.build();
+        assert_eq!(
+            flags_used(&cli_command, &matches),
+            ["locked", "offline", "project-dir"]
+                .into_iter()
+                .map(String::from)
+                .collect()
+        );
+    }
+}
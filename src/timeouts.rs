@@ -0,0 +1,168 @@
+//! Bounds on how long riff spends setting up an environment and running a command in it, so a CI
+//! job fails with a status saying which one ran over, rather than being killed by its runner.
+
+use std::future::Future;
+use std::time::Duration;
+
+use clap::Args;
+use eyre::WrapErr;
+use owo_colors::OwoColorize;
+use tokio::process::Child;
+
+/// The exit status when setting up the environment runs over `--setup-timeout`
+pub const SETUP_TIMEOUT_STATUS: i32 = 123;
+/// The exit status when the command runs over `--command-timeout`, the same as `timeout`'s
+pub const COMMAND_TIMEOUT_STATUS: i32 = 124;
+/// How long a command which ran over gets to exit after being terminated before it is killed
+const TERMINATE_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct Timeouts {
+    /// Give up setting up the environment after this long, like `10m`, and exit with 123
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        env = "RIFF_SETUP_TIMEOUT"
+    )]
+    pub(crate) setup_timeout: Option<Duration>,
+    /// End the command after this long, like `1h`, and exit with 124, as `timeout` does
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        env = "RIFF_COMMAND_TIMEOUT"
+    )]
+    pub(crate) command_timeout: Option<Duration>,
+}
+
+impl Timeouts {
+    /// The result of `setup`, or `None` if it ran over `--setup-timeout`
+    ///
+    /// Running over drops `setup`, so the `nix` and `cargo` it started must be spawned with
+    /// `kill_on_drop` for them to end with it rather than keep building in the background.
+    pub async fn setup<T>(
+        &self,
+        setup: impl Future<Output = color_eyre::Result<T>>,
+    ) -> color_eyre::Result<Option<T>> {
+        let timeout = match self.setup_timeout {
+            Some(timeout) => timeout,
+            None => return setup.await.map(Some),
+        };
+        match tokio::time::timeout(timeout, setup).await {
+            Ok(result) => result.map(Some),
+            Err(_) => {
+                eprintln!(
+                    "{warning} Setting up the environment took longer than {seconds}s, giving up (see `{flag}`)",
+                    warning = "⚠️".yellow(),
+                    seconds = timeout.as_secs(),
+                    flag = "--setup-timeout".cyan(),
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Wait for `child` to exit, terminating it if it runs over `--command-timeout`, and return
+    /// its exit code, or [`COMMAND_TIMEOUT_STATUS`] if it ran over
+    pub async fn wait(&self, child: &mut Child) -> color_eyre::Result<Option<i32>> {
+        let timeout = match self.command_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(child.wait().await?.code()),
+        };
+        if let Ok(status) = tokio::time::timeout(timeout, child.wait()).await {
+            return Ok(status?.code());
+        }
+        eprintln!(
+            "{warning} The command took longer than {seconds}s, ending it (see `{flag}`)",
+            warning = "⚠️".yellow(),
+            seconds = timeout.as_secs(),
+            flag = "--command-timeout".cyan(),
+        );
+        if let Some(pid) = child.id() {
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+        }
+        if tokio::time::timeout(TERMINATE_GRACE, child.wait())
+            .await
+            .is_err()
+        {
+            child.kill().await.wrap_err("Could not end the command")?;
+        }
+        Ok(Some(COMMAND_TIMEOUT_STATUS))
+    }
+}
+
+/// Parse a duration like `90`, `90s`, `30m`, `2h`, `1d` or `1h30m`; plain numbers are seconds
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("`{s}` is not a duration like `30m` or `1h30m`");
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let mut total = 0u64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let amount: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = match rest[digits..].chars().next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        total = amount
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + 1..];
+    }
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("30x").is_err());
+        assert!(parse_duration("1h30").is_err());
+    }
+
+    #[tokio::test]
+    async fn timeouts() -> eyre::Result<()> {
+        let timeouts = Timeouts {
+            setup_timeout: Some(Duration::from_millis(10)),
+            command_timeout: Some(Duration::from_millis(10)),
+        };
+        let setup = timeouts
+            .setup(async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(())
+            })
+            .await?;
+        assert_eq!(setup, None);
+        assert_eq!(timeouts.setup(async { Ok(1) }).await?, Some(1));
+
+        let mut child = tokio::process::Command::new("sleep").arg("10").spawn()?;
+        assert_eq!(
+            timeouts.wait(&mut child).await?,
+            Some(COMMAND_TIMEOUT_STATUS)
+        );
+        let mut child = tokio::process::Command::new("true").spawn()?;
+        assert_eq!(Timeouts::default().wait(&mut child).await?, Some(0));
+        Ok(())
+    }
+}
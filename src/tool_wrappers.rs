@@ -0,0 +1,161 @@
+//! Wrapper scripts which run tools from the riff environment.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, WrapErr};
+use owo_colors::OwoColorize;
+
+use crate::nix_dev_env::{shell_quote, ActivationScript};
+
+const NIX_STORE_DIR: &str = "/nix/store";
+
+/// Check that `tool` is the plain file name of a command, as the `value_parser` of tool arguments
+///
+/// Wrappers are written to the wrapper directory joined with the name, so anything like a path
+/// would write outside it.
+pub fn parse_tool_name(tool: &str) -> Result<String, String> {
+    if tool.is_empty() || tool == "." || tool == ".." {
+        return Err(format!("`{tool}` is not the name of a tool"));
+    }
+    if tool.contains('/') || tool.chars().any(char::is_control) {
+        return Err(format!(
+            "`{}` is not the name of a tool, which can't contain `/` or control characters",
+            tool.escape_debug()
+        ));
+    }
+    Ok(tool.to_string())
+}
+
+/// Find the store path `tool` resolves to on the `PATH` of `activation_script`
+pub fn resolve_tool(
+    activation_script: &ActivationScript,
+    tool: &str,
+) -> color_eyre::Result<PathBuf> {
+    for dir in activation_script.path() {
+        let candidate = dir.join(tool);
+        if !candidate.is_file() {
+            continue;
+        }
+        let resolved = candidate
+            .canonicalize()
+            .wrap_err_with(|| format!("Could not resolve `{}`", candidate.display()))?;
+        if resolved.starts_with(NIX_STORE_DIR) {
+            return Ok(resolved);
+        }
+    }
+    Err(eyre!(
+        "`{tool}` is not provided by the riff environment, try adding it with `{riff_add}`",
+        riff_add = format!("riff add {tool}").cyan(),
+    ))
+}
+
+/// A wrapper which `exec`s exactly `store_path`, failing loudly if it has gone missing
+pub fn pinned_wrapper_script(tool: &str, store_path: &Path) -> String {
+    format!(
+        "\
+#!/bin/sh
+# Generated by riff, do not edit. `{tool}` is pinned to the store path below.
+tool={quoted_tool}
+pinned={store_path}
+if [ ! -x \"$pinned\" ]; then
+  echo \"riff: pinned \\`$tool\\` ($pinned) is missing, rerun \\`riff pin $tool\\`\" >&2
+  exit 127
+fi
+exec \"$pinned\" \"$@\"
+",
+        quoted_tool = shell_quote(tool),
+        store_path = shell_quote(&store_path.display().to_string()),
+    )
+}
+
+/// A wrapper which runs `tool` through `riff run` in `project_dir`
+///
+/// The environment's `PATH` comes first inside `riff run`, so `tool` resolves to the real tool
+/// rather than back to the wrapper. If the environment lacks `tool`, the wrapper would find
+/// itself again, so nested invocations fail instead of looping.
+pub fn exec_wrapper_script(tool: &str, riff: &Path, project_dir: &Path) -> String {
+    format!(
+        "\
+#!/bin/sh
+# Generated by riff, do not edit. Runs `{tool}` inside the riff environment of the project below.
+tool={quoted_tool}
+if [ -n \"$RIFF_EXEC_WRAPPER\" ]; then
+  echo \"riff: \\`$tool\\` is not provided by the riff environment\" >&2
+  exit 127
+fi
+RIFF_EXEC_WRAPPER=1 exec {riff} run --project-dir {project_dir} -- \"$tool\" \"$@\"
+",
+        riff = shell_quote(&riff.display().to_string()),
+        project_dir = shell_quote(&project_dir.display().to_string()),
+        quoted_tool = shell_quote(tool),
+    )
+}
+
+pub async fn write_wrapper(
+    wrapper_dir: &Path,
+    tool: &str,
+    script: &str,
+) -> color_eyre::Result<PathBuf> {
+    parse_tool_name(tool).map_err(|e| eyre!(e))?;
+    tokio::fs::create_dir_all(wrapper_dir)
+        .await
+        .wrap_err_with(|| format!("Could not create `{}`", wrapper_dir.display()))?;
+    let wrapper_path = wrapper_dir.join(tool);
+    tokio::fs::write(&wrapper_path, script)
+        .await
+        .wrap_err_with(|| format!("Could not write `{}`", wrapper_path.display()))?;
+    tokio::fs::set_permissions(&wrapper_path, std::fs::Permissions::from_mode(0o755)).await?;
+    Ok(wrapper_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_names() {
+        assert_eq!(parse_tool_name("cargo"), Ok("cargo".to_string()));
+        assert_eq!(
+            parse_tool_name("x86_64-linux-gnu-gcc"),
+            Ok("x86_64-linux-gnu-gcc".to_string())
+        );
+        for tool in [
+            "",
+            ".",
+            "..",
+            "../../.bashrc",
+            "/usr/bin/cargo",
+            "cargo\nrm -rf ~",
+        ] {
+            assert!(parse_tool_name(tool).is_err(), "{tool}");
+        }
+    }
+
+    #[test]
+    fn wrapper_execs_pinned_path() {
+        let script = pinned_wrapper_script("cargo", Path::new("/nix/store/aaa-cargo/bin/cargo"));
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("pinned='/nix/store/aaa-cargo/bin/cargo'"));
+        assert!(script.contains(r#"exec "$pinned" "$@""#));
+
+        // Shell syntax in the name is never run
+        let script = pinned_wrapper_script("$(id)", Path::new("/nix/store/aaa-id/bin/id"));
+        assert!(script.contains("tool='$(id)'\n"));
+        assert!(!script.contains("riff pin $(id)"));
+    }
+
+    #[test]
+    fn exec_wrapper_runs_through_riff() {
+        let script = exec_wrapper_script(
+            "cargo",
+            Path::new("/usr/bin/riff"),
+            Path::new("/src/it's here"),
+        );
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("tool='cargo'\n"));
+        assert!(script.contains(
+            r#"exec '/usr/bin/riff' run --project-dir '/src/it'\''s here' -- "$tool" "$@""#
+        ));
+    }
+}
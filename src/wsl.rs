@@ -0,0 +1,167 @@
+//! The Windows Subsystem for Linux, which many people on Windows run riff in.
+//!
+//! riff runs there like on any Linux, but Windows paths like `C:\Users\me\project` can be passed
+//! to it, WSL 1 can't run Nix well, and WSL only starts the Nix daemon with systemd enabled.
+
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::OnceCell;
+
+/// Where the Nix daemon of a multi-user install listens
+pub const NIX_DAEMON_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+
+/// The version of WSL riff runs under, 1 or 2, if it does
+pub fn version() -> Option<u8> {
+    static VERSION: OnceCell<Option<u8>> = OnceCell::new();
+    *VERSION.get_or_init(|| {
+        std::fs::read_to_string("/proc/version")
+            .ok()
+            .and_then(|proc_version| version_of(&proc_version))
+    })
+}
+
+/// The version of WSL whose kernel describes itself with `proc_version`
+///
+/// WSL 1 emulates a kernel like `4.4.0-19041-Microsoft`, and WSL 2 runs a real one like
+/// `5.15.90.1-microsoft-standard-WSL2`.
+fn version_of(proc_version: &str) -> Option<u8> {
+    let proc_version = proc_version.to_lowercase();
+    if !proc_version.contains("microsoft") {
+        return None;
+    }
+    if proc_version.contains("wsl2") || proc_version.contains("microsoft-standard") {
+        Some(2)
+    } else {
+        Some(1)
+    }
+}
+
+/// Parse a path from the command line, translating a Windows path like `C:\Users\me\project`
+/// under WSL
+pub fn parse_path(s: &str) -> Result<PathBuf, String> {
+    if version().is_some() {
+        if let Some(path) = translate(s, &mount_root()) {
+            tracing::debug!(windows_path = s, path = %path.display(), "Translated a Windows path");
+            return Ok(path);
+        }
+    }
+    Ok(PathBuf::from(s))
+}
+
+/// The path inside WSL of the Windows path `path`, if it is one
+///
+/// Drives are mounted in `mount_root`, like `C:\Users\me` in `/mnt/c/Users/me`. Paths into the
+/// distributions themselves, like `\\wsl$\Ubuntu\home\me`, are already inside it.
+fn translate(path: &str, mount_root: &Path) -> Option<PathBuf> {
+    let is_separator = |c: char| c == '\\' || c == '/';
+    let components = |rest: &str| {
+        rest.split(is_separator)
+            .filter(|component| !component.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    for prefix in [
+        r"\\wsl$\",
+        r"\\wsl.localhost\",
+        "//wsl$/",
+        "//wsl.localhost/",
+    ] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            // The first component is the distribution
+            let mut translated = PathBuf::from("/");
+            translated.extend(components(rest).iter().skip(1));
+            return Some(translated);
+        }
+    }
+
+    let mut chars = path.chars();
+    let drive = chars.next().filter(char::is_ascii_alphabetic)?;
+    if chars.next() != Some(':') {
+        return None;
+    }
+    let rest = chars.as_str();
+    if !rest.is_empty() && !rest.starts_with(is_separator) {
+        // A path relative to the drive's current directory, like `C:project`, can't be translated
+        return None;
+    }
+    let mut translated = mount_root.join(drive.to_ascii_lowercase().to_string());
+    translated.extend(components(rest));
+    Some(translated)
+}
+
+/// Where WSL mounts Windows drives, which `/etc/wsl.conf` can change from `/mnt/`
+fn mount_root() -> PathBuf {
+    let wsl_conf = std::fs::read_to_string("/etc/wsl.conf").unwrap_or_default();
+    PathBuf::from(automount_root(&wsl_conf).unwrap_or("/mnt/"))
+}
+
+/// The `root` in the `[automount]` section of a `wsl.conf`
+fn automount_root(wsl_conf: &str) -> Option<&str> {
+    let mut section = "";
+    for line in wsl_conf.lines().map(str::trim) {
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            section = name.trim();
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) if section == "automount" && key.trim() == "root" => {
+                return Some(value.trim().trim_matches('"'));
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        assert_eq!(
+            version_of("Linux version 4.4.0-19041-Microsoft (Microsoft@Microsoft.com)"),
+            Some(1)
+        );
+        assert_eq!(
+            version_of("Linux version 5.15.90.1-microsoft-standard-WSL2 (oe-user@oe-host)"),
+            Some(2)
+        );
+        assert_eq!(version_of("Linux version 6.1.0-13-amd64"), None);
+    }
+
+    #[test]
+    fn windows_paths() {
+        let mnt = Path::new("/mnt/");
+        assert_eq!(
+            translate(r"C:\Users\me\project", mnt),
+            Some(PathBuf::from("/mnt/c/Users/me/project"))
+        );
+        assert_eq!(
+            translate("d:/src/riff/", mnt),
+            Some(PathBuf::from("/mnt/d/src/riff"))
+        );
+        assert_eq!(translate("C:", mnt), Some(PathBuf::from("/mnt/c")));
+        assert_eq!(
+            translate(r"\\wsl$\Ubuntu\home\me\project", mnt),
+            Some(PathBuf::from("/home/me/project"))
+        );
+        assert_eq!(
+            translate(r"C:\project", Path::new("/")),
+            Some(PathBuf::from("/c/project"))
+        );
+        assert_eq!(translate("C:project", mnt), None);
+        assert_eq!(translate("/home/me/project", mnt), None);
+        assert_eq!(translate("project", mnt), None);
+
+        assert_eq!(
+            automount_root("[boot]\nsystemd=true\n\n[automount]\nenabled = true\nroot = /\n"),
+            Some("/")
+        );
+        assert_eq!(automount_root("[boot]\nroot = /boot\n"), None);
+    }
+}
@@ -0,0 +1,473 @@
+//! riff's commands run end to end against a fake `nix` and `cargo`, see [`support`].
+
+mod support;
+
+use std::io::Write;
+use std::process::Stdio;
+
+use serde_json::json;
+use support::{stderr, stdout, FakeNix};
+
+fn openssl_project() -> FakeNix {
+    FakeNix::rust_project(json!({
+        "build-inputs": ["openssl"],
+        "environment-variables": { "HI": "BYE" },
+    }))
+    .dev_env(json!({
+        "PATH": "/nix/store/abc-openssl/bin",
+        "HI": "BYE",
+    }))
+    .versions(json!({ "openssl": "3.0.7" }))
+}
+
+#[test]
+fn print_dev_env_formats() {
+    let fake = openssl_project();
+
+    let activation_script: serde_json::Value =
+        serde_json::from_str(&stdout(&fake.run(["print-dev-env", "--format", "json"]))).unwrap();
+    assert_eq!(
+        activation_script,
+        json!({
+            "set": { "HI": "BYE" },
+            "prepend": { "PATH": "/nix/store/abc-openssl/bin" },
+        })
+    );
+    let fish = stdout(&fake.run(["print-dev-env", "--format", "fish"]));
+    assert!(fish.contains("set -gx HI 'BYE'\n"), "{fish}");
+    assert!(
+        fish.contains("set -gx --path PATH '/nix/store/abc-openssl/bin' $PATH\n"),
+        "{fish}"
+    );
+    let dotenv = stdout(&fake.run(["print-dev-env", "--format", "dotenv"]));
+    assert!(dotenv.contains("HI='BYE'\n"), "{dotenv}");
+
+    let github_env = fake.project_dir().join("github-env");
+    let github_path = fake.project_dir().join("github-path");
+    std::fs::write(&github_env, "EARLIER=step\n").unwrap();
+    let github = stdout(
+        &fake
+            .riff(["print-dev-env", "--format", "github"])
+            .env("GITHUB_ENV", &github_env)
+            .env("GITHUB_PATH", &github_path)
+            .output()
+            .unwrap(),
+    );
+    assert!(github.is_empty(), "{github}");
+    assert_eq!(
+        std::fs::read_to_string(&github_env).unwrap(),
+        "EARLIER=step\nHI=BYE\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&github_path).unwrap(),
+        "/nix/store/abc-openssl/bin\n"
+    );
+    let output = fake.run(["print-dev-env", "--format", "github"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("GITHUB_ENV"),
+        "{}",
+        stderr(&output)
+    );
+
+    let calls = fake.calls();
+    assert_eq!(
+        calls[0],
+        format!(
+            "cargo metadata --format-version 1 --manifest-path {} --offline",
+            fake.project_dir().join("Cargo.toml").display()
+        )
+    );
+    assert!(calls
+        .iter()
+        .any(|call| call.starts_with("nix flake lock") && call.ends_with("--offline")));
+    assert!(calls
+        .iter()
+        .any(|call| call.starts_with("nix print-dev-env --json")));
+}
+
+#[test]
+fn env_diff() {
+    let fake = openssl_project();
+    let output = fake
+        .riff(["env", "diff"])
+        .env("HI", "HELLO")
+        .output()
+        .unwrap();
+    let diff = stdout(&output);
+    assert!(diff.contains(" HI=BYE "), "{diff}");
+    assert!(diff.contains("(was HELLO)"), "{diff}");
+    assert!(diff.contains(" /nix/store/abc-openssl/bin\n"), "{diff}");
+}
+
+#[test]
+fn run_exit_code() {
+    let fake = openssl_project();
+    let output = fake.run(["run", "--", "sh", "-c", "echo \"$HI $IN_RIFF\"; exit 3"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "BYE 1\n");
+}
+
+#[test]
+fn timeouts() {
+    let fake = openssl_project();
+    let output = fake.run(["run", "--command-timeout", "1", "--", "sleep", "10"]);
+    assert_eq!(output.status.code(), Some(124), "{}", stderr(&output));
+    let output = fake.run(["run", "--command-timeout", "1m", "--", "true"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn shell() {
+    let fake = openssl_project();
+    let mut shell = fake
+        .riff(["shell"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    shell
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"echo \"$HI\"\nexit 4\n")
+        .unwrap();
+    let output = shell.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "BYE\n");
+
+    // `SHELL` is `/bin/sh`, which doesn't set `BASH_VERSION`
+    let run = |args: &[&str]| {
+        let mut shell = fake
+            .riff(args.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        shell
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"echo \"$HI ${BASH_VERSION:+bash}\"\n")
+            .unwrap();
+        stdout(&shell.wait_with_output().unwrap())
+    };
+    assert_eq!(run(&["shell"]), "BYE \n");
+    assert_eq!(run(&["shell", "--shell", "bash"]), "BYE bash\n");
+    assert_eq!(run(&["shell", "--shell", "bash", "--no-rc"]), "BYE bash\n");
+    let output = fake.run(["shell", "--shell", "nu", "--no-rc"]);
+    assert!(!output.status.success());
+
+    let output = fake.run(["shell", "-c", "printf '%s|' \"$HI\" 'a b'; exit 5"]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "BYE|a b|");
+    let output = fake.run(["shell", "--command", "kill -TERM $$"]);
+    assert_eq!(output.status.code(), Some(128 + 15), "{}", stderr(&output));
+}
+
+#[test]
+fn nix_failures() {
+    let fake = openssl_project().fail("print-dev-env", 1, "error: attribute 'openssl' missing\n");
+    let output = fake.run(["run", "--", "true"]);
+    assert_eq!(output.status.code(), Some(1));
+    let message = stderr(&output);
+    assert!(
+        message.contains("error: attribute 'openssl' missing"),
+        "{message}"
+    );
+    assert!(
+        message.contains("`nix print-dev-env` exited with code 1"),
+        "{message}"
+    );
+
+    let fake = openssl_project().fail("flake", 1, "error: unable to download\n");
+    let output = fake.run(["print-dev-env", "--format", "json"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let message = stderr(&output);
+    assert!(
+        message.contains("`nix flake lock` exited with code 1"),
+        "{message}"
+    );
+}
+
+#[test]
+fn cargo_subcommand() {
+    let fake = openssl_project();
+    let output = fake
+        .cargo_riff(["run", "--", "sh", "-c", "echo \"$HI\"; exit 3"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "BYE\n");
+}
+
+#[test]
+fn read_only_home() {
+    let fake = openssl_project();
+    // Nothing can be created inside a file, even by root
+    let home = fake.project_dir().join("Cargo.toml");
+    let run = |args: &[&str]| {
+        fake.riff(args.iter().copied())
+            .env("HOME", &home)
+            .env("XDG_CACHE_HOME", &home)
+            .env("XDG_STATE_HOME", &home)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["run", "--", "sh", "-c", "echo \"$HI\""]);
+    assert_eq!(stdout(&output), "BYE\n");
+    let message = stderr(&output);
+    assert_eq!(
+        message.matches("can't write to its directories").count(),
+        1,
+        "{message}"
+    );
+    // The second run uses the flake locked and cached in the temporary directory
+    assert_eq!(stdout(&run(&["run", "--", "true"])), "");
+    let locks = fake
+        .calls()
+        .iter()
+        .filter(|call| call.starts_with("nix flake lock"))
+        .count();
+    assert_eq!(locks, 1);
+}
+
+#[test]
+fn partial_detection() {
+    let fake = openssl_project();
+    std::fs::write(fake.project_dir().join("package.json"), "{ not json").unwrap();
+
+    let output = fake.run(["print-dev-env", "--format", "json"]);
+    let message = stderr(&output);
+    assert!(output.status.success(), "{message}");
+    assert!(message.contains("detection failed"), "{message}");
+    assert!(stdout(&output).contains("BYE"));
+
+    let output = fake.run(["--strict-detect", "print-dev-env", "--format", "json"]);
+    let message = stderr(&output);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(message.contains("could not detect its environment"), "{message}");
+    assert!(!message.contains("doesn't contain a project"), "{message}");
+    assert!(message.contains("package.json"), "{message}");
+}
+
+#[test]
+fn conflicting_packages() {
+    let fake = openssl_project();
+    std::fs::write(
+        fake.project_dir().join("riff.toml"),
+        "build-inputs = [\"postgresql_14\", \"postgresql_15\"]\n",
+    )
+    .unwrap();
+    let output = fake.run(["print-dev-env", "--format", "json"]);
+    let message = stderr(&output);
+    assert!(output.status.success(), "{message}");
+    assert!(message.contains("conflicting packages"), "{message}");
+    assert!(
+        message.contains("remove-build-inputs = [\"postgresql_14\"]"),
+        "{message}"
+    );
+
+    std::fs::write(
+        fake.project_dir().join("riff.toml"),
+        "build-inputs = [\"postgresql_14\", \"postgresql_15\"]\nremove-build-inputs = [\"postgresql_14\"]\n",
+    )
+    .unwrap();
+    let output = fake.run(["print-dev-env", "--format", "json"]);
+    assert!(!stderr(&output).contains("conflicting packages"));
+}
+
+#[test]
+fn config_layers() {
+    let fake = openssl_project();
+    let user_config = fake.home_dir().join(".config/riff/config.toml");
+    std::fs::create_dir_all(user_config.parent().unwrap()).unwrap();
+    std::fs::write(
+        &user_config,
+        "spinner-delay = 50\nno-dev = true\nbuild-inputs = [\"cmake\"]\n",
+    )
+    .unwrap();
+    let project_config = fake.project_dir().join("riff.toml");
+    std::fs::write(
+        &project_config,
+        "build-inputs = [\"protobuf\"]\nno-dev = false\n",
+    )
+    .unwrap();
+    let extra = fake.project_dir().join("ci.toml");
+    std::fs::write(&extra, "conservative = true\n").unwrap();
+
+    let output = fake
+        .riff(["config", "show", "--origin", "--config", "ci.toml"])
+        .env("RIFF_SPINNER_DELAY", "0")
+        .output()
+        .unwrap();
+    let settings = stdout(&output);
+    let setting = |name: &str| {
+        settings
+            .lines()
+            .find(|line| line.starts_with(&format!("{name} = ")))
+            .unwrap_or_else(|| panic!("No `{name}` in:\n{settings}"))
+            .to_string()
+    };
+    assert_eq!(
+        setting("build-inputs"),
+        format!(
+            "build-inputs = [\"cmake\", \"protobuf\"]  # user config `{}`, project config `{}`",
+            user_config.display(),
+            project_config.display()
+        )
+    );
+    assert_eq!(
+        setting("no-dev"),
+        format!(
+            "no-dev = false  # project config `{}`",
+            project_config.display()
+        )
+    );
+    assert_eq!(
+        setting("conservative"),
+        "conservative = true  # `--config ci.toml`"
+    );
+    assert_eq!(
+        setting("spinner-delay"),
+        "spinner-delay = 0  # environment variable `RIFF_SPINNER_DELAY`"
+    );
+    assert_eq!(setting("offline"), "offline = true  # flag `--offline`");
+    assert_eq!(setting("no-network"), "no-network = false  # default");
+
+    let output = fake.run(["config", "show", "--config", "missing.toml"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("missing.toml"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn config_set_and_get() {
+    let fake = openssl_project();
+    let user_config = fake.home_dir().join(".config/riff/config.toml");
+
+    stdout(&fake.run(["config", "set", "spinner-delay", "250"]));
+    stdout(&fake.run(["config", "set", "build-inputs", "[\"cmake\"]"]));
+    assert_eq!(
+        std::fs::read_to_string(&user_config).unwrap(),
+        "spinner-delay = 250\nbuild-inputs = [\"cmake\"]\n"
+    );
+    assert_eq!(
+        stdout(&fake.run(["config", "get", "spinner-delay"])),
+        "250\n"
+    );
+    assert_eq!(
+        stdout(&fake.run(["config", "get", "build-inputs"])),
+        "[\"cmake\"]\n"
+    );
+
+    for (name, value) in [("spinner-delay", "soon"), ("build-input", "cmake")] {
+        let output = fake.run(["config", "set", name, value]);
+        assert!(!output.status.success(), "{name} = {value}");
+    }
+
+    stdout(&fake.run(["config", "unset", "build-inputs"]));
+    assert_eq!(
+        std::fs::read_to_string(&user_config).unwrap(),
+        "spinner-delay = 250\n"
+    );
+    let output = fake.run(["config", "get", "build-inputs"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn reproducible_exports() {
+    let fake = openssl_project();
+    let output = fake.run(["verify-exports"]);
+    let message = stderr(&output);
+    assert!(output.status.success(), "{message}");
+    // flake, oci, devcontainer and sbom, but not the machine's github-actions or cargo-config
+    let reproducible = if cfg!(feature = "sbom") { 4 } else { 3 };
+    assert_eq!(
+        message.matches("is reproducible").count(),
+        reproducible,
+        "{message}"
+    );
+
+    let oci = |epoch: &str| {
+        stdout(
+            &fake
+                .riff(["export", "oci"])
+                .env("SOURCE_DATE_EPOCH", epoch)
+                .output()
+                .unwrap(),
+        )
+    };
+    assert!(oci("1662033600").contains("created = \"2022-09-01T12:00:00Z\";"));
+    assert!(!oci("").contains("created"));
+    if !cfg!(feature = "sbom") {
+        return;
+    }
+
+    let sbom: serde_json::Value =
+        serde_json::from_str(&stdout(&fake.run(["export", "sbom"]))).unwrap();
+    assert_eq!(sbom["metadata"]["component"]["name"], "fixture");
+    let openssl = sbom["components"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|component| component["name"] == "openssl");
+    assert_eq!(
+        openssl,
+        Some(&json!({
+            "type": "library",
+            "bom-ref": "nixpkgs#openssl",
+            "name": "openssl",
+            "version": "3.0.7",
+            "properties": [{ "name": "riff:input", "value": "build" }],
+        }))
+    );
+}
+
+#[test]
+fn github_actions_export() {
+    let fake = openssl_project();
+    let github_env = fake.project_dir().join("github-env");
+    let github_path = fake.project_dir().join("github-path");
+    std::fs::write(&github_env, "EARLIER=step").unwrap();
+    let output = fake
+        .riff(["export", "github-actions"])
+        .env("GITHUB_ENV", &github_env)
+        .env("GITHUB_PATH", &github_path)
+        .output()
+        .unwrap();
+    assert!(stdout(&output).is_empty());
+    assert_eq!(
+        std::fs::read_to_string(&github_env).unwrap(),
+        "EARLIER=step\nHI=BYE\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&github_path).unwrap(),
+        "/nix/store/abc-openssl/bin\n"
+    );
+
+    // Outside of GitHub Actions, only into a directory
+    let output = fake.run(["export", "github-actions"]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("GITHUB_ENV"),
+        "{}",
+        stderr(&output)
+    );
+    let output_dir = fake.project_dir().join("github");
+    let output_dir_arg = output_dir.display().to_string();
+    stdout(&fake.run(["export", "github-actions", "--output-dir", &output_dir_arg]));
+    assert_eq!(
+        std::fs::read_to_string(output_dir.join("env")).unwrap(),
+        "HI=BYE\n"
+    );
+}
@@ -0,0 +1,208 @@
+//! A fake `nix` and `cargo` for running riff end to end, without either installed.
+//!
+//! [`FakeNix`] writes a small project and shell scripts named `nix` and `cargo` into a temporary
+//! directory, and runs the riff binary with only that directory and the system's `/usr/bin` and
+//! `/bin` on the `PATH`. The scripts record every call, answer `nix --version`, `nix flake lock`,
+//! `nix eval` and `nix print-dev-env --json` with the environment and versions the test gives, and
+//! answer `cargo metadata` with the project's `package.metadata.riff` table. Everything riff writes, like
+//! its cache, stays in the temporary directory.
+
+#![allow(dead_code)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use tempfile::TempDir;
+
+const NIX: &str = r#"#!/bin/sh
+fake="$(dirname "$0")/.."
+echo "nix $*" >> "$fake/calls"
+if [ -f "$fake/fail-$1" ]; then
+    cat "$fake/fail-$1" >&2
+    exit "$(cat "$fake/fail-$1.code")"
+fi
+case "$1" in
+    --version) echo "nix (Nix) 2.11.1" ;;
+    flake)
+        for arg; do
+            case "$arg" in
+                path://*) echo '{"nodes":{"nixpkgs":{"locked":{"type":"github","owner":"NixOS","repo":"nixpkgs","rev":"0123456789abcdef0123456789abcdef01234567"}},"root":{"inputs":{"nixpkgs":"nixpkgs"}}},"root":"root","version":7}' > "${arg#path://}/flake.lock" ;;
+            esac
+        done
+        ;;
+    eval)
+        case "$*" in
+            *builtins.currentSystem*) printf x86_64-linux ;;
+            *) cat "$fake/versions.json" ;;
+        esac
+        ;;
+    print-dev-env) cat "$fake/dev-env.json" ;;
+    *) echo "fake nix: \`nix $1\` isn't faked" >&2; exit 1 ;;
+esac
+"#;
+
+const CARGO: &str = r#"#!/bin/sh
+fake="$(dirname "$0")/.."
+echo "cargo $*" >> "$fake/calls"
+case "$1" in
+    metadata) cat "$fake/cargo-metadata.json" ;;
+    *) echo "fake cargo: \`cargo $1\` isn't faked" >&2; exit 1 ;;
+esac
+"#;
+
+/// A Rust project, and a fake `nix` which provides `variables` as its environment
+pub struct FakeNix {
+    dir: TempDir,
+}
+
+impl FakeNix {
+    /// A Rust project with the `package.metadata.riff` table `riff`, whose environment sets no
+    /// variables until [`dev_env`](Self::dev_env) gives some
+    pub fn rust_project(riff: serde_json::Value) -> Self {
+        let fake = Self {
+            dir: TempDir::new().expect("Could not create a temporary directory"),
+        };
+        let project_dir = fake.project_dir();
+        std::fs::create_dir_all(project_dir.join("src")).unwrap();
+        std::fs::write(
+            project_dir.join("Cargo.toml"),
+            "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        )
+        .unwrap();
+        std::fs::write(project_dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+
+        let id = "fixture 0.1.0 (path+file:///fixture)";
+        let metadata = serde_json::json!({
+            "packages": [{
+                "name": "fixture",
+                "id": id,
+                "version": "0.1.0",
+                "manifest_path": project_dir.join("Cargo.toml"),
+                "metadata": { "riff": riff },
+            }],
+            "resolve": { "root": id, "nodes": [{ "id": id }] },
+            "workspace_members": [id],
+        });
+        fake.write("cargo-metadata.json", &metadata.to_string());
+
+        let bin = fake.dir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        for (name, script) in [("nix", NIX), ("cargo", CARGO)] {
+            let path = bin.join(name);
+            std::fs::write(&path, script).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fake.dev_env(serde_json::json!({}))
+            .versions(serde_json::json!({}))
+    }
+
+    /// Have `nix print-dev-env` export `variables`, like `{"PATH": "/nix/store/...-openssl/bin"}`
+    pub fn dev_env(self, variables: serde_json::Value) -> Self {
+        let variables = variables
+            .as_object()
+            .expect("The variables are an object")
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.clone(),
+                    serde_json::json!({ "type": "exported", "value": value }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        self.write(
+            "dev-env.json",
+            &serde_json::json!({ "variables": variables, "bashFunctions": {} }).to_string(),
+        );
+        self
+    }
+
+    /// Have `nix eval` resolve the inputs to `versions`, like `{"openssl": "3.0.7"}`
+    pub fn versions(self, versions: serde_json::Value) -> Self {
+        self.write("versions.json", &versions.to_string());
+        self
+    }
+
+    /// Have `nix <subcommand>` print `stderr` and exit with `code`
+    pub fn fail(self, subcommand: &str, code: i32, stderr: &str) -> Self {
+        self.write(&format!("fail-{subcommand}"), stderr);
+        self.write(&format!("fail-{subcommand}.code"), &code.to_string());
+        self
+    }
+
+    pub fn project_dir(&self) -> PathBuf {
+        self.dir.path().join("project")
+    }
+
+    /// The `HOME` riff runs with, which starts out empty
+    pub fn home_dir(&self) -> PathBuf {
+        self.dir.path().join("home")
+    }
+
+    /// riff, run offline in the project with the fakes first on the `PATH`
+    pub fn riff<'a>(&self, args: impl IntoIterator<Item = &'a str>) -> Command {
+        let mut command = self.command(env!("CARGO_BIN_EXE_riff"));
+        command.arg("--offline").args(args);
+        command
+    }
+
+    /// Like [`riff`](Self::riff), but run as `cargo riff`
+    pub fn cargo_riff<'a>(&self, args: impl IntoIterator<Item = &'a str>) -> Command {
+        let mut command = self.command(env!("CARGO_BIN_EXE_cargo-riff"));
+        command.args(["riff", "--offline"]).args(args);
+        command
+    }
+
+    fn command(&self, program: &str) -> Command {
+        let home = self.home_dir();
+        let mut command = Command::new(program);
+        command
+            .current_dir(self.project_dir())
+            .env_clear()
+            .env(
+                "PATH",
+                format!("{}:/usr/bin:/bin", self.dir.path().join("bin").display()),
+            )
+            .env("HOME", &home)
+            .env("XDG_CACHE_HOME", home.join(".cache"))
+            .env("XDG_CONFIG_HOME", home.join(".config"))
+            .env("XDG_CONFIG_DIRS", self.dir.path().join("etc"))
+            .env("TMPDIR", self.dir.path())
+            .env("SHELL", "/bin/sh")
+            .env("RIFF_DISABLE_TELEMETRY", "1");
+        command
+    }
+
+    /// Run riff like [`riff`](Self::riff) does, and wait for it to finish
+    pub fn run<'a>(&self, args: impl IntoIterator<Item = &'a str>) -> Output {
+        self.riff(args).output().expect("Could not run riff")
+    }
+
+    /// The commands riff ran the fakes with, like `nix --version`, in order
+    pub fn calls(&self) -> Vec<String> {
+        std::fs::read_to_string(self.dir.path().join("calls"))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn write(&self, name: &str, contents: &str) {
+        std::fs::write(self.dir.path().join(name), contents).unwrap();
+    }
+}
+
+/// The standard output of riff, which must have succeeded
+pub fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "riff failed with {}:\n{}",
+        output.status,
+        stderr(output)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}